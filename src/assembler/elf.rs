@@ -0,0 +1,204 @@
+//! Minimal MIPS ELF writer: a single loadable segment containing the
+//! text plus a symbol table so that debuggers and disassemblers can
+//! display label names.
+
+use std::io;
+
+/// 32bit little endian MIPS executable
+pub struct Elf {
+    /// Address the text is loaded at
+    pub base: u32,
+    /// Executable entry point
+    pub entry: u32,
+    /// Executable code and data
+    pub text: Vec<u8>,
+    /// Symbol names and addresses, see `Assembler::globals`
+    pub symbols: Vec<(String, u32)>,
+}
+
+impl Elf {
+    /// Create an executable loaded at `base` and whose entry point
+    /// is the first byte of `text`
+    pub fn new(base: u32, text: Vec<u8>) -> Elf {
+        Elf {
+            base: base,
+            entry: base,
+            text: text,
+            symbols: Vec::new(),
+        }
+    }
+
+    pub fn write(&self, w: &mut io::Write) -> io::Result<()> {
+        // Section name table, the offsets are used in the section
+        // headers below
+        let shstrtab = b"\0.text\0.symtab\0.strtab\0.shstrtab\0";
+
+        let mut strtab = vec![0];
+        let mut symtab = vec![0; SYM_SIZE];
+
+        for &(ref name, addr) in &self.symbols {
+            let name_off = strtab.len() as u32;
+
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+
+            push_u32(&mut symtab, name_off);
+            push_u32(&mut symtab, addr);
+            // Size unknown
+            push_u32(&mut symtab, 0);
+            // STB_GLOBAL, STT_NOTYPE
+            symtab.push(0x10);
+            symtab.push(0);
+            // Section index: .text
+            push_u16(&mut symtab, 1);
+        }
+
+        let text_off = align4(EHDR_SIZE + PHDR_SIZE);
+        let symtab_off = align4(text_off + self.text.len());
+        let strtab_off = symtab_off + symtab.len();
+        let shstrtab_off = strtab_off + strtab.len();
+        let shdr_off = align4(shstrtab_off + shstrtab.len());
+
+        let mut out = Vec::new();
+
+        // ELF header. Identification: 32bit, little endian, version
+        // 1, System V ABI
+        out.extend_from_slice(b"\x7fELF\x01\x01\x01\0\0\0\0\0\0\0\0\0");
+        // ET_EXEC
+        push_u16(&mut out, 2);
+        // EM_MIPS
+        push_u16(&mut out, 8);
+        push_u32(&mut out, 1);
+        push_u32(&mut out, self.entry);
+        push_u32(&mut out, EHDR_SIZE as u32);
+        push_u32(&mut out, shdr_off as u32);
+        // Flags: MIPS I, o32 ABI
+        push_u32(&mut out, 0x1000);
+        push_u16(&mut out, EHDR_SIZE as u16);
+        push_u16(&mut out, PHDR_SIZE as u16);
+        push_u16(&mut out, 1);
+        push_u16(&mut out, SHDR_SIZE as u16);
+        // Section count: null, .text, .symtab, .strtab, .shstrtab
+        push_u16(&mut out, 5);
+        // Index of .shstrtab
+        push_u16(&mut out, 4);
+
+        // Program header: PT_LOAD, RWX
+        push_u32(&mut out, 1);
+        push_u32(&mut out, text_off as u32);
+        push_u32(&mut out, self.base);
+        push_u32(&mut out, self.base);
+        push_u32(&mut out, self.text.len() as u32);
+        push_u32(&mut out, self.text.len() as u32);
+        push_u32(&mut out, 7);
+        push_u32(&mut out, 4);
+
+        out.resize(text_off, 0);
+        out.extend_from_slice(&self.text);
+        out.resize(symtab_off, 0);
+        out.extend_from_slice(&symtab);
+        out.extend_from_slice(&strtab);
+        out.extend_from_slice(shstrtab);
+        out.resize(shdr_off, 0);
+
+        // Section headers: name offset, type, flags, address, file
+        // offset, size, link, info, alignment, entry size
+        push_section(&mut out, &[0; 10]);
+
+        // .text: SHT_PROGBITS, SHF_WRITE | SHF_ALLOC | SHF_EXECINSTR
+        push_section(&mut out, &[1, 1, 7, self.base,
+                                 text_off as u32, self.text.len() as u32,
+                                 0, 0, 4, 0]);
+        // .symtab: SHT_SYMTAB linked to .strtab. The info field is
+        // the index of the first non-local symbol.
+        push_section(&mut out, &[7, 2, 0, 0,
+                                 symtab_off as u32, symtab.len() as u32,
+                                 3, 1, 4, SYM_SIZE as u32]);
+        // .strtab: SHT_STRTAB
+        push_section(&mut out, &[15, 3, 0, 0,
+                                 strtab_off as u32, strtab.len() as u32,
+                                 0, 0, 1, 0]);
+        // .shstrtab: SHT_STRTAB
+        push_section(&mut out, &[23, 3, 0, 0,
+                                 shstrtab_off as u32, shstrtab.len() as u32,
+                                 0, 0, 1, 0]);
+
+        w.write_all(&out)
+    }
+}
+
+/// Push a section header
+fn push_section(out: &mut Vec<u8>, fields: &[u32; 10]) {
+    for &f in fields {
+        push_u32(out, f);
+    }
+}
+
+fn push_u16(v: &mut Vec<u8>, h: u16) {
+    v.push(h as u8);
+    v.push((h >> 8) as u8);
+}
+
+fn push_u32(v: &mut Vec<u8>, w: u32) {
+    v.push(w as u8);
+    v.push((w >> 8) as u8);
+    v.push((w >> 16) as u8);
+    v.push((w >> 24) as u8);
+}
+
+fn align4(v: usize) -> usize {
+    (v + 3) & !3
+}
+
+/// Size of the ELF header
+const EHDR_SIZE: usize = 52;
+/// Size of a program header
+const PHDR_SIZE: usize = 32;
+/// Size of a section header
+const SHDR_SIZE: usize = 40;
+/// Size of a symbol table entry
+const SYM_SIZE: usize = 16;
+
+#[test]
+fn elf_symbols() {
+    use assembler::Assembler;
+    use assembler::syntax::*;
+    use debugger::symbols::SymbolTable;
+
+    let mut asm = Assembler::from_base(0x80010000);
+
+    asm.assemble(&[
+        Global("main"),
+        Jal(Label::Global("delay")),
+        Nop,
+        Global("delay"),
+        Jr(RA),
+        Nop,
+    ]).unwrap();
+
+    let symbols = asm.globals().iter()
+        .map(|&(name, addr)| (name.to_string(), addr))
+        .collect();
+
+    let (text, base) = asm.machine_code().unwrap();
+
+    let mut elf = Elf::new(base, text);
+    elf.symbols = symbols;
+
+    let mut out = Vec::new();
+    elf.write(&mut out).unwrap();
+
+    assert!(&out[0..4] == b"\x7fELF");
+
+    let table = SymbolTable::from_elf(&out).unwrap();
+
+    assert!(table.len() == 2);
+    assert!(table.address_of("main") == Some(0x80010000));
+    assert!(table.address_of("delay") == Some(0x80010008));
+
+    // The text is loaded from the offset given by the program
+    // header
+    let text_off = out[56] as usize | (out[57] as usize) << 8;
+
+    assert!(&out[text_off..text_off + 16] == &elf.text[..]);
+}
@@ -0,0 +1,106 @@
+//! PS-X EXE writer, the format understood by the BIOS's `Exec`
+//! functions and by `parallel_io::exe_loader`.
+
+use std::io;
+
+use cdrom::disc::Region;
+
+/// PlayStation executable
+pub struct Exe {
+    /// Address the text is loaded at
+    pub base: u32,
+    /// Executable entry point
+    pub entry: u32,
+    /// GP value before jumping to the entry point
+    pub initial_gp: u32,
+    /// SP value before jumping to the entry point. If 0 the BIOS
+    /// leaves SP untouched.
+    pub initial_sp: u32,
+    /// Base address of the 0-filled area
+    pub memfill_base: u32,
+    /// Length of the 0-filled area, 0 to disable
+    pub memfill_len: u32,
+    /// Region written in the license string. Some BIOS versions
+    /// refuse to run executables for other regions.
+    pub region: Option<Region>,
+    /// Executable code and data
+    pub text: Vec<u8>,
+}
+
+impl Exe {
+    /// Create an executable loaded at `base` and whose entry point
+    /// is the first byte of `text`. The stack is set to the top of
+    /// RAM like the BIOS does by default.
+    pub fn new(base: u32, text: Vec<u8>) -> Exe {
+        Exe {
+            base: base,
+            entry: base,
+            initial_gp: 0,
+            initial_sp: 0x801ffff0,
+            memfill_base: 0,
+            memfill_len: 0,
+            region: None,
+            text: text,
+        }
+    }
+
+    /// Write the executable to `w`. The text is padded with zeroes to
+    /// a multiple of the CD sector size, like the official tools do.
+    pub fn write(&self, w: &mut io::Write) -> io::Result<()> {
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+
+        header.extend_from_slice(b"PS-X EXE\0\0\0\0\0\0\0\0");
+
+        let text_len = (self.text.len() + SECTOR_SIZE - 1) & !(SECTOR_SIZE - 1);
+
+        push_u32(&mut header, self.entry);
+        push_u32(&mut header, self.initial_gp);
+        push_u32(&mut header, self.base);
+        push_u32(&mut header, text_len as u32);
+        // Unused data section
+        push_u32(&mut header, 0);
+        push_u32(&mut header, 0);
+        push_u32(&mut header, self.memfill_base);
+        push_u32(&mut header, self.memfill_len);
+        // SP base and offset
+        push_u32(&mut header, self.initial_sp);
+        push_u32(&mut header, 0);
+
+        // Padding up to the license string
+        header.extend_from_slice(&[0; 20]);
+
+        let license: &[u8] =
+            match self.region {
+                Some(Region::Japan) =>
+                    b"Sony Computer Entertainment Inc. for Japan area",
+                Some(Region::Europe) =>
+                    b"Sony Computer Entertainment Inc. for Europe area",
+                Some(Region::NorthAmerica) =>
+                    b"Sony Computer Entertainment Inc. for North America area",
+                None => b"",
+            };
+
+        header.extend_from_slice(license);
+        header.resize(HEADER_SIZE, 0);
+
+        try!(w.write_all(&header));
+        try!(w.write_all(&self.text));
+
+        let padding = vec![0; text_len - self.text.len()];
+
+        w.write_all(&padding)
+    }
+}
+
+fn push_u32(v: &mut Vec<u8>, w: u32) {
+    v.push(w as u8);
+    v.push((w >> 8) as u8);
+    v.push((w >> 16) as u8);
+    v.push((w >> 24) as u8);
+}
+
+/// Size of the EXE header, the text starts right after it
+const HEADER_SIZE: usize = 2048;
+
+/// The text length must be a multiple of a CD sector
+const SECTOR_SIZE: usize = 2048;
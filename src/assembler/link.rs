@@ -0,0 +1,308 @@
+//! Linker combining several assembler units into a single program.
+//! Each section (text, data and bss) of every unit is concatenated
+//! with the same section of the other units, then references
+//! between units are resolved.
+
+use std::collections::HashMap;
+
+use super::{Assembler, Target, read_word, write_word};
+
+/// Linker configuration: where to place each section
+pub struct Linker {
+    text_base: u32,
+    /// If `None` the section is placed right after the previous one
+    data_base: Option<u32>,
+    /// If `None` the section is placed right after the previous one
+    bss_base: Option<u32>,
+}
+
+impl Linker {
+    /// Create a linker placing the text at `text_base`, followed by
+    /// the data and bss sections
+    pub fn new(text_base: u32) -> Linker {
+        Linker {
+            text_base: text_base,
+            data_base: None,
+            bss_base: None,
+        }
+    }
+
+    /// Place the data section at `base` instead of after the text
+    pub fn set_data_base(&mut self, base: u32) {
+        self.data_base = Some(base);
+    }
+
+    /// Place the bss section at `base` instead of after the data
+    pub fn set_bss_base(&mut self, base: u32) {
+        self.bss_base = Some(base);
+    }
+
+    /// Lay out the sections of all `units` and resolve the references
+    /// between them. Units are placed in order.
+    pub fn link(&self, units: &[Assembler]) -> Result<Program, String> {
+        let starts = [Some(self.text_base), self.data_base, self.bss_base];
+
+        // Address of each section of each unit
+        let mut bases = vec![[0u32; 3]; units.len()];
+        // Address range of each output section
+        let mut ranges = [(0u32, 0u32); 3];
+
+        let mut end = self.text_base;
+
+        for s in 0..3 {
+            let align = units.iter().map(|u| u.alignments[s]).max();
+
+            let start =
+                match starts[s] {
+                    Some(b) => b,
+                    None => align_up(end, align.unwrap_or(0)),
+                };
+
+            let mut addr = start;
+
+            for (u, unit) in units.iter().enumerate() {
+                match unit.bases[s] {
+                    Some(b) => if b != addr {
+                        return Err(format!("Unit {} was assembled for \
+                                            address 0x{:08x} but is placed \
+                                            at 0x{:08x}", u, b, addr));
+                    },
+                    None => addr = align_up(addr, unit.alignments[s]),
+                }
+
+                bases[u][s] = addr;
+                addr = addr.wrapping_add(unit.sections[s].len() as u32);
+            }
+
+            ranges[s] = (start, addr);
+            end = addr;
+        }
+
+        let mut symbols: HashMap<&str, (u32, usize)> = HashMap::new();
+
+        for (u, unit) in units.iter().enumerate() {
+            for (&name, &(section, offset)) in &unit.globals {
+                let addr = bases[u][section.index()].wrapping_add(offset);
+
+                if let Some((_, other)) = symbols.insert(name, (addr, u)) {
+                    return Err(format!("Global label '{}' is defined in \
+                                        unit {} and unit {}",
+                                       name, other, u));
+                }
+            }
+        }
+
+        // Copy the text and data. The bss only contains zeroes.
+        let mut text = vec![0; (ranges[0].1 - ranges[0].0) as usize];
+        let mut data = vec![0; (ranges[1].1 - ranges[1].0) as usize];
+
+        for (u, unit) in units.iter().enumerate() {
+            for s in 0..2 {
+                let out = if s == 0 { &mut text } else { &mut data };
+
+                let pos = (bases[u][s] - ranges[s].0) as usize;
+                let code = &unit.sections[s];
+
+                out[pos..pos + code.len()].copy_from_slice(code);
+            }
+        }
+
+        let mut unresolved = Vec::new();
+
+        for (u, unit) in units.iter().enumerate() {
+            for r in &unit.relocations {
+                let target =
+                    match r.target {
+                        Target::Absolute(a) => a,
+                        Target::Defined(section, offset) =>
+                            bases[u][section.index()].wrapping_add(offset),
+                        Target::Symbol(name) =>
+                            match symbols.get(name) {
+                                Some(&(addr, _)) => addr,
+                                None => {
+                                    if !unresolved.contains(&name) {
+                                        unresolved.push(name);
+                                    }
+                                    continue;
+                                }
+                            },
+                    };
+
+                let target = target.wrapping_add(r.addend as u32);
+
+                let s = r.section.index();
+                let pc = bases[u][s].wrapping_add(r.offset);
+                let pos = (pc - ranges[s].0) as usize;
+
+                let out = if s == 0 { &mut text } else { &mut data };
+
+                let word = try!(r.kind.apply(read_word(&out[pos..]),
+                                             target,
+                                             pc));
+
+                write_word(&mut out[pos..], word);
+            }
+        }
+
+        if !unresolved.is_empty() {
+            return Err(format!("Unresolved symbols: {}",
+                               unresolved.join(", ")));
+        }
+
+        let mut symbols: Vec<(String, u32)> =
+            symbols.iter()
+            .map(|(&name, &(addr, _))| (name.to_string(), addr))
+            .collect();
+
+        symbols.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+
+        Ok(Program {
+            text_base: ranges[0].0,
+            text: text,
+            data_base: ranges[1].0,
+            data: data,
+            bss_base: ranges[2].0,
+            bss_len: ranges[2].1 - ranges[2].0,
+            symbols: symbols,
+        })
+    }
+}
+
+/// Round `addr` up to a multiple of `1 << order`
+fn align_up(addr: u32, order: u8) -> u32 {
+    let mask = (1u32 << order) - 1;
+
+    addr.wrapping_add(mask) & !mask
+}
+
+/// Linked program
+pub struct Program {
+    pub text_base: u32,
+    pub text: Vec<u8>,
+    pub data_base: u32,
+    pub data: Vec<u8>,
+    pub bss_base: u32,
+    pub bss_len: u32,
+    /// Name and address of all the global labels, sorted by address
+    pub symbols: Vec<(String, u32)>,
+}
+
+impl Program {
+    /// Return the address of global label `name`
+    pub fn symbol(&self, name: &str) -> Option<u32> {
+        self.symbols.iter().find(|s| s.0 == name).map(|s| s.1)
+    }
+}
+
+#[test]
+fn link_units() {
+    use super::syntax::*;
+
+    let mut main = Assembler::relocatable();
+
+    main.assemble(&[
+        Global("main"),
+        Jal(Label::Global("putchar")),
+        Nop,
+        Hi(Label::Offset("message", 4)),
+        Lui(A0, 0),
+        Lo(Label::Offset("message", 4)),
+        Lw(A0, A0, 0),
+        B(Label::Global("main")),
+        Nop,
+        Data,
+        Global("message"),
+        Word(0x11111111),
+        Word(0x22222222),
+        Address(Label::Global("putchar")),
+        Bss,
+        Global("buffer"),
+        Space(0x100),
+    ]).unwrap();
+
+    let mut lib = Assembler::relocatable();
+
+    lib.assemble(&[
+        Global("putchar"),
+        Jr(RA),
+        Nop,
+        Data,
+        Byte(0x42),
+    ]).unwrap();
+
+    let mut linker = Linker::new(0x80010000);
+    linker.set_data_base(0x80018000);
+
+    let program = linker.link(&[main, lib]).unwrap();
+
+    let putchar = 0x80010000 + 6 * 4;
+    let message = 0x80018000;
+
+    assert!(program.symbol("main") == Some(0x80010000));
+    assert!(program.symbol("putchar") == Some(putchar));
+    assert!(program.symbol("message") == Some(message));
+    // Placed after the data of both units, aligned
+    assert!(program.symbol("buffer") == Some(0x80018010));
+    assert!(program.bss_len == 0x100);
+
+    let word = |b: &[u8], i: usize| read_word(&b[i * 4..]);
+
+    // jal putchar
+    assert!(word(&program.text, 0) == 0x0c000000 | (putchar >> 2) & 0x3ffffff);
+    // lui a0, %hi(message + 4)
+    assert!(word(&program.text, 2) == 0x3c048002);
+    // lw a0, %lo(message + 4)(a0)
+    assert!(word(&program.text, 3) == 0x8c848004);
+    // b main
+    assert!(word(&program.text, 4) == 0x1000fffb);
+    // Address of putchar in the data
+    assert!(word(&program.data, 2) == putchar);
+    assert!(program.data[0xc] == 0x42);
+
+    let mut broken = Assembler::relocatable();
+
+    broken.assemble(&[
+        Jal(Label::Global("missing")),
+        J(Label::Global("also_missing")),
+    ]).unwrap();
+
+    let e = Linker::new(0).link(&[broken]).unwrap_err();
+
+    assert!(e.contains("missing") && e.contains("also_missing"));
+}
+
+#[test]
+fn out_of_range_targets() {
+    use super::syntax::*;
+
+    // Branch to a global in an other unit placed too far away
+    let mut main = Assembler::relocatable();
+    main.assemble(&[B(Label::Global("far")), Nop]).unwrap();
+
+    let mut lib = Assembler::relocatable();
+    lib.assemble(&[Space(0x20000), Global("far"), Jr(RA), Nop]).unwrap();
+
+    let e = Linker::new(0x80010000).link(&[main, lib]).unwrap_err();
+    assert!(e.contains("Branch target 0x80030008"));
+
+    // The furthest reachable target is fine
+    let mut main = Assembler::relocatable();
+    main.assemble(&[B(Label::Global("far")), Nop]).unwrap();
+
+    let mut lib = Assembler::relocatable();
+    lib.assemble(&[Space(0x1fff8), Global("far"), Jr(RA), Nop]).unwrap();
+
+    let program = Linker::new(0x80010000).link(&[main, lib]).unwrap();
+    assert!(read_word(&program.text) == 0x10007fff);
+
+    // Jumps can't leave the current 256MB segment
+    let mut main = Assembler::relocatable();
+    main.assemble(&[Jal(Label::Absolute(0xbfc00000)), Nop]).unwrap();
+
+    let e = Linker::new(0x80010000).link(&[main]).unwrap_err();
+    assert!(e.contains("Jump target 0xbfc00000"));
+
+    // Same thing when the address is known at assembly time
+    let mut asm = Assembler::from_base(0x80010000);
+    assert!(asm.assemble(&[J(Label::Absolute(0x00001000))]).is_err());
+}
@@ -0,0 +1,1430 @@
+//! Basic MIPS assembler to generate PlayStation machine code in pure
+//! rust
+
+use std::collections::HashMap;
+
+pub mod parser;
+pub mod exe;
+pub mod elf;
+pub mod link;
+
+pub mod syntax {
+    #[derive(Clone, Copy)]
+    pub struct Register(pub u8);
+
+    #[derive(Clone, Copy)]
+    pub enum Label<'a> {
+        Local(&'a str, char),
+        Global(&'a str),
+        /// Global label plus a byte offset
+        Offset(&'a str, i32),
+        Absolute(u32),
+    }
+
+    /// Sections the code and data can be placed into. Each section
+    /// is laid out contiguously in memory, independently of the
+    /// others.
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    pub enum Section {
+        /// Code
+        Text,
+        /// Initialized data
+        Data,
+        /// Zero-initialized data, not stored in the output
+        Bss,
+    }
+
+    #[derive(Clone, Copy)]
+    pub enum Instruction<'a> {
+        Sll(Register, Register, u8),
+        Srl(Register, Register, u8),
+        Sra(Register, Register, u8),
+        Sllv(Register, Register, Register),
+        Srlv(Register, Register, Register),
+        Srav(Register, Register, Register),
+        Jr(Register),
+        Jalr(Register, Register),
+        Syscall(u32),
+        Break(u32),
+        Mfhi(Register),
+        Mthi(Register),
+        Mflo(Register),
+        Mtlo(Register),
+        Mult(Register, Register),
+        Multu(Register, Register),
+        Div(Register, Register),
+        Divu(Register, Register),
+        Add(Register, Register, Register),
+        Addu(Register, Register, Register),
+        Sub(Register, Register, Register),
+        Subu(Register, Register, Register),
+        And(Register, Register, Register),
+        Or(Register, Register, Register),
+        Xor(Register, Register, Register),
+        Nor(Register, Register, Register),
+        Slt(Register, Register, Register),
+        Sltu(Register, Register, Register),
+        Bgez(Register, Label<'a>),
+        Bltz(Register, Label<'a>),
+        Bgezal(Register, Label<'a>),
+        Bltzal(Register, Label<'a>),
+        J(Label<'a>),
+        Jal(Label<'a>),
+        Beq(Register, Register, Label<'a>),
+        Bne(Register, Register, Label<'a>),
+        Blez(Register, Label<'a>),
+        Bgtz(Register, Label<'a>),
+        Addi(Register, Register, i16),
+        Addiu(Register, Register, i16),
+        Slti(Register, Register, i16),
+        Sltiu(Register, Register, i16),
+        Andi(Register, Register, u16),
+        Ori(Register, Register, u16),
+        Xori(Register, Register, u16),
+        Lui(Register, u16),
+        Lb(Register, Register, i16),
+        Lh(Register, Register, i16),
+        Lwl(Register, Register, i16),
+        Lw(Register, Register, i16),
+        Lbu(Register, Register, i16),
+        Lhu(Register, Register, i16),
+        Lwr(Register, Register, i16),
+        Sb(Register, Register, i16),
+        Sh(Register, Register, i16),
+        Swl(Register, Register, i16),
+        Sw(Register, Register, i16),
+        Swr(Register, Register, i16),
+
+        // Coprocessor opcodes
+        Mfc0(Register, u8),
+        Mtc0(Register, u8),
+        Rfe,
+        Mfc2(Register, u8),
+        Cfc2(Register, u8),
+        Mtc2(Register, u8),
+        Ctc2(Register, u8),
+        /// Load GTE data register from memory
+        Lwc2(u8, Register, i16),
+        /// Store GTE data register to memory
+        Swc2(u8, Register, i16),
+        /// GTE command. The argument is the 25bit command word, see
+        /// the `gte` module for the usual values.
+        Gte(u32),
+
+        /// Global labels: can't be redefined
+        Global(&'a str),
+        /// Local labels: can be redefined
+        Local(&'a str),
+
+        /// Switch to the text section
+        Text,
+        /// Switch to the data section
+        Data,
+        /// Switch to the bss section
+        Bss,
+
+        /// Replace the immediate of the next instruction with the
+        /// high half of the label's address, adjusted for the sign
+        /// extension of the low half (like GNU's `%hi`)
+        Hi(Label<'a>),
+        /// Replace the immediate of the next instruction with the low
+        /// half of the label's address (like GNU's `%lo`)
+        Lo(Label<'a>),
+
+        /// Add padding (if necessary) to reach the desired byte
+        /// alignment expressed as a power of two. E.g. Align(2)
+        /// aligns on 4 bytes.
+        Align(u8),
+        /// Pad with zeroes up to the given offset from the start of
+        /// the current section (like GNU's `.org`)
+        Org(u32),
+        /// Raw data
+        Word(u32),
+        Half(u16),
+        Byte(u8),
+        /// 32bit word containing the address of a label
+        Address(Label<'a>),
+        /// Reserve the given number of zeroed bytes
+        Space(u32),
+
+        // Pseudo-instructions
+        Nop,
+        Move(Register, Register),
+        /// Load immediate, can take two instructions if the immediate
+        /// has low and high halfword both non-zero.
+        Li(Register, u32),
+        /// Load address: always takes two instructions (LUI + ADDIU)
+        La(Register, Label<'a>),
+        B(Label<'a>),
+        Beqz(Register, Label<'a>),
+        Bnez(Register, Label<'a>),
+    }
+
+    impl<'a> Instruction<'a> {
+        // Length of the instruction in bytes. `here` is the address
+        // of the instruction and `offset` its offset in the section.
+        pub fn bytes(&self, here: u32, offset: u32) -> u32 {
+            match *self {
+                Local(_) | Global(_) => 0,
+                Text | Data | Bss | Hi(_) | Lo(_) => 0,
+                Li(_, v) => {
+                    let mut b = 0;
+
+                    if v & 0xffff0000 != 0{
+                        b += 4;
+                    }
+
+                    if v & 0xffff != 0 || v == 0 {
+                        b += 4;
+                    }
+
+                    b
+                }
+                La(..) => 8,
+                Align(o) => {
+                    super::pad_to_order(here, o)
+                }
+                // Going backwards is reported as an error when the
+                // instruction is assembled
+                Org(o) => o.saturating_sub(offset),
+                Half(_) => 2,
+                Byte(_) => 1,
+                Space(n) => n,
+                _ => 4,
+            }
+        }
+    }
+
+    pub use self::Instruction::*;
+
+    /// Common GTE command words, using the same encoding as the
+    /// official SDK macros (including the unused bits 20-24). Use
+    /// them with the `Gte` instruction.
+    pub mod gte {
+        /// Shift fraction: shift MAC results right by 12
+        pub const SF: u32 = 1 << 19;
+        /// Saturate IR results to 0 instead of -0x8000
+        pub const LM: u32 = 1 << 10;
+
+        pub const RTPS: u32 = 0x0180001;
+        pub const NCLIP: u32 = 0x1400006;
+        pub const OP: u32 = 0x170000c;
+        pub const DPCS: u32 = 0x0780010;
+        pub const INTPL: u32 = 0x0980011;
+        /// Rotation matrix * V0 + translation vector, other variants
+        /// can be obtained by changing the mx/v/cv fields
+        pub const MVMVA: u32 = 0x0400012;
+        pub const NCDS: u32 = 0x0e80413;
+        pub const CDP: u32 = 0x1280414;
+        pub const NCDT: u32 = 0x0f80416;
+        pub const NCCS: u32 = 0x108041b;
+        pub const CC: u32 = 0x138041c;
+        pub const NCS: u32 = 0x0c8041e;
+        pub const NCT: u32 = 0x0d80420;
+        pub const SQR: u32 = 0x0a00428;
+        pub const DCPL: u32 = 0x0680029;
+        pub const DPCT: u32 = 0x0f8002a;
+        pub const AVSZ3: u32 = 0x158002d;
+        pub const AVSZ4: u32 = 0x168002e;
+        pub const RTPT: u32 = 0x0280030;
+        pub const GPF: u32 = 0x190003d;
+        pub const GPL: u32 = 0x1a0003e;
+        pub const NCCT: u32 = 0x118043f;
+    }
+
+    pub const R0: Register = Register(0);
+    pub const R1: Register = Register(1);
+    pub const R2: Register = Register(2);
+    pub const R3: Register = Register(3);
+    pub const R4: Register = Register(4);
+    pub const R5: Register = Register(5);
+    pub const R6: Register = Register(6);
+    pub const R7: Register = Register(7);
+    pub const R8: Register = Register(8);
+    pub const R9: Register = Register(9);
+    pub const R10: Register = Register(10);
+    pub const R11: Register = Register(11);
+    pub const R12: Register = Register(12);
+    pub const R13: Register = Register(13);
+    pub const R14: Register = Register(14);
+    pub const R15: Register = Register(15);
+    pub const R16: Register = Register(16);
+    pub const R17: Register = Register(17);
+    pub const R18: Register = Register(18);
+    pub const R19: Register = Register(19);
+    pub const R20: Register = Register(20);
+    pub const R21: Register = Register(21);
+    pub const R22: Register = Register(22);
+    pub const R23: Register = Register(23);
+    pub const R24: Register = Register(24);
+    pub const R25: Register = Register(25);
+    pub const R26: Register = Register(26);
+    pub const R27: Register = Register(27);
+    pub const R28: Register = Register(28);
+    pub const R29: Register = Register(29);
+    pub const R30: Register = Register(30);
+    pub const R31: Register = Register(31);
+
+    pub const AT: Register = R1;
+    pub const V0: Register = R2;
+    pub const V1: Register = R3;
+    pub const A0: Register = R4;
+    pub const A1: Register = R5;
+    pub const A2: Register = R6;
+    pub const A3: Register = R7;
+    pub const T0: Register = R8;
+    pub const T1: Register = R9;
+    pub const T2: Register = R10;
+    pub const T3: Register = R11;
+    pub const T4: Register = R12;
+    pub const T5: Register = R13;
+    pub const T6: Register = R14;
+    pub const T7: Register = R15;
+    pub const S0: Register = R16;
+    pub const S1: Register = R17;
+    pub const S2: Register = R18;
+    pub const S3: Register = R19;
+    pub const S4: Register = R20;
+    pub const S5: Register = R21;
+    pub const S6: Register = R22;
+    pub const S7: Register = R23;
+    pub const T8: Register = R24;
+    pub const T9: Register = R25;
+    pub const K0: Register = R26;
+    pub const K1: Register = R27;
+    pub const GP: Register = R28;
+    pub const SP: Register = R29;
+    pub const FP: Register = R30;
+    pub const RA: Register = R31;
+}
+
+use self::syntax::*;
+
+/// Assembler state. Code and data can be spread over three sections
+/// (text, data and bss). When the address of a referenced label
+/// isn't known yet a relocation is recorded, it's resolved once the
+/// sections are laid out by `machine_code` or the `link` module.
+pub struct Assembler<'a> {
+    /// Currently generated machine code for each section
+    sections: [Vec<u8>; 3],
+    /// Address of the first byte of each section, if known
+    bases: [Option<u32>; 3],
+    /// Biggest alignment required in each section, expressed as a
+    /// power of two
+    alignments: [u8; 3],
+    /// Section currently being assembled
+    current: Section,
+    /// Hash table containing the section and offset of all known
+    /// global labels. Global labels are unique and can't be
+    /// redefined.
+    globals: HashMap<&'a str, (Section, u32)>,
+    /// List of all the local labels with their section and
+    /// offset. Local labels can be redefined.
+    locals: Vec<(Section, u32, &'a str)>,
+    /// References to labels whose address isn't known yet
+    relocations: Vec<Relocation<'a>>,
+    /// Relocation requested by `Hi` or `Lo` for the next instruction
+    pending: Option<(RelocKind, Label<'a>)>,
+    /// If true references to unknown global labels are left for the
+    /// linker to resolve instead of being reported as errors
+    relocatable: bool,
+}
+
+impl<'a> Assembler<'a> {
+    /// Create a new assembler instance which will generate code meant
+    /// to be loaded at the `base` address
+    pub fn from_base(base: u32) -> Assembler<'a> {
+        let mut asm = Assembler::relocatable();
+
+        asm.bases[Section::Text.index()] = Some(base);
+        asm.relocatable = false;
+
+        asm
+    }
+
+    /// Create a new assembler instance for a unit meant to be
+    /// combined with others by `link::Linker`. The address of the
+    /// sections is only known once linked and references to global
+    /// labels defined in other units are allowed.
+    pub fn relocatable() -> Assembler<'a> {
+        Assembler {
+            sections: [Vec::new(), Vec::new(), Vec::new()],
+            bases: [None; 3],
+            // Word alignment by default
+            alignments: [2; 3],
+            current: Section::Text,
+            globals: HashMap::new(),
+            locals: Vec::new(),
+            relocations: Vec::new(),
+            pending: None,
+            relocatable: true,
+        }
+    }
+
+    /// Attempt to assemble the provided instructions. Returns the
+    /// size of the generated machine code in bytes on success, a
+    /// String describing the assembler error on failure.
+    pub fn assemble(&mut self,
+                    instructions: &[Instruction<'a>]) -> Result<u32, String> {
+        self.assemble_located(instructions).map_err(|(_, e)| e)
+    }
+
+    /// Same as `assemble` but on error also returns the index of the
+    /// offending instruction in `instructions`
+    fn assemble_located(&mut self,
+                        instructions: &[Instruction<'a>])
+                        -> Result<u32, (usize, String)> {
+        let start_len = self.total_len();
+
+        // Clear local labels, seems convenient?
+        self.locals.clear();
+
+        // First we map the labels
+        try!(self.parse_labels(instructions));
+
+        for (index, &i) in instructions.iter().enumerate() {
+            try!(self.assemble_instruction(i).map_err(|e| (index, e)));
+        }
+
+        if self.pending.take().is_some() {
+            return Err((instructions.len() - 1,
+                        "Missing instruction after Hi/Lo".into()));
+        }
+
+        Ok(self.total_len() - start_len)
+    }
+
+    /// Consume the Assembler and return the generated machine code
+    /// alongside the base address. The data section is placed right
+    /// after the text, the bss isn't included since it only contains
+    /// zeroes.
+    ///
+    /// Fails if a reference can't be resolved, for instance if some
+    /// global labels are undefined (which can only happen with
+    /// relocatable units: use `link::Linker` for those) or if a
+    /// branch target is out of range.
+    pub fn machine_code(self) -> Result<(Vec<u8>, u32), String> {
+        let base = self.bases[Section::Text.index()].unwrap_or(0);
+
+        let program = try!(link::Linker::new(base).link(&[self]));
+
+        let mut code = program.text;
+
+        if !program.data.is_empty() {
+            code.resize((program.data_base - base) as usize, 0);
+            code.extend_from_slice(&program.data);
+        }
+
+        Ok((code, base))
+    }
+
+    /// Return the address of all the global labels whose address is
+    /// already known, sorted by address
+    pub fn globals(&self) -> Vec<(&'a str, u32)> {
+        let mut globals: Vec<_> =
+            self.globals.iter()
+            .filter_map(|(&name, &(section, offset))| {
+                self.bases[section.index()]
+                    .map(|base| (name, base.wrapping_add(offset)))
+            })
+            .collect();
+
+        globals.sort_by(|a, b| (a.1, a.0).cmp(&(b.1, b.0)));
+
+        globals
+    }
+
+    /// Total size of the generated code and data in bytes
+    fn total_len(&self) -> u32 {
+        self.sections.iter().map(|s| s.len() as u32).sum()
+    }
+
+    /// Offset of the next byte in the current section
+    fn offset(&self) -> u32 {
+        self.sections[self.current.index()].len() as u32
+    }
+
+    /// Absolute address of the next byte in the current section, if
+    /// known
+    fn location(&self) -> Option<u32> {
+        self.bases[self.current.index()]
+            .map(|base| base.wrapping_add(self.offset()))
+    }
+
+    /// Look for global and local labels in `instructions` and collect
+    /// them
+    fn parse_labels(&mut self,
+                    instructions: &[Instruction<'a>])
+                    -> Result<(), (usize, String)> {
+        let mut offsets = [0; 3];
+
+        for (o, s) in offsets.iter_mut().zip(self.sections.iter()) {
+            *o = s.len() as u32;
+        }
+
+        let mut section = self.current;
+
+        for (index, &i) in instructions.iter().enumerate() {
+            let s = section.index();
+
+            match i {
+                Global(name) =>
+                    if let Some(_) = self.globals.insert(name,
+                                                         (section,
+                                                          offsets[s])) {
+                        // Globals can't be redefined
+                        return Err((index,
+                                    format!("Global label '{}' is redefined",
+                                            name)));
+                    },
+                // Locals can be redefined any number of times
+                Local(id) => self.locals.push((section, offsets[s], id)),
+                Text => section = Section::Text,
+                Data => section = Section::Data,
+                Bss => section = Section::Bss,
+                _ => {
+                    let here = self.bases[s].unwrap_or(0)
+                        .wrapping_add(offsets[s]);
+
+                    offsets[s] += i.bytes(here, offsets[s]);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Find what `label` refers to. `here` is the offset of the
+    /// referencing instruction in the current section, used to look
+    /// up local labels. Returns the target and an addend.
+    fn label_target(&self,
+                    label: Label<'a>,
+                    here: u32) -> Result<(Target<'a>, i32), String> {
+        match label {
+            Label::Global(l) => self.global_target(l).map(|t| (t, 0)),
+            Label::Offset(l, o) => self.global_target(l).map(|t| (t, o)),
+            Label::Local(l, d) => {
+                let current = self.current;
+
+                // Search for the closest label with name `l` in the
+                // relative direction gived by `d`
+                let v =
+                    match d {
+                        // Search for the closest label *before* (and
+                        // including) `here`. We start by the end and
+                        // iterate backwards.
+                        'b' => self.locals.iter().rev()
+                            .find(|&&(section, offset, name)| {
+                                section == current &&
+                                    offset <= here &&
+                                    l == name
+                            }),
+                        // Search for the closest label *after* `here`
+                        'f' => self.locals.iter()
+                            .find(|&&(section, offset, name)| {
+                                section == current &&
+                                    offset > here &&
+                                    l == name
+                            }),
+                        _ => return
+                            Err(format!("Unknown local label direction '{}'",
+                                        d)),
+                    };
+
+                match v {
+                    Some(&(section, offset, _)) =>
+                        Ok((Target::Defined(section, offset), 0)),
+                    None => Err(format!("Unknown local label '{}'", l)),
+                }
+            },
+            Label::Absolute(a) => Ok((Target::Absolute(a), 0)),
+        }
+    }
+
+    fn global_target(&self, name: &'a str) -> Result<Target<'a>, String> {
+        match self.globals.get(name) {
+            Some(&(section, offset)) => Ok(Target::Defined(section, offset)),
+            // Hopefully defined in an other unit
+            None if self.relocatable => Ok(Target::Symbol(name)),
+            None => Err(format!("Unknown global label '{}'", name)),
+        }
+    }
+
+    /// Make the word at `offset` in the current section reference
+    /// `label`. If the address isn't known yet a relocation is
+    /// recorded to patch it once the sections are laid out.
+    fn relocate(&mut self,
+                offset: u32,
+                kind: RelocKind,
+                label: Label<'a>) -> Result<(), String> {
+        let (target, addend) = try!(self.label_target(label, offset));
+
+        let section = self.current;
+        let pc = self.bases[section.index()].map(|b| b.wrapping_add(offset));
+
+        let resolved =
+            match target {
+                // Branches within a section don't depend on its
+                // address
+                Target::Defined(s, o) if s == section &&
+                    kind == RelocKind::Branch16 => Some((o, offset)),
+                Target::Defined(s, o) =>
+                    match (self.bases[s.index()], pc) {
+                        (Some(b), Some(pc)) => Some((b.wrapping_add(o), pc)),
+                        (Some(b), None) if !kind.needs_pc() =>
+                            Some((b.wrapping_add(o), 0)),
+                        _ => None,
+                    },
+                Target::Absolute(a) =>
+                    match pc {
+                        Some(pc) => Some((a, pc)),
+                        None if !kind.needs_pc() => Some((a, 0)),
+                        None => None,
+                    },
+                Target::Symbol(_) => None,
+            };
+
+        match resolved {
+            Some((target, pc)) => {
+                let target = target.wrapping_add(addend as u32);
+                let s = &mut self.sections[section.index()];
+                let pos = offset as usize;
+
+                let word = try!(kind.apply(read_word(&s[pos..]), target, pc));
+
+                write_word(&mut s[pos..], word);
+            }
+            None => self.relocations.push(Relocation {
+                section: section,
+                offset: offset,
+                kind: kind,
+                target: target,
+                addend: addend,
+            }),
+        }
+
+        Ok(())
+    }
+
+    /// Emit `code` and make it reference `label`
+    fn emit_reloc(&mut self,
+                  code: MachineCode,
+                  kind: RelocKind,
+                  label: Label<'a>) -> Result<(), String> {
+        let offset = self.offset();
+
+        self.emit_code(code);
+
+        self.relocate(offset, kind, label)
+    }
+
+    fn assemble_instruction(&mut self,
+                            instruction: Instruction<'a>)
+                            -> Result<(), String> {
+        if self.current == Section::Bss {
+            match instruction {
+                Global(_) | Local(_) | Text | Data | Bss |
+                Align(_) | Space(_) => (),
+                _ => return Err("Only labels, Align and Space are allowed \
+                                 in the bss section".into()),
+            }
+        }
+
+        let reloc =
+            match instruction {
+                Hi(l) => Some((RelocKind::Hi16, l)),
+                Lo(l) => Some((RelocKind::Lo16, l)),
+                _ => None,
+            };
+
+        if reloc.is_some() {
+            if self.pending.is_some() {
+                return Err("Hi/Lo can't be chained".into());
+            }
+
+            self.pending = reloc;
+
+            return Ok(());
+        }
+
+        let pending = self.pending.take();
+        let section = self.current;
+        let offset = self.offset();
+
+        let data =
+            match instruction {
+                Global(_) | Local(_) | Text | Data | Bss |
+                Align(_) | Org(_) | Word(_) | Half(_) | Byte(_) |
+                Address(_) | Space(_) => true,
+                _ => false,
+            };
+
+        // We never realign behind the user's back
+        if !data && self.location().unwrap_or(offset) % 4 != 0 {
+            return Err(format!("Misaligned instruction at offset 0x{:x}, \
+                                use Align after byte and halfword data",
+                               offset));
+        }
+
+        try!(self.emit_instruction(instruction));
+
+        if let Some((kind, label)) = pending {
+            if self.current != section || self.offset() < offset + 4 {
+                return Err("Hi/Lo must be followed by an instruction".into());
+            }
+
+            try!(self.relocate(offset, kind, label));
+        }
+
+        Ok(())
+    }
+
+    fn emit_instruction(&mut self,
+                        instruction: Instruction<'a>) -> Result<(), String> {
+        match instruction {
+            Sll(r0, r1, shift) =>
+                self.emit_code(MachineCode::sub(0b000000)
+                               .d(r0)
+                               .t(r1)
+                               .shift(shift)),
+            Srl(r0, r1, shift) =>
+                self.emit_code(MachineCode::sub(0b000010)
+                               .d(r0)
+                               .t(r1)
+                               .shift(shift)),
+            Sra(r0, r1, shift) =>
+                self.emit_code(MachineCode::sub(0b000011)
+                               .d(r0)
+                               .t(r1)
+                               .shift(shift)),
+            Sllv(r0, r1, r2) =>
+                self.emit_code(MachineCode::sub(0b000100)
+                               .d(r0)
+                               .t(r1)
+                               .s(r2)),
+            Srlv(r0, r1, r2) =>
+                self.emit_code(MachineCode::sub(0b000110)
+                               .d(r0)
+                               .t(r1)
+                               .s(r2)),
+            Srav(r0, r1, r2) =>
+                self.emit_code(MachineCode::sub(0b000111)
+                               .d(r0)
+                               .t(r1)
+                               .s(r2)),
+            Jr(r0) =>
+                self.emit_code(MachineCode::sub(0b001000)
+                               .s(r0)),
+
+            Jalr(r0, r1) =>
+                self.emit_code(MachineCode::sub(0b001001)
+                               .d(r0)
+                               .s(r1)),
+            Syscall(v) =>
+                self.emit_code(MachineCode::sub(0b001100)
+                               .sys_brk(v)),
+            Break(v) =>
+                self.emit_code(MachineCode::sub(0b001101)
+                               .sys_brk(v)),
+            Mfhi(r0) =>
+                self.emit_code(MachineCode::sub(0b010000)
+                               .d(r0)),
+            Mthi(r0) =>
+                self.emit_code(MachineCode::sub(0b010001)
+                               .s(r0)),
+            Mflo(r0) =>
+                self.emit_code(MachineCode::sub(0b010010)
+                               .d(r0)),
+            Mtlo(r0) =>
+                self.emit_code(MachineCode::sub(0b010011)
+                               .s(r0)),
+            Mult(r0, r1) =>
+                self.emit_code(MachineCode::sub(0b011000)
+                               .s(r0)
+                               .t(r1)),
+            Multu(r0, r1) =>
+                self.emit_code(MachineCode::sub(0b011001)
+                               .s(r0)
+                               .t(r1)),
+            Div(r0, r1) =>
+                self.emit_code(MachineCode::sub(0b011010)
+                               .s(r0)
+                               .t(r1)),
+            Divu(r0, r1) =>
+                self.emit_code(MachineCode::sub(0b011011)
+                               .s(r0)
+                               .t(r1)),
+            Add(r0, r1, r2) =>
+                self.emit_code(MachineCode::sub(0b100000)
+                               .d(r0)
+                               .s(r1)
+                               .t(r2)),
+            Addu(r0, r1, r2) =>
+                self.emit_code(MachineCode::sub(0b100001)
+                               .d(r0)
+                               .s(r1)
+                               .t(r2)),
+            Sub(r0, r1, r2) =>
+                self.emit_code(MachineCode::sub(0b100010)
+                               .d(r0)
+                               .s(r1)
+                               .t(r2)),
+            Subu(r0, r1, r2) =>
+                self.emit_code(MachineCode::sub(0b100011)
+                               .d(r0)
+                               .s(r1)
+                               .t(r2)),
+            And(r0, r1, r2) =>
+                self.emit_code(MachineCode::sub(0b100100)
+                               .d(r0)
+                               .s(r1)
+                               .t(r2)),
+            Or(r0, r1, r2) =>
+                self.emit_code(MachineCode::sub(0b100101)
+                               .d(r0)
+                               .s(r1)
+                               .t(r2)),
+            Xor(r0, r1, r2) =>
+                self.emit_code(MachineCode::sub(0b100110)
+                               .d(r0)
+                               .s(r1)
+                               .t(r2)),
+            Nor(r0, r1, r2) =>
+                self.emit_code(MachineCode::sub(0b100111)
+                               .d(r0)
+                               .s(r1)
+                               .t(r2)),
+            Slt(r0, r1, r2) =>
+                self.emit_code(MachineCode::sub(0b101010)
+                               .d(r0)
+                               .s(r1)
+                               .t(r2)),
+            Sltu(r0, r1, r2) =>
+                self.emit_code(MachineCode::sub(0b101011)
+                               .d(r0)
+                               .s(r1)
+                               .t(r2)),
+            Bgez(r0, l) => {
+                let code = MachineCode::op(0b000001)
+                    .is_link(false)
+                    .is_bgez(true)
+                    .s(r0);
+
+                try!(self.emit_reloc(code, RelocKind::Branch16, l));
+            }
+            Bltz(r0, l) => {
+                let code = MachineCode::op(0b000001)
+                    .is_link(false)
+                    .is_bgez(false)
+                    .s(r0);
+
+                try!(self.emit_reloc(code, RelocKind::Branch16, l));
+            }
+            Bgezal(r0, l) => {
+                let code = MachineCode::op(0b000001)
+                    .is_link(true)
+                    .is_bgez(true)
+                    .s(r0);
+
+                try!(self.emit_reloc(code, RelocKind::Branch16, l));
+            }
+            Bltzal(r0, l) => {
+                let code = MachineCode::op(0b000001)
+                    .is_link(true)
+                    .is_bgez(false)
+                    .s(r0);
+
+                try!(self.emit_reloc(code, RelocKind::Branch16, l));
+            }
+            J(l) => {
+                let code = MachineCode::op(0b000010);
+
+                try!(self.emit_reloc(code, RelocKind::Jump26, l));
+            }
+            Jal(l) => {
+                let code = MachineCode::op(0b000011);
+
+                try!(self.emit_reloc(code, RelocKind::Jump26, l));
+            }
+            Beq(r0, r1, l) => {
+                let code = MachineCode::op(0b000100)
+                    .s(r0)
+                    .t(r1);
+
+                try!(self.emit_reloc(code, RelocKind::Branch16, l));
+            }
+            Bne(r0, r1, l) => {
+                let code = MachineCode::op(0b000101)
+                    .s(r0)
+                    .t(r1);
+
+                try!(self.emit_reloc(code, RelocKind::Branch16, l));
+            }
+            Blez(r0, l) => {
+                let code = MachineCode::op(0b000110)
+                    .s(r0);
+
+                try!(self.emit_reloc(code, RelocKind::Branch16, l));
+            }
+            Bgtz(r0, l) => {
+                let code = MachineCode::op(0b000111)
+                    .s(r0);
+
+                try!(self.emit_reloc(code, RelocKind::Branch16, l));
+            }
+            Addi(r0, r1, i) => {
+                self.emit_code(MachineCode::op(0b001000)
+                               .t(r0)
+                               .s(r1)
+                               .imm_se(i));
+            }
+            Addiu(r0, r1, i) => {
+                self.emit_code(MachineCode::op(0b001001)
+                               .t(r0)
+                               .s(r1)
+                               .imm_se(i));
+            }
+            Slti(r0, r1, i) => {
+                self.emit_code(MachineCode::op(0b001010)
+                               .t(r0)
+                               .s(r1)
+                               .imm_se(i));
+            }
+            Sltiu(r0, r1, i) => {
+                self.emit_code(MachineCode::op(0b001011)
+                               .t(r0)
+                               .s(r1)
+                               .imm_se(i));
+            }
+            Andi(r0, r1, u) => {
+                self.emit_code(MachineCode::op(0b001100)
+                               .t(r0)
+                               .s(r1)
+                               .imm(u));
+            }
+            Ori(r0, r1, u) => {
+                self.emit_code(MachineCode::op(0b001101)
+                               .t(r0)
+                               .s(r1)
+                               .imm(u));
+            }
+            Xori(r0, r1, u) => {
+                self.emit_code(MachineCode::op(0b001110)
+                               .t(r0)
+                               .s(r1)
+                               .imm(u));
+            }
+            Lui(r0, u) => {
+                self.emit_code(MachineCode::op(0b001111)
+                               .t(r0)
+                               .imm(u));
+            }
+            Lb(r0, r1, i) => {
+                self.emit_code(MachineCode::op(0b100000)
+                               .t(r0)
+                               .s(r1)
+                               .imm_se(i));
+            }
+            Lh(r0, r1, i) => {
+                self.emit_code(MachineCode::op(0b100001)
+                               .t(r0)
+                               .s(r1)
+                               .imm_se(i));
+            }
+            Lwl(r0, r1, i) => {
+                self.emit_code(MachineCode::op(0b100010)
+                               .t(r0)
+                               .s(r1)
+                               .imm_se(i));
+            }
+            Lw(r0, r1, i) => {
+                self.emit_code(MachineCode::op(0b100011)
+                               .t(r0)
+                               .s(r1)
+                               .imm_se(i));
+            }
+            Lbu(r0, r1, i) => {
+                self.emit_code(MachineCode::op(0b100100)
+                               .t(r0)
+                               .s(r1)
+                               .imm_se(i));
+            }
+            Lhu(r0, r1, i) => {
+                self.emit_code(MachineCode::op(0b100101)
+                               .t(r0)
+                               .s(r1)
+                               .imm_se(i));
+            }
+            Lwr(r0, r1, i) => {
+                self.emit_code(MachineCode::op(0b100110)
+                               .t(r0)
+                               .s(r1)
+                               .imm_se(i));
+            }
+            Sb(r0, r1, i) => {
+                self.emit_code(MachineCode::op(0b101000)
+                               .t(r0)
+                               .s(r1)
+                               .imm_se(i));
+            }
+            Sh(r0, r1, i) => {
+                self.emit_code(MachineCode::op(0b101001)
+                               .t(r0)
+                               .s(r1)
+                               .imm_se(i));
+            }
+            Swl(r0, r1, i) => {
+                self.emit_code(MachineCode::op(0b101010)
+                               .t(r0)
+                               .s(r1)
+                               .imm_se(i));
+            }
+            Sw(r0, r1, i) => {
+                self.emit_code(MachineCode::op(0b101011)
+                               .t(r0)
+                               .s(r1)
+                               .imm_se(i));
+            }
+            Swr(r0, r1, i) => {
+                self.emit_code(MachineCode::op(0b101110)
+                               .t(r0)
+                               .s(r1)
+                               .imm_se(i));
+            }
+            Mfc0(r0, cop_r) => {
+                self.emit_code(MachineCode::op(0b010000)
+                               .cop_opcode(0b00000)
+                               .t(r0)
+                               .cop_r(cop_r))
+            }
+            Mtc0(r0, cop_r) => {
+                self.emit_code(MachineCode::op(0b010000)
+                               .cop_opcode(0b00100)
+                               .t(r0)
+                               .cop_r(cop_r))
+            }
+            Rfe => {
+                self.emit_code(MachineCode::op(0b010000)
+                               .cop_opcode(0b10000)
+                               .imm(0b010000))
+            }
+            Mfc2(r0, cop_r) => {
+                self.emit_code(MachineCode::op(0b010010)
+                               .cop_opcode(0b00000)
+                               .t(r0)
+                               .cop_r(cop_r))
+            }
+            Cfc2(r0, cop_r) => {
+                self.emit_code(MachineCode::op(0b010010)
+                               .cop_opcode(0b00010)
+                               .t(r0)
+                               .cop_r(cop_r))
+            }
+            Mtc2(r0, cop_r) => {
+                self.emit_code(MachineCode::op(0b010010)
+                               .cop_opcode(0b00100)
+                               .t(r0)
+                               .cop_r(cop_r))
+            }
+            Ctc2(r0, cop_r) => {
+                self.emit_code(MachineCode::op(0b010010)
+                               .cop_opcode(0b00110)
+                               .t(r0)
+                               .cop_r(cop_r))
+            }
+            Lwc2(cop_r, r0, i) => {
+                self.emit_code(MachineCode::op(0b110010)
+                               .t(Register(cop_r))
+                               .s(r0)
+                               .imm_se(i));
+            }
+            Swc2(cop_r, r0, i) => {
+                self.emit_code(MachineCode::op(0b111010)
+                               .t(Register(cop_r))
+                               .s(r0)
+                               .imm_se(i));
+            }
+            Gte(command) => {
+                self.emit_code(MachineCode::op(0b010010)
+                               .cop_command(command))
+            }
+
+            Text => self.current = Section::Text,
+            Data => self.current = Section::Data,
+            Bss => self.current = Section::Bss,
+
+            /// Alignment padding
+            Align(o) => {
+                let s = self.current.index();
+
+                if o > self.alignments[s] {
+                    self.alignments[s] = o;
+                }
+
+                // If we don't know our address yet the linker will
+                // align the section
+                let here = self.location().unwrap_or(self.offset());
+
+                try!(self.pad(pad_to_order(here, o)));
+            }
+            Org(o) => {
+                let here = self.offset();
+
+                if o < here {
+                    return Err(format!("Can't move location backwards \
+                                        from offset 0x{:x} to 0x{:x}",
+                                       here, o));
+                }
+
+                try!(self.pad(o - here));
+            }
+            Word(w) => self.emit_word(w),
+            Half(h) => {
+                self.emit_byte(h as u8);
+                self.emit_byte((h >> 8) as u8);
+            }
+            Byte(b) => self.emit_byte(b),
+            Address(l) => {
+                let offset = self.offset();
+
+                self.emit_word(0);
+
+                try!(self.relocate(offset, RelocKind::Word32, l));
+            }
+            Space(n) => try!(self.pad(n)),
+
+            // Pseudo instructions
+            Nop =>
+                try!(self.assemble_instruction(Sll(R0, R0, 0))),
+            Move(r0, r1) =>
+                try!(self.assemble_instruction(Addu(r0, r1, R0))),
+            Li(r0, v) => {
+                let hi = (v >> 16) as u16;
+                let lo = v as u16;
+
+                if hi != 0 {
+                    try!(self.assemble_instruction(Lui(r0, hi)));
+
+                    if lo != 0 {
+                        try!(self.assemble_instruction(Ori(r0, r0, lo)));
+                    }
+                } else {
+                    try!(self.assemble_instruction(Ori(r0, R0, lo)));
+                }
+            }
+            La(r0, l) => {
+                try!(self.assemble_instruction(Hi(l)));
+                try!(self.assemble_instruction(Lui(r0, 0)));
+                try!(self.assemble_instruction(Lo(l)));
+                try!(self.assemble_instruction(Addiu(r0, r0, 0)));
+            }
+            B(l) =>
+                try!(self.assemble_instruction(Beq(R0, R0, l))),
+            Beqz(r0, l) =>
+                try!(self.assemble_instruction(Beq(r0, R0, l))),
+            Bnez(r0, l) =>
+                try!(self.assemble_instruction(Bne(r0, R0, l))),
+
+            // Labels should already have been handled and relocations
+            // are dealt with by `assemble_instruction`
+            Local(..) | Global(..) | Hi(..) | Lo(..) => (),
+        }
+
+        Ok(())
+    }
+
+    /// Emit `n` zeroes
+    fn pad(&mut self, n: u32) -> Result<(), String> {
+        if self.offset().saturating_add(n) > MAX_SECTION_SIZE {
+            return Err(format!("Section would be bigger than 0x{:x} bytes",
+                               MAX_SECTION_SIZE));
+        }
+
+        for _ in 0..n {
+            self.emit_byte(0);
+        }
+
+        Ok(())
+    }
+
+    fn emit_byte(&mut self, b: u8) {
+        self.sections[self.current.index()].push(b);
+    }
+
+    fn emit_code(&mut self, code: MachineCode) {
+        self.emit_word(code.0);
+    }
+
+    fn emit_word(&mut self, word: u32) {
+        self.emit_byte(word as u8);
+        self.emit_byte((word >> 8) as u8);
+        self.emit_byte((word >> 16) as u8);
+        self.emit_byte((word >> 24) as u8);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct MachineCode(u32);
+
+impl MachineCode {
+    fn op(op: u8) -> MachineCode {
+        MachineCode((op as u32) << 26)
+    }
+
+    fn sub(op: u8) -> MachineCode {
+        MachineCode(op as u32)
+    }
+
+    fn cop_opcode(self, op: u32) -> MachineCode {
+        MachineCode(self.0 | (op << 21))
+    }
+
+    /// Coprocessor command: sets bit 25 and the 25bit command word
+    fn cop_command(self, command: u32) -> MachineCode {
+        MachineCode(self.0 | (1 << 25) | (command & 0x1ffffff))
+    }
+
+    fn s(self, r: Register) -> MachineCode {
+        MachineCode(self.0 | ((r.0 as u32) << 21))
+    }
+
+    fn t(self, r: Register) -> MachineCode {
+        MachineCode(self.0 | ((r.0 as u32) << 16))
+    }
+
+    fn d(self, r: Register) -> MachineCode {
+        MachineCode(self.0 | ((r.0 as u32) << 11))
+    }
+
+    fn cop_r(self, cop_r: u8) -> MachineCode {
+        MachineCode(self.0 | ((cop_r as u32) << 11))
+    }
+
+    fn shift(self, s: u8) -> MachineCode {
+        MachineCode(self.0 | ((s as u32) << 6))
+    }
+
+    fn sys_brk(self, v: u32) -> MachineCode {
+        MachineCode(self.0 | ((v & 0xfffff) << 6))
+    }
+
+    fn is_link(self, is_link: bool) -> MachineCode {
+        MachineCode(self.0 | ((is_link as u32) << 20))
+    }
+
+    fn is_bgez(self, is_bgez: bool) -> MachineCode {
+        MachineCode(self.0| ((is_bgez as u32) << 16))
+    }
+
+    fn imm(self, v: u16) -> MachineCode {
+        MachineCode(self.0 | (v as u32))
+    }
+
+    fn imm_se(self, v: i16) -> MachineCode {
+        MachineCode(self.0 | (v as u16 as u32))
+    }
+}
+
+impl Section {
+    fn index(self) -> usize {
+        match self {
+            Section::Text => 0,
+            Section::Data => 1,
+            Section::Bss => 2,
+        }
+    }
+}
+
+/// The various ways a word can reference an address
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RelocKind {
+    /// High half of a `%hi`/`%lo` pair in an immediate field
+    Hi16,
+    /// Low half of a `%hi`/`%lo` pair in an immediate field
+    Lo16,
+    /// J/JAL target
+    Jump26,
+    /// Branch offset
+    Branch16,
+    /// Full 32bit address
+    Word32,
+}
+
+impl RelocKind {
+    /// Returns true if the encoding depends on the address of the
+    /// patched word
+    fn needs_pc(self) -> bool {
+        match self {
+            RelocKind::Jump26 | RelocKind::Branch16 => true,
+            _ => false,
+        }
+    }
+
+    /// Patch `word` located at `pc` to reference `target`. Fails if
+    /// `target` can't be reached from `pc`.
+    fn apply(self, word: u32, target: u32, pc: u32) -> Result<u32, String> {
+        let word =
+            match self {
+                RelocKind::Hi16 =>
+                    // The low half will be sign extended, compensate
+                    // for it if it's negative
+                    (word & 0xffff0000) | (target.wrapping_add(0x8000) >> 16),
+                RelocKind::Lo16 =>
+                    (word & 0xffff0000) | (target & 0xffff),
+                RelocKind::Jump26 => {
+                    // The 4 MSBs come from the address of the delay
+                    // slot, the target must be in the same 256MB
+                    // segment
+                    let segment = pc.wrapping_add(4) & 0xf0000000;
+
+                    if target & 0xf0000000 != segment {
+                        return Err(format!("Jump target 0x{:08x} is out of \
+                                            range of 0x{:08x}",
+                                           target, pc));
+                    }
+
+                    // 2 LSBs are truncated since PC addresses are
+                    // always word aligned
+                    (word & 0xfc000000) | ((target >> 2) & 0x3ffffff)
+                }
+                RelocKind::Branch16 => {
+                    // The offset is relative to the *next* instruction
+                    // and must fit a signed 16bit word count
+                    let delta = target.wrapping_sub(pc.wrapping_add(4)) as i32;
+
+                    if delta < -0x20000 || delta > 0x1fffc {
+                        return Err(format!("Branch target 0x{:08x} is out \
+                                            of range of 0x{:08x}",
+                                           target, pc));
+                    }
+
+                    (word & 0xffff0000) | (((delta as u32) >> 2) & 0xffff)
+                }
+                RelocKind::Word32 => target,
+            };
+
+        Ok(word)
+    }
+}
+
+/// What a relocation points to
+#[derive(Clone, Copy)]
+enum Target<'a> {
+    Absolute(u32),
+    /// Section and offset of a label defined in the same unit
+    Defined(Section, u32),
+    /// Global label defined in an other unit
+    Symbol(&'a str),
+}
+
+/// Reference to an address that couldn't be resolved when the code
+/// was assembled
+#[derive(Clone, Copy)]
+struct Relocation<'a> {
+    /// Section containing the word to patch
+    section: Section,
+    /// Offset of the word to patch in `section`
+    offset: u32,
+    kind: RelocKind,
+    target: Target<'a>,
+    addend: i32,
+}
+
+fn read_word(b: &[u8]) -> u32 {
+    b[0] as u32
+        | ((b[1] as u32) << 8)
+        | ((b[2] as u32) << 16)
+        | ((b[3] as u32) << 24)
+}
+
+fn write_word(b: &mut [u8], w: u32) {
+    b[0] = w as u8;
+    b[1] = (w >> 8) as u8;
+    b[2] = (w >> 16) as u8;
+    b[3] = (w >> 24) as u8;
+}
+
+/// Biggest section we're willing to generate, way more than what
+/// fits in the console's RAM. Guards against typos in `Org`, `Space`
+/// or `Align` generating gigabytes of padding.
+const MAX_SECTION_SIZE: u32 = 16 * 1024 * 1024;
+
+/// Return the number of bytes necessary to add after `loc` in order
+/// to reach an address aligned on `1 << order`
+fn pad_to_order(loc: u32, order: u8) -> u32 {
+    let mask = (1u32 << order) - 1;
+
+    ((!loc).wrapping_add(1)) & mask
+}
+
+#[test]
+fn opcodes() {
+    fn test_instruction(instruction: Instruction, expected: &[u8]) {
+        let mut asm = Assembler::from_base(0);
+
+        asm.assemble(&[instruction]).unwrap();
+
+        let (mc, _) = asm.machine_code().unwrap();
+
+        println!("{:?}", mc);
+
+        assert!(mc == expected);
+    }
+
+    let tests = [
+        (Nop,                         [0, 0, 0, 0]),
+        (Sll(R0, R0, 0),              [0, 0, 0, 0]),
+        (Lui(T5, 0xdead),             [0xad, 0xde, 0x0d, 0x3c]),
+        (Ori(T5, T5, 0xbeef),         [0xef, 0xbe, 0xad, 0x35]),
+        (Break(0x1234),               [0x0d, 0x8d, 0x04, 0x00]),
+        (Jal(Label::Absolute(0xabc)), [0xaf, 0x02, 0x00, 0x0c]),
+        (Rfe,                         [0x10, 0x00, 0x00, 0x42]),
+        (Lwl(T0, A0, 1),              [0x01, 0x00, 0x88, 0x88]),
+        (Lwr(T0, A0, 4),              [0x04, 0x00, 0x88, 0x98]),
+        (Swl(T0, A0, -1),             [0xff, 0xff, 0x88, 0xa8]),
+        (Swr(T0, A0, 0),              [0x00, 0x00, 0x88, 0xb8]),
+        (Lwc2(2, A0, 8),              [0x08, 0x00, 0x82, 0xc8]),
+        (Swc2(7, SP, -4),             [0xfc, 0xff, 0xa7, 0xeb]),
+        (Mfc2(T0, 9),                 [0x00, 0x48, 0x08, 0x48]),
+        (Cfc2(T0, 31),                [0x00, 0xf8, 0x48, 0x48]),
+        (Mtc2(A0, 0),                 [0x00, 0x00, 0x84, 0x48]),
+        (Ctc2(A1, 13),                [0x00, 0x68, 0xc5, 0x48]),
+        (Gte(gte::RTPT),              [0x30, 0x00, 0x28, 0x4a]),
+        (Gte(gte::NCLIP),             [0x06, 0x00, 0x40, 0x4b]),
+        (Gte(gte::SQR | gte::SF),     [0x28, 0x04, 0xa8, 0x4a]),
+    ];
+
+    for &(instruction, ref expected) in &tests {
+        test_instruction(instruction, expected);
+    }
+}
+
+#[test]
+fn org_and_alignment() {
+    // `Org` is relative to the start of the section
+    let mut asm = Assembler::from_base(0x80010000);
+
+    asm.assemble(&[Byte(1), Org(8), Global("end"), Word(2)]).unwrap();
+
+    assert!(asm.globals() == [("end", 0x80010008)]);
+
+    let (mc, _) = asm.machine_code().unwrap();
+
+    assert!(mc == [1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0]);
+
+    let mut asm = Assembler::from_base(0x80010000);
+
+    assert!(asm.assemble(&[Word(0), Org(2)]).is_err());
+    assert!(asm.assemble(&[Org(0xffffffff)]).is_err());
+    assert!(asm.assemble(&[Space(0xffffffff)]).is_err());
+    assert!(asm.assemble(&[Align(31)]).is_err());
+
+    // Data never realigns the following instructions
+    let mut asm = Assembler::from_base(0x80010000);
+
+    assert!(asm.assemble(&[Byte(1), Nop]).is_err());
+
+    let mut asm = Assembler::from_base(0x80010000);
+
+    asm.assemble(&[Byte(1), Align(2), Nop]).unwrap();
+}
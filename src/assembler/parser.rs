@@ -0,0 +1,981 @@
+//! Text front end for the assembler, accepting GNU-style MIPS
+//! assembly:
+//!
+//! ```text
+//! # Comments start with a hash
+//! main:
+//!     la      $t0, message
+//! 1:  lbu     $a0, 0($t0)
+//!     beqz    $a0, 1f
+//!     addiu   $t0, $t0, 1
+//!     b       1b
+//!     nop
+//! 1:  jr      $ra
+//!     nop
+//! message:
+//!     .ascii  "hello\n\0"
+//! ```
+//!
+//! Numeric labels are local and can be redefined, they're referenced
+//! as `Nb` (closest definition backwards) or `Nf` (closest definition
+//! forward). Like the rest of the assembler there's no instruction
+//! reordering: delay slots must be filled explicitly. Unlike GNU as
+//! data directives and instructions are never implicitly aligned, an
+//! instruction following misaligned data is an error. `.org` is
+//! relative to the start of the section like in GNU as.
+//!
+//! Code and data can be placed in the `.text`, `.data` and `.bss`
+//! sections. Immediates accept `%hi(label)` and `%lo(label)`, global
+//! labels can be offset by a constant (`table+4`).
+
+use std::fmt;
+
+use super::Assembler;
+use super::syntax::*;
+
+/// Error while assembling source code
+#[derive(Debug)]
+pub struct Error {
+    /// Line where the error occured, starting at 1. 0 if the error
+    /// is not tied to a particular line (for instance when an
+    /// address can't be resolved once the code is laid out).
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            0 => write!(f, "{}", self.message),
+            l => write!(f, "line {}: {}", l, self.message),
+        }
+    }
+}
+
+/// Assemble `source` into machine code meant to be loaded at `base`
+pub fn assemble(base: u32, source: &str) -> Result<Vec<u8>, Error> {
+    let (instructions, lines) = try!(parse(source));
+
+    let mut asm = Assembler::from_base(base);
+
+    if let Err((index, message)) = asm.assemble_located(&instructions) {
+        return Err(Error {
+            line: lines[index],
+            message: message,
+        });
+    }
+
+    match asm.machine_code() {
+        Ok((code, _)) => Ok(code),
+        Err(message) => Err(Error {
+            line: 0,
+            message: message,
+        }),
+    }
+}
+
+/// Assemble `source` into a relocatable unit to be combined with
+/// others using `link::Linker`. References to undefined global labels
+/// are only reported when linking.
+pub fn assemble_unit(source: &str) -> Result<Assembler, Error> {
+    let (instructions, lines) = try!(parse(source));
+
+    let mut asm = Assembler::relocatable();
+
+    if let Err((index, message)) = asm.assemble_located(&instructions) {
+        return Err(Error {
+            line: lines[index],
+            message: message,
+        });
+    }
+
+    Ok(asm)
+}
+
+/// Parse `source` into a list of instructions suitable for
+/// `Assembler::assemble`. Also returns the source line of each
+/// instruction.
+pub fn parse(source: &str) -> Result<(Vec<Instruction>, Vec<usize>), Error> {
+    let mut instructions = Vec::new();
+    let mut lines = Vec::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let line_no = i + 1;
+
+        let start = instructions.len();
+
+        if let Err(message) = parse_line(line, &mut instructions) {
+            return Err(Error {
+                line: line_no,
+                message: message,
+            });
+        }
+
+        for _ in start..instructions.len() {
+            lines.push(line_no);
+        }
+    }
+
+    Ok((instructions, lines))
+}
+
+fn parse_line<'a>(line: &'a str,
+                  out: &mut Vec<Instruction<'a>>) -> Result<(), String> {
+    let mut line = strip_comment(line).trim();
+
+    // Labels
+    while let Some(colon) = line.find(':') {
+        let name = line[..colon].trim();
+
+        if !is_identifier(name) {
+            // Probably a colon in a string or an otherwise invalid
+            // statement, let the rest of the parser complain about it
+            break;
+        }
+
+        if is_number(name) {
+            out.push(Local(name));
+        } else {
+            out.push(Global(name));
+        }
+
+        line = line[colon + 1..].trim();
+    }
+
+    if line.is_empty() {
+        return Ok(());
+    }
+
+    let (mnemonic, operands) =
+        match line.find(|c: char| c.is_whitespace()) {
+            Some(p) => (&line[..p], line[p..].trim()),
+            None => (line, ""),
+        };
+
+    let mnemonic = mnemonic.to_lowercase();
+
+    // Strings may contain commas, handle them separately
+    if mnemonic == ".ascii" || mnemonic == ".asciiz" {
+        let bytes = try!(parse_strings(operands));
+
+        for b in bytes {
+            out.push(Byte(b));
+        }
+
+        if mnemonic == ".asciiz" {
+            out.push(Byte(0));
+        }
+
+        return Ok(());
+    }
+
+    let ops: Vec<&str> =
+        if operands.is_empty() {
+            Vec::new()
+        } else {
+            operands.split(',').map(|o| o.trim()).collect()
+        };
+
+    let ops = &ops[..];
+
+    // `Hi` or `Lo` relocation for `%hi`/`%lo` operands, it must be
+    // output before the instruction
+    let mut reloc = None;
+
+    let instruction =
+        match &*mnemonic {
+            // Data directives can take any number of values
+            ".word" => {
+                for &o in ops {
+                    let w =
+                        match number_in(o, -0x80000000, 0xffffffff) {
+                            Ok(w) => Word(w as u32),
+                            Err(_) => Address(try!(label(o))),
+                        };
+
+                    out.push(w);
+                }
+                return Ok(());
+            }
+            ".half" => {
+                for &o in ops {
+                    out.push(Half(try!(number_in(o, -0x8000, 0xffff))
+                                  as u16));
+                }
+                return Ok(());
+            }
+            ".byte" => {
+                for &o in ops {
+                    out.push(Byte(try!(number_in(o, -0x80, 0xff)) as u8));
+                }
+                return Ok(());
+            }
+            // We never reorder instructions or use $at behind the
+            // user's back and all labels are visible everywhere so
+            // these can safely be ignored.
+            ".set" | ".globl" | ".global" => return Ok(()),
+            ".align" => {
+                try!(count(ops, 1));
+                Align(try!(number_in(ops[0], 0, 31)) as u8)
+            }
+            ".org" => {
+                try!(count(ops, 1));
+                Org(try!(number_in(ops[0], 0, 0xffffffff)) as u32)
+            }
+            ".space" => {
+                try!(count(ops, 1));
+                Space(try!(number_in(ops[0], 0, 0xffffffff)) as u32)
+            }
+            ".text" => Text,
+            ".data" => Data,
+            ".bss" => Bss,
+
+            "sll" | "srl" | "sra" => {
+                try!(count(ops, 3));
+                let rd = try!(register(ops[0]));
+                let rt = try!(register(ops[1]));
+                let shift = try!(number_in(ops[2], 0, 31)) as u8;
+
+                match &*mnemonic {
+                    "sll" => Sll(rd, rt, shift),
+                    "srl" => Srl(rd, rt, shift),
+                    _ => Sra(rd, rt, shift),
+                }
+            }
+            "sllv" | "srlv" | "srav" |
+            "add" | "addu" | "sub" | "subu" |
+            "and" | "or" | "xor" | "nor" | "slt" | "sltu" => {
+                try!(count(ops, 3));
+                let r0 = try!(register(ops[0]));
+                let r1 = try!(register(ops[1]));
+                let r2 = try!(register(ops[2]));
+
+                match &*mnemonic {
+                    "sllv" => Sllv(r0, r1, r2),
+                    "srlv" => Srlv(r0, r1, r2),
+                    "srav" => Srav(r0, r1, r2),
+                    "add" => Add(r0, r1, r2),
+                    "addu" => Addu(r0, r1, r2),
+                    "sub" => Sub(r0, r1, r2),
+                    "subu" => Subu(r0, r1, r2),
+                    "and" => And(r0, r1, r2),
+                    "or" => Or(r0, r1, r2),
+                    "xor" => Xor(r0, r1, r2),
+                    "nor" => Nor(r0, r1, r2),
+                    "slt" => Slt(r0, r1, r2),
+                    _ => Sltu(r0, r1, r2),
+                }
+            }
+            "jr" => {
+                try!(count(ops, 1));
+                Jr(try!(register(ops[0])))
+            }
+            "jalr" =>
+                match ops.len() {
+                    // Link in $ra by default
+                    1 => Jalr(RA, try!(register(ops[0]))),
+                    _ => {
+                        try!(count(ops, 2));
+                        Jalr(try!(register(ops[0])), try!(register(ops[1])))
+                    }
+                },
+            "syscall" | "break" => {
+                let code =
+                    match ops.len() {
+                        0 => 0,
+                        _ => {
+                            try!(count(ops, 1));
+                            try!(number_in(ops[0], 0, 0xfffff)) as u32
+                        }
+                    };
+
+                match &*mnemonic {
+                    "syscall" => Syscall(code),
+                    _ => Break(code),
+                }
+            }
+            "mfhi" | "mthi" | "mflo" | "mtlo" => {
+                try!(count(ops, 1));
+                let r = try!(register(ops[0]));
+
+                match &*mnemonic {
+                    "mfhi" => Mfhi(r),
+                    "mthi" => Mthi(r),
+                    "mflo" => Mflo(r),
+                    _ => Mtlo(r),
+                }
+            }
+            "mult" | "multu" | "div" | "divu" => {
+                try!(count(ops, 2));
+                let rs = try!(register(ops[0]));
+                let rt = try!(register(ops[1]));
+
+                match &*mnemonic {
+                    "mult" => Mult(rs, rt),
+                    "multu" => Multu(rs, rt),
+                    "div" => Div(rs, rt),
+                    _ => Divu(rs, rt),
+                }
+            }
+            "bgez" | "bltz" | "bgezal" | "bltzal" | "blez" | "bgtz" |
+            "beqz" | "bnez" => {
+                try!(count(ops, 2));
+                let rs = try!(register(ops[0]));
+                let l = try!(label(ops[1]));
+
+                match &*mnemonic {
+                    "bgez" => Bgez(rs, l),
+                    "bltz" => Bltz(rs, l),
+                    "bgezal" => Bgezal(rs, l),
+                    "bltzal" => Bltzal(rs, l),
+                    "blez" => Blez(rs, l),
+                    "bgtz" => Bgtz(rs, l),
+                    "beqz" => Beqz(rs, l),
+                    _ => Bnez(rs, l),
+                }
+            }
+            "j" | "jal" | "b" => {
+                try!(count(ops, 1));
+                let l = try!(label(ops[0]));
+
+                match &*mnemonic {
+                    "j" => J(l),
+                    "jal" => Jal(l),
+                    _ => B(l),
+                }
+            }
+            "beq" | "bne" => {
+                try!(count(ops, 3));
+                let rs = try!(register(ops[0]));
+                let rt = try!(register(ops[1]));
+                let l = try!(label(ops[2]));
+
+                match &*mnemonic {
+                    "beq" => Beq(rs, rt, l),
+                    _ => Bne(rs, rt, l),
+                }
+            }
+            "addi" | "addiu" | "slti" | "sltiu" => {
+                try!(count(ops, 3));
+                let rt = try!(register(ops[0]));
+                let rs = try!(register(ops[1]));
+                let i = try!(simm(ops[2], &mut reloc));
+
+                match &*mnemonic {
+                    "addi" => Addi(rt, rs, i),
+                    "addiu" => Addiu(rt, rs, i),
+                    "slti" => Slti(rt, rs, i),
+                    _ => Sltiu(rt, rs, i),
+                }
+            }
+            "andi" | "ori" | "xori" => {
+                try!(count(ops, 3));
+                let rt = try!(register(ops[0]));
+                let rs = try!(register(ops[1]));
+                let u = try!(uimm(ops[2], &mut reloc));
+
+                match &*mnemonic {
+                    "andi" => Andi(rt, rs, u),
+                    "ori" => Ori(rt, rs, u),
+                    _ => Xori(rt, rs, u),
+                }
+            }
+            "lui" => {
+                try!(count(ops, 2));
+                Lui(try!(register(ops[0])),
+                    try!(uimm(ops[1], &mut reloc)))
+            }
+            "lb" | "lh" | "lwl" | "lw" | "lbu" | "lhu" | "lwr" |
+            "sb" | "sh" | "swl" | "sw" | "swr" => {
+                try!(count(ops, 2));
+                let rt = try!(register(ops[0]));
+                let (offset, base) = try!(memory(ops[1], &mut reloc));
+
+                match &*mnemonic {
+                    "lb" => Lb(rt, base, offset),
+                    "lh" => Lh(rt, base, offset),
+                    "lwl" => Lwl(rt, base, offset),
+                    "lw" => Lw(rt, base, offset),
+                    "lbu" => Lbu(rt, base, offset),
+                    "lhu" => Lhu(rt, base, offset),
+                    "lwr" => Lwr(rt, base, offset),
+                    "sb" => Sb(rt, base, offset),
+                    "sh" => Sh(rt, base, offset),
+                    "swl" => Swl(rt, base, offset),
+                    "sw" => Sw(rt, base, offset),
+                    _ => Swr(rt, base, offset),
+                }
+            }
+            "mfc0" | "mtc0" | "mfc2" | "cfc2" | "mtc2" | "ctc2" => {
+                try!(count(ops, 2));
+                let rt = try!(register(ops[0]));
+                let cop_r = try!(register(ops[1])).0;
+
+                match &*mnemonic {
+                    "mfc0" => Mfc0(rt, cop_r),
+                    "mtc0" => Mtc0(rt, cop_r),
+                    "mfc2" => Mfc2(rt, cop_r),
+                    "cfc2" => Cfc2(rt, cop_r),
+                    "mtc2" => Mtc2(rt, cop_r),
+                    _ => Ctc2(rt, cop_r),
+                }
+            }
+            "lwc2" | "swc2" => {
+                try!(count(ops, 2));
+                let cop_r = try!(register(ops[0])).0;
+                let (offset, base) = try!(memory(ops[1], &mut reloc));
+
+                match &*mnemonic {
+                    "lwc2" => Lwc2(cop_r, base, offset),
+                    _ => Swc2(cop_r, base, offset),
+                }
+            }
+            "rfe" => {
+                try!(count(ops, 0));
+                Rfe
+            }
+            "cop2" => {
+                try!(count(ops, 1));
+                Gte(try!(number_in(ops[0], 0, 0x1ffffff)) as u32)
+            }
+
+            // Pseudo-instructions
+            "nop" => {
+                try!(count(ops, 0));
+                Nop
+            }
+            "move" => {
+                try!(count(ops, 2));
+                Move(try!(register(ops[0])), try!(register(ops[1])))
+            }
+            "li" => {
+                try!(count(ops, 2));
+                Li(try!(register(ops[0])),
+                   try!(number_in(ops[1], -0x80000000, 0xffffffff)) as u32)
+            }
+            "la" => {
+                try!(count(ops, 2));
+                La(try!(register(ops[0])), try!(label(ops[1])))
+            }
+            m =>
+                match gte_command(m) {
+                    Some(command) => {
+                        try!(count(ops, 0));
+                        Gte(command)
+                    }
+                    None => return Err(format!("Unknown instruction '{}'", m)),
+                },
+        };
+
+    if let Some(r) = reloc {
+        out.push(r);
+    }
+
+    out.push(instruction);
+
+    Ok(())
+}
+
+/// Remove the comment at the end of `line`, if any
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (i, b) in line.bytes().enumerate() {
+        if in_string {
+            match b {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => (),
+            }
+        } else {
+            match b {
+                b'"' => in_string = true,
+                b'#' => return &line[..i],
+                _ => (),
+            }
+        }
+    }
+
+    line
+}
+
+fn count(ops: &[&str], expected: usize) -> Result<(), String> {
+    if ops.len() == expected {
+        Ok(())
+    } else {
+        Err(format!("Expected {} operands, got {}", expected, ops.len()))
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| {
+        match b {
+            b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'_' | b'.' | b'$' =>
+                true,
+            _ => false,
+        }
+    })
+}
+
+fn is_number(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b >= b'0' && b <= b'9')
+}
+
+/// Parse a decimal, hexadecimal (0x) or binary (0b) integer,
+/// optionally negative
+fn number(s: &str) -> Result<i64, String> {
+    let (negative, abs) =
+        if s.starts_with('-') {
+            (true, s[1..].trim())
+        } else {
+            (false, s)
+        };
+
+    let (radix, digits) =
+        if abs.starts_with("0x") || abs.starts_with("0X") {
+            (16, &abs[2..])
+        } else if abs.starts_with("0b") || abs.starts_with("0B") {
+            (2, &abs[2..])
+        } else {
+            (10, abs)
+        };
+
+    // from_str_radix accepts a leading sign, we don't want that here
+    if digits.is_empty() || digits.starts_with('+') || digits.starts_with('-') {
+        return Err(format!("Invalid number '{}'", s));
+    }
+
+    match i64::from_str_radix(digits, radix) {
+        Ok(v) => Ok(if negative { -v } else { v }),
+        Err(_) => Err(format!("Invalid number '{}'", s)),
+    }
+}
+
+fn number_in(s: &str, min: i64, max: i64) -> Result<i64, String> {
+    let v = try!(number(s));
+
+    if v < min || v > max {
+        Err(format!("Value {} out of range [{}, {}]", s, min, max))
+    } else {
+        Ok(v)
+    }
+}
+
+fn register(s: &str) -> Result<Register, String> {
+    if !s.starts_with('$') {
+        return Err(format!("Expected register, got '{}'", s));
+    }
+
+    let name = &s[1..];
+
+    if is_number(name) {
+        return match name.parse::<u8>() {
+            Ok(r) if r < 32 => Ok(Register(r)),
+            _ => Err(format!("Invalid register '{}'", s)),
+        };
+    }
+
+    let r =
+        match name {
+            "zero" => R0,
+            "at" => AT,
+            "v0" => V0,
+            "v1" => V1,
+            "a0" => A0,
+            "a1" => A1,
+            "a2" => A2,
+            "a3" => A3,
+            "t0" => T0,
+            "t1" => T1,
+            "t2" => T2,
+            "t3" => T3,
+            "t4" => T4,
+            "t5" => T5,
+            "t6" => T6,
+            "t7" => T7,
+            "s0" => S0,
+            "s1" => S1,
+            "s2" => S2,
+            "s3" => S3,
+            "s4" => S4,
+            "s5" => S5,
+            "s6" => S6,
+            "s7" => S7,
+            "t8" => T8,
+            "t9" => T9,
+            "k0" => K0,
+            "k1" => K1,
+            "gp" => GP,
+            "sp" => SP,
+            "fp" | "s8" => FP,
+            "ra" => RA,
+            _ => return Err(format!("Invalid register '{}'", s)),
+        };
+
+    Ok(r)
+}
+
+/// Parse a branch or jump target: either a label, a global label
+/// plus or minus a constant or an absolute address
+fn label(s: &str) -> Result<Label, String> {
+    if let Ok(addr) = number_in(s, 0, 0xffffffff) {
+        return Ok(Label::Absolute(addr as u32));
+    }
+
+    // Look for an offset, skipping the first character in case it's
+    // a sign
+    let sign = s.char_indices().skip(1).find(|&(_, c)| c == '+' || c == '-');
+
+    if let Some((p, _)) = sign {
+        let name = s[..p].trim();
+
+        if !is_identifier(name) || is_number(name) {
+            return Err(format!("Invalid label '{}'", name));
+        }
+
+        let offset = s[p + 1..].trim();
+
+        let offset =
+            if s.as_bytes()[p] == b'-' {
+                -try!(number_in(offset, 0, 0x80000000))
+            } else {
+                try!(number_in(offset, 0, 0x7fffffff))
+            };
+
+        return Ok(Label::Offset(name, offset as i32));
+    }
+
+    let len = s.len();
+
+    if len > 1 && is_number(&s[..len - 1]) {
+        return match s.as_bytes()[len - 1] {
+            b'b' => Ok(Label::Local(&s[..len - 1], 'b')),
+            b'f' => Ok(Label::Local(&s[..len - 1], 'f')),
+            _ => Err(format!("Invalid local label reference '{}'", s)),
+        };
+    }
+
+    if is_identifier(s) && !is_number(s) {
+        Ok(Label::Global(s))
+    } else {
+        Err(format!("Invalid label '{}'", s))
+    }
+}
+
+/// Parse `%hi(label)` or `%lo(label)`, returns `None` if `s` isn't a
+/// relocation operator
+fn relocation(s: &str) -> Result<Option<Instruction>, String> {
+    if !s.ends_with(')') {
+        return Ok(None);
+    }
+
+    if let Some(l) = operator_argument(s, "%hi(") {
+        Ok(Some(Hi(try!(label(l.trim())))))
+    } else if let Some(l) = operator_argument(s, "%lo(") {
+        Ok(Some(Lo(try!(label(l.trim())))))
+    } else {
+        Ok(None)
+    }
+}
+
+/// If `s` starts with `prefix` return what's between it and the
+/// final parenthesis
+fn operator_argument<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    if s.starts_with(prefix) {
+        Some(&s[prefix.len()..s.len() - 1])
+    } else {
+        None
+    }
+}
+
+/// Parse a signed 16bit immediate, `%hi` and `%lo` are stored in
+/// `reloc` and 0 is returned
+fn simm<'a>(s: &'a str,
+            reloc: &mut Option<Instruction<'a>>) -> Result<i16, String> {
+    if let Some(r) = try!(relocation(s)) {
+        *reloc = Some(r);
+        return Ok(0);
+    }
+
+    number_in(s, -0x8000, 0x7fff).map(|v| v as i16)
+}
+
+/// Parse an unsigned 16bit immediate, `%hi` and `%lo` are stored in
+/// `reloc` and 0 is returned
+fn uimm<'a>(s: &'a str,
+            reloc: &mut Option<Instruction<'a>>) -> Result<u16, String> {
+    if let Some(r) = try!(relocation(s)) {
+        *reloc = Some(r);
+        return Ok(0);
+    }
+
+    number_in(s, 0, 0xffff).map(|v| v as u16)
+}
+
+/// Parse a memory operand `offset($base)`, the offset can be omitted
+/// or be a `%lo` relocation
+fn memory<'a>(s: &'a str,
+              reloc: &mut Option<Instruction<'a>>)
+              -> Result<(i16, Register), String> {
+    let open =
+        match s.rfind('(') {
+            Some(o) if s.ends_with(')') => o,
+            _ => return Err(format!("Expected 'offset($reg)', got '{}'", s)),
+        };
+
+    let offset = s[..open].trim();
+
+    let offset =
+        if offset.is_empty() {
+            0
+        } else {
+            try!(simm(offset, reloc))
+        };
+
+    let base = try!(register(s[open + 1..s.len() - 1].trim()));
+
+    Ok((offset, base))
+}
+
+/// Parse a comma-separated list of double-quoted strings
+fn parse_strings(s: &str) -> Result<Vec<u8>, String> {
+    let mut string = String::new();
+    let mut chars = s.chars();
+
+    loop {
+        // Skip to the opening quote
+        match chars.by_ref().find(|c| !c.is_whitespace()) {
+            Some('"') => (),
+            Some(c) => return Err(format!("Unexpected '{}' in string list", c)),
+            None => return Err("Expected string".into()),
+        }
+
+        loop {
+            let c =
+                match chars.next() {
+                    Some(c) => c,
+                    None => return Err("Unterminated string".into()),
+                };
+
+            let c =
+                match c {
+                    '"' => break,
+                    '\\' =>
+                        match chars.next() {
+                            Some('n') => '\n',
+                            Some('r') => '\r',
+                            Some('t') => '\t',
+                            Some('0') => '\0',
+                            Some('\\') => '\\',
+                            Some('"') => '"',
+                            Some(e) =>
+                                return Err(format!("Unknown escape '\\{}'", e)),
+                            None => return Err("Unterminated string".into()),
+                        },
+                    c => c,
+                };
+
+            string.push(c);
+        }
+
+        match chars.by_ref().find(|c| !c.is_whitespace()) {
+            Some(',') => (),
+            Some(c) => return Err(format!("Unexpected '{}' after string", c)),
+            None => return Ok(string.into_bytes()),
+        }
+    }
+}
+
+/// Look up a GTE command by mnemonic
+fn gte_command(mnemonic: &str) -> Option<u32> {
+    let command =
+        match mnemonic {
+            "rtps" => gte::RTPS,
+            "nclip" => gte::NCLIP,
+            "op" => gte::OP,
+            "dpcs" => gte::DPCS,
+            "intpl" => gte::INTPL,
+            "mvmva" => gte::MVMVA,
+            "ncds" => gte::NCDS,
+            "cdp" => gte::CDP,
+            "ncdt" => gte::NCDT,
+            "nccs" => gte::NCCS,
+            "cc" => gte::CC,
+            "ncs" => gte::NCS,
+            "nct" => gte::NCT,
+            "sqr" => gte::SQR,
+            "dcpl" => gte::DCPL,
+            "dpct" => gte::DPCT,
+            "avsz3" => gte::AVSZ3,
+            "avsz4" => gte::AVSZ4,
+            "rtpt" => gte::RTPT,
+            "gpf" => gte::GPF,
+            "gpl" => gte::GPL,
+            "ncct" => gte::NCCT,
+            _ => return None,
+        };
+
+    Some(command)
+}
+
+#[test]
+fn same_code_as_rust_api() {
+    let source = "
+    # Copy a null-terminated string
+    strcpy:
+        move    $v0, $a0
+    1:  lbu     $t0, ($a1)
+        addiu   $a1, $a1, 1
+        sb      $t0, 0($a0)
+        bnez    $t0, 1b
+        addiu   $a0, $a0, 1     # delay slot
+        jr      $ra
+        nop
+    main:
+        li      $sp, 0x801ffff0
+        la      $a1, strcpy
+        lw      $t1, -4($sp)
+        jal     strcpy
+        sll     $2, $31, 3
+        mtc2    $t0, $9
+        rtpt
+        lwc2    $2, 8($a0)
+        cop2    0x1400006
+    ";
+
+    let expected = [
+        Global("strcpy"),
+        Move(V0, A0),
+        Local("1"),
+        Lbu(T0, A1, 0),
+        Addiu(A1, A1, 1),
+        Sb(T0, A0, 0),
+        Bnez(T0, Label::Local("1", 'b')),
+        Addiu(A0, A0, 1),
+        Jr(RA),
+        Nop,
+        Global("main"),
+        Li(SP, 0x801ffff0),
+        La(A1, Label::Global("strcpy")),
+        Lw(T1, SP, -4),
+        Jal(Label::Global("strcpy")),
+        Sll(V0, RA, 3),
+        Mtc2(T0, 9),
+        Gte(gte::RTPT),
+        Lwc2(2, A0, 8),
+        Gte(gte::NCLIP),
+    ];
+
+    let mut asm = Assembler::from_base(0x80010000);
+    asm.assemble(&expected).unwrap();
+    let (expected, _) = asm.machine_code().unwrap();
+
+    assert!(assemble(0x80010000, source).unwrap() == expected);
+}
+
+#[test]
+fn directives() {
+    let source = "
+        .byte   1, 0xff, -1
+        .align  2
+        .half   0x1234
+        .word   0xdeadbeef, -2
+        .ascii  \"a,b\\n\", \"#\"
+        .org    0x18
+    end:
+        .word   0
+    ";
+
+    let code = assemble(0x10, source).unwrap();
+
+    assert!(code == [1, 0xff, 0xff, 0,
+                     0x34, 0x12,
+                     0xef, 0xbe, 0xad, 0xde,
+                     0xfe, 0xff, 0xff, 0xff,
+                     b'a', b',', b'b', b'\n', b'#',
+                     0, 0, 0, 0, 0,
+                     0, 0, 0, 0]);
+}
+
+#[test]
+fn sections_and_relocations() {
+    use super::link::Linker;
+
+    let main = assemble_unit("
+        .text
+    main:
+        lui     $a0, %hi(table + 4)
+        lw      $a0, %lo(table+4)($a0)
+        la      $a1, counter
+        jal     print
+        nop
+        .data
+    table:
+        .word   1, print, table-4
+        .bss
+    counter:
+        .space  4
+    ").unwrap();
+
+    let lib = assemble_unit("
+    print:
+        jr      $ra
+        nop
+    ").unwrap();
+
+    let program = Linker::new(0x80010000).link(&[main, lib]).unwrap();
+
+    let print = program.symbol("print").unwrap();
+    let table = program.symbol("table").unwrap();
+    let counter = program.symbol("counter").unwrap();
+
+    assert!(print == 0x80010018);
+    assert!(table == 0x80010020);
+    assert!(counter == 0x8001002c);
+
+    let expected = [
+        Lui(A0, 0x8001),
+        Lw(A0, A0, 0x24),
+        Lui(A1, 0x8001),
+        Addiu(A1, A1, 0x2c),
+        Jal(Label::Absolute(print)),
+        Nop,
+        Jr(RA),
+        Nop,
+    ];
+
+    let mut asm = Assembler::from_base(0x80010000);
+    asm.assemble(&expected).unwrap();
+    let (expected, _) = asm.machine_code().unwrap();
+
+    assert!(program.text == expected);
+    assert!(program.data == [1, 0, 0, 0,
+                             0x18, 0x00, 0x01, 0x80,
+                             0x1c, 0x00, 0x01, 0x80]);
+
+    let e = Linker::new(0)
+        .link(&[assemble_unit("nop\njal missing\nnop").unwrap()])
+        .unwrap_err();
+
+    assert!(e.contains("missing"));
+}
+
+#[test]
+fn errors_report_line() {
+    let e = assemble(0, "nop\n\n  frob $t0\n").unwrap_err();
+    assert!(e.line == 3);
+
+    let e = assemble(0, "nop\nb nowhere\nnop").unwrap_err();
+    assert!(e.line == 2);
+
+    let e = assemble(0, "addiu $t0, $t0, 0x8000").unwrap_err();
+    assert!(e.line == 1);
+
+    let e = assemble(0, "lw $t0, 4($t12)").unwrap_err();
+    assert!(e.line == 1);
+
+    let e = assemble(0, "a:\nnop\na:").unwrap_err();
+    assert!(e.line == 3);
+}
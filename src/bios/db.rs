@@ -379,7 +379,7 @@ fn patch_debug_uart_na_30(bios: &mut Bios) {
     //
     // Credit to the No$ spec for documenting this hack.
 
-    let mut asm = Assembler::from_base(0);
+    let mut asm = Assembler::from_base(0xbfc06f0c);
 
     asm.assemble(&[
         Li(AT, 1),
@@ -387,7 +387,7 @@ fn patch_debug_uart_na_30(bios: &mut Bios) {
         Sw(AT, GP, -18000 - 0xff0),
     ]).unwrap();
 
-    let (mc, _) = asm.machine_code();
+    let (mc, _) = asm.machine_code().unwrap();
 
     assert!(mc.len() == 12);
 
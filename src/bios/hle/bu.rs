@@ -0,0 +1,303 @@
+//! Memory card filesystem for the HLE kernel's "bu" devices. Like
+//! `cdfs` we bypass the peripheral emulation entirely and access the
+//! card's image directly.
+//!
+//! The card is split in 16 blocks of 8KiB. The first one holds the
+//! directory: one frame per remaining block saying which file it
+//! belongs to. Files spanning several blocks are chained through
+//! their directory frames.
+
+use std::cmp;
+use std::ops::Range;
+
+use padmemcard::memorycard::{self, MemoryCard, FRAME_SIZE};
+
+/// Number of blocks available for files, the first block of the card
+/// holds the directory
+const NUM_BLOCKS: usize = 15;
+/// Size of a block in bytes
+const BLOCK_SIZE: u32 = 0x2000;
+/// Size of the file name field in a directory frame, including the
+/// terminating `\0`
+const NAME_LEN: usize = 21;
+
+/// Directory frame states
+const BLOCK_FREE: u8 = 0xa0;
+const BLOCK_FIRST: u8 = 0x51;
+const BLOCK_MIDDLE: u8 = 0x52;
+const BLOCK_LAST: u8 = 0x53;
+
+/// Location of a file on a memory card
+#[derive(Clone, Copy, RustcDecodable, RustcEncodable)]
+pub struct CardFile {
+    /// Index of the first block of the file, not counting the
+    /// directory block
+    pub block: usize,
+    /// Length of the file in bytes
+    pub size: u32,
+}
+
+impl CardFile {
+    /// Index of the first frame of the file on the card
+    pub fn first_frame(&self) -> u32 {
+        block_offset(self.block) as u32 / FRAME_SIZE as u32
+    }
+}
+
+/// File found in the directory
+pub struct Entry {
+    /// File name, each byte is converted to the corresponding
+    /// Latin-1 code point
+    pub name: String,
+    pub file: CardFile,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// A file with the same name already exists
+    AlreadyExists,
+    /// Not enough free blocks
+    NoSpace,
+    /// The file name is empty or too long
+    BadName,
+}
+
+/// Look up the file `name`. Unlike "cdrom:" the lookup is
+/// case-sensitive.
+pub fn lookup(card: &MemoryCard, name: &str) -> Option<Entry> {
+    (0..NUM_BLOCKS)
+        .filter_map(|b| entry(card, b))
+        .find(|e| e.name == name)
+}
+
+/// Return the first file starting at or after `block` whose name
+/// matches `pattern`. Like the BIOS we support the `?` wildcard
+/// matching any character and `*` matching the rest of the name.
+pub fn find(card: &MemoryCard, pattern: &str, block: usize) -> Option<Entry> {
+    (block..NUM_BLOCKS)
+        .filter_map(|b| entry(card, b))
+        .find(|e| matches(pattern, &e.name))
+}
+
+fn matches(pattern: &str, name: &str) -> bool {
+    let mut name = name.chars();
+
+    for p in pattern.chars() {
+        match p {
+            '*' => return true,
+            '?' => {
+                if name.next().is_none() {
+                    return false;
+                }
+            }
+            c => {
+                if name.next() != Some(c) {
+                    return false;
+                }
+            }
+        }
+    }
+
+    name.next().is_none()
+}
+
+/// Create the file `name` spanning `blocks` blocks
+pub fn create(card: &mut MemoryCard,
+              name: &str,
+              blocks: u32) -> Result<Entry, Error> {
+    let name_len = name.chars().count();
+
+    if name_len == 0 || name_len >= NAME_LEN {
+        return Err(Error::BadName);
+    }
+
+    if lookup(card, name).is_some() {
+        return Err(Error::AlreadyExists);
+    }
+
+    let free: Vec<usize> =
+        (0..NUM_BLOCKS)
+        .filter(|&b| directory(card, b)[0] == BLOCK_FREE)
+        .collect();
+
+    if blocks == 0 || blocks as usize > free.len() {
+        return Err(Error::NoSpace);
+    }
+
+    let free = &free[..blocks as usize];
+    let size = blocks * BLOCK_SIZE;
+
+    for (i, &block) in free.iter().enumerate() {
+        let offset = (block + 1) * FRAME_SIZE;
+        let dir = &mut card.image_mut()[offset..offset + FRAME_SIZE];
+
+        for b in dir.iter_mut() {
+            *b = 0;
+        }
+
+        dir[0] =
+            if i == 0 {
+                BLOCK_FIRST
+            } else if i == free.len() - 1 {
+                BLOCK_LAST
+            } else {
+                BLOCK_MIDDLE
+            };
+
+        if i == 0 {
+            for (j, b) in (0..4).map(|j| (size >> (j * 8)) as u8).enumerate() {
+                dir[4 + j] = b;
+            }
+
+            for (j, c) in name.chars().enumerate() {
+                dir[0xa + j] = c as u8;
+            }
+        }
+
+        let next =
+            match free.get(i + 1) {
+                Some(&b) => b as u16,
+                None => 0xffff,
+            };
+
+        dir[8] = next as u8;
+        dir[9] = (next >> 8) as u8;
+
+        let c = memorycard::checksum(dir);
+
+        dir[FRAME_SIZE - 1] = c;
+    }
+
+    Ok(Entry {
+        name: name.to_string(),
+        file: CardFile {
+            block: free[0],
+            size: size,
+        },
+    })
+}
+
+/// Read `len` bytes starting at `offset` in `file`. The read is
+/// truncated if it goes past the end of the file.
+pub fn read(card: &MemoryCard,
+            file: CardFile,
+            offset: u32,
+            len: u32) -> Vec<u8> {
+    let mut data = Vec::new();
+
+    for r in extents(card, file, offset, len) {
+        data.extend_from_slice(&card.image()[r]);
+    }
+
+    data
+}
+
+/// Write `data` at `offset` in `file`. The write is truncated if it
+/// goes past the end of the file. Returns the number of bytes
+/// written.
+pub fn write(card: &mut MemoryCard,
+             file: CardFile,
+             offset: u32,
+             data: &[u8]) -> u32 {
+    let mut written = 0;
+
+    for r in extents(card, file, offset, data.len() as u32) {
+        let n = r.end - r.start;
+
+        card.image_mut()[r].copy_from_slice(&data[written..written + n]);
+
+        written += n;
+    }
+
+    written as u32
+}
+
+/// Return the directory frame of `block`
+fn directory(card: &MemoryCard, block: usize) -> &[u8] {
+    let offset = (block + 1) * FRAME_SIZE;
+
+    &card.image()[offset..offset + FRAME_SIZE]
+}
+
+/// Return the offset of `block` in the card image
+fn block_offset(block: usize) -> usize {
+    (block + 1) * BLOCK_SIZE as usize
+}
+
+/// Parse the directory entry of `block`, returns `None` if it's not
+/// the first block of a file
+fn entry(card: &MemoryCard, block: usize) -> Option<Entry> {
+    let dir = directory(card, block);
+
+    if dir[0] != BLOCK_FIRST {
+        return None;
+    }
+
+    let size = (0..4).fold(0, |s, i| s | (dir[4 + i] as u32) << (i * 8));
+
+    let name =
+        dir[0xa..0xa + NAME_LEN].iter()
+        .take_while(|&&b| b != 0)
+        .map(|&b| b as char)
+        .collect();
+
+    Some(Entry {
+        name: name,
+        file: CardFile {
+            block: block,
+            size: size,
+        },
+    })
+}
+
+/// Return the blocks of `file` in order
+fn chain(card: &MemoryCard, file: CardFile) -> Vec<usize> {
+    let mut blocks = vec![file.block];
+
+    // The length check protects us against corrupted cards with
+    // looping chains
+    while blocks.len() < NUM_BLOCKS {
+        let last = blocks[blocks.len() - 1];
+        let dir = directory(card, last);
+
+        let next = (dir[8] as usize) | ((dir[9] as usize) << 8);
+
+        if next >= NUM_BLOCKS {
+            break;
+        }
+
+        blocks.push(next);
+    }
+
+    blocks
+}
+
+/// Return the ranges of the card image holding the `len` bytes at
+/// `offset` in `file`, truncated at the end of the file
+fn extents(card: &MemoryCard,
+           file: CardFile,
+           offset: u32,
+           len: u32) -> Vec<Range<usize>> {
+    let blocks = chain(card, file);
+
+    let end = cmp::min(file.size, blocks.len() as u32 * BLOCK_SIZE);
+    let end = cmp::min(end as u64, offset as u64 + len as u64) as u32;
+
+    let mut ranges = Vec::new();
+    let mut pos = offset;
+
+    while pos < end {
+        let block = blocks[(pos / BLOCK_SIZE) as usize];
+        let block_pos = pos % BLOCK_SIZE;
+
+        let n = cmp::min(BLOCK_SIZE - block_pos, end - pos);
+
+        let start = block_offset(block) + block_pos as usize;
+
+        ranges.push(start..start + n as usize);
+
+        pos += n;
+    }
+
+    ranges
+}
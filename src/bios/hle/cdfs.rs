@@ -0,0 +1,123 @@
+//! Minimal read-only ISO9660 access for the HLE kernel's "cdrom:"
+//! device. We bypass the CD controller emulation entirely and read
+//! the sectors straight from the disc image.
+
+use cdimage::{Image, CdError};
+use cdimage::msf::Msf;
+use cdimage::bcd::Bcd;
+use cdimage::sector::Sector;
+
+use cdrom::iso9660::{self, Directory, Error};
+
+/// Location of a file on the disc
+#[derive(Clone, Copy, RustcDecodable, RustcEncodable)]
+pub struct FileExtent {
+    /// Index of the first sector of the file
+    pub lba: u32,
+    /// Length of the file in bytes
+    pub len: u32,
+}
+
+/// Look up `path` on the disc. `path` is the part of the BIOS file
+/// name after the "cdrom:" device, for instance `\MOVIE\INTRO.STR;1`.
+/// Like the BIOS the lookup is case-insensitive and the `;1` version
+/// suffix is optional.
+pub fn lookup(image: &mut Image, path: &str) -> Result<FileExtent, Error> {
+    let components: Vec<&str> =
+        path.split(|c| c == '\\' || c == '/')
+        .filter(|c| !c.is_empty())
+        .collect();
+
+    let (file, dirs) =
+        match components.split_last() {
+            Some(s) => s,
+            None => return Err(Error::EntryNotFound),
+        };
+
+    let mut dir = try!(iso9660::open_image(image));
+
+    for d in dirs {
+        let name = try!(find_entry(&dir, d)).name().to_vec();
+
+        dir = try!(dir.cd(image, &name));
+    }
+
+    let entry = try!(find_entry(&dir, file));
+
+    if entry.is_dir() {
+        return Err(Error::NotAFile);
+    }
+
+    Ok(FileExtent {
+        lba: entry.extent_location(),
+        len: entry.extent_len(),
+    })
+}
+
+fn find_entry<'a>(dir: &'a Directory,
+                  name: &str) -> Result<&'a iso9660::Entry, Error> {
+    let name = name.to_uppercase();
+    let name = name.trim_right_matches(";1");
+
+    dir.ls().iter()
+        .find(|e| {
+            let entry_name = String::from_utf8_lossy(e.name()).to_uppercase();
+
+            entry_name.trim_right_matches(";1") == name
+        })
+        .ok_or(Error::EntryNotFound)
+}
+
+/// Read `len` bytes starting at `offset` in `file`. The read is
+/// truncated if it goes past the end of the file.
+pub fn read(image: &mut Image,
+            file: FileExtent,
+            offset: u32,
+            len: u32) -> Result<Vec<u8>, Error> {
+    if offset >= file.len {
+        return Ok(Vec::new());
+    }
+
+    let len = ::std::cmp::min(len, file.len - offset) as usize;
+
+    let mut data = Vec::with_capacity(len);
+
+    let first = file.lba + offset / 2048;
+    let mut sector_offset = (offset % 2048) as usize;
+
+    let track_msf =
+        match Msf::from_sector_index(first) {
+            Some(m) => m,
+            None => return Err(Error::BadExtent(first)),
+        };
+
+    let mut msf = try!(image.track_msf(Bcd::one(), track_msf));
+
+    let mut sector = Sector::empty();
+
+    while data.len() < len {
+        try!(image.read_sector(&mut sector, msf));
+
+        let payload = try!(sector.mode2_xa_payload());
+
+        let n = ::std::cmp::min(2048 - sector_offset, len - data.len());
+
+        data.extend_from_slice(&payload[sector_offset..sector_offset + n]);
+
+        sector_offset = 0;
+
+        if data.len() == len {
+            break;
+        }
+
+        // A file going past 99:59:74 means that the filesystem is
+        // corrupted
+        msf =
+            match msf.next() {
+                Some(m) => m,
+                None => return Err(Error::CdError(CdError::BadFormat)),
+            };
+    }
+
+    Ok(data)
+}
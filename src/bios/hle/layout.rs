@@ -0,0 +1,116 @@
+//! Addresses used by the HLE kernel, both in the replacement ROM and
+//! in the kernel area of the RAM (first 64KB).
+
+/// Base address of the ROM (KSEG1)
+pub const ROM_BASE: u32 = 0xbfc00000;
+
+/// Boot code, the first word is the "animation jump hook" patched by
+/// `ExeLoader`
+pub const BOOT: u32 = 0xbfc00080;
+/// Infinite loop, used when there's nothing left to run
+pub const IDLE: u32 = 0xbfc00090;
+
+/// Exception handler, the RAM vector jumps there
+pub const EXCEPTION_HANDLER: u32 = 0xbfc01000;
+/// Restore the current thread's registers and return from exception
+pub const RETURN_FROM_EXCEPTION: u32 = 0xbfc01200;
+/// Call the event callbacks listed at `CALLBACK_LIST`
+pub const DELIVER_CALLBACKS: u32 = 0xbfc01300;
+/// Return path for executables started with DoExecute
+pub const EXEC_RETURN: u32 = 0xbfc01380;
+/// `syscall; jr ra`
+pub const SYSCALL: u32 = 0xbfc013c0;
+/// Busy loop for WaitEvent
+pub const WAIT_EVENT: u32 = 0xbfc013e0;
+
+/// A0/B0/C0 dispatchers, the RAM vectors jump there
+pub const DISPATCH_A: u32 = 0xbfc01400;
+pub const DISPATCH_B: u32 = 0xbfc01420;
+pub const DISPATCH_C: u32 = 0xbfc01440;
+
+/// Start of the trap area. Any instruction fetched in this range
+/// invokes the Rust kernel. Must be aligned to 8KB and not cross
+/// `TRAP_START + 0x2000`.
+pub const TRAP_START: u32 = 0xbfc10000;
+
+/// Traps used by the ROM code
+pub const TRAP_INIT: u32 = 0xbfc11800;
+pub const TRAP_BOOT: u32 = 0xbfc11808;
+pub const TRAP_EXCEPTION: u32 = 0xbfc11810;
+pub const TRAP_EXCEPTION_EXIT: u32 = 0xbfc11818;
+
+pub const TRAP_END: u32 = 0xbfc11820;
+
+/// Trap address for A0 function `n`
+pub fn trap_a(n: u32) -> u32 {
+    TRAP_START + n * 8
+}
+
+/// Trap address for B0 function `n`
+pub fn trap_b(n: u32) -> u32 {
+    TRAP_START + 0x800 + n * 8
+}
+
+/// Trap address for C0 function `n`
+pub fn trap_c(n: u32) -> u32 {
+    TRAP_START + 0x1000 + n * 8
+}
+
+/// Number of entries in each function table
+pub const TABLE_A_LEN: u32 = 0xc0;
+pub const TABLE_B_LEN: u32 = 0x60;
+pub const TABLE_C_LEN: u32 = 0x20;
+
+/// RAM vectors
+pub const VECTOR_EXCEPTION: u32 = 0x80000080;
+pub const VECTOR_A: u32 = 0x800000a0;
+pub const VECTOR_B: u32 = 0x800000b0;
+pub const VECTOR_C: u32 = 0x800000c0;
+
+/// "Table of tables": pairs of (address, size) describing the
+/// kernel structures. Games sometimes access these directly.
+pub const TOT_EXCB: u32 = 0x100;
+pub const TOT_PCB: u32 = 0x108;
+pub const TOT_TCB: u32 = 0x110;
+pub const TOT_EVCB: u32 = 0x120;
+
+/// Function tables, at the same location as in the real BIOS
+pub const TABLE_A: u32 = 0x80000200;
+pub const TABLE_C: u32 = 0x80000674;
+pub const TABLE_B: u32 = 0x80000874;
+
+/// Exception handler chains: 4 priorities, 8 bytes each
+pub const EXCB: u32 = 0x80007000;
+/// Process control block: pointer to the current TCB
+pub const PCB: u32 = 0x80007020;
+/// 0-terminated list of callbacks for DELIVER_CALLBACKS
+pub const CALLBACK_LIST: u32 = 0x80007040;
+pub const MAX_CALLBACKS: usize = 15;
+/// Executable header buffer used when booting
+pub const EXEC_HEADER: u32 = 0x80007080;
+/// Thread control blocks
+pub const TCB: u32 = 0x80007100;
+pub const TCB_SIZE: u32 = 0xc0;
+pub const NUM_TCB: u32 = 4;
+/// Event control blocks
+pub const EVCB: u32 = 0x80007400;
+pub const EVCB_SIZE: u32 = 0x1c;
+pub const NUM_EVCB: u32 = 16;
+/// Stack used by the exception handler
+pub const KERNEL_STACK: u32 = 0x80007ff0;
+/// Memory handed out by alloc_kernel_memory
+pub const KERNEL_HEAP: u32 = 0x80008000;
+pub const KERNEL_HEAP_END: u32 = 0x8000e000;
+
+/// Stack used while booting and default stack for executables
+pub const BOOT_STACK: u32 = 0x801ffff0;
+
+/// Offset of the register save area in a TCB
+pub const TCB_REGS: u32 = 8;
+/// Offsets of the other registers in the save area (after the 32
+/// GPRs)
+pub const REGS_EPC: u32 = 0x80;
+pub const REGS_HI: u32 = 0x84;
+pub const REGS_LO: u32 = 0x88;
+pub const REGS_SR: u32 = 0x8c;
+pub const REGS_CAUSE: u32 = 0x90;
@@ -0,0 +1,446 @@
+//! C library functions from the A0 table. These only touch guest
+//! memory so they're straightforward to implement natively.
+
+use memory::{Byte, Word};
+
+use super::Context;
+
+/// Maximum length of the strings we're willing to read from guest
+/// memory, in case we get passed a garbage pointer
+const MAX_STRING_LEN: u32 = 0x10000;
+
+pub fn strlen(ctx: &mut Context, s: u32) -> u32 {
+    let mut len = 0;
+
+    while len < MAX_STRING_LEN && ctx.load::<Byte>(s + len) != 0 {
+        len += 1;
+    }
+
+    len
+}
+
+/// Copy at most `max` bytes of the string at `src` to `dst`,
+/// including the terminating `\0` if it fits.
+pub fn strncpy(ctx: &mut Context, dst: u32, src: u32, max: u32) {
+    for i in 0..max {
+        let b = ctx.load::<Byte>(src + i);
+
+        ctx.store::<Byte>(dst + i, b);
+
+        if b == 0 {
+            break;
+        }
+    }
+}
+
+pub fn strncmp(ctx: &mut Context, a: u32, b: u32, max: u32) -> u32 {
+    for i in 0..max {
+        let ca = ctx.load::<Byte>(a + i) as i32;
+        let cb = ctx.load::<Byte>(b + i) as i32;
+
+        if ca != cb || ca == 0 {
+            return (ca - cb) as u32;
+        }
+    }
+
+    0
+}
+
+/// Return the address of the first (or last if `reverse` is true)
+/// occurence of `c` in `s` or 0 if it can't be found
+pub fn strchr(ctx: &mut Context, s: u32, c: u32, reverse: bool) -> u32 {
+    let c = c & 0xff;
+    let mut found = 0;
+
+    for i in 0..MAX_STRING_LEN {
+        let b = ctx.load::<Byte>(s + i);
+
+        if b == c {
+            found = s + i;
+
+            if !reverse {
+                break;
+            }
+        }
+
+        if b == 0 {
+            break;
+        }
+    }
+
+    found
+}
+
+/// strspn if `accept` is true, strcspn otherwise
+pub fn strspn(ctx: &mut Context, s: u32, set: u32, accept: bool) -> u32 {
+    let set = ctx.read_cstr(set);
+    let mut len = 0;
+
+    loop {
+        let b = ctx.load::<Byte>(s + len) as u8;
+
+        if b == 0 || set.contains(&b) != accept {
+            return len;
+        }
+
+        len += 1;
+    }
+}
+
+pub fn strpbrk(ctx: &mut Context, s: u32, set: u32) -> u32 {
+    let n = strspn(ctx, s, set, false);
+
+    if ctx.load::<Byte>(s + n) == 0 {
+        0
+    } else {
+        s + n
+    }
+}
+
+pub fn strstr(ctx: &mut Context, s: u32, needle: u32) -> u32 {
+    let haystack = ctx.read_cstr(s);
+    let needle = ctx.read_cstr(needle);
+
+    if needle.is_empty() {
+        return s;
+    }
+
+    match haystack.windows(needle.len()).position(|w| w == &needle[..]) {
+        Some(p) => s + p as u32,
+        None => 0,
+    }
+}
+
+pub fn memcpy(ctx: &mut Context, dst: u32, src: u32, len: u32) {
+    let data = ctx.read_bytes(src, len);
+
+    ctx.write_bytes(dst, &data);
+}
+
+pub fn memset(ctx: &mut Context, dst: u32, val: u32, len: u32) {
+    for i in 0..len {
+        ctx.store::<Byte>(dst + i, val & 0xff);
+    }
+}
+
+pub fn memcmp(ctx: &mut Context, a: u32, b: u32, len: u32) -> u32 {
+    for i in 0..len {
+        let ca = ctx.load::<Byte>(a + i) as i32;
+        let cb = ctx.load::<Byte>(b + i) as i32;
+
+        if ca != cb {
+            return (ca - cb) as u32;
+        }
+    }
+
+    0
+}
+
+pub fn memchr(ctx: &mut Context, s: u32, c: u32, len: u32) -> u32 {
+    for i in 0..len {
+        if ctx.load::<Byte>(s + i) == c & 0xff {
+            return s + i;
+        }
+    }
+
+    0
+}
+
+pub fn toupper(c: u32) -> u32 {
+    let c = c & 0xff;
+
+    match c as u8 {
+        b'a'...b'z' => c - 0x20,
+        _ => c,
+    }
+}
+
+pub fn tolower(c: u32) -> u32 {
+    let c = c & 0xff;
+
+    match c as u8 {
+        b'A'...b'Z' => c + 0x20,
+        _ => c,
+    }
+}
+
+/// Parse an integer like `strtol`. A `base` of 0 means that the base
+/// is deduced from the prefix ("0x" for hex, "0" for octal). If
+/// `endptr` is non-0 the address of the first character that wasn't
+/// parsed is stored there.
+pub fn strtol(ctx: &mut Context, s: u32, endptr: u32, base: u32) -> u32 {
+    let mut p = s;
+
+    while (ctx.load::<Byte>(p) as u8 as char).is_whitespace() {
+        p += 1;
+    }
+
+    let negative =
+        match ctx.load::<Byte>(p) as u8 {
+            b'-' => { p += 1; true }
+            b'+' => { p += 1; false }
+            _ => false,
+        };
+
+    let mut base = base;
+
+    let has_hex_prefix =
+        ctx.load::<Byte>(p) as u8 == b'0' &&
+        (ctx.load::<Byte>(p + 1) as u8 | 0x20) == b'x';
+
+    if (base == 0 || base == 16) && has_hex_prefix {
+        base = 16;
+        p += 2;
+    } else if base == 0 && ctx.load::<Byte>(p) as u8 == b'0' {
+        base = 8;
+    } else if base == 0 {
+        base = 10;
+    }
+
+    let mut v: u32 = 0;
+
+    loop {
+        let c = ctx.load::<Byte>(p) as u8 as char;
+
+        match c.to_digit(base) {
+            Some(d) => v = v.wrapping_mul(base).wrapping_add(d),
+            None => break,
+        }
+
+        p += 1;
+    }
+
+    if endptr != 0 {
+        ctx.store::<Word>(endptr, p);
+    }
+
+    if negative {
+        v.wrapping_neg()
+    } else {
+        v
+    }
+}
+
+/// Format the string at `fmt` with the arguments starting at
+/// argument index `first_arg`. Supports the usual `%d`, `%i`, `%u`,
+/// `%x`, `%X`, `%o`, `%c`, `%s`, `%p` conversions with flags, field
+/// width and precision.
+pub fn format(ctx: &mut Context, fmt: u32, first_arg: u32) -> String {
+    let fmt = ctx.read_cstr(fmt);
+    let mut arg = first_arg;
+    let mut out = String::new();
+
+    let mut i = 0;
+
+    while i < fmt.len() {
+        let c = fmt[i];
+        i += 1;
+
+        if c != b'%' {
+            out.push(c as char);
+            continue;
+        }
+
+        let mut left = false;
+        let mut zero = false;
+        let mut plus = false;
+
+        while i < fmt.len() {
+            match fmt[i] {
+                b'-' => left = true,
+                b'0' => zero = true,
+                b'+' => plus = true,
+                b' ' | b'#' => (),
+                _ => break,
+            }
+            i += 1;
+        }
+
+        let mut width = 0;
+
+        if i < fmt.len() && fmt[i] == b'*' {
+            width = ctx.arg(arg) as usize;
+            arg += 1;
+            i += 1;
+        }
+
+        while i < fmt.len() && (fmt[i] as char).is_digit(10) {
+            width = width * 10 + (fmt[i] - b'0') as usize;
+            i += 1;
+        }
+
+        let mut precision = None;
+
+        if i < fmt.len() && fmt[i] == b'.' {
+            let mut p = 0;
+            i += 1;
+
+            while i < fmt.len() && (fmt[i] as char).is_digit(10) {
+                p = p * 10 + (fmt[i] - b'0') as usize;
+                i += 1;
+            }
+
+            precision = Some(p);
+        }
+
+        // Length modifiers are meaningless on a 32bit CPU
+        while i < fmt.len() && (fmt[i] == b'l' || fmt[i] == b'h') {
+            i += 1;
+        }
+
+        if i >= fmt.len() {
+            break;
+        }
+
+        let conversion = fmt[i];
+        i += 1;
+
+        let s =
+            match conversion {
+                b'%' => {
+                    out.push('%');
+                    continue;
+                }
+                b'd' | b'i' => {
+                    let v = ctx.arg(arg) as i32;
+
+                    if plus && v >= 0 {
+                        format!("+{}", v)
+                    } else {
+                        format!("{}", v)
+                    }
+                }
+                b'u' => format!("{}", ctx.arg(arg)),
+                b'x' => format!("{:x}", ctx.arg(arg)),
+                b'X' => format!("{:X}", ctx.arg(arg)),
+                b'o' => format!("{:o}", ctx.arg(arg)),
+                b'p' => format!("{:08x}", ctx.arg(arg)),
+                b'c' => ((ctx.arg(arg) as u8) as char).to_string(),
+                b's' => {
+                    let p = ctx.arg(arg);
+                    let s = ctx.read_string(p);
+
+                    match precision {
+                        Some(p) => s.chars().take(p).collect(),
+                        None => s,
+                    }
+                }
+                // Unknown conversion, output it verbatim
+                _ => {
+                    out.push('%');
+                    out.push(conversion as char);
+                    continue;
+                }
+            };
+
+        arg += 1;
+
+        if s.len() >= width {
+            out.push_str(&s);
+        } else if left {
+            out.push_str(&s);
+            out.extend(::std::iter::repeat(' ').take(width - s.len()));
+        } else if zero && conversion != b's' && conversion != b'c' {
+            let (sign, digits) =
+                if s.starts_with('-') || s.starts_with('+') {
+                    s.split_at(1)
+                } else {
+                    ("", &*s)
+                };
+
+            out.push_str(sign);
+            out.extend(::std::iter::repeat('0').take(width - s.len()));
+            out.push_str(digits);
+        } else {
+            out.extend(::std::iter::repeat(' ').take(width - s.len()));
+            out.push_str(&s);
+        }
+    }
+
+    out
+}
+
+/// Simple first-fit allocator for the malloc family, the
+/// bookkeeping is done on the host side.
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct Heap {
+    start: u32,
+    end: u32,
+    /// Allocated blocks as (address, size), sorted by address
+    blocks: Vec<(u32, u32)>,
+}
+
+impl Heap {
+    pub fn new() -> Heap {
+        Heap {
+            start: 0,
+            end: 0,
+            blocks: Vec::new(),
+        }
+    }
+
+    /// InitHeap: use `size` bytes at `addr`, forgets about all
+    /// previous allocations
+    pub fn init(&mut self, addr: u32, size: u32) {
+        self.start = addr;
+        self.end = addr.wrapping_add(size);
+        self.blocks.clear();
+    }
+
+    /// Return the address of the new block or 0 if we're out of
+    /// memory
+    pub fn alloc(&mut self, size: u32) -> u32 {
+        let size = (size.wrapping_add(7) & !7) as u64;
+
+        let mut candidate = ((self.start + 7) & !7) as u64;
+        let mut pos = self.blocks.len();
+
+        for (i, &(addr, len)) in self.blocks.iter().enumerate() {
+            if candidate + size <= addr as u64 {
+                pos = i;
+                break;
+            }
+
+            candidate = addr as u64 + len as u64;
+        }
+
+        if candidate + size > self.end as u64 {
+            return 0;
+        }
+
+        self.blocks.insert(pos, (candidate as u32, size as u32));
+
+        candidate as u32
+    }
+
+    /// Free the block at `addr`, returns its size if it was found
+    pub fn free(&mut self, addr: u32) -> Option<u32> {
+        match self.blocks.iter().position(|&(a, _)| a == addr) {
+            Some(i) => Some(self.blocks.remove(i).1),
+            None => None,
+        }
+    }
+}
+
+#[test]
+fn heap_first_fit() {
+    let mut heap = Heap::new();
+
+    heap.init(0x1000, 0x100);
+
+    let a = heap.alloc(0x10);
+    let b = heap.alloc(0x20);
+    let c = heap.alloc(0x10);
+
+    assert!(a == 0x1000);
+    assert!(b == 0x1010);
+    assert!(c == 0x1030);
+
+    // Allocating more than what's left fails
+    assert!(heap.alloc(0x100) == 0);
+
+    // Freed memory gets reused
+    assert!(heap.free(b) == Some(0x20));
+    assert!(heap.alloc(0x18) == 0x1010);
+    assert!(heap.free(0x1234).is_none());
+}
@@ -0,0 +1,1872 @@
+//! High level emulation of the BIOS kernel.
+//!
+//! This lets us boot games without a dump of the original firmware:
+//! `Bios::hle()` builds a small replacement ROM (see `rom`) whose
+//! A0/B0/C0 function tables point to "trap" addresses. When the CPU
+//! reaches one of those it calls `HleKernel::trap` which implements
+//! the function natively, reading its arguments from the guest
+//! registers and memory.
+//!
+//! The kernel structures the games might look at (thread and event
+//! control blocks, interrupt handler chains, function tables) live
+//! in guest RAM at the usual places. Host side state is limited to
+//! things like open files and the malloc bookkeeping.
+//!
+//! Memory cards are accessed through the "bu" devices (see `bu`),
+//! like the CD the cards are read and written directly without going
+//! through the peripheral emulation. The low level card functions
+//! complete immediately.
+//!
+//! Not emulated: renaming, deleting and formatting memory card files,
+//! the TTY input, CD directory listing and the less common functions
+//! which are logged and return 0.
+
+pub mod layout;
+
+mod rom;
+mod libc;
+mod cdfs;
+mod bu;
+
+#[cfg(test)]
+mod tests;
+
+use cdimage::Image;
+
+use cpu::Cpu;
+use memory::{Addressable, Byte, HalfWord, Word};
+use memory::map;
+use shared::SharedState;
+use gpu::renderer::Renderer;
+use padmemcard::memorycard::{MemoryCard, FRAME_SIZE};
+use error::Device;
+use assembler::syntax::{K0, T2};
+
+use super::tracer;
+
+use self::cdfs::FileExtent;
+use self::bu::CardFile;
+use self::libc::Heap;
+
+pub use self::rom::assemble as assemble_rom;
+
+/// Host side kernel state
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct HleKernel {
+    /// Heap used by malloc & co
+    heap: Heap,
+    /// Next free address for alloc_kernel_memory
+    kernel_heap: u32,
+    /// Open files, indexed by `fd - FIRST_FD`
+    files: Vec<Option<OpenFile>>,
+    /// Error code returned by GetLastError
+    last_error: u32,
+    /// Jump buffer set by SetCustomExitFromException or 0 if the
+    /// default exit is used
+    custom_exit: u32,
+    /// Bitmask of the root counters whose interrupt is acknowledged
+    /// by the kernel (see ChangeClearRCnt)
+    clear_rcnt: u8,
+    /// Pad buffers set by InitPad: (address, length) for each port
+    pad_buffers: Vec<(u32, u32)>,
+    /// True if the pads are polled at each vblank
+    pads_started: bool,
+    /// State of the `rand` PRNG
+    rand_seed: u32,
+    /// Current TTY line
+    tty_line: String,
+    /// Memory card search started by firstfile
+    card_search: Option<CardSearch>,
+}
+
+impl HleKernel {
+    pub fn new() -> HleKernel {
+        HleKernel {
+            heap: Heap::new(),
+            kernel_heap: layout::KERNEL_HEAP,
+            files: (0..MAX_FILES).map(|_| None).collect(),
+            last_error: 0,
+            custom_exit: 0,
+            clear_rcnt: 0xf,
+            pad_buffers: Vec::new(),
+            pads_started: false,
+            rand_seed: 0x24040001,
+            tty_line: String::new(),
+            card_search: None,
+        }
+    }
+
+    /// Returns true if `pc` is the entry of a trap in the HLE ROM.
+    /// Each trap is a `jr ra; nop` stub so only the first instruction
+    /// of each 8 byte slot triggers the kernel, otherwise the
+    /// function would run a second time from the delay slot. The
+    /// caller is also responsible for checking that the HLE BIOS is
+    /// in use.
+    pub fn is_trap(pc: u32) -> bool {
+        (pc & 0x1fffe000) == (layout::TRAP_START & 0x1fffe000) &&
+            pc % 8 == 0
+    }
+
+    /// Called by the CPU when it's about to execute the instruction at
+    /// trap address `pc`. Runs the corresponding kernel function.
+    /// Returns the address where the execution must continue if the
+    /// function doesn't return to its caller through the trap stub.
+    pub fn trap(&mut self,
+                pc: u32,
+                cpu: &mut Cpu,
+                shared: &mut SharedState,
+                renderer: &mut Renderer) -> Option<u32> {
+        // Normalize to KSEG1
+        let pc = (pc & 0x1fffffff) | 0xa0000000;
+
+        let mut ctx = Context {
+            cpu: cpu,
+            shared: shared,
+            renderer: renderer,
+        };
+
+        let outcome =
+            match pc {
+                layout::TRAP_INIT => self.init(&mut ctx),
+                layout::TRAP_BOOT => self.boot(&mut ctx),
+                layout::TRAP_EXCEPTION => self.exception(&mut ctx),
+                layout::TRAP_EXCEPTION_EXIT =>
+                    Outcome::Return(self.custom_exit),
+                _ => {
+                    let offset = pc - layout::TRAP_START;
+                    let function = (offset % 0x800) / 8;
+
+                    match offset / 0x800 {
+                        0 => self.call_a(&mut ctx, function),
+                        1 => self.call_b(&mut ctx, function),
+                        2 => self.call_c(&mut ctx, function),
+                        _ => {
+                            warn!("HLE BIOS: unknown trap 0x{:08x}", pc);
+                            Outcome::Void
+                        }
+                    }
+                }
+            };
+
+        match outcome {
+            Outcome::Return(v) => {
+                ctx.set_reg(2, v);
+                None
+            }
+            Outcome::Void => None,
+            Outcome::Jump(addr) => Some(addr),
+        }
+    }
+
+    /// Setup the RAM vectors and kernel structures, called by the
+    /// reset code
+    fn init(&mut self, ctx: &mut Context) -> Outcome {
+        *self = HleKernel::new();
+
+        let vectors = [
+            (layout::VECTOR_EXCEPTION,
+             rom::vector_stub(layout::EXCEPTION_HANDLER, K0)),
+            (layout::VECTOR_A,
+             rom::vector_stub(layout::DISPATCH_A, T2)),
+            (layout::VECTOR_B,
+             rom::vector_stub(layout::DISPATCH_B, T2)),
+            (layout::VECTOR_C,
+             rom::vector_stub(layout::DISPATCH_C, T2)),
+        ];
+
+        for &(addr, ref code) in &vectors {
+            ctx.write_bytes(addr, code);
+        }
+
+        let tables = [
+            (layout::TABLE_A,
+             layout::TABLE_A_LEN,
+             layout::trap_a as fn(u32) -> u32),
+            (layout::TABLE_B, layout::TABLE_B_LEN, layout::trap_b),
+            (layout::TABLE_C, layout::TABLE_C_LEN, layout::trap_c),
+        ];
+
+        for &(table, len, trap) in &tables {
+            for n in 0..len {
+                ctx.store::<Word>(table + n * 4, trap(n));
+            }
+        }
+
+        let tcb_len = layout::NUM_TCB * layout::TCB_SIZE;
+        let evcb_len = layout::NUM_EVCB * layout::EVCB_SIZE;
+
+        let tot = [
+            (layout::TOT_EXCB, layout::EXCB, 4 * 8),
+            (layout::TOT_PCB, layout::PCB, 4),
+            (layout::TOT_TCB, layout::TCB, tcb_len),
+            (layout::TOT_EVCB, layout::EVCB, evcb_len),
+        ];
+
+        for &(entry, addr, len) in &tot {
+            ctx.store::<Word>(entry, addr);
+            ctx.store::<Word>(entry + 4, len);
+        }
+
+        ctx.write_bytes(layout::EXCB, &[0; 4 * 8]);
+        ctx.write_bytes(layout::TCB, &vec![0; tcb_len as usize]);
+        ctx.write_bytes(layout::EVCB, &vec![0; evcb_len as usize]);
+
+        for i in 0..layout::NUM_TCB {
+            let status =
+                if i == 0 {
+                    TCB_USED
+                } else {
+                    TCB_FREE
+                };
+
+            ctx.store::<Word>(tcb(i), status);
+        }
+
+        ctx.store::<Word>(layout::PCB, tcb(0));
+        ctx.store::<Word>(layout::CALLBACK_LIST, 0);
+
+        ctx.cpu.flush_icache();
+
+        Outcome::Void
+    }
+
+    /// Boot the disc: load the executable referenced in SYSTEM.CNF
+    /// (or PSX.EXE if there's none) and run it
+    fn boot(&mut self, ctx: &mut Context) -> Outcome {
+        if ctx.disc_image().is_none() {
+            warn!("HLE BIOS: no disc, nothing to boot");
+            return Outcome::Void;
+        }
+
+        let mut boot = "cdrom:\\PSX.EXE;1".to_string();
+        let mut stack = layout::BOOT_STACK;
+
+        match self.read_file(ctx, "cdrom:\\SYSTEM.CNF;1", 0, 2048) {
+            Some(cnf) => {
+                let cnf = String::from_utf8_lossy(&cnf).into_owned();
+
+                for line in cnf.lines() {
+                    let mut kv = line.splitn(2, '=');
+
+                    let key = kv.next().unwrap_or("").trim();
+                    let value = kv.next().unwrap_or("").trim();
+
+                    match key {
+                        "BOOT" => boot = value.to_string(),
+                        "STACK" =>
+                            if let Ok(s) = u32::from_str_radix(value, 16) {
+                                stack = s;
+                            },
+                        // We have a fixed number of events and threads
+                        _ => (),
+                    }
+                }
+            }
+            None => warn!("HLE BIOS: no SYSTEM.CNF, trying PSX.EXE"),
+        }
+
+        info!("HLE BIOS: booting {}", boot);
+
+        let header = layout::EXEC_HEADER;
+
+        if !self.load_exe(ctx, &boot, header, true) {
+            warn!("HLE BIOS: couldn't load {}", boot);
+            return Outcome::Void;
+        }
+
+        ctx.store::<Word>(header + 0x20, stack);
+        ctx.store::<Word>(header + 0x24, 0);
+
+        self.do_execute(ctx, header, 1, 0)
+    }
+
+    /// Called by the ROM exception handler after saving the registers
+    /// in the current TCB. Returns 1 if the interrupt chains should be
+    /// run, 0 if we can return from the exception right away.
+    fn exception(&mut self, ctx: &mut Context) -> Outcome {
+        let regs = ctx.load::<Word>(layout::PCB) + layout::TCB_REGS;
+
+        let cause = ctx.load::<Word>(regs + layout::REGS_CAUSE);
+        let epc = ctx.load::<Word>(regs + layout::REGS_EPC);
+
+        match (cause >> 2) & 0x1f {
+            // Interrupt
+            0 => {
+                let mut callbacks = Vec::new();
+
+                self.handle_irq(ctx, &mut callbacks);
+                self.queue_callbacks(ctx, &callbacks);
+
+                Outcome::Return(1)
+            }
+            // Syscall
+            8 => {
+                self.syscall(ctx, regs);
+                Outcome::Return(0)
+            }
+            // Break
+            9 => {
+                warn!("HLE BIOS: break at 0x{:08x}", epc);
+                ctx.store::<Word>(regs + layout::REGS_EPC, epc + 4);
+                Outcome::Return(0)
+            }
+            code => {
+                fatal!(ctx.shared, Device::Cpu,
+                       "HLE BIOS: unhandled exception {} at 0x{:08x}",
+                       code, epc);
+                Outcome::Return(0)
+            }
+        }
+    }
+
+    fn syscall(&mut self, ctx: &mut Context, regs: u32) {
+        let reg = |r: u32| regs + r * 4;
+
+        let a0 = ctx.load::<Word>(reg(4));
+        let sr_addr = regs + layout::REGS_SR;
+        let sr = ctx.load::<Word>(sr_addr);
+
+        // Return to the instruction following the syscall
+        let epc = ctx.load::<Word>(regs + layout::REGS_EPC);
+        ctx.store::<Word>(regs + layout::REGS_EPC, epc + 4);
+
+        match a0 {
+            0 => (),
+            // EnterCriticalSection: mask the interrupts in the SR
+            // value restored by RFE
+            1 => {
+                let was_enabled = (sr & 0x404) == 0x404;
+
+                ctx.store::<Word>(sr_addr, sr & !0x404);
+                ctx.store::<Word>(reg(2), was_enabled as u32);
+            }
+            // ExitCriticalSection
+            2 => ctx.store::<Word>(sr_addr, sr | 0x404),
+            // ChangeThread: a1 is the new TCB. The ROM restores the
+            // registers from the new current thread.
+            3 => {
+                let new_tcb = ctx.load::<Word>(reg(5));
+
+                ctx.store::<Word>(reg(2), 1);
+                ctx.store::<Word>(layout::PCB, new_tcb);
+            }
+            _ => warn!("HLE BIOS: unknown syscall {}", a0),
+        }
+    }
+
+    /// Default interrupt handling: deliver the root counter events,
+    /// acknowledge the root counter interrupts if requested and poll
+    /// the gamepads
+    fn handle_irq(&mut self, ctx: &mut Context, callbacks: &mut Vec<u32>) {
+        let irq_state = *ctx.shared.irq_state();
+
+        let pending = irq_state.status() & irq_state.mask();
+
+        // (interrupt bit, root counter)
+        let rcnts = [(0, 3), (4, 0), (5, 1), (6, 2)];
+
+        for &(bit, rcnt) in &rcnts {
+            if pending & (1 << bit) == 0 {
+                continue;
+            }
+
+            self.deliver_event(ctx, 0xf2000000 | rcnt, EVSP_INT, callbacks);
+
+            if self.clear_rcnt & (1 << rcnt) != 0 {
+                ctx.shared.irq_state().ack(!(1 << bit));
+            }
+        }
+
+        if pending & 1 != 0 && self.pads_started {
+            self.poll_pads(ctx);
+        }
+    }
+
+    /// Write the list of callbacks for the DELIVER_CALLBACKS ROM
+    /// routine
+    fn queue_callbacks(&mut self, ctx: &mut Context, callbacks: &[u32]) {
+        if callbacks.len() > layout::MAX_CALLBACKS {
+            warn!("HLE BIOS: too many event callbacks ({})",
+                  callbacks.len());
+        }
+
+        let mut addr = layout::CALLBACK_LIST;
+
+        for &f in callbacks.iter().take(layout::MAX_CALLBACKS) {
+            ctx.store::<Word>(addr, f);
+            addr += 4;
+        }
+
+        ctx.store::<Word>(addr, 0);
+    }
+
+    /// Return from a function that triggered events, calling their
+    /// callbacks first if necessary
+    fn return_with_callbacks(&mut self,
+                             ctx: &mut Context,
+                             callbacks: &[u32]) -> Outcome {
+        if callbacks.is_empty() {
+            Outcome::Return(1)
+        } else {
+            self.queue_callbacks(ctx, callbacks);
+            ctx.set_reg(2, 1);
+            Outcome::Jump(layout::DELIVER_CALLBACKS)
+        }
+    }
+
+    /// Mark all the enabled events matching `class` and `spec` as
+    /// ready or add their callback to `callbacks` depending on their
+    /// mode
+    fn deliver_event(&mut self,
+                     ctx: &mut Context,
+                     class: u32,
+                     spec: u32,
+                     callbacks: &mut Vec<u32>) {
+        for i in 0..layout::NUM_EVCB {
+            let ev = evcb(i);
+
+            if ctx.load::<Word>(ev + EV_CLASS) != class ||
+                ctx.load::<Word>(ev + EV_SPEC) != spec ||
+                ctx.load::<Word>(ev + EV_STATUS) != EV_ENABLED {
+                continue;
+            }
+
+            match ctx.load::<Word>(ev + EV_MODE) {
+                EV_MODE_CALLBACK => {
+                    let func = ctx.load::<Word>(ev + EV_FUNC);
+
+                    if func != 0 {
+                        callbacks.push(func);
+                    }
+                }
+                _ => ctx.store::<Word>(ev + EV_STATUS, EV_READY),
+            }
+        }
+    }
+
+    /// Read the state of the gamepads into the buffers set by InitPad
+    fn poll_pads(&mut self, ctx: &mut Context) {
+        let buffers = self.pad_buffers.clone();
+
+        for (port, &(addr, len)) in buffers.iter().enumerate() {
+            if addr == 0 || len == 0 {
+                continue;
+            }
+
+            let mut reply = Vec::new();
+
+            {
+                let mut pads = ctx.cpu.interconnect_mut()
+                    .pad_memcard_mut()
+                    .gamepads_mut();
+
+                let pad = &mut *pads[port];
+
+                pad.select();
+
+                let (_, mut dsr) = pad.send_command(0x01);
+
+                if dsr {
+                    // Read buttons command
+                    let mut cmd = 0x42;
+
+                    while dsr {
+                        let (r, d) = pad.send_command(cmd);
+
+                        reply.push(r);
+                        dsr = d;
+                        cmd = 0;
+                    }
+                }
+            }
+
+            // First byte is the status, 0xff if nothing answered.
+            // Then the pad ID (without the 0x5a) and the data.
+            let mut buf = Vec::with_capacity(reply.len() + 1);
+
+            if reply.len() < 2 {
+                buf.push(0xff);
+            } else {
+                buf.push(0x00);
+                buf.push(reply[0]);
+                buf.extend_from_slice(&reply[2..]);
+            }
+
+            buf.truncate(len as usize);
+
+            ctx.write_bytes(addr, &buf);
+        }
+    }
+
+    fn open_event(&mut self,
+                  ctx: &mut Context,
+                  class: u32,
+                  spec: u32,
+                  mode: u32,
+                  func: u32) -> u32 {
+        for i in 0..layout::NUM_EVCB {
+            let ev = evcb(i);
+
+            if ctx.load::<Word>(ev + EV_STATUS) == EV_FREE {
+                ctx.store::<Word>(ev + EV_CLASS, class);
+                ctx.store::<Word>(ev + EV_STATUS, EV_DISABLED);
+                ctx.store::<Word>(ev + EV_SPEC, spec);
+                ctx.store::<Word>(ev + EV_MODE, mode);
+                ctx.store::<Word>(ev + EV_FUNC, func);
+
+                return 0xf1000000 | i;
+            }
+        }
+
+        warn!("HLE BIOS: out of events");
+
+        !0
+    }
+
+    /// Return the address of the EvCB for `handle` or `None` if the
+    /// handle is invalid
+    fn event(&self, handle: u32) -> Option<u32> {
+        let i = handle & 0xffff;
+
+        if handle & 0xffff0000 == 0xf1000000 && i < layout::NUM_EVCB {
+            Some(evcb(i))
+        } else {
+            None
+        }
+    }
+
+    fn event_call(&mut self,
+                  ctx: &mut Context,
+                  function: u32,
+                  handle: u32) -> Outcome {
+        let ev =
+            match self.event(handle) {
+                Some(ev) => ev,
+                None => return Outcome::Return(0),
+            };
+
+        let status = ctx.load::<Word>(ev + EV_STATUS);
+
+        match function {
+            // CloseEvent
+            0x09 => ctx.store::<Word>(ev + EV_STATUS, EV_FREE),
+            // WaitEvent
+            0x0a => match status {
+                EV_READY => ctx.store::<Word>(ev + EV_STATUS, EV_ENABLED),
+                // Loop until an interrupt delivers the event
+                EV_ENABLED => return Outcome::Jump(layout::WAIT_EVENT),
+                _ => return Outcome::Return(0),
+            },
+            // TestEvent
+            0x0b => match status {
+                EV_READY => ctx.store::<Word>(ev + EV_STATUS, EV_ENABLED),
+                _ => return Outcome::Return(0),
+            },
+            // EnableEvent
+            0x0c => if status != EV_FREE {
+                ctx.store::<Word>(ev + EV_STATUS, EV_ENABLED);
+            },
+            // DisableEvent
+            0x0d => if status != EV_FREE {
+                ctx.store::<Word>(ev + EV_STATUS, EV_DISABLED);
+            },
+            _ => unreachable!(),
+        }
+
+        Outcome::Return(1)
+    }
+
+    fn open_thread(&mut self,
+                   ctx: &mut Context,
+                   pc: u32,
+                   sp: u32,
+                   gp: u32) -> u32 {
+        for i in 0..layout::NUM_TCB {
+            let t = tcb(i);
+
+            if ctx.load::<Word>(t) != TCB_FREE {
+                continue;
+            }
+
+            let regs = t + layout::TCB_REGS;
+
+            ctx.write_bytes(regs, &[0; 0x94]);
+
+            ctx.store::<Word>(t, TCB_USED);
+            ctx.store::<Word>(regs + 28 * 4, gp);
+            ctx.store::<Word>(regs + 29 * 4, sp);
+            ctx.store::<Word>(regs + 30 * 4, sp);
+            ctx.store::<Word>(regs + layout::REGS_EPC, pc);
+            // Interrupts enabled after RFE
+            ctx.store::<Word>(regs + layout::REGS_SR, 0x404);
+
+            return 0xff000000 | i;
+        }
+
+        warn!("HLE BIOS: out of threads");
+
+        !0
+    }
+
+    /// Return the address of the TCB for `handle` or `None` if the
+    /// handle is invalid
+    fn thread(&self, handle: u32) -> Option<u32> {
+        let i = handle & 0xffff;
+
+        if handle & 0xffff0000 == 0xff000000 && i < layout::NUM_TCB {
+            Some(tcb(i))
+        } else {
+            None
+        }
+    }
+
+    fn putchar(&mut self, c: u8) {
+        match c {
+            b'\n' => {
+                info!("TTY: {}", self.tty_line);
+                self.tty_line.clear();
+            }
+            b'\r' => (),
+            _ => self.tty_line.push(c as char),
+        }
+    }
+
+    fn puts(&mut self, s: &str) {
+        for b in s.bytes() {
+            self.putchar(b);
+        }
+    }
+
+    /// Read `len` bytes at `offset` in the file at `path` (including
+    /// the device name). Returns `None` if the file can't be read.
+    fn read_file(&mut self,
+                 ctx: &mut Context,
+                 path: &str,
+                 offset: u32,
+                 len: u32) -> Option<Vec<u8>> {
+        let extent =
+            match self.lookup_file(ctx, path) {
+                Ok(e) => e,
+                Err(_) => return None,
+            };
+
+        let image =
+            match ctx.disc_image() {
+                Some(i) => i,
+                None => return None,
+            };
+
+        match cdfs::read(image, extent, offset, len) {
+            Ok(d) => Some(d),
+            Err(e) => {
+                warn!("HLE BIOS: can't read {}: {:?}", path, e);
+                None
+            }
+        }
+    }
+
+    /// Find the file `path` on the disc, returns an error code
+    /// suitable for GetLastError on failure.
+    fn lookup_file(&mut self,
+                   ctx: &mut Context,
+                   path: &str) -> Result<FileExtent, u32> {
+        let mut split = path.splitn(2, ':');
+
+        let device = split.next().unwrap_or("").to_lowercase();
+        let path =
+            match split.next() {
+                Some(p) => p,
+                None => return Err(ENODEV),
+            };
+
+        if !device.starts_with("cdrom") {
+            // Memory cards are handled by `file_open`
+            return Err(ENODEV);
+        }
+
+        let image =
+            match ctx.disc_image() {
+                Some(i) => i,
+                None => return Err(EBUSY),
+            };
+
+        cdfs::lookup(image, path).map_err(|_| ENOENT)
+    }
+
+    /// Open the file at `path`. For memory cards bit 9 of `mode`
+    /// creates the file, in which case bits [31:16] contain its size
+    /// in blocks.
+    fn file_open(&mut self, ctx: &mut Context, path: u32, mode: u32) -> u32 {
+        let path = ctx.read_string(path);
+
+        let slot =
+            match self.files.iter().position(|f| f.is_none()) {
+                Some(s) => s,
+                None => {
+                    self.last_error = EMFILE;
+                    return !0;
+                }
+            };
+
+        let location =
+            match memory_card_path(&path) {
+                Some((port, name)) =>
+                    card_open(ctx, port, name, mode)
+                    .map(|f| FileLocation::MemoryCard(port, f)),
+                None =>
+                    self.lookup_file(ctx, &path).map(FileLocation::Cdrom),
+            };
+
+        match location {
+            Ok(location) => {
+                self.files[slot] = Some(OpenFile {
+                    location: location,
+                    pos: 0,
+                });
+
+                (slot as u32) + FIRST_FD
+            }
+            Err(e) => {
+                debug!("HLE BIOS: can't open {}", path);
+                self.last_error = e;
+                !0
+            }
+        }
+    }
+
+    fn file(&mut self, fd: u32) -> Option<&mut OpenFile> {
+        match fd.checked_sub(FIRST_FD) {
+            Some(i) => match self.files.get_mut(i as usize) {
+                Some(&mut Some(ref mut f)) => Some(f),
+                _ => None,
+            },
+            None => None,
+        }
+    }
+
+    fn file_read(&mut self,
+                 ctx: &mut Context,
+                 fd: u32,
+                 dst: u32,
+                 len: u32) -> u32 {
+        let (location, pos) =
+            match self.file(fd) {
+                Some(f) => (f.location, f.pos),
+                None => {
+                    self.last_error = EBADF;
+                    return !0;
+                }
+            };
+
+        let data =
+            match location {
+                FileLocation::Cdrom(extent) => match ctx.disc_image() {
+                    Some(image) =>
+                        cdfs::read(image, extent, pos, len).map_err(|e| {
+                            warn!("HLE BIOS: CD read error: {:?}", e);
+                            EIO
+                        }),
+                    None => Err(EBUSY),
+                },
+                FileLocation::MemoryCard(port, file) =>
+                    match ctx.memory_card(port) {
+                        Some(card) => Ok(bu::read(card, file, pos, len)),
+                        None => Err(EBUSY),
+                    },
+            };
+
+        match data {
+            Ok(data) => {
+                ctx.write_bytes(dst, &data);
+
+                self.file(fd).unwrap().pos += data.len() as u32;
+
+                data.len() as u32
+            }
+            Err(e) => {
+                self.last_error = e;
+                !0
+            }
+        }
+    }
+
+    fn file_write(&mut self,
+                  ctx: &mut Context,
+                  fd: u32,
+                  src: u32,
+                  len: u32) -> u32 {
+        if fd == STDOUT {
+            // The length comes straight from the guest, a bogus value
+            // would stall the emulator
+            let len = ::std::cmp::min(len, MAX_TTY_WRITE);
+
+            for i in 0..len {
+                let b = ctx.load::<Byte>(src.wrapping_add(i)) as u8;
+
+                self.putchar(b);
+            }
+
+            return len;
+        }
+
+        let (location, pos) =
+            match self.file(fd) {
+                Some(f) => (f.location, f.pos),
+                None => {
+                    self.last_error = EBADF;
+                    return !0;
+                }
+            };
+
+        let (port, file) =
+            match location {
+                FileLocation::MemoryCard(port, file) => (port, file),
+                // The CD is read-only
+                FileLocation::Cdrom(_) => {
+                    self.last_error = EBADF;
+                    return !0;
+                }
+            };
+
+        // Writes are truncated at the end of the file which also
+        // bounds the length
+        let len = ::std::cmp::min(len, file.size.saturating_sub(pos));
+
+        let data = ctx.read_bytes(src, len);
+
+        let written =
+            match ctx.memory_card(port) {
+                Some(card) => bu::write(card, file, pos, &data),
+                None => {
+                    self.last_error = EBUSY;
+                    return !0;
+                }
+            };
+
+        self.file(fd).unwrap().pos += written;
+
+        written
+    }
+
+    fn file_seek(&mut self, fd: u32, offset: u32, whence: u32) -> u32 {
+        let f =
+            match self.file(fd) {
+                Some(f) => f,
+                None => return !0,
+            };
+
+        match whence {
+            0 => f.pos = offset,
+            1 => f.pos = f.pos.wrapping_add(offset),
+            _ => return !0,
+        }
+
+        f.pos
+    }
+
+    fn file_close(&mut self, fd: u32) -> u32 {
+        match fd.checked_sub(FIRST_FD) {
+            Some(i) if (i as usize) < self.files.len() => {
+                self.files[i as usize] = None;
+                fd
+            }
+            _ => {
+                self.last_error = EBADF;
+                !0
+            }
+        }
+    }
+
+    /// Load the executable at `path`, copy its header to `header` and
+    /// optionally load its code into RAM
+    fn load_exe(&mut self,
+                ctx: &mut Context,
+                path: &str,
+                header: u32,
+                load_text: bool) -> bool {
+        let head =
+            match self.read_file(ctx, path, 0, 0x800) {
+                Some(h) => h,
+                None => return false,
+            };
+
+        if head.len() < 0x800 || &head[0..8] != b"PS-X EXE" {
+            warn!("HLE BIOS: {} is not a PS-X EXE", path);
+            return false;
+        }
+
+        // The BIOS header structure starts with the entry point
+        ctx.write_bytes(header, &head[0x10..0x4c]);
+
+        if !load_text {
+            return true;
+        }
+
+        let t_addr = ctx.load::<Word>(header + 0x08);
+        let t_size = ctx.load::<Word>(header + 0x0c);
+
+        let text =
+            match self.read_file(ctx, path, 0x800, t_size) {
+                Some(t) => t,
+                None => return false,
+            };
+
+        ctx.write_bytes(t_addr, &text);
+        ctx.cpu.flush_icache();
+
+        true
+    }
+
+    /// Start the executable whose header is at `header`. Like the
+    /// real BIOS we save the caller's registers in the header so
+    /// that the executable can return.
+    fn do_execute(&mut self,
+                  ctx: &mut Context,
+                  header: u32,
+                  arg0: u32,
+                  arg1: u32) -> Outcome {
+        let pc = ctx.load::<Word>(header + 0x00);
+        let gp = ctx.load::<Word>(header + 0x04);
+        let b_addr = ctx.load::<Word>(header + 0x18);
+        let b_size = ctx.load::<Word>(header + 0x1c);
+        let s_addr = ctx.load::<Word>(header + 0x20);
+        let s_size = ctx.load::<Word>(header + 0x24);
+
+        libc::memset(ctx, b_addr, 0, b_size);
+
+        let saved = [(0x28, 29), (0x2c, 30), (0x30, 28), (0x34, 31), (0x38, 16)];
+
+        for &(offset, r) in &saved {
+            let v = ctx.reg(r);
+
+            ctx.store::<Word>(header + offset, v);
+        }
+
+        if s_addr != 0 {
+            let sp = s_addr.wrapping_add(s_size);
+
+            ctx.set_reg(29, sp);
+            ctx.set_reg(30, sp);
+        }
+
+        ctx.set_reg(28, gp);
+        ctx.set_reg(4, arg0);
+        ctx.set_reg(5, arg1);
+        ctx.set_reg(16, header);
+        ctx.set_reg(31, layout::EXEC_RETURN);
+
+        Outcome::Jump(pc)
+    }
+
+    /// Write `data` to the GPU GP0 port
+    fn gpu_send(&mut self, ctx: &mut Context, data: &[u32]) {
+        for &w in data {
+            ctx.store::<Word>(map::GPU.0, w);
+        }
+    }
+
+    /// Deliver the events signaling the end of an asynchronous memory
+    /// card operation: EvSpIOE if `ok` is true, EvSpTIMOUT otherwise
+    /// (like when no card is present).
+    fn card_events(&mut self, ctx: &mut Context, ok: bool) -> Outcome {
+        let spec = if ok { 0x0004 } else { 0x0100 };
+
+        let mut callbacks = Vec::new();
+
+        // HwCARD and SwCARD
+        self.deliver_event(ctx, 0xf0000011, spec, &mut callbacks);
+        self.deliver_event(ctx, 0xf4000001, spec, &mut callbacks);
+
+        self.return_with_callbacks(ctx, &callbacks)
+    }
+
+    /// Continue the memory card search started by firstfile: fill
+    /// `direntry` with the next matching file. Returns `direntry` or
+    /// 0 if there are no more files.
+    fn next_file(&mut self, ctx: &mut Context, direntry: u32) -> u32 {
+        let (port, pattern, block) =
+            match self.card_search {
+                Some(ref s) => (s.port, s.pattern.clone(), s.block),
+                None => return 0,
+            };
+
+        let entry =
+            match ctx.memory_card(port) {
+                Some(card) => bu::find(card, &pattern, block),
+                None => None,
+            };
+
+        let entry =
+            match entry {
+                Some(e) => e,
+                None => {
+                    self.card_search = None;
+                    return 0;
+                }
+            };
+
+        self.card_search = Some(CardSearch {
+            port: port,
+            pattern: pattern,
+            block: entry.file.block + 1,
+        });
+
+        let mut name = [0u8; 20];
+
+        for (d, c) in name.iter_mut().zip(entry.name.chars()) {
+            *d = c as u8;
+        }
+
+        ctx.write_bytes(direntry, &name);
+        // Attributes
+        ctx.store::<Word>(direntry + 0x14, 0x50);
+        ctx.store::<Word>(direntry + 0x18, entry.file.size);
+        ctx.store::<Word>(direntry + 0x1c, 0);
+        ctx.store::<Word>(direntry + 0x20, entry.file.first_frame());
+        ctx.store::<Word>(direntry + 0x24, 0);
+
+        direntry
+    }
+
+    fn unimplemented(&mut self, table: char, function: u32) -> Outcome {
+        let name =
+            tracer::function_name(table, function).unwrap_or("<unknown>");
+
+        warn!("HLE BIOS: unimplemented function {}({:02X}h) {}",
+              table, function, name);
+
+        Outcome::Return(0)
+    }
+
+    fn call_a(&mut self, ctx: &mut Context, function: u32) -> Outcome {
+        let a0 = ctx.reg(4);
+        let a1 = ctx.reg(5);
+        let a2 = ctx.reg(6);
+
+        let v =
+            match function {
+                0x00 => self.file_open(ctx, a0, a1),
+                0x01 => self.file_seek(a0, a1, a2),
+                0x02 => self.file_read(ctx, a0, a1, a2),
+                0x03 => self.file_write(ctx, a0, a1, a2),
+                0x04 => self.file_close(a0),
+                0x06 => {
+                    info!("HLE BIOS: exit({})", a0 as i32);
+                    return Outcome::Jump(layout::IDLE);
+                }
+                0x07 => 0,
+                0x08 => !0,
+                0x09 => {
+                    if a1 == STDOUT {
+                        self.putchar(a0 as u8);
+                    }
+                    a0
+                }
+                0x0a => match (a0 as u8 as char).to_digit(36) {
+                    Some(d) => d,
+                    None => 9999999,
+                },
+                0x0c | 0x0d => libc::strtol(ctx, a0, a1, a2),
+                0x0e | 0x0f => (a0 as i32).wrapping_abs() as u32,
+                0x10 | 0x11 => libc::strtol(ctx, a0, 0, 10),
+                // SaveState (setjmp)
+                0x13 => {
+                    for (i, &r) in JMP_BUF_REGS.iter().enumerate() {
+                        let v = ctx.reg(r);
+
+                        ctx.store::<Word>(a0 + i as u32 * 4, v);
+                    }
+                    0
+                }
+                // RestoreState (longjmp)
+                0x14 => {
+                    for (i, &r) in JMP_BUF_REGS.iter().enumerate() {
+                        let v = ctx.load::<Word>(a0 + i as u32 * 4);
+
+                        ctx.set_reg(r, v);
+                    }
+
+                    ctx.set_reg(2, a1);
+
+                    return Outcome::Jump(ctx.reg(31));
+                }
+                // strcat
+                0x15 => {
+                    let len = libc::strlen(ctx, a0);
+
+                    libc::strncpy(ctx, a0 + len, a1, !0);
+                    a0
+                }
+                // strncat
+                0x16 => {
+                    let len = libc::strlen(ctx, a0);
+
+                    libc::strncpy(ctx, a0 + len, a1, a2);
+                    ctx.store::<Byte>(a0 + len + a2, 0);
+                    a0
+                }
+                0x17 => libc::strncmp(ctx, a0, a1, !0),
+                0x18 => libc::strncmp(ctx, a0, a1, a2),
+                0x19 => {
+                    libc::strncpy(ctx, a0, a1, !0);
+                    a0
+                }
+                0x1a => {
+                    libc::strncpy(ctx, a0, a1, a2);
+                    a0
+                }
+                0x1b => libc::strlen(ctx, a0),
+                0x1c | 0x1e => libc::strchr(ctx, a0, a1, false),
+                0x1d | 0x1f => libc::strchr(ctx, a0, a1, true),
+                0x20 => libc::strpbrk(ctx, a0, a1),
+                0x21 => libc::strspn(ctx, a0, a1, true),
+                0x22 => libc::strspn(ctx, a0, a1, false),
+                0x24 => libc::strstr(ctx, a0, a1),
+                0x25 => libc::toupper(a0),
+                0x26 => libc::tolower(a0),
+                // bcopy(src, dst, len)
+                0x27 => {
+                    libc::memcpy(ctx, a1, a0, a2);
+                    a1
+                }
+                // bzero
+                0x28 => {
+                    libc::memset(ctx, a0, 0, a1);
+                    a0
+                }
+                0x29 | 0x2d => libc::memcmp(ctx, a0, a1, a2),
+                0x2a | 0x2c => {
+                    libc::memcpy(ctx, a0, a1, a2);
+                    a0
+                }
+                0x2b => {
+                    libc::memset(ctx, a0, a1, a2);
+                    a0
+                }
+                0x2e => libc::memchr(ctx, a0, a1, a2),
+                0x2f => {
+                    self.rand_seed =
+                        self.rand_seed.wrapping_mul(0x41c64e6d)
+                        .wrapping_add(0x3039);
+
+                    (self.rand_seed >> 16) & 0x7fff
+                }
+                0x30 => {
+                    self.rand_seed = a0;
+                    0
+                }
+                0x33 => self.heap.alloc(a0),
+                0x34 => {
+                    self.heap.free(a0);
+                    0
+                }
+                // calloc
+                0x37 => {
+                    let size = a0.wrapping_mul(a1);
+                    let p = self.heap.alloc(size);
+
+                    if p != 0 {
+                        libc::memset(ctx, p, 0, size);
+                    }
+                    p
+                }
+                // realloc
+                0x38 => {
+                    let p = self.heap.alloc(a1);
+
+                    if p != 0 && a0 != 0 {
+                        let old = self.heap.free(a0).unwrap_or(0);
+
+                        libc::memcpy(ctx, p, a0, ::std::cmp::min(old, a1));
+                    }
+                    p
+                }
+                0x39 => {
+                    self.heap.init(a0, a1);
+                    0
+                }
+                0x3a | 0x40 | 0xa1 => {
+                    warn!("HLE BIOS: system error {:02X}h ({:x}, {:x})",
+                          function, a0, a1);
+                    return Outcome::Jump(layout::IDLE);
+                }
+                // std_in_getchar/std_in_gets: no TTY input
+                0x3b | 0x3d => 0,
+                0x3c => {
+                    self.putchar(a0 as u8);
+                    a0
+                }
+                0x3e => {
+                    let s = ctx.read_string(a0);
+
+                    self.puts(&s);
+                    self.putchar(b'\n');
+                    0
+                }
+                0x3f => {
+                    let s = libc::format(ctx, a0, 1);
+
+                    self.puts(&s);
+                    s.len() as u32
+                }
+                0x41 | 0x42 => {
+                    let path = ctx.read_string(a0);
+
+                    self.load_exe(ctx, &path, a1, function == 0x42) as u32
+                }
+                0x43 => return self.do_execute(ctx, a0, a1, a2),
+                0x44 => {
+                    ctx.cpu.flush_icache();
+                    0
+                }
+                // GPU_dw(x, y, w, h, src)
+                0x46 | 0x47 => {
+                    let a3 = ctx.reg(7);
+                    let src = ctx.arg(4);
+                    let words = (a2 * a3 + 1) / 2;
+
+                    self.gpu_send(ctx, &[0xa0000000,
+                                         (a1 << 16) | (a0 & 0xffff),
+                                         (a3 << 16) | (a2 & 0xffff)]);
+
+                    for i in 0..words {
+                        let w = ctx.load::<Word>(src + i * 4);
+
+                        self.gpu_send(ctx, &[w]);
+                    }
+                    0
+                }
+                0x48 => {
+                    ctx.store::<Word>(map::GPU.0 + 4, a0);
+                    0
+                }
+                0x49 => {
+                    self.gpu_send(ctx, &[a0]);
+                    0
+                }
+                0x4a => {
+                    for i in 0..a1 {
+                        let w = ctx.load::<Word>(a0 + i * 4);
+
+                        self.gpu_send(ctx, &[w]);
+                    }
+                    0
+                }
+                // send_gpu_linked_list
+                0x4b => {
+                    let mut node = a0 & 0xffffff;
+                    // Guard against lists that loop
+                    let mut max = 0x10000;
+
+                    while node != 0xffffff && max > 0 {
+                        let header = ctx.load::<Word>(node);
+
+                        for i in 0..(header >> 24) {
+                            let w = ctx.load::<Word>(node + 4 + i * 4);
+
+                            self.gpu_send(ctx, &[w]);
+                        }
+
+                        node = header & 0xffffff;
+                        max -= 1;
+                    }
+                    0
+                }
+                0x4c => 0,
+                0x4d => ctx.load::<Word>(map::GPU.0 + 4),
+                0x4e => 0,
+                // LoadAndExecute(path, stack base, stack offset)
+                0x51 => {
+                    let path = ctx.read_string(a0);
+                    let header = layout::EXEC_HEADER;
+
+                    if !self.load_exe(ctx, &path, header, true) {
+                        return Outcome::Return(0);
+                    }
+
+                    ctx.store::<Word>(header + 0x20, a1);
+                    ctx.store::<Word>(header + 0x24, a2);
+
+                    return self.do_execute(ctx, header, 1, 0);
+                }
+                0x52 => layout::KERNEL_STACK,
+                // CdInit
+                0x54 | 0x71 => 1,
+                // _bu_init, CdRemove
+                0x55 | 0x56 | 0x70 | 0x72 => 0,
+                // SetMemSize
+                0x9f => 0,
+                0xa0 => return Outcome::Jump(layout::ROM_BASE),
+                // _card_info, _card_async_load_directory
+                0xab | 0xac => {
+                    let present = ctx.memory_card(card_port(a0)).is_some();
+
+                    return self.card_events(ctx, present);
+                }
+                // GetSystemInfo: only the kernel date is supported
+                0xb4 => match a0 {
+                    0 => 0x19951204,
+                    _ => 0,
+                },
+                _ => return self.unimplemented('A', function),
+            };
+
+        Outcome::Return(v)
+    }
+
+    fn call_b(&mut self, ctx: &mut Context, function: u32) -> Outcome {
+        let a0 = ctx.reg(4);
+        let a1 = ctx.reg(5);
+        let a2 = ctx.reg(6);
+        let a3 = ctx.reg(7);
+
+        let timer = map::TIMERS.0 + (a0 & 3) * 0x10;
+        let i_mask = map::IRQ_CONTROL.0 + 4;
+        let irq_bit = if a0 & 3 == 3 { 1 } else { 0x10 << (a0 & 3) };
+
+        let v =
+            match function {
+                0x00 => {
+                    let size = (a0 + 3) & !3;
+                    let p = self.kernel_heap;
+
+                    if p + size > layout::KERNEL_HEAP_END {
+                        0
+                    } else {
+                        self.kernel_heap += size;
+                        p
+                    }
+                }
+                0x01 => 0,
+                // init_timer(t, reload, flags)
+                0x02 => {
+                    if a0 & 3 != 3 {
+                        let mut mode = 0;
+
+                        if a2 & 0x1000 != 0 {
+                            // IRQ on target, repeated
+                            mode |= 0x50;
+                        }
+                        if a2 & 0x0100 != 0 {
+                            // Reset counter at target
+                            mode |= 0x08;
+                        }
+                        if a2 & 0x0010 != 0 {
+                            mode |= 0x01;
+                        }
+                        if a2 & 0x0001 != 0 {
+                            mode |= if a0 & 3 == 2 { 0x200 } else { 0x100 };
+                        }
+
+                        ctx.store::<HalfWord>(timer + 8, a1);
+                        ctx.store::<HalfWord>(timer + 4, mode);
+                    }
+                    1
+                }
+                0x03 => ctx.load::<HalfWord>(timer),
+                0x04 => {
+                    let m = ctx.load::<Word>(i_mask);
+
+                    ctx.store::<Word>(i_mask, m | irq_bit);
+                    1
+                }
+                0x05 => {
+                    let m = ctx.load::<Word>(i_mask);
+
+                    ctx.store::<Word>(i_mask, m & !irq_bit);
+                    1
+                }
+                0x06 => {
+                    if a0 & 3 != 3 {
+                        ctx.store::<HalfWord>(timer, 0);
+                    }
+                    1
+                }
+                0x07 => {
+                    let mut callbacks = Vec::new();
+
+                    self.deliver_event(ctx, a0, a1, &mut callbacks);
+
+                    return self.return_with_callbacks(ctx, &callbacks);
+                }
+                0x08 => self.open_event(ctx, a0, a1, a2, a3),
+                0x09..=0x0d => return self.event_call(ctx, function, a0),
+                0x0e => self.open_thread(ctx, a0, a1, a2),
+                0x0f => match self.thread(a0) {
+                    Some(t) => {
+                        ctx.store::<Word>(t, TCB_FREE);
+                        1
+                    }
+                    None => 0,
+                },
+                // ChangeThread: done through a syscall so that the
+                // registers are saved in the current TCB
+                0x10 => match self.thread(a0) {
+                    Some(t) if ctx.load::<Word>(t) == TCB_USED => {
+                        ctx.set_reg(4, 3);
+                        ctx.set_reg(5, t);
+
+                        return Outcome::Jump(layout::SYSCALL);
+                    }
+                    _ => !0,
+                },
+                // InitPad(buf1, siz1, buf2, siz2)
+                0x12 => {
+                    self.pad_buffers = vec![(a0, a1), (a2, a3)];
+
+                    for &(addr, len) in &[(a0, a1), (a2, a3)] {
+                        if addr != 0 {
+                            libc::memset(ctx, addr, 0xff, len);
+                        }
+                    }
+                    2
+                }
+                0x13 => {
+                    self.pads_started = true;
+
+                    let m = ctx.load::<Word>(i_mask);
+
+                    ctx.store::<Word>(i_mask, m | 1);
+                    1
+                }
+                0x14 => {
+                    self.pads_started = false;
+                    1
+                }
+                0x17 => return Outcome::Jump(layout::RETURN_FROM_EXCEPTION),
+                0x18 => {
+                    self.custom_exit = 0;
+                    0
+                }
+                0x19 => {
+                    self.custom_exit = a0;
+                    0
+                }
+                // UnDeliverEvent
+                0x20 => {
+                    for i in 0..layout::NUM_EVCB {
+                        let ev = evcb(i);
+
+                        if ctx.load::<Word>(ev + EV_CLASS) == a0 &&
+                            ctx.load::<Word>(ev + EV_SPEC) == a1 &&
+                            ctx.load::<Word>(ev + EV_STATUS) == EV_READY {
+                            ctx.store::<Word>(ev + EV_STATUS, EV_ENABLED);
+                        }
+                    }
+                    0
+                }
+                // File functions, same as A(00h)...A(09h)
+                0x32..=0x3b => return self.call_a(ctx, function - 0x32),
+                0x3c | 0x3e => 0,
+                0x3d => {
+                    self.putchar(a0 as u8);
+                    a0
+                }
+                0x3f => {
+                    let s = ctx.read_string(a0);
+
+                    self.puts(&s);
+                    self.putchar(b'\n');
+                    0
+                }
+                // firstfile(pattern, direntry)
+                0x42 => {
+                    let pattern = ctx.read_string(a0);
+
+                    self.card_search =
+                        memory_card_path(&pattern).map(|(port, p)| CardSearch {
+                            port: port,
+                            pattern: p.to_string(),
+                            block: 0,
+                        });
+
+                    if self.card_search.is_none() {
+                        debug!("HLE BIOS: can't list {}", pattern);
+                    }
+
+                    self.next_file(ctx, a1)
+                }
+                // nextfile(direntry)
+                0x43 => self.next_file(ctx, a0),
+                // InitCard, StartCard, StopCard
+                0x4a..=0x4c => 1,
+                // _card_info_subfunc
+                0x4d => {
+                    let present = ctx.memory_card(card_port(a0)).is_some();
+
+                    return self.card_events(ctx, present);
+                }
+                // write_card_sector(port, sector, src)
+                0x4e => {
+                    let ok = card_write(ctx, card_port(a0), a1, a2);
+
+                    return self.card_events(ctx, ok);
+                }
+                // read_card_sector(port, sector, dst)
+                0x4f => {
+                    let ok = card_read(ctx, card_port(a0), a1, a2);
+
+                    return self.card_events(ctx, ok);
+                }
+                0x50 => 0,
+                0x54 => self.last_error,
+                0x55 => self.last_error,
+                0x56 => layout::TABLE_C,
+                0x57 => layout::TABLE_B,
+                0x5b => 0,
+                // get_card_status: ready if there's a card
+                0x5c => match ctx.memory_card(card_port(a0)) {
+                    Some(_) => 1,
+                    None => 0,
+                },
+                _ => return self.unimplemented('B', function),
+            };
+
+        Outcome::Return(v)
+    }
+
+    fn call_c(&mut self, ctx: &mut Context, function: u32) -> Outcome {
+        let a0 = ctx.reg(4);
+        let a1 = ctx.reg(5);
+
+        let v =
+            match function {
+                // The timer, vblank and syscall handlers are built into
+                // the kernel
+                0x00 | 0x01 => 0,
+                // SysEnqIntRP(priority, entry)
+                0x02 => {
+                    let head = layout::EXCB + (a0 & 3) * 8;
+                    let first = ctx.load::<Word>(head);
+
+                    ctx.store::<Word>(a1, first);
+                    ctx.store::<Word>(head, a1);
+                    0
+                }
+                // SysDeqIntRP(priority, entry)
+                0x03 => {
+                    let mut link = layout::EXCB + (a0 & 3) * 8;
+                    // Guard against loops
+                    let mut max = 0x100;
+
+                    loop {
+                        let entry = ctx.load::<Word>(link);
+
+                        if entry == 0 || max == 0 {
+                            break;
+                        }
+
+                        if entry == a1 {
+                            let next = ctx.load::<Word>(entry);
+
+                            ctx.store::<Word>(link, next);
+                            break;
+                        }
+
+                        link = entry;
+                        max -= 1;
+                    }
+                    0
+                }
+                0x04 => (0..layout::NUM_EVCB)
+                    .find(|&i| ctx.load::<Word>(evcb(i) + EV_STATUS) == EV_FREE)
+                    .unwrap_or(!0),
+                0x05 => (0..layout::NUM_TCB)
+                    .find(|&i| ctx.load::<Word>(tcb(i)) == TCB_FREE)
+                    .unwrap_or(!0),
+                0x07 | 0x09 | 0x0c | 0x0d | 0x12 | 0x1c => 0,
+                // SysInitMemory(addr, size)
+                0x08 => {
+                    self.kernel_heap = a0;
+                    0
+                }
+                // ChangeClearRCnt(t, flag): returns the old value
+                0x0a => {
+                    let bit = 1 << (a0 & 3);
+                    let old = (self.clear_rcnt & bit != 0) as u32;
+
+                    if a1 != 0 {
+                        self.clear_rcnt |= bit;
+                    } else {
+                        self.clear_rcnt &= !bit;
+                    }
+                    old
+                }
+                _ => return self.unimplemented('C', function),
+            };
+
+        Outcome::Return(v)
+    }
+}
+
+/// Result of a kernel function
+enum Outcome {
+    /// Return to the caller with the given value in V0
+    Return(u32),
+    /// Return to the caller without touching V0
+    Void,
+    /// Continue execution at the given address
+    Jump(u32),
+}
+
+#[derive(RustcDecodable, RustcEncodable)]
+struct OpenFile {
+    location: FileLocation,
+    /// Current position in the file
+    pos: u32,
+}
+
+#[derive(Clone, Copy, RustcDecodable, RustcEncodable)]
+enum FileLocation {
+    /// File on the disc
+    Cdrom(FileExtent),
+    /// File on the memory card in the given slot
+    MemoryCard(usize, CardFile),
+}
+
+/// State of the memory card search for firstfile/nextfile
+#[derive(RustcDecodable, RustcEncodable)]
+struct CardSearch {
+    /// Memory card slot
+    port: usize,
+    /// File name pattern
+    pattern: String,
+    /// Block where the search continues
+    block: usize,
+}
+
+/// Everything a kernel function needs to access the guest state
+pub struct Context<'a> {
+    cpu: &'a mut Cpu,
+    shared: &'a mut SharedState,
+    renderer: &'a mut Renderer,
+}
+
+impl<'a> Context<'a> {
+    fn reg(&self, r: u32) -> u32 {
+        self.cpu.regs()[r as usize]
+    }
+
+    fn set_reg(&mut self, r: u32, v: u32) {
+        self.cpu.write_reg(r, v)
+    }
+
+    /// Return function argument `n`, the first four are in registers,
+    /// the rest on the stack
+    fn arg(&mut self, n: u32) -> u32 {
+        if n < 4 {
+            self.reg(4 + n)
+        } else {
+            let sp = self.reg(29);
+
+            self.load::<Word>(sp.wrapping_add(n * 4))
+        }
+    }
+
+    fn load<A: Addressable>(&mut self, addr: u32) -> u32 {
+        self.cpu.interconnect_mut().load::<A>(self.shared, addr)
+    }
+
+    fn store<A: Addressable>(&mut self, addr: u32, val: u32) {
+        self.cpu.interconnect_mut().store::<A>(self.shared,
+                                                self.renderer,
+                                                addr,
+                                                val)
+    }
+
+    fn read_bytes(&mut self, addr: u32, len: u32) -> Vec<u8> {
+        (0..len).map(|i| self.load::<Byte>(addr.wrapping_add(i)) as u8)
+            .collect()
+    }
+
+    fn write_bytes(&mut self, addr: u32, data: &[u8]) {
+        for (i, &b) in data.iter().enumerate() {
+            self.store::<Byte>(addr.wrapping_add(i as u32), b as u32);
+        }
+    }
+
+    /// Read a `\0`-terminated string
+    fn read_cstr(&mut self, addr: u32) -> Vec<u8> {
+        let mut s = Vec::new();
+
+        if addr == 0 {
+            return s;
+        }
+
+        for i in 0..0x10000 {
+            let b = self.load::<Byte>(addr.wrapping_add(i)) as u8;
+
+            if b == 0 {
+                break;
+            }
+
+            s.push(b);
+        }
+
+        s
+    }
+
+    /// Read a `\0`-terminated string, each byte is converted to the
+    /// corresponding Latin-1 code point
+    fn read_string(&mut self, addr: u32) -> String {
+        self.read_cstr(addr).into_iter().map(|b| b as char).collect()
+    }
+
+    fn memory_card(&mut self, port: usize) -> Option<&mut MemoryCard> {
+        self.cpu.interconnect_mut()
+            .pad_memcard_mut()
+            .memory_card_mut(port)
+    }
+
+    fn disc_image(&mut self) -> Option<&mut Image> {
+        self.cpu.interconnect_mut()
+            .cdrom_mut()
+            .disc_mut()
+            .map(|d| d.image())
+    }
+}
+
+/// If `path` is on one of the "bu" devices return the memory card
+/// slot and the file name. The device name is "bu" followed by the
+/// slot number and the multitap port which must be 0.
+fn memory_card_path(path: &str) -> Option<(usize, &str)> {
+    let mut split = path.splitn(2, ':');
+
+    let device = split.next().unwrap_or("").to_lowercase();
+    let name =
+        match split.next() {
+            Some(n) => n,
+            None => return None,
+        };
+
+    match device.as_ref() {
+        "bu00" => Some((0, name)),
+        "bu10" => Some((1, name)),
+        _ => None,
+    }
+}
+
+/// Convert the port argument of the low level memory card functions
+/// (0x00 for slot 1, 0x10 for slot 2) to a slot number
+fn card_port(port: u32) -> usize {
+    ((port >> 4) & 1) as usize
+}
+
+/// Open or create (depending on `mode`) the file `name` on the
+/// memory card in `port`. Returns an error code suitable for
+/// GetLastError on failure.
+fn card_open(ctx: &mut Context,
+             port: usize,
+             name: &str,
+             mode: u32) -> Result<CardFile, u32> {
+    let card =
+        match ctx.memory_card(port) {
+            Some(c) => c,
+            None => return Err(EBUSY),
+        };
+
+    if mode & 0x200 != 0 {
+        let blocks = mode >> 16;
+
+        match bu::create(card, name, blocks) {
+            Ok(e) => Ok(e.file),
+            Err(e) => {
+                warn!("HLE BIOS: can't create bu{}0:{}: {:?}", port, name, e);
+
+                Err(match e {
+                    bu::Error::AlreadyExists => EEXIST,
+                    bu::Error::NoSpace => ENOSPC,
+                    bu::Error::BadName => EINVAL,
+                })
+            }
+        }
+    } else {
+        bu::lookup(card, name).map(|e| e.file).ok_or(ENOENT)
+    }
+}
+
+/// Copy `sector` of the memory card in `port` to `dst`. Returns false
+/// if there's no card or the sector is out of range.
+fn card_read(ctx: &mut Context, port: usize, sector: u32, dst: u32) -> bool {
+    let data =
+        match ctx.memory_card(port).and_then(|c| c.frame(sector)) {
+            Some(f) => f.to_vec(),
+            None => return false,
+        };
+
+    ctx.write_bytes(dst, &data);
+
+    true
+}
+
+/// Copy a frame from `src` to `sector` of the memory card in
+/// `port`. Returns false if there's no card or the sector is out of
+/// range.
+fn card_write(ctx: &mut Context, port: usize, sector: u32, src: u32) -> bool {
+    let data = ctx.read_bytes(src, FRAME_SIZE as u32);
+
+    match ctx.memory_card(port).and_then(|c| c.frame_mut(sector)) {
+        Some(f) => {
+            f.copy_from_slice(&data);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Address of TCB `i`
+fn tcb(i: u32) -> u32 {
+    layout::TCB + i * layout::TCB_SIZE
+}
+
+/// Address of EvCB `i`
+fn evcb(i: u32) -> u32 {
+    layout::EVCB + i * layout::EVCB_SIZE
+}
+
+/// TCB status
+const TCB_FREE: u32 = 0x1000;
+const TCB_USED: u32 = 0x4000;
+
+/// EvCB fields
+const EV_CLASS: u32 = 0x00;
+const EV_STATUS: u32 = 0x04;
+const EV_SPEC: u32 = 0x08;
+const EV_MODE: u32 = 0x0c;
+const EV_FUNC: u32 = 0x10;
+
+/// Event status
+const EV_FREE: u32 = 0x0000;
+const EV_DISABLED: u32 = 0x1000;
+const EV_ENABLED: u32 = 0x2000;
+const EV_READY: u32 = 0x4000;
+
+/// Event mode: call the function when delivered (the other mode,
+/// 0x2000, marks the event as ready)
+const EV_MODE_CALLBACK: u32 = 0x1000;
+
+/// Event spec used by the root counters
+const EVSP_INT: u32 = 0x0002;
+
+/// Registers saved by setjmp: RA, SP, FP, S0-S7, GP
+const JMP_BUF_REGS: [u32; 12] = [31, 29, 30, 16, 17, 18, 19, 20, 21, 22, 23, 28];
+
+/// File descriptor for the TTY output
+const STDOUT: u32 = 1;
+/// First file descriptor used for files
+const FIRST_FD: u32 = 2;
+/// Maximum number of open files
+const MAX_FILES: usize = 16;
+/// Maximum length of a single write to stdout
+const MAX_TTY_WRITE: u32 = 0x10000;
+
+/// Error codes returned by GetLastError
+const ENOENT: u32 = 2;
+const EIO: u32 = 5;
+const EBADF: u32 = 9;
+const EBUSY: u32 = 16;
+const EEXIST: u32 = 17;
+const ENODEV: u32 = 19;
+const EINVAL: u32 = 22;
+const EMFILE: u32 = 24;
+const ENOSPC: u32 = 28;
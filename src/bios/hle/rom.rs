@@ -0,0 +1,357 @@
+//! Replacement BIOS ROM used by the HLE kernel.
+//!
+//! The ROM only contains the glue code that's inconvenient to write
+//! in Rust: the reset code, the exception entry and exit paths (which
+//! need to save and restore the CPU registers), the interrupt handler
+//! chains and a few trampolines used to call guest code. Everything
+//! else is implemented by the Rust kernel which is invoked whenever
+//! the CPU reaches one of the "trap" addresses. Each trap contains a
+//! `jr ra; nop` sequence so that the CPU returns to the caller once
+//! the Rust handler is done.
+
+use assembler::Assembler;
+use assembler::syntax::*;
+
+use bios::BIOS_SIZE;
+
+use super::layout;
+
+/// Build the HLE ROM image
+pub fn assemble() -> Box<[u8; BIOS_SIZE]> {
+    let mut rom = box_array![0; BIOS_SIZE];
+
+    assemble_reset(&mut rom);
+    assemble_boot(&mut rom);
+    assemble_exception_handler(&mut rom);
+    assemble_return_from_exception(&mut rom);
+    assemble_deliver_callbacks(&mut rom);
+    assemble_trampolines(&mut rom);
+    assemble_dispatchers(&mut rom);
+    assemble_traps(&mut rom);
+
+    rom
+}
+
+/// Code for the vectors copied in RAM at init time: a jump to
+/// `target` using `scratch` as temporary register. Always 16 bytes
+/// long.
+pub fn vector_stub(target: u32, scratch: Register) -> Vec<u8> {
+    let mut asm = Assembler::from_base(0);
+
+    asm.assemble(&[
+        Lui(scratch, (target >> 16) as u16),
+        Ori(scratch, scratch, target as u16),
+        Jr(scratch),
+        Nop,
+    ]).unwrap();
+
+    let (mc, _) = asm.machine_code().unwrap();
+
+    assert!(mc.len() == 16);
+
+    mc
+}
+
+/// Assemble `instructions` at the absolute address `addr` and copy
+/// the resulting machine code into `rom`. The code must fit in `max`
+/// bytes.
+fn assemble_at(rom: &mut [u8; BIOS_SIZE],
+               addr: u32,
+               max: u32,
+               instructions: &[Instruction]) {
+    let mut asm = Assembler::from_base(addr);
+
+    asm.assemble(instructions).unwrap();
+
+    let (mc, _) = asm.machine_code().unwrap();
+
+    assert!(mc.len() as u32 <= max);
+
+    let offset = (addr - layout::ROM_BASE) as usize;
+
+    rom[offset..offset + mc.len()].copy_from_slice(&mc);
+}
+
+fn assemble_reset(rom: &mut [u8; BIOS_SIZE]) {
+    assemble_at(rom, layout::ROM_BASE, 0x80, &[
+        // Clear BEV so that exceptions use the RAM vector and mask
+        // all interrupts
+        Mtc0(R0, 12),
+        Li(SP, layout::BOOT_STACK),
+        // Let the kernel setup the RAM vectors and structures
+        Jal(Label::Absolute(layout::TRAP_INIT)),
+        Nop,
+        J(Label::Absolute(layout::BOOT)),
+        Nop,
+    ]);
+}
+
+fn assemble_boot(rom: &mut [u8; BIOS_SIZE]) {
+    assemble_at(rom, layout::BOOT, layout::IDLE - layout::BOOT, &[
+        // Boot hook: this is where the real BIOS jumps to the boot
+        // logo animation. `ExeLoader` replaces this NOP with a jump
+        // to its own loader code.
+        Nop,
+        Nop,
+        Jal(Label::Absolute(layout::TRAP_BOOT)),
+        Nop,
+    ]);
+
+    // We get here if there was nothing to boot, if the executable
+    // returned or if it called exit()
+    assemble_at(rom, layout::IDLE, 0x10, &[
+        Local("idle"),
+        B(Label::Local("idle", 'b')),
+        Nop,
+    ]);
+}
+
+fn assemble_exception_handler(rom: &mut [u8; BIOS_SIZE]) {
+    let mut code = vec![
+        // Load the address of the current thread's register save
+        // area: [[PCB]] + 8
+        Lw(K0, R0, layout::TOT_PCB as i16),
+        Nop,
+        Lw(K0, K0, 0),
+        Nop,
+        Addiu(K0, K0, layout::TCB_REGS as i16),
+    ];
+
+    for r in 1..32 {
+        // We can't save K0, it's already been trashed by the vector
+        if r != 26 {
+            code.push(Sw(Register(r), K0, r as i16 * 4));
+        }
+    }
+
+    code.extend_from_slice(&[
+        Mfhi(T0),
+        Sw(T0, K0, layout::REGS_HI as i16),
+        Mflo(T0),
+        Sw(T0, K0, layout::REGS_LO as i16),
+        Mfc0(T0, 14),
+        Sw(T0, K0, layout::REGS_EPC as i16),
+        Mfc0(T0, 12),
+        Sw(T0, K0, layout::REGS_SR as i16),
+        Mfc0(T0, 13),
+        Sw(T0, K0, layout::REGS_CAUSE as i16),
+
+        Li(SP, layout::KERNEL_STACK),
+
+        // The kernel handles syscalls and delivers the root counter
+        // events. It returns 0 if we can return from the exception
+        // right away.
+        Jal(Label::Absolute(layout::TRAP_EXCEPTION)),
+        Nop,
+        Beqz(V0, Label::Absolute(layout::RETURN_FROM_EXCEPTION)),
+        Nop,
+
+        // Run the event callbacks queued by the kernel
+        Jal(Label::Absolute(layout::DELIVER_CALLBACKS)),
+        Nop,
+
+        // Walk the 4 interrupt handler chains installed with
+        // SysEnqIntRP. Each entry contains:
+        //
+        //   00h: next entry
+        //   04h: func2, called with func1's return value if non-0
+        //   08h: func1
+        Lw(S0, R0, layout::TOT_EXCB as i16),
+        Nop,
+        Addiu(S1, S0, 4 * 8),
+
+        Local("priority"),
+        Lw(S2, S0, 0),
+        Nop,
+
+        Local("entry"),
+        Beqz(S2, Label::Local("next_priority", 'f')),
+        Nop,
+        Lw(T0, S2, 8),
+        Nop,
+        Beqz(T0, Label::Local("next_entry", 'f')),
+        Nop,
+        Jalr(RA, T0),
+        Nop,
+        Beqz(V0, Label::Local("next_entry", 'f')),
+        Nop,
+        Lw(T0, S2, 4),
+        Nop,
+        Beqz(T0, Label::Local("next_entry", 'f')),
+        Nop,
+        Jalr(RA, T0),
+        Move(A0, V0),
+
+        Local("next_entry"),
+        Lw(S2, S2, 0),
+        B(Label::Local("entry", 'b')),
+        Nop,
+
+        Local("next_priority"),
+        Addiu(S0, S0, 8),
+        Bne(S0, S1, Label::Local("priority", 'b')),
+        Nop,
+
+        // Returns the custom exit jump buffer or 0
+        Jal(Label::Absolute(layout::TRAP_EXCEPTION_EXIT)),
+        Nop,
+        Beqz(V0, Label::Absolute(layout::RETURN_FROM_EXCEPTION)),
+        Move(S0, V0),
+
+        // Custom exit: "longjmp" into the buffer set by
+        // SetCustomExitFromException. The handler is expected to
+        // call ReturnFromException once it's done.
+        Lw(RA, S0, 0x00),
+        Lw(SP, S0, 0x04),
+        Lw(FP, S0, 0x08),
+        Lw(S1, S0, 0x10),
+        Lw(S2, S0, 0x14),
+        Lw(S3, S0, 0x18),
+        Lw(S4, S0, 0x1c),
+        Lw(S5, S0, 0x20),
+        Lw(S6, S0, 0x24),
+        Lw(S7, S0, 0x28),
+        Lw(GP, S0, 0x2c),
+        Lw(S0, S0, 0x0c),
+        Jr(RA),
+        Li(V0, 1),
+    ]);
+
+    assemble_at(rom, layout::EXCEPTION_HANDLER, 0x200, &code);
+}
+
+fn assemble_return_from_exception(rom: &mut [u8; BIOS_SIZE]) {
+    let mut code = vec![
+        Lw(K0, R0, layout::TOT_PCB as i16),
+        Nop,
+        Lw(K0, K0, 0),
+        Nop,
+        Addiu(K0, K0, layout::TCB_REGS as i16),
+
+        Lw(T0, K0, layout::REGS_HI as i16),
+        Nop,
+        Mthi(T0),
+        Lw(T0, K0, layout::REGS_LO as i16),
+        Nop,
+        Mtlo(T0),
+        Lw(T0, K0, layout::REGS_SR as i16),
+        Nop,
+        Mtc0(T0, 12),
+    ];
+
+    for r in 1..32 {
+        if r != 26 {
+            code.push(Lw(Register(r), K0, r as i16 * 4));
+        }
+    }
+
+    code.extend_from_slice(&[
+        Lw(K0, K0, layout::REGS_EPC as i16),
+        Nop,
+        Jr(K0),
+        Rfe,
+    ]);
+
+    assemble_at(rom, layout::RETURN_FROM_EXCEPTION, 0x100, &code);
+}
+
+fn assemble_deliver_callbacks(rom: &mut [u8; BIOS_SIZE]) {
+    // Call every function in the 0-terminated list built by the
+    // kernel
+    assemble_at(rom, layout::DELIVER_CALLBACKS, 0x80, &[
+        Addiu(SP, SP, -8),
+        Sw(RA, SP, 0),
+        Sw(S0, SP, 4),
+        Li(S0, layout::CALLBACK_LIST),
+
+        Local("loop"),
+        Lw(T0, S0, 0),
+        Nop,
+        Beqz(T0, Label::Local("done", 'f')),
+        Nop,
+        Jalr(RA, T0),
+        Addiu(S0, S0, 4),
+        B(Label::Local("loop", 'b')),
+        Nop,
+
+        Local("done"),
+        Li(T0, layout::CALLBACK_LIST),
+        Sw(R0, T0, 0),
+        Lw(RA, SP, 0),
+        Lw(S0, SP, 4),
+        Jr(RA),
+        Addiu(SP, SP, 8),
+    ]);
+}
+
+fn assemble_trampolines(rom: &mut [u8; BIOS_SIZE]) {
+    // Return path for DoExecute: S0 still points at the executable
+    // header, restore the caller's registers from its save area.
+    assemble_at(rom, layout::EXEC_RETURN, 0x40, &[
+        Lw(SP, S0, 0x28),
+        Lw(FP, S0, 0x2c),
+        Lw(GP, S0, 0x30),
+        Lw(RA, S0, 0x34),
+        Lw(S0, S0, 0x38),
+        Jr(RA),
+        Li(V0, 1),
+    ]);
+
+    // Syscall with the arguments already set by the kernel
+    assemble_at(rom, layout::SYSCALL, 0x10, &[
+        Syscall(0),
+        Jr(RA),
+        Nop,
+    ]);
+
+    // WaitEvent busy loop: give the CPU a chance to take interrupts
+    // and call WaitEvent again
+    assemble_at(rom, layout::WAIT_EVENT, 0x20, &[
+        Nop,
+        Nop,
+        Nop,
+        Nop,
+        J(Label::Absolute(layout::trap_b(0x0a))),
+        Nop,
+    ]);
+}
+
+fn assemble_dispatchers(rom: &mut [u8; BIOS_SIZE]) {
+    let tables = [
+        (layout::DISPATCH_A, layout::TABLE_A),
+        (layout::DISPATCH_B, layout::TABLE_B),
+        (layout::DISPATCH_C, layout::TABLE_C),
+    ];
+
+    // Like the real BIOS we jump through function tables in RAM so
+    // that the guest can patch them. Clobbers T1 and T2.
+    for &(dispatch, table) in &tables {
+        assemble_at(rom, dispatch, 0x20, &[
+            Li(T2, table),
+            Sll(T1, T1, 2),
+            Addu(T1, T1, T2),
+            Lw(T1, T1, 0),
+            Nop,
+            Jr(T1),
+            Nop,
+        ]);
+    }
+}
+
+fn assemble_traps(rom: &mut [u8; BIOS_SIZE]) {
+    let mut asm = Assembler::from_base(layout::TRAP_START);
+
+    let trap = [Jr(RA), Nop];
+
+    let ntraps = (layout::TRAP_END - layout::TRAP_START) / 8;
+
+    for _ in 0..ntraps {
+        asm.assemble(&trap).unwrap();
+    }
+
+    let (mc, _) = asm.machine_code().unwrap();
+
+    let offset = (layout::TRAP_START - layout::ROM_BASE) as usize;
+
+    rom[offset..offset + mc.len()].copy_from_slice(&mc);
+}
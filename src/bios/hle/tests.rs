@@ -0,0 +1,286 @@
+//! End-to-end tests of the HLE kernel: a small program is booted
+//! through the replacement ROM and calls the kernel functions like a
+//! game would.
+
+use gpu::{Gpu, VideoClock};
+use memory::{Interconnect, Byte, Word};
+use debugger::DummyDebugger;
+use shared::SharedState;
+use assembler::Assembler;
+use assembler::syntax::*;
+use cpu::Cpu;
+use test_utils::DummyRenderer;
+use bios::Bios;
+use padmemcard::memorycard::MemoryCard;
+
+use super::{HleKernel, layout, EBADF, EBUSY};
+
+/// Where the test program is loaded
+const PROGRAM: u32 = 0x80010000;
+/// Where the strings used by the test program are stored
+const STRINGS: u32 = 0x80011000;
+/// Buffer used for file reads
+const BUFFER: u32 = 0x80012000;
+/// Heap given to InitHeap
+const HEAP: u32 = 0x80100000;
+
+/// Maximum number of instructions executed by a test
+const TIMEOUT: u32 = 1000000;
+
+/// Call A0 function `f`
+fn call_a<'a>(f: u32) -> [Instruction<'a>; 3] {
+    [Li(T1, f), Jal(Label::Absolute(layout::VECTOR_A)), Nop]
+}
+
+/// Call B0 function `f`
+fn call_b<'a>(f: u32) -> [Instruction<'a>; 3] {
+    [Li(T1, f), Jal(Label::Absolute(layout::VECTOR_B)), Nop]
+}
+
+/// Boot the HLE BIOS with `program` hooked in place of the boot
+/// animation. `strings` are copied at `STRINGS`, separated by
+/// `\0`. Runs until the program jumps to the idle loop and returns
+/// the CPU to inspect the results.
+fn run(program: &[Instruction], strings: &[&str]) -> Cpu {
+    run_with_card(program, strings, None)
+}
+
+/// Same as `run` with `card` inserted in the first memory card slot
+fn run_with_card(program: &[Instruction],
+                 strings: &[&str],
+                 card: Option<MemoryCard>) -> Cpu {
+    let mut bios = Bios::hle();
+
+    // J PROGRAM
+    let jump = (2 << 26) | ((PROGRAM >> 2) & 0x3ffffff);
+    bios.patch_animation_jump_hook(jump).unwrap();
+
+    let gpu = Gpu::new(VideoClock::Ntsc);
+    let inter = Interconnect::new(bios, gpu, None);
+    let mut cpu = Cpu::new(inter);
+    let mut shared = SharedState::new();
+    let mut debugger = DummyDebugger;
+
+    *cpu.interconnect_mut().pad_memcard_mut().memory_cards_mut()[0] = card;
+    let mut renderer = DummyRenderer;
+
+    let mut asm = Assembler::from_base(PROGRAM);
+
+    asm.assemble(program).unwrap();
+    asm.assemble(&[Li(T0, layout::IDLE), Jr(T0), Nop]).unwrap();
+
+    let (code, _) = asm.machine_code().unwrap();
+
+    for (i, &b) in code.iter().enumerate() {
+        assert!(cpu.interconnect_mut().poke::<Byte>(PROGRAM + i as u32,
+                                                    b as u32));
+    }
+
+    let mut addr = STRINGS;
+
+    for s in strings {
+        for b in s.bytes().chain(Some(0)) {
+            cpu.interconnect_mut().poke::<Byte>(addr, b as u32);
+            addr += 1;
+        }
+    }
+
+    for _ in 0..TIMEOUT {
+        if cpu.pc() == layout::IDLE {
+            return cpu;
+        }
+
+        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer)
+            .unwrap();
+    }
+
+    panic!("HLE test program timed out at 0x{:08x}", cpu.pc());
+}
+
+fn reg(cpu: &Cpu, r: Register) -> u32 {
+    cpu.regs()[r.0 as usize]
+}
+
+fn kernel(cpu: &Cpu) -> &HleKernel {
+    cpu.hle_kernel().unwrap()
+}
+
+#[test]
+fn trap_entries() {
+    // Only the first instruction of each stub is a trap, the delay
+    // slot must not run the function a second time
+    assert!(HleKernel::is_trap(layout::trap_a(0x33)));
+    assert!(!HleKernel::is_trap(layout::trap_a(0x33) + 4));
+    assert!(HleKernel::is_trap(layout::trap_c(0x1f)));
+    assert!(HleKernel::is_trap(layout::TRAP_EXCEPTION_EXIT));
+    assert!(!HleKernel::is_trap(layout::TRAP_BOOT + 4));
+    assert!(!HleKernel::is_trap(layout::DISPATCH_A));
+}
+
+#[test]
+fn malloc() {
+    let mut program = vec![Li(A0, HEAP), Li(A1, 0x1000)];
+    program.extend_from_slice(&call_a(0x39));
+    // malloc(0x10)
+    program.push(Li(A0, 0x10));
+    program.extend_from_slice(&call_a(0x33));
+    program.push(Move(S0, V0));
+    // malloc(0x20)
+    program.push(Li(A0, 0x20));
+    program.extend_from_slice(&call_a(0x33));
+    program.push(Move(S1, V0));
+    // free the first block and allocate it again
+    program.push(Move(A0, S0));
+    program.extend_from_slice(&call_a(0x34));
+    program.push(Li(A0, 0x8));
+    program.extend_from_slice(&call_a(0x33));
+    program.push(Move(S2, V0));
+    // malloc(0x2000) doesn't fit
+    program.push(Li(A0, 0x2000));
+    program.extend_from_slice(&call_a(0x33));
+    program.push(Move(S3, V0));
+
+    let cpu = run(&program, &[]);
+
+    // Each call allocates exactly one block
+    assert!(reg(&cpu, S0) == HEAP);
+    assert!(reg(&cpu, S1) == HEAP + 0x10);
+    assert!(reg(&cpu, S2) == HEAP);
+    assert!(reg(&cpu, S3) == 0);
+}
+
+#[test]
+fn printf() {
+    let fmt = STRINGS;
+
+    let mut program = vec![Li(A0, fmt), Li(A1, 42), Li(A2, fmt + 7)];
+    program.extend_from_slice(&call_a(0x3f));
+    program.push(Move(S0, V0));
+
+    let cpu = run(&program, &["%d-%s!", "ok"]);
+
+    assert!(reg(&cpu, S0) == 6);
+    // Printed exactly once
+    assert!(kernel(&cpu).tty_line == "42-ok!");
+}
+
+#[test]
+fn file_io() {
+    let msg = STRINGS;
+    let path = STRINGS + 4;
+
+    // write(stdout, "abc", 3)
+    let mut program = vec![Li(A0, 1), Li(A1, msg), Li(A2, 3)];
+    program.extend_from_slice(&call_a(0x03));
+    program.push(Move(S0, V0));
+    // open("cdrom:\FOO.BIN;1", 1) fails since there's no disc
+    program.extend_from_slice(&[Li(A0, path), Li(A1, 1)]);
+    program.extend_from_slice(&call_a(0x00));
+    program.push(Move(S1, V0));
+    program.extend_from_slice(&call_b(0x54));
+    program.push(Move(S2, V0));
+    // read(5, buf, 16) on a file that's not open
+    program.extend_from_slice(&[Li(A0, 5), Li(A1, BUFFER), Li(A2, 16)]);
+    program.extend_from_slice(&call_a(0x02));
+    program.push(Move(S3, V0));
+    program.extend_from_slice(&call_b(0x54));
+    program.push(Move(S4, V0));
+    // write to a file descriptor that's not stdout
+    program.extend_from_slice(&[Li(A0, 5), Li(A1, msg), Li(A2, 3)]);
+    program.extend_from_slice(&call_a(0x03));
+    program.push(Move(S5, V0));
+
+    let cpu = run(&program, &["abc", "cdrom:\\FOO.BIN;1"]);
+
+    assert!(reg(&cpu, S0) == 3);
+    assert!(kernel(&cpu).tty_line == "abc");
+
+    assert!(reg(&cpu, S1) == !0);
+    assert!(reg(&cpu, S2) == EBUSY);
+
+    assert!(reg(&cpu, S3) == !0);
+    assert!(reg(&cpu, S4) == EBADF);
+    assert!(cpu.interconnect().ram().load::<Word>(BUFFER & 0x1fffff) == 0);
+
+    assert!(reg(&cpu, S5) == !0);
+}
+
+#[test]
+fn memory_card_files() {
+    let data = STRINGS;
+    let path = STRINGS + 6;
+    let pattern = STRINGS + 28;
+    let missing = STRINGS + 38;
+    let direntry = BUFFER + 0x100;
+
+    // open("bu00:BESLES-00000TEST", create one block)
+    let mut program = vec![Li(A0, path), Li(A1, 0x10200)];
+    program.extend_from_slice(&call_a(0x00));
+    program.push(Move(S0, V0));
+    // write(fd, data, 0x80) then close it
+    program.extend_from_slice(&[Move(A0, S0), Li(A1, data), Li(A2, 0x80)]);
+    program.extend_from_slice(&call_a(0x03));
+    program.push(Move(S1, V0));
+    program.push(Move(A0, S0));
+    program.extend_from_slice(&call_a(0x04));
+    // Open it again and read it back
+    program.extend_from_slice(&[Li(A0, path), Li(A1, 1)]);
+    program.extend_from_slice(&call_a(0x00));
+    program.extend_from_slice(&[Move(A0, V0), Li(A1, BUFFER), Li(A2, 0x80)]);
+    program.extend_from_slice(&call_a(0x02));
+    program.push(Move(S2, V0));
+    // firstfile("bu00:BES*", direntry) then nextfile(direntry)
+    program.extend_from_slice(&[Li(A0, pattern), Li(A1, direntry)]);
+    program.extend_from_slice(&call_b(0x42));
+    program.push(Move(S3, V0));
+    program.push(Li(A0, direntry));
+    program.extend_from_slice(&call_b(0x43));
+    program.push(Move(S4, V0));
+    // Nothing in the second slot
+    program.extend_from_slice(&[Li(A0, missing), Li(A1, 1)]);
+    program.extend_from_slice(&call_a(0x00));
+    program.push(Move(S5, V0));
+    program.extend_from_slice(&call_b(0x54));
+    program.push(Move(S6, V0));
+
+    let mut cpu = run_with_card(&program,
+                                &["hello", "bu00:BESLES-00000TEST",
+                                  "bu00:BES*", "bu10:FOO"],
+                                Some(MemoryCard::new()));
+
+    assert!(reg(&cpu, S0) != !0);
+    assert!(reg(&cpu, S1) == 0x80);
+    assert!(reg(&cpu, S2) == 0x80);
+
+    {
+        let ram = cpu.interconnect().ram();
+
+        for (i, &b) in b"hello\0".iter().enumerate() {
+            assert!(ram.load::<Byte>((BUFFER & 0x1fffff) + i as u32) == b as u32);
+        }
+
+        let d = direntry & 0x1fffff;
+
+        for (i, &b) in b"BESLES-00000TEST\0".iter().enumerate() {
+            assert!(ram.load::<Byte>(d + i as u32) == b as u32);
+        }
+
+        // One block
+        assert!(ram.load::<Word>(d + 0x18) == 0x2000);
+        // First frame of the second block
+        assert!(ram.load::<Word>(d + 0x20) == 0x40);
+    }
+
+    assert!(reg(&cpu, S3) == direntry);
+    assert!(reg(&cpu, S4) == 0);
+
+    assert!(reg(&cpu, S5) == !0);
+    assert!(reg(&cpu, S6) == EBUSY);
+
+    let card = cpu.interconnect_mut().pad_memcard_mut()
+        .memory_card_mut(0).unwrap();
+
+    // The file got the first free block
+    assert!(card.image()[0x80] == 0x51);
+    assert!(&card.image()[0x2000..0x2005] == b"hello");
+}
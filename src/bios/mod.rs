@@ -6,6 +6,8 @@ use cdrom::disc::Region;
 use self::db::Metadata;
 
 pub mod db;
+pub mod tracer;
+pub mod hle;
 
 /// BIOS image
 pub struct Bios {
@@ -49,6 +51,21 @@ impl Bios {
         bios
     }
 
+    /// Build the replacement BIOS used for high level emulation of
+    /// the kernel. Games can be booted without a BIOS dump but
+    /// compatibility is not as good as with the real thing.
+    pub fn hle() -> Bios {
+        Bios {
+            data: hle::assemble_rom(),
+            metadata: &HLE_METADATA,
+        }
+    }
+
+    /// Return true if this is the HLE BIOS built by `Bios::hle`
+    pub fn is_hle(&self) -> bool {
+        self.metadata.sha256 == HLE_METADATA.sha256
+    }
+
     /// Attempt to modify the BIOS ROM to remove the call to the code
     /// responsible for the boot logo animations (SCEx/PS) and
     /// directly boot the game. This can break some games!  Returns
@@ -104,6 +121,16 @@ impl Bios {
         r
     }
 
+    /// Store the little endian value `val` at `offset`. Used for
+    /// debugging, the modification won't be saved in savestates.
+    pub fn store<T: Addressable>(&mut self, offset: u32, val: u32) {
+        let offset = offset as usize;
+
+        for i in 0..T::size() as usize {
+            self.data[offset + i] = (val >> (8 * i)) as u8;
+        }
+    }
+
     /// Return a static pointer to the BIOS's Metadata
     pub fn metadata(&self) -> &'static Metadata {
         self.metadata
@@ -142,6 +169,11 @@ impl Decodable for Bios {
                 *b = try!(d.read_seq_elt(i, |d| Decodable::decode(d)))
             }
 
+            // The HLE BIOS can be rebuilt from scratch
+            if sha256 == HLE_METADATA.sha256 {
+                return Ok(Bios::hle());
+            }
+
             let meta =
                 match db::lookup_sha256(&sha256) {
                     Some(m) => m,
@@ -171,5 +203,19 @@ static DUMMY_METADATA: Metadata =
         patch_debug_uart: None,
     };
 
+/// Metadata for the HLE BIOS. The checksum is not the hash of the ROM,
+/// it's only used to recognize the HLE BIOS in savestates.
+static HLE_METADATA: Metadata =
+    Metadata {
+        sha256: [0x00; 32],
+        version_major: 0,
+        version_minor: 0,
+        // The HLE kernel doesn't care about the region
+        region: Region::NorthAmerica,
+        known_bad: false,
+        animation_jump_hook: Some(0x80),
+        patch_debug_uart: None,
+    };
+
 /// BIOS images are always 512KB in length
 pub const BIOS_SIZE: usize = 512 * 1024;
@@ -0,0 +1,523 @@
+//! BIOS kernel call tracer.
+//!
+//! The BIOS exposes its functions through three jump tables located
+//! at 0xA0, 0xB0 and 0xC0, the function number being passed in
+//! register `$t1`. When enabled the tracer logs every call with its
+//! decoded arguments and, when the function returns to `$ra`, its
+//! return value. Characters sent to `std_out_putchar` are collected
+//! and logged line by line, which gives us the kernel TTY output even
+//! when the BIOS can't be patched to use the debug UART.
+//!
+//! If the debugger has a symbol table the callers are displayed as
+//! `function+offset`.
+
+use cpu::Cpu;
+use debugger::symbols::SymbolTable;
+use memory::Byte;
+use memory::Word;
+
+/// Kernel call tracer state
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct KernelTracer {
+    /// Calls waiting for their return value, innermost last
+    pending: Vec<PendingCall>,
+    /// Current TTY line
+    tty_line: String,
+    /// Everything printed on the TTY since the tracer was created
+    /// (or since the last call to `take_tty_output`)
+    tty_output: String,
+    /// If false the calls to `std_out_putchar` aren't logged
+    /// individually, only the resulting TTY output.
+    log_putchar: bool,
+}
+
+impl KernelTracer {
+    pub fn new() -> KernelTracer {
+        KernelTracer {
+            pending: Vec::new(),
+            tty_line: String::new(),
+            tty_output: String::new(),
+            log_putchar: false,
+        }
+    }
+
+    /// Log the individual calls to `std_out_putchar` (disabled by
+    /// default since it's very verbose)
+    pub fn set_log_putchar(&mut self, enable: bool) {
+        self.log_putchar = enable;
+    }
+
+    /// Return the TTY output collected so far and clear it
+    pub fn take_tty_output(&mut self) -> String {
+        ::std::mem::replace(&mut self.tty_output, String::new())
+    }
+
+    /// Called by the CPU before executing the instruction at
+    /// `cpu.pc()`. `symbols` is used to resolve the callers.
+    pub fn pc_change(&mut self,
+                     cpu: &mut Cpu,
+                     symbols: Option<&SymbolTable>) {
+        let pc = cpu.pc();
+
+        // Check if we're returning from a pending call
+        if let Some(pos) = self.pending.iter().rposition(|p| p.ra == pc) {
+            let v0 = cpu.regs()[2];
+
+            // Everything past `pos` didn't return normally
+            self.pending.truncate(pos + 1);
+
+            let call = self.pending.pop().unwrap();
+
+            info!("BIOS {}({:02X}h) {} -> 0x{:08x}",
+                  call.table, call.function, call.name, v0);
+        }
+
+        // The BIOS jump tables can be called through any of the
+        // memory mirrors
+        let table =
+            match pc & 0x1fffffff {
+                0xa0 => 'A',
+                0xb0 => 'B',
+                0xc0 => 'C',
+                _ => return,
+            };
+
+        let function = cpu.regs()[9];
+        let ra = cpu.regs()[31];
+
+        let (name, args) =
+            match lookup(table, function) {
+                Some(f) => f,
+                None => ("<unknown>", &[] as &[Arg]),
+            };
+
+        if table == 'B' && function == 0x3d {
+            // std_out_putchar
+            self.putchar(cpu.regs()[4] as u8);
+
+            if !self.log_putchar {
+                return;
+            }
+        }
+
+        let mut desc = String::new();
+
+        for (i, arg) in args.iter().enumerate() {
+            let val =
+                if i < 4 {
+                    // $a0-$a3
+                    cpu.regs()[4 + i]
+                } else {
+                    // The following arguments are on the stack. We
+                    // read them with `Cpu::examine` which doesn't see
+                    // the interrupt controller registers but the
+                    // stack and strings are expected to be in RAM.
+                    let sp = cpu.regs()[29];
+
+                    cpu.examine::<Word>(sp.wrapping_add(i as u32 * 4))
+                };
+
+            if i > 0 {
+                desc.push_str(", ");
+            }
+
+            desc.push_str(&arg.decode(cpu, val));
+        }
+
+        info!("BIOS {}({:02X}h) {}({}) from {}",
+              table, function, name, desc, caller(ra, symbols));
+
+        if self.pending.len() >= MAX_PENDING {
+            // Some calls don't return (exit, ReturnFromException...)
+            self.pending.remove(0);
+        }
+
+        self.pending.push(PendingCall {
+            table: table,
+            function: function,
+            name: name.into(),
+            ra: ra,
+        });
+    }
+
+    fn putchar(&mut self, c: u8) {
+        let c = c as char;
+
+        self.tty_output.push(c);
+
+        if c == '\n' {
+            info!("TTY: {}", self.tty_line);
+            self.tty_line.clear();
+        } else if c != '\r' {
+            self.tty_line.push(c);
+        }
+    }
+}
+
+#[derive(RustcDecodable, RustcEncodable)]
+struct PendingCall {
+    table: char,
+    function: u32,
+    name: String,
+    /// Return address
+    ra: u32,
+}
+
+/// Argument types used to decode the kernel function parameters
+#[derive(Clone, Copy)]
+enum Arg {
+    /// Signed integer
+    Int,
+    /// Hexadecimal value (pointers, flags...)
+    Hex,
+    /// Pointer to a `\0`-terminated string
+    Str,
+    /// Character
+    Char,
+}
+
+impl Arg {
+    fn decode(self, cpu: &mut Cpu, val: u32) -> String {
+        match self {
+            Arg::Int => format!("{}", val as i32),
+            Arg::Hex => format!("0x{:x}", val),
+            Arg::Char => format!("{:?}", (val as u8) as char),
+            Arg::Str => {
+                if val == 0 {
+                    return "NULL".into();
+                }
+
+                let mut s = String::new();
+
+                for i in 0..MAX_STRING_LEN {
+                    let b = cpu.examine::<Byte>(val.wrapping_add(i)) as u8;
+
+                    if b == 0 {
+                        return format!("{:?}", s);
+                    }
+
+                    s.push(b as char);
+                }
+
+                format!("{:?}...", s)
+            }
+        }
+    }
+}
+
+/// Format the return address `ra`, adding the function it belongs to
+/// if we know it
+fn caller(ra: u32, symbols: Option<&SymbolTable>) -> String {
+    match symbols.and_then(|s| s.describe(ra)) {
+        Some(name) => format!("0x{:08x} <{}>", ra, name),
+        None => format!("0x{:08x}", ra),
+    }
+}
+
+/// Maximum number of characters displayed for string arguments
+const MAX_STRING_LEN: u32 = 64;
+
+/// Maximum number of calls waiting for a return
+const MAX_PENDING: usize = 32;
+
+/// Return the name of `function` in `table` ('A', 'B' or 'C') if
+/// it's known
+pub fn function_name(table: char, function: u32) -> Option<&'static str> {
+    lookup(table, function).map(|(name, _)| name)
+}
+
+/// Look up the name and argument types of `function` in `table`
+/// ('A', 'B' or 'C')
+fn lookup(table: char, function: u32)
+          -> Option<(&'static str, &'static [Arg])> {
+    let functions =
+        match table {
+            'A' => A0_FUNCTIONS,
+            'B' => B0_FUNCTIONS,
+            'C' => C0_FUNCTIONS,
+            _ => return None,
+        };
+
+    functions.binary_search_by(|f| f.0.cmp(&function))
+        .ok()
+        .map(|i| (functions[i].1, functions[i].2))
+}
+
+const I: Arg = Arg::Int;
+const H: Arg = Arg::Hex;
+const S: Arg = Arg::Str;
+const C: Arg = Arg::Char;
+
+/// Known A0 functions, sorted by function number. Names and
+/// parameters are from the No$ PSX specs.
+static A0_FUNCTIONS: &'static [(u32, &'static str, &'static [Arg])] = &[
+    (0x00, "FileOpen", &[S, H]),
+    (0x01, "FileSeek", &[I, I, I]),
+    (0x02, "FileRead", &[I, H, I]),
+    (0x03, "FileWrite", &[I, H, I]),
+    (0x04, "FileClose", &[I]),
+    (0x05, "FileIoctl", &[I, H, H]),
+    (0x06, "exit", &[I]),
+    (0x07, "FileGetDeviceFlag", &[I]),
+    (0x08, "FileGetc", &[I]),
+    (0x09, "FilePutc", &[C, I]),
+    (0x0a, "todigit", &[C]),
+    (0x0b, "atof", &[S]),
+    (0x0c, "strtoul", &[S, H, I]),
+    (0x0d, "strtol", &[S, H, I]),
+    (0x0e, "abs", &[I]),
+    (0x0f, "labs", &[I]),
+    (0x10, "atoi", &[S]),
+    (0x11, "atol", &[S]),
+    (0x12, "atob", &[S, H]),
+    (0x13, "SaveState", &[H]),
+    (0x14, "RestoreState", &[H, H]),
+    (0x15, "strcat", &[H, S]),
+    (0x16, "strncat", &[H, S, I]),
+    (0x17, "strcmp", &[S, S]),
+    (0x18, "strncmp", &[S, S, I]),
+    (0x19, "strcpy", &[H, S]),
+    (0x1a, "strncpy", &[H, S, I]),
+    (0x1b, "strlen", &[S]),
+    (0x1c, "index", &[S, C]),
+    (0x1d, "rindex", &[S, C]),
+    (0x1e, "strchr", &[S, C]),
+    (0x1f, "strrchr", &[S, C]),
+    (0x20, "strpbrk", &[S, S]),
+    (0x21, "strspn", &[S, S]),
+    (0x22, "strcspn", &[S, S]),
+    (0x23, "strtok", &[S, S]),
+    (0x24, "strstr", &[S, S]),
+    (0x25, "toupper", &[C]),
+    (0x26, "tolower", &[C]),
+    (0x27, "bcopy", &[H, H, I]),
+    (0x28, "bzero", &[H, I]),
+    (0x29, "bcmp", &[H, H, I]),
+    (0x2a, "memcpy", &[H, H, I]),
+    (0x2b, "memset", &[H, H, I]),
+    (0x2c, "memmove", &[H, H, I]),
+    (0x2d, "memcmp", &[H, H, I]),
+    (0x2e, "memchr", &[H, H, I]),
+    (0x2f, "rand", &[]),
+    (0x30, "srand", &[H]),
+    (0x31, "qsort", &[H, I, I, H]),
+    (0x32, "strtod", &[S, H]),
+    (0x33, "malloc", &[I]),
+    (0x34, "free", &[H]),
+    (0x35, "lsearch", &[H, H, I, I, H]),
+    (0x36, "bsearch", &[H, H, I, I, H]),
+    (0x37, "calloc", &[I, I]),
+    (0x38, "realloc", &[H, I]),
+    (0x39, "InitHeap", &[H, I]),
+    (0x3a, "SystemErrorExit", &[I]),
+    (0x3b, "std_in_getchar", &[]),
+    (0x3c, "std_out_putchar", &[C]),
+    (0x3d, "std_in_gets", &[H]),
+    (0x3e, "std_out_puts", &[S]),
+    (0x3f, "printf", &[S, H, H, H]),
+    (0x40, "SystemErrorUnresolvedException", &[]),
+    (0x41, "LoadExeHeader", &[S, H]),
+    (0x42, "LoadExeFile", &[S, H]),
+    (0x43, "DoExecute", &[H, H, H]),
+    (0x44, "FlushCache", &[]),
+    (0x45, "init_a0_b0_c0_vectors", &[]),
+    (0x46, "GPU_dw", &[I, I, I, I, H]),
+    (0x47, "gpu_send_dma", &[I, I, I, I, H]),
+    (0x48, "SendGP1Command", &[H]),
+    (0x49, "GPU_cw", &[H]),
+    (0x4a, "GPU_cwp", &[H, I]),
+    (0x4b, "send_gpu_linked_list", &[H]),
+    (0x4c, "gpu_abort_dma", &[]),
+    (0x4d, "GetGPUStatus", &[]),
+    (0x4e, "gpu_sync", &[]),
+    (0x51, "LoadAndExecute", &[S, H, H]),
+    (0x52, "GetSysSp", &[]),
+    (0x54, "CdInit", &[]),
+    (0x55, "_bu_init", &[]),
+    (0x56, "CdRemove", &[]),
+    (0x5b, "dev_tty_init", &[]),
+    (0x5c, "dev_tty_open", &[H, S, H]),
+    (0x5d, "dev_tty_in_out", &[H, H]),
+    (0x5e, "dev_tty_ioctl", &[H, H, H]),
+    (0x5f, "dev_cd_open", &[H, S, H]),
+    (0x60, "dev_cd_read", &[H, H, I]),
+    (0x61, "dev_cd_close", &[H]),
+    (0x62, "dev_cd_firstfile", &[H, S, H]),
+    (0x63, "dev_cd_nextfile", &[H, H]),
+    (0x64, "dev_cd_chdir", &[H, S]),
+    (0x65, "dev_card_open", &[H, S, H]),
+    (0x66, "dev_card_read", &[H, H, I]),
+    (0x67, "dev_card_write", &[H, H, I]),
+    (0x68, "dev_card_close", &[H]),
+    (0x69, "dev_card_firstfile", &[H, S, H]),
+    (0x6a, "dev_card_nextfile", &[H, H]),
+    (0x6b, "dev_card_erase", &[H, S]),
+    (0x6c, "dev_card_undelete", &[H, S]),
+    (0x6d, "dev_card_format", &[H]),
+    (0x6e, "dev_card_rename", &[H, S, H, S]),
+    (0x70, "_bu_init", &[]),
+    (0x71, "CdInit", &[]),
+    (0x72, "CdRemove", &[]),
+    (0x78, "CdAsyncSeekL", &[H]),
+    (0x7c, "CdAsyncGetStatus", &[H]),
+    (0x7e, "CdAsyncReadSector", &[I, H, H]),
+    (0x81, "CdAsyncSetMode", &[H]),
+    (0x90, "CdromIoIrqFunc1", &[]),
+    (0x91, "CdromDmaIrqFunc1", &[]),
+    (0x92, "CdromIoIrqFunc2", &[]),
+    (0x93, "CdromDmaIrqFunc2", &[]),
+    (0x94, "CdromGetInt5errCode", &[H, H]),
+    (0x95, "CdInitSubFunc", &[]),
+    (0x96, "AddCDROMDevice", &[]),
+    (0x97, "AddMemCardDevice", &[]),
+    (0x98, "AddDuartTtyDevice", &[]),
+    (0x99, "AddDummyTtyDevice", &[]),
+    (0x9c, "SetConf", &[I, I, H]),
+    (0x9d, "GetConf", &[H, H, H]),
+    (0x9e, "SetCdromIrqAutoAbort", &[I, I]),
+    (0x9f, "SetMemSize", &[I]),
+    (0xa0, "WarmBoot", &[]),
+    (0xa1, "SystemErrorBootOrDiskFailure", &[C, H]),
+    (0xa2, "EnqueueCdIntr", &[]),
+    (0xa3, "DequeueCdIntr", &[]),
+    (0xa4, "CdGetLbn", &[S]),
+    (0xa5, "CdReadSector", &[I, I, H]),
+    (0xa6, "CdGetStatus", &[]),
+    (0xa7, "bu_callback_okay", &[]),
+    (0xa8, "bu_callback_err_write", &[]),
+    (0xa9, "bu_callback_err_busy", &[]),
+    (0xaa, "bu_callback_err_eject", &[]),
+    (0xab, "_card_info", &[H]),
+    (0xac, "_card_async_load_directory", &[H]),
+    (0xad, "set_card_auto_format", &[H]),
+    (0xae, "bu_callback_err_prev_write", &[]),
+    (0xaf, "card_write_test", &[H]),
+    (0xb2, "ioabort_raw", &[H]),
+    (0xb4, "GetSystemInfo", &[H]),
+];
+
+/// Known B0 functions, sorted by function number
+static B0_FUNCTIONS: &'static [(u32, &'static str, &'static [Arg])] = &[
+    (0x00, "alloc_kernel_memory", &[I]),
+    (0x01, "free_kernel_memory", &[H]),
+    (0x02, "init_timer", &[I, H, H]),
+    (0x03, "get_timer", &[I]),
+    (0x04, "enable_timer_irq", &[I]),
+    (0x05, "disable_timer_irq", &[I]),
+    (0x06, "restart_timer", &[I]),
+    (0x07, "DeliverEvent", &[H, H]),
+    (0x08, "OpenEvent", &[H, H, H, H]),
+    (0x09, "CloseEvent", &[H]),
+    (0x0a, "WaitEvent", &[H]),
+    (0x0b, "TestEvent", &[H]),
+    (0x0c, "EnableEvent", &[H]),
+    (0x0d, "DisableEvent", &[H]),
+    (0x0e, "OpenThread", &[H, H, H]),
+    (0x0f, "CloseThread", &[H]),
+    (0x10, "ChangeThread", &[H]),
+    (0x11, "jump_to_00000000h", &[]),
+    (0x12, "InitPad", &[H, I, H, I]),
+    (0x13, "StartPad", &[]),
+    (0x14, "StopPad", &[]),
+    (0x15, "OutdatedPadInitAndStart", &[H, H, H, H]),
+    (0x16, "OutdatedPadGetButtons", &[]),
+    (0x17, "ReturnFromException", &[]),
+    (0x18, "SetDefaultExitFromException", &[]),
+    (0x19, "SetCustomExitFromException", &[H]),
+    (0x20, "UnDeliverEvent", &[H, H]),
+    (0x32, "FileOpen", &[S, H]),
+    (0x33, "FileSeek", &[I, I, I]),
+    (0x34, "FileRead", &[I, H, I]),
+    (0x35, "FileWrite", &[I, H, I]),
+    (0x36, "FileClose", &[I]),
+    (0x37, "FileIoctl", &[I, H, H]),
+    (0x38, "exit", &[I]),
+    (0x39, "FileGetDeviceFlag", &[I]),
+    (0x3a, "FileGetc", &[I]),
+    (0x3b, "FilePutc", &[C, I]),
+    (0x3c, "std_in_getchar", &[]),
+    (0x3d, "std_out_putchar", &[C]),
+    (0x3e, "std_in_gets", &[H]),
+    (0x3f, "std_out_puts", &[S]),
+    (0x40, "chdir", &[S]),
+    (0x41, "FormatDevice", &[S]),
+    (0x42, "firstfile", &[S, H]),
+    (0x43, "nextfile", &[H]),
+    (0x44, "FileRename", &[S, S]),
+    (0x45, "FileDelete", &[S]),
+    (0x46, "FileUndelete", &[S]),
+    (0x47, "AddDevice", &[H]),
+    (0x48, "RemoveDevice", &[S]),
+    (0x49, "PrintInstalledDevices", &[]),
+    (0x4a, "InitCard", &[H]),
+    (0x4b, "StartCard", &[]),
+    (0x4c, "StopCard", &[]),
+    (0x4d, "_card_info_subfunc", &[H]),
+    (0x4e, "write_card_sector", &[H, I, H]),
+    (0x4f, "read_card_sector", &[H, I, H]),
+    (0x50, "allow_new_card", &[]),
+    (0x51, "Krom2RawAdd", &[H]),
+    (0x53, "Krom2Offset", &[H]),
+    (0x54, "GetLastError", &[]),
+    (0x55, "GetLastFileError", &[I]),
+    (0x56, "GetC0Table", &[]),
+    (0x57, "GetB0Table", &[]),
+    (0x58, "get_bu_callback_port", &[]),
+    (0x59, "testdevice", &[S]),
+    (0x5b, "ChangeClearPad", &[H]),
+    (0x5c, "get_card_status", &[I]),
+    (0x5d, "wait_card_status", &[I]),
+];
+
+/// Known C0 functions, sorted by function number
+static C0_FUNCTIONS: &'static [(u32, &'static str, &'static [Arg])] = &[
+    (0x00, "EnqueueTimerAndVblankIrqs", &[I]),
+    (0x01, "EnqueueSyscallHandler", &[I]),
+    (0x02, "SysEnqIntRP", &[I, H]),
+    (0x03, "SysDeqIntRP", &[I, H]),
+    (0x04, "get_free_EvCB_slot", &[]),
+    (0x05, "get_free_TCB_slot", &[]),
+    (0x06, "ExceptionHandler", &[]),
+    (0x07, "InstallExceptionHandlers", &[]),
+    (0x08, "SysInitMemory", &[H, I]),
+    (0x09, "SysInitKernelVariables", &[]),
+    (0x0a, "ChangeClearRCnt", &[I, H]),
+    (0x0c, "InitDefInt", &[I]),
+    (0x0d, "SetIrqAutoAck", &[I, H]),
+    (0x12, "InstallDevices", &[H]),
+    (0x13, "FlushStdInOutPut", &[]),
+    (0x15, "tty_cdevinput", &[H, C]),
+    (0x16, "tty_cdevscan", &[]),
+    (0x17, "tty_circgetc", &[H]),
+    (0x18, "tty_circputc", &[C, H]),
+    (0x19, "ioabort", &[S, S]),
+    (0x1a, "set_card_find_mode", &[H]),
+    (0x1b, "KernelRedirect", &[H]),
+    (0x1c, "AdjustA0Table", &[]),
+    (0x1d, "get_card_find_mode", &[]),
+];
+
+#[test]
+fn function_tables_sorted() {
+    for table in &[A0_FUNCTIONS, B0_FUNCTIONS, C0_FUNCTIONS] {
+        for w in table.windows(2) {
+            assert!(w[0].0 < w[1].0);
+        }
+    }
+
+    assert!(lookup('A', 0x3f).unwrap().0 == "printf");
+    assert!(lookup('B', 0x3d).unwrap().0 == "std_out_putchar");
+    assert!(lookup('C', 0x1c).unwrap().0 == "AdjustA0Table");
+    assert!(lookup('C', 0xff).is_none());
+}
+
+#[test]
+fn caller_symbols() {
+    let mut symbols = SymbolTable::new();
+
+    symbols.add(0x80010000, "main".into(), Some(0x100));
+
+    assert!(caller(0x80010010, Some(&symbols)) == "0x80010010 <main+0x10>");
+    assert!(caller(0x80010010, None) == "0x80010010");
+    assert!(caller(0x80000010, Some(&symbols)) == "0x80000010");
+}
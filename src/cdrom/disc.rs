@@ -1,4 +1,5 @@
 use std::fmt;
+use std::io;
 
 use cdimage::{Image, CdError};
 use cdimage::msf::Msf;
@@ -39,14 +40,16 @@ impl Disc {
         Ok(disc)
     }
 
-    pub fn region(&self) -> Region {
-        // For now I prefer to panic to catch potential issues with
-        // the serial number handling code, alternatively we could
-        // fallback on `extract_system_region`
-        match self.serial.region() {
-            Some(r) => r,
-            None => panic!("Can't establish the region of {}", self.serial),
+    /// Return the region of the disc deduced from its serial number
+    /// or `None` if the serial number's prefix is unknown.
+    pub fn region(&self) -> Option<Region> {
+        let region = self.serial.region();
+
+        if region.is_none() {
+            warn!("Can't establish the region of {}", self.serial);
         }
+
+        region
     }
 
     pub fn serial_number(&self) -> SerialNumber {
@@ -85,18 +88,23 @@ struct MissingImage;
 
 impl Image for MissingImage {
     fn image_format(&self) -> String {
-        panic!("Missing CD image!");
+        "Missing CD image".into()
     }
 
     fn read_sector(&mut self, _: &mut Sector, _: Msf) -> Result<(), CdError> {
-        panic!("Missing CD image!");
+        Err(missing_image())
     }
 
     fn track_msf(&self, _: Bcd, _: Msf) -> Result<Msf, CdError> {
-        panic!("Missing CD image!");
+        Err(missing_image())
     }
 }
 
+fn missing_image() -> CdError {
+    CdError::IoError(io::Error::new(io::ErrorKind::NotFound,
+                                    "Missing CD image!"))
+}
+
 /// Disc region
 #[derive(Clone, Copy, Debug, PartialEq, Eq, RustcDecodable, RustcEncodable)]
 pub enum Region {
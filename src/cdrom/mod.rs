@@ -19,6 +19,7 @@ use memory::Addressable;
 use timekeeper::{Peripheral, Cycles};
 use interrupt::Interrupt;
 use shared::SharedState;
+use error::Device;
 use arrayvec::ArrayVec;
 use cdimage::sector::Sector;
 use cdimage::msf::Msf;
@@ -196,7 +197,7 @@ impl CdRom {
                     let leftover = elapsed - delay;
 
                     // Read the current sector
-                    self.read_sector();
+                    self.read_sector(shared);
                     self.maybe_notify_read(shared);
 
                     // Schedule the next sector read
@@ -212,6 +213,11 @@ impl CdRom {
         self.predict_next_sync(shared);
     }
 
+    /// Return a mutable reference to the current disc, if any
+    pub fn disc_mut(&mut self) -> Option<&mut Disc> {
+        self.disc.as_mut()
+    }
+
     // Remove the disc. Returns the disc instance, if any.
     pub fn remove_disc(&mut self) -> Option<Disc> {
         self.set_disc(None)
@@ -259,17 +265,13 @@ impl CdRom {
         self.sync(shared);
 
         if T::size() != 1 {
-            panic!("Unhandled CDROM load ({})", T::size());
+            fatal!(shared, Device::CdRom,
+                   "Unhandled CDROM load ({})", T::size());
+            return 0;
         }
 
         let index = self.index;
 
-        let unimplemented = || {
-            panic!("read CDROM register {}.{}",
-                   offset,
-                   index)
-        };
-
         // CXD1199AQ Datasheet section 3 documents the host interface
         let val =
             match offset {
@@ -284,20 +286,36 @@ impl CdRom {
                     // response bytes.
 
                     if self.host_response.is_empty() {
-                        panic!("CDROM response FIFO underflow");
+                        fatal!(shared, Device::CdRom,
+                               "CDROM response FIFO underflow");
                     }
 
                     self.host_response.pop()
                 }
-                3 =>
-                    match index {
-                        // IRQ mask/flags have the 3 MSB set when
-                        // read.
-                        0 => self.irq_mask | 0xe0,
-                        1 => self.irq_flags | 0xe0,
-                        _ => unimplemented(),
-                    },
-                _ => unimplemented(),
+                // IRQ mask/flags have the 3 MSB set when read.
+                3 if index == 0 => self.irq_mask | 0xe0,
+                3 if index == 1 => self.irq_flags | 0xe0,
+                _ => {
+                    fatal!(shared, Device::CdRom,
+                           "read CDROM register {}.{}", offset, index);
+                    0
+                }
+            };
+
+        val as u32
+    }
+
+    /// Register value without side effects, used for debugging. The
+    /// RESULT register returns the next response byte without
+    /// popping it.
+    pub fn peek(&self, offset: u32) -> u32 {
+        let val =
+            match offset {
+                0 => self.host_status(),
+                1 => self.host_response.peek(),
+                3 if self.index == 0 => self.irq_mask | 0xe0,
+                3 if self.index == 1 => self.irq_flags | 0xe0,
+                _ => 0,
             };
 
         val as u32
@@ -311,7 +329,9 @@ impl CdRom {
         self.sync(shared);
 
         if T::size() != 1 {
-            panic!("Unhandled CDROM store ({})", T::size());
+            fatal!(shared, Device::CdRom,
+                   "Unhandled CDROM store ({})", T::size());
+            return;
         }
 
         // All writeable registers are 8bit wide
@@ -319,13 +339,6 @@ impl CdRom {
 
         let index = self.index;
 
-        let unimplemented = || {
-            panic!("write CDROM register {}.{} {:x}",
-                   offset,
-                   index,
-                   val)
-        };
-
         match offset {
             // ADDRESS register
             0 => self.index = val & 3,
@@ -334,21 +347,25 @@ impl CdRom {
                     0 => self.set_command(shared, val),
                     // ATV2 register
                     3 => self.mixer.cd_right_to_spu_right = val,
-                    _ => unimplemented(),
+                    _ => fatal!(shared, Device::CdRom,
+                                "write CDROM register {}.{} {:x}",
+                                offset, index, val),
                 },
             2 =>
                 match index {
-                    0 => self.set_parameter(val),
+                    0 => self.set_parameter(shared, val),
                     1 => self.set_host_interrupt_mask(val),
                     // ATV0 register
                     2 => self.mixer.cd_left_to_spu_left = val,
                     // ATV3 register
                     3 => self.mixer.cd_right_to_spu_left = val,
-                    _ => unimplemented(),
+                    _ => fatal!(shared, Device::CdRom,
+                                "write CDROM register {}.{} {:x}",
+                                offset, index, val),
                 },
             3 =>
                 match index {
-                    0 => self.set_host_chip_control(val),
+                    0 => self.set_host_chip_control(shared, val),
                     // HCLRCTL (host clear control) register
                     1 => {
                         self.irq_ack(shared, val & 0x1f);
@@ -358,32 +375,37 @@ impl CdRom {
                         }
 
                         if val & 0xa0 != 0 {
-                            panic!("Unhandled CDROM 3.1: {:02x}", val);
+                            fatal!(shared, Device::CdRom,
+                                   "Unhandled CDROM 3.1: {:02x}", val);
                         }
                     }
                     // ATV1 register
                     2 => self.mixer.cd_left_to_spu_right = val,
                     // ADPCTL register
                     3 => debug!("CDROM Mixer apply {:02x}", val),
-                    _ => unimplemented(),
+                    _ => fatal!(shared, Device::CdRom,
+                                "write CDROM register {}.{} {:x}",
+                                offset, index, val),
                 },
-            _ => unimplemented(),
+            _ => fatal!(shared, Device::CdRom,
+                        "write CDROM register {}.{} {:x}",
+                        offset, index, val),
         }
     }
 
     /// The DMA can read the RX buffer one word at a time
-    pub fn dma_read_word(&mut self) -> u32 {
-        let b0 = self.read_byte() as u32;
-        let b1 = self.read_byte() as u32;
-        let b2 = self.read_byte() as u32;
-        let b3 = self.read_byte() as u32;
+    pub fn dma_read_word(&mut self, shared: &mut SharedState) -> u32 {
+        let b0 = self.read_byte(shared) as u32;
+        let b1 = self.read_byte(shared) as u32;
+        let b2 = self.read_byte(shared) as u32;
+        let b3 = self.read_byte(shared) as u32;
 
         // Pack in a little endian word
         b0 | (b1 << 8) | (b2 << 16) | (b3 << 24)
     }
 
     /// HSTS register read
-    fn host_status(&mut self) -> u8 {
+    fn host_status(&self) -> u8 {
         let mut r = self.index;
 
         // TODO: ADPCM busy (ADPBUSY)
@@ -413,7 +435,9 @@ impl CdRom {
     /// COMMAND register write
     fn set_command(&mut self, shared: &mut SharedState, cmd: u8) {
         if let Some(c) = self.command {
-            panic!("Nested CDC command! ({:02x} + {:02x})", c, cmd);
+            fatal!(shared, Device::CdRom,
+                   "Nested CDC command! ({:02x} + {:02x})", c, cmd);
+            return;
         }
 
         self.command = Some(cmd);
@@ -422,14 +446,17 @@ impl CdRom {
     }
 
     /// PARAMETER register write
-    fn set_parameter(&mut self, param: u8) {
+    fn set_parameter(&mut self, shared: &mut SharedState, param: u8) {
         if let Some(c) = self.command {
-            panic!("Parameter push during command {:02x})", c);
+            fatal!(shared, Device::CdRom,
+                   "Parameter push during command {:02x})", c);
+            return;
         }
 
         if self.host_params.is_full() {
             // Wraps around on real hardware
-            panic!("CDROM parameter FIFO overflow");
+            fatal!(shared, Device::CdRom, "CDROM parameter FIFO overflow");
+            return;
         }
 
         self.host_params.push(param);
@@ -446,7 +473,7 @@ impl CdRom {
     }
 
     /// HCHPCTL register write
-    fn set_host_chip_control(&mut self, ctrl: u8) {
+    fn set_host_chip_control(&mut self, shared: &mut SharedState, ctrl: u8) {
 
         let prev_active = self.rx_active;
 
@@ -480,7 +507,8 @@ impl CdRom {
         }
 
         if ctrl & 0x7f != 0 {
-            panic!("CDROM: unhandled HCHPCTL {:02x}", ctrl);
+            fatal!(shared, Device::CdRom,
+                   "CDROM: unhandled HCHPCTL {:02x}", ctrl);
         }
     }
 
@@ -513,6 +541,18 @@ impl CdRom {
 
                 let timer = timings::COMMAND_PENDING + variation;
 
+                if self.sub_cpu.async_command_pending() {
+                    // Not sure what's supposed to happen here, might
+                    // be command dependant. Can't really see why
+                    // anybody would want to start a new command
+                    // without waiting for the response to the
+                    // previous one though.
+                    fatal!(shared, Device::CdRom,
+                           "New CD command while still waiting for an \
+                            async response");
+                    return;
+                }
+
                 self.sub_cpu.start_command(timer);
                 self.predict_next_sync(shared);
             }
@@ -577,7 +617,7 @@ impl CdRom {
                 if self.host_params.is_empty() {
                     // We have all the parameters (if any), we can run
                     // the actual command
-                    self.execute_command();
+                    self.execute_command(shared);
 
                     self.sub_cpu.timer = timings::EXECUTION;
                     self.sub_cpu.sequence = SubCpuSequence::Execution;
@@ -663,7 +703,7 @@ impl CdRom {
     }
 
     /// Retreive a single byte from the RX buffer
-    fn read_byte(&mut self) -> u8 {
+    fn read_byte(&mut self, shared: &mut SharedState) -> u8 {
         let b = self.rx_buffer[self.rx_index as usize];
 
         if self.rx_active {
@@ -675,7 +715,7 @@ impl CdRom {
                 self.rx_active = false;
             }
         } else {
-            panic!("read byte while !rx_active");
+            fatal!(shared, Device::CdRom, "read byte while !rx_active");
         }
 
         b
@@ -693,11 +733,12 @@ impl CdRom {
 
     /// Execute a pending seek (if any). On the real console that
     /// would mean physically moving the read head.
-    fn do_seek(&mut self) {
+    fn do_seek(&mut self, shared: &mut SharedState) {
         // Make sure we don't end up in track1's pregap, I don't know
         // if it's ever useful? Needs special handling at least...
         if self.seek_target < Msf::from_bcd(0x00, 0x02, 0x00).unwrap() {
-            panic!("Seek to track. 1 pregap: {}", self.seek_target);
+            fatal!(shared, Device::CdRom,
+                   "Seek to track. 1 pregap: {}", self.seek_target);
         }
 
         self.position = self.seek_target;
@@ -705,9 +746,11 @@ impl CdRom {
     }
 
     /// Called when a new sector must be read
-    fn read_sector(&mut self) {
+    fn read_sector(&mut self, shared: &mut SharedState) {
         if self.read_pending {
-            panic!("Sector read while previous one is still pending");
+            fatal!(shared, Device::CdRom,
+                   "Sector read while previous one is still pending");
+            return;
         }
 
         let position = self.position;
@@ -717,9 +760,14 @@ impl CdRom {
             Some(ref mut d) =>
                 if let Err(e) = d.image().read_sector(&mut self.sector,
                                                       position) {
-                    panic!("Couldn't read sector: {}", e);
+                    fatal!(shared, Device::CdRom,
+                           "Couldn't read sector {}: {}", position, e);
+                    return;
                 },
-            None => panic!("Sector read without a disc"),
+            None => {
+                fatal!(shared, Device::CdRom, "Sector read without a disc");
+                return;
+            }
         }
 
         {
@@ -731,9 +779,12 @@ impl CdRom {
                     let data =
                         match self.sector.data_2352() {
                             Ok(d) => d,
-                            Err(e) =>
-                                panic!("Failed to read whole sector {}: {}",
-                                       position, e),
+                            Err(e) => {
+                                fatal!(shared, Device::CdRom,
+                                       "Failed to read whole sector {}: {}",
+                                       position, e);
+                                return;
+                            }
                         };
 
                     // Skip the sync pattern
@@ -743,9 +794,12 @@ impl CdRom {
                     let data =
                         match self.sector.mode2_xa_payload() {
                             Ok(d) => d as &[u8],
-                            Err(e) =>
-                                panic!("Failed to read sector {}: {}",
-                                       position, e),
+                            Err(e) => {
+                                fatal!(shared, Device::CdRom,
+                                       "Failed to read sector {}: {}",
+                                       position, e);
+                                return;
+                            }
                         };
 
                     if data.len() > 2048 {
@@ -772,7 +826,11 @@ impl CdRom {
         self.position =
             match self.position.next() {
                 Some(m) => m,
-                None => panic!("MSF overflow!"),
+                None => {
+                    fatal!(shared, Device::CdRom,
+                           "MSF overflow after sector {}", position);
+                    return;
+                }
             };
 
         self.read_pending = true;
@@ -803,9 +861,10 @@ impl CdRom {
 
     /// Run the command designated by `self.command`. Panics if
     /// `self.command` is None.
-    fn execute_command(&mut self) {
+    fn execute_command(&mut self, shared: &mut SharedState) {
 
-        let (min_param, max_param, handler): (u8, u8, fn(&mut CdRom)) =
+        let (min_param, max_param, handler):
+            (u8, u8, fn(&mut CdRom, &mut SharedState)) =
             match self.command.unwrap() {
                 0x01 => (0, 0, CdRom::cmd_get_stat),
                 0x02 => (3, 3, CdRom::cmd_set_loc),
@@ -825,22 +884,28 @@ impl CdRom {
                 // ReadS
                 0x1b => (0, 0, CdRom::cmd_read),
                 0x1e => (0, 0, CdRom::cmd_read_toc),
-                c => panic!("Unhandled CDROM command 0x{:02x} {:?}",
-                            c, self.sub_cpu.params),
+                c => {
+                    fatal!(shared, Device::CdRom,
+                           "Unhandled CDROM command 0x{:02x} {:?}",
+                           c, self.sub_cpu.params);
+                    return;
+                }
             };
 
         let nparams = self.sub_cpu.params.len();
 
         if nparams < min_param || nparams > max_param {
-            panic!("Wrong number of parameters for command {:02x} ({})",
+            fatal!(shared, Device::CdRom,
+                   "Wrong number of parameters for command {:02x} ({})",
                    self.command.unwrap(), nparams);
+            return;
         }
 
-        handler(self);
+        handler(self, shared);
     }
 
     /// Read the drive's status byte
-    fn cmd_get_stat(&mut self) {
+    fn cmd_get_stat(&mut self, _: &mut SharedState) {
         let status = self.drive_status();
 
         self.sub_cpu.response.push(status);
@@ -848,7 +913,7 @@ impl CdRom {
 
     /// Tell the CDROM controller where the next seek should take us
     /// (but do not physically perform the seek yet)
-    fn cmd_set_loc(&mut self) {
+    fn cmd_set_loc(&mut self, shared: &mut SharedState) {
 
         // Parameters are in BCD.
         let m = self.sub_cpu.params.pop();
@@ -859,8 +924,12 @@ impl CdRom {
             match Msf::from_bcd(m, s, f) {
                 Some(m) => m,
                 // XXX: what happens if invalid BCD is used?
-                None => panic!("Invalid MSF in set loc: {:02x}:{:02x}:{:02x}",
-                               m, s, f),
+                None => {
+                    fatal!(shared, Device::CdRom,
+                           "Invalid MSF in set loc: {:02x}:{:02x}:{:02x}",
+                           m, s, f);
+                    return;
+                }
             };
 
         self.seek_target_pending = true;
@@ -876,14 +945,14 @@ impl CdRom {
     /// will continue to the next sector (useful for streaming
     /// audio/movies). In our emulator we'll just pretend no error
     /// ever occurs.
-    fn cmd_read(&mut self) {
+    fn cmd_read(&mut self, shared: &mut SharedState) {
         if !self.read_state.is_idle() {
             warn!("CDROM READ while we're already reading");
         }
 
         if self.seek_target_pending {
             // XXX That should take some time...
-            self.do_seek();
+            self.do_seek(shared);
         }
 
         let read_delay = self.cycles_per_sector();
@@ -897,7 +966,7 @@ impl CdRom {
 
     /// Stop reading sectors but remain at the same position on the
     /// disc
-    fn cmd_pause(&mut self) {
+    fn cmd_pause(&mut self, _: &mut SharedState) {
 
         let status = self.drive_status();
 
@@ -928,7 +997,7 @@ impl CdRom {
     }
 
     /// Reinitialize the CD ROM controller
-    fn cmd_init(&mut self) {
+    fn cmd_init(&mut self, _: &mut SharedState) {
         let status = self.drive_status();
         self.sub_cpu.response.push(status);
 
@@ -961,21 +1030,21 @@ impl CdRom {
     }
 
     /// Mute CDROM audio playback
-    fn cmd_mute(&mut self) {
+    fn cmd_mute(&mut self, _: &mut SharedState) {
         let status = self.drive_status();
 
         self.sub_cpu.response.push(status);
     }
 
     /// Demute CDROM audio playback
-    fn cmd_demute(&mut self) {
+    fn cmd_demute(&mut self, _: &mut SharedState) {
         let status = self.drive_status();
 
         self.sub_cpu.response.push(status);
     }
 
     /// Filter for ADPCM sectors
-    fn cmd_set_filter(&mut self) {
+    fn cmd_set_filter(&mut self, _: &mut SharedState) {
 
         self.filter_file = self.sub_cpu.params.pop();
         self.filter_channel = self.sub_cpu.params.pop();
@@ -986,7 +1055,7 @@ impl CdRom {
     }
 
     /// Configure the behaviour of the CDROM drive
-    fn cmd_set_mode(&mut self) {
+    fn cmd_set_mode(&mut self, shared: &mut SharedState) {
 
         let mode = self.sub_cpu.params.pop();
 
@@ -1003,7 +1072,8 @@ impl CdRom {
            self.autopause ||
            self.report_interrupts ||
            self.sector_size_override {
-            panic!("CDROM: unhandled mode: {:02x}", mode);
+            fatal!(shared, Device::CdRom,
+                   "CDROM: unhandled mode: {:02x}", mode);
         }
 
         let status = self.drive_status();
@@ -1012,7 +1082,7 @@ impl CdRom {
     }
 
     /// Return various parameters of the CDROM controller
-    fn cmd_get_param(&mut self) {
+    fn cmd_get_param(&mut self, _: &mut SharedState) {
         let mut mode = 0u8;
 
         mode |= (self.double_speed as u8) << 7;
@@ -1035,7 +1105,7 @@ impl CdRom {
 
     /// Get the current position of the drive head by returning the
     /// contents of the Q subchannel
-    fn cmd_get_loc_p(&mut self) {
+    fn cmd_get_loc_p(&mut self, shared: &mut SharedState) {
         if self.position < Msf::from_bcd(0x00, 0x02, 0x00).unwrap() {
             // The values returned in the track 01 pregap are strange,
             // The absolute MSF seems correct but the track MSF looks
@@ -1043,7 +1113,8 @@ impl CdRom {
             //
             // For instance after seeking at 00:01:25 the track MSF
             // returned by GetLocP is 00:00:49 with my PAL Spyro disc.
-            panic!("GetLocP while in track1 pregap");
+            fatal!(shared, Device::CdRom, "GetLocP while in track1 pregap");
+            return;
         }
 
         // Fixme: All this data should be extracted from the
@@ -1078,8 +1149,8 @@ impl CdRom {
     }
 
     /// Execute seek. Target is given by previous "set loc" command.
-    fn cmd_seek_l(&mut self) {
-        self.do_seek();
+    fn cmd_seek_l(&mut self, shared: &mut SharedState) {
+        self.do_seek(shared);
 
         let status = self.drive_status();
 
@@ -1104,20 +1175,24 @@ impl CdRom {
 
     /// The test command can do a whole bunch of stuff, the first
     /// parameter says what
-    fn cmd_test(&mut self) {
+    fn cmd_test(&mut self, shared: &mut SharedState) {
         if self.sub_cpu.params.len() != 1 {
-            panic!("Unexpected number of parameters for CDROM test command: {}",
+            fatal!(shared, Device::CdRom,
+                   "Unexpected number of parameters for CDROM test \
+                    command: {}",
                    self.sub_cpu.params.len());
+            return;
         }
 
         match self.sub_cpu.params.pop() {
              0x20 => self.test_version(),
-             n    => panic!("Unhandled CDROM test subcommand 0x{:02x}", n),
+             n    => fatal!(shared, Device::CdRom,
+                            "Unhandled CDROM test subcommand 0x{:02x}", n),
         }
     }
 
     /// Instruct the CD drive to read the table of contents
-    fn cmd_read_toc(&mut self) {
+    fn cmd_read_toc(&mut self, _: &mut SharedState) {
         let status = self.drive_status();
 
         self.sub_cpu.response.push(status);
@@ -1139,7 +1214,7 @@ impl CdRom {
     /// Read the CD-ROM's identification string. This is how the BIOS
     /// checks that the disc is an official PlayStation disc (and not
     /// a copy) and handles region locking.
-    fn cmd_get_id(&mut self) {
+    fn cmd_get_id(&mut self, _: &mut SharedState) {
 
         match self.disc {
             Some(_) => {
@@ -1161,7 +1236,25 @@ impl CdRom {
 
     fn async_get_id(&mut self) -> u32 {
         // If we're here we must have a disc
-        let disc = self.disc.as_ref().unwrap();
+        let region = self.disc.as_ref().unwrap().region();
+
+        let region =
+            match region {
+                Some(r) => r,
+                None => {
+                    // Reply like the drive does for an unlicensed
+                    // mode 2 disc, the BIOS will refuse to boot it
+                    let status = self.drive_status();
+
+                    self.sub_cpu.response.push_slice(&[
+                        status | 0x08, 0x80, 0x20, 0x00,
+                        0x00, 0x00, 0x00, 0x00]);
+
+                    self.sub_cpu.irq_code = IrqCode::Error;
+
+                    return timings::GET_ID_RX_PUSH;
+                }
+            };
 
         let response = [
             // Status + bit 3 if unlicensed/audio
@@ -1178,7 +1271,7 @@ impl CdRom {
             // Region string: "SCEI" for japan, "SCEE" for
             // Europe and "SCEA" for US.
             b'S', b'C', b'E',
-            match disc.region() {
+            match region {
                 Region::Japan => b'I',
                 Region::NorthAmerica => b'A',
                 Region::Europe => b'E',
@@ -1264,6 +1357,13 @@ impl Fifo {
         }
     }
 
+    /// Return the next element without removing it
+    fn peek(&self) -> u8 {
+        let idx = (self.read_idx & 0xf) as usize;
+
+        self.buffer[idx]
+    }
+
     fn pop(&mut self) -> u8 {
         let idx = (self.read_idx & 0xf) as usize;
 
@@ -1350,14 +1450,7 @@ impl SubCpu {
 
     fn start_command(&mut self, pending_delay: u32) {
         assert!(self.in_command() == false);
-
-        if self.async_command_pending() {
-            // Not sure what's supposed to happen here, might be
-            // command dependant. Can't really see why anybody would
-            // want to start a new command without waiting for the
-            // response to the previous one though.
-            panic!("New CD command while still waiting for an async response");
-        }
+        assert!(self.async_command_pending() == false);
 
         self.sequence = SubCpuSequence::CommandPending;
         self.timer = pending_delay;
@@ -0,0 +1,722 @@
+//! GameShark / Action Replay cheat code engine.
+//!
+//! Each code is made of a 32bit "address" word whose top byte gives
+//! the code type followed by a 16bit value, usually written as
+//! `XXXXXXXX YYYY`. The supported code types are:
+//!
+//! * `80aaaaaa vvvv`: write the 16bit value `vvvv` at `aaaaaa`
+//! * `30aaaaaa 00vv`: write the 8bit value `vv` at `aaaaaa`
+//! * `10aaaaaa vvvv`/`11aaaaaa vvvv`: increment/decrement the 16bit
+//!   value at `aaaaaa` by `vvvv`
+//! * `20aaaaaa 00vv`/`21aaaaaa 00vv`: increment/decrement the 8bit
+//!   value at `aaaaaa` by `vv`
+//! * `D0aaaaaa vvvv`/`D1aaaaaa vvvv`: only run the next code if the
+//!   16bit value at `aaaaaa` is equal/different to `vvvv`
+//! * `E0aaaaaa 00vv`/`E1aaaaaa 00vv`: same as above for 8bit values
+//! * `5000nnss vvvv`: serial repeater, the next code must be an `80`
+//!   or `30` write which is repeated `nn` times, incrementing the
+//!   address by `ss` and the value by `vvvv` each time
+//!
+//! The codes don't hook into the emulated CPU, the frontend is
+//! expected to call `CheatList::apply` once per frame, like the real
+//! cartridge which patched the RAM on every vertical blanking
+//! interrupt.
+
+use std::fmt;
+use std::io;
+use std::io::Read;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use memory::Byte;
+use memory::Ram;
+use cdrom::disc::SerialNumber;
+
+/// A single decoded cheat code
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Code {
+    /// `80`: 16bit constant write
+    Write16 { addr: u32, val: u16 },
+    /// `30`: 8bit constant write
+    Write8 { addr: u32, val: u8 },
+    /// `10`: 16bit increment
+    Increment16 { addr: u32, val: u16 },
+    /// `11`: 16bit decrement
+    Decrement16 { addr: u32, val: u16 },
+    /// `20`: 8bit increment
+    Increment8 { addr: u32, val: u8 },
+    /// `21`: 8bit decrement
+    Decrement8 { addr: u32, val: u8 },
+    /// `D0`: run the next code if the 16bit value is equal
+    Equal16 { addr: u32, val: u16 },
+    /// `D1`: run the next code if the 16bit value is different
+    NotEqual16 { addr: u32, val: u16 },
+    /// `E0`: run the next code if the 8bit value is equal
+    Equal8 { addr: u32, val: u8 },
+    /// `E1`: run the next code if the 8bit value is different
+    NotEqual8 { addr: u32, val: u8 },
+    /// `50`: repeat the following write `count` times
+    Serial { count: u8, addr_step: u8, val_step: u16 },
+}
+
+impl Code {
+    /// Parse a code of the form `XXXXXXXX YYYY`. Whitespace between
+    /// the two words is optional.
+    pub fn parse(code: &str) -> Result<Code, String> {
+        let digits: String =
+            code.chars().filter(|c| !c.is_whitespace()).collect();
+
+        if digits.len() != 12 || !digits.chars().all(|c| c.is_digit(16)) {
+            return Err(format!("Invalid code '{}'", code));
+        }
+
+        // We've just validated the digits, these can't fail
+        let op = u32::from_str_radix(&digits[0..8], 16).unwrap();
+        let val = u16::from_str_radix(&digits[8..12], 16).unwrap();
+
+        let addr = op & 0xffffff;
+        let byte = val as u8;
+
+        let code =
+            match op >> 24 {
+                0x80 => Code::Write16 { addr: addr, val: val },
+                0x30 => Code::Write8 { addr: addr, val: byte },
+                0x10 => Code::Increment16 { addr: addr, val: val },
+                0x11 => Code::Decrement16 { addr: addr, val: val },
+                0x20 => Code::Increment8 { addr: addr, val: byte },
+                0x21 => Code::Decrement8 { addr: addr, val: byte },
+                0xd0 => Code::Equal16 { addr: addr, val: val },
+                0xd1 => Code::NotEqual16 { addr: addr, val: val },
+                0xe0 => Code::Equal8 { addr: addr, val: byte },
+                0xe1 => Code::NotEqual8 { addr: addr, val: byte },
+                0x50 => Code::Serial {
+                    count: (op >> 8) as u8,
+                    addr_step: op as u8,
+                    val_step: val,
+                },
+                t => return Err(format!("Unsupported code type {:02X}", t)),
+            };
+
+        Ok(code)
+    }
+
+    /// Run a single code. Returns false if the code is a condition
+    /// that wasn't met.
+    fn run(self, ram: &mut Ram) -> bool {
+        match self {
+            Code::Write16 { addr, val } => store16(ram, addr, val),
+            Code::Write8 { addr, val } => store8(ram, addr, val),
+            Code::Increment16 { addr, val } => {
+                let v = load16(ram, addr).wrapping_add(val);
+                store16(ram, addr, v);
+            }
+            Code::Decrement16 { addr, val } => {
+                let v = load16(ram, addr).wrapping_sub(val);
+                store16(ram, addr, v);
+            }
+            Code::Increment8 { addr, val } => {
+                let v = load8(ram, addr).wrapping_add(val);
+                store8(ram, addr, v);
+            }
+            Code::Decrement8 { addr, val } => {
+                let v = load8(ram, addr).wrapping_sub(val);
+                store8(ram, addr, v);
+            }
+            Code::Equal16 { addr, val } => return load16(ram, addr) == val,
+            Code::NotEqual16 { addr, val } => return load16(ram, addr) != val,
+            Code::Equal8 { addr, val } => return load8(ram, addr) == val,
+            Code::NotEqual8 { addr, val } => return load8(ram, addr) != val,
+            // Handled in `Cheat::apply` since it needs the next code
+            Code::Serial { .. } => (),
+        }
+
+        true
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (op, addr, val) =
+            match *self {
+                Code::Write16 { addr, val } => (0x80, addr, val),
+                Code::Write8 { addr, val } => (0x30, addr, val as u16),
+                Code::Increment16 { addr, val } => (0x10, addr, val),
+                Code::Decrement16 { addr, val } => (0x11, addr, val),
+                Code::Increment8 { addr, val } => (0x20, addr, val as u16),
+                Code::Decrement8 { addr, val } => (0x21, addr, val as u16),
+                Code::Equal16 { addr, val } => (0xd0, addr, val),
+                Code::NotEqual16 { addr, val } => (0xd1, addr, val),
+                Code::Equal8 { addr, val } => (0xe0, addr, val as u16),
+                Code::NotEqual8 { addr, val } => (0xe1, addr, val as u16),
+                Code::Serial { count, addr_step, val_step } =>
+                    (0x50,
+                     ((count as u32) << 8) | addr_step as u32,
+                     val_step),
+            };
+
+        write!(f, "{:02X}{:06X} {:04X}", op, addr, val)
+    }
+}
+
+/// A named group of codes that can be toggled as a whole
+#[derive(Clone, Debug)]
+pub struct Cheat {
+    pub name: String,
+    pub enabled: bool,
+    codes: Vec<Code>,
+    /// Source line of each code, used to report errors
+    lines: Vec<usize>,
+}
+
+impl Cheat {
+    /// Build an enabled cheat from a list of codes, one per line
+    pub fn new(name: &str, codes: &str) -> Result<Cheat, Error> {
+        let mut cheat = Cheat {
+            name: name.into(),
+            enabled: true,
+            codes: Vec::new(),
+            lines: Vec::new(),
+        };
+
+        for (i, line) in codes.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            try!(cheat.push(line, i + 1));
+        }
+
+        try!(cheat.validate());
+
+        Ok(cheat)
+    }
+
+    pub fn codes(&self) -> &[Code] {
+        &self.codes
+    }
+
+    /// Apply the cheat to `ram`, regardless of `enabled`
+    pub fn apply(&self, ram: &mut Ram) {
+        let mut codes = self.codes.iter().cloned();
+
+        // Set when the previous condition failed
+        let mut skip = false;
+
+        while let Some(code) = codes.next() {
+            // The repeater and its write are handled as a single code
+            // by the conditionals
+            let repeated =
+                match code {
+                    Code::Serial { .. } => codes.next(),
+                    _ => None,
+                };
+
+            if skip {
+                skip = false;
+                continue;
+            }
+
+            match (code, repeated) {
+                (Code::Serial { count, addr_step, val_step }, Some(w)) => {
+                    let addr_step = addr_step as u32;
+
+                    for i in 0..count as u32 {
+                        let offset = addr_step.wrapping_mul(i);
+                        let inc = val_step.wrapping_mul(i as u16);
+
+                        match w {
+                            Code::Write16 { addr, val } =>
+                                store16(ram,
+                                        addr.wrapping_add(offset),
+                                        val.wrapping_add(inc)),
+                            Code::Write8 { addr, val } =>
+                                store8(ram,
+                                       addr.wrapping_add(offset),
+                                       val.wrapping_add(inc as u8)),
+                            // Rejected by `validate`
+                            _ => (),
+                        }
+                    }
+                }
+                _ => skip = !code.run(ram),
+            }
+        }
+    }
+
+    /// Parse `line` and append it to the code list
+    fn push(&mut self, line: &str, line_no: usize) -> Result<(), Error> {
+        match Code::parse(line) {
+            Ok(c) => {
+                self.codes.push(c);
+                self.lines.push(line_no);
+                Ok(())
+            }
+            Err(e) => Err(Error::Parse { line: line_no, message: e }),
+        }
+    }
+
+    /// Make sure that every serial repeater is followed by a write
+    fn validate(&self) -> Result<(), Error> {
+        let mut codes = self.codes.iter().zip(self.lines.iter());
+
+        while let Some((code, &line_no)) = codes.next() {
+            if let Code::Serial { .. } = *code {
+                match codes.next() {
+                    Some((&Code::Write16 { .. }, _)) => (),
+                    Some((&Code::Write8 { .. }, _)) => (),
+                    _ => {
+                        let message =
+                            format!("'{}': serial repeater must be followed \
+                                     by an 80 or 30 code in cheat '{}'",
+                                    code, self.name);
+
+                        return Err(Error::Parse {
+                            line: line_no,
+                            message: message,
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// List of cheats for a game
+pub struct CheatList {
+    cheats: Vec<Cheat>,
+}
+
+impl CheatList {
+    /// Create an empty cheat list
+    pub fn new() -> CheatList {
+        CheatList {
+            cheats: Vec::new(),
+        }
+    }
+
+    /// Load a cheat file, see `CheatList::parse` for the format
+    pub fn load_file(path: &Path) -> Result<CheatList, Error> {
+        let mut f = try!(File::open(path));
+
+        let mut source = String::new();
+
+        try!(f.read_to_string(&mut source));
+
+        CheatList::parse(&source)
+    }
+
+    /// Location of the cheat file for the game `serial` in `dir`:
+    /// `dir/<serial>.cht`
+    pub fn game_file(dir: &Path, serial: SerialNumber) -> PathBuf {
+        dir.join(format!("{}.cht", serial))
+    }
+
+    /// Parse a list of cheats. Each cheat starts with its name in
+    /// brackets followed by its codes, one per line. Everything
+    /// following a `#` is ignored. All the cheats are enabled:
+    ///
+    /// ```text
+    /// # Comment
+    /// [Infinite lives]
+    /// 800B4C5A 0009
+    ///
+    /// [Max gold when in town]
+    /// D00A0100 0002
+    /// 800A2000 FFFF
+    /// ```
+    pub fn parse(source: &str) -> Result<CheatList, Error> {
+        let mut list = CheatList::new();
+
+        for (i, line) in source.lines().enumerate() {
+            let line_no = i + 1;
+
+            let line =
+                match line.find('#') {
+                    Some(p) => &line[..p],
+                    None => line,
+                };
+
+            let line = line.trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                if let Some(c) = list.cheats.last() {
+                    try!(c.validate());
+                }
+
+                list.cheats.push(Cheat {
+                    name: line[1..line.len() - 1].trim().into(),
+                    enabled: true,
+                    codes: Vec::new(),
+                    lines: Vec::new(),
+                });
+
+                continue;
+            }
+
+            match list.cheats.last_mut() {
+                Some(c) => try!(c.push(line, line_no)),
+                None => return Err(Error::Parse {
+                    line: line_no,
+                    message: "Code outside of a cheat".into(),
+                }),
+            }
+        }
+
+        if let Some(c) = list.cheats.last() {
+            try!(c.validate());
+        }
+
+        Ok(list)
+    }
+
+    pub fn add(&mut self, cheat: Cheat) {
+        self.cheats.push(cheat);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Cheat {
+        self.cheats.remove(index)
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    pub fn len(&self) -> usize {
+        self.cheats.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    /// Enable or disable the cheat at `index`
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
+        self.cheats[index].enabled = enabled;
+    }
+
+    /// Toggle the cheat at `index`, returns the new state
+    pub fn toggle(&mut self, index: usize) -> bool {
+        let cheat = &mut self.cheats[index];
+
+        cheat.enabled = !cheat.enabled;
+
+        cheat.enabled
+    }
+
+    /// Apply all the enabled cheats to `ram`. Should be called once
+    /// per frame.
+    pub fn apply(&self, ram: &mut Ram) {
+        for cheat in self.cheats.iter().filter(|c| c.enabled) {
+            cheat.apply(ram);
+        }
+    }
+}
+
+/// The halfword accesses are split in bytes to avoid going out of
+/// bounds when a code targets the last byte of the RAM
+fn load16(ram: &Ram, addr: u32) -> u16 {
+    let lo = ram.load::<Byte>(addr);
+    let hi = ram.load::<Byte>(addr.wrapping_add(1));
+
+    (lo | (hi << 8)) as u16
+}
+
+fn store16(ram: &mut Ram, addr: u32, val: u16) {
+    ram.store::<Byte>(addr, val as u32);
+    ram.store::<Byte>(addr.wrapping_add(1), (val >> 8) as u32);
+}
+
+fn load8(ram: &Ram, addr: u32) -> u8 {
+    ram.load::<Byte>(addr) as u8
+}
+
+fn store8(ram: &mut Ram, addr: u32, val: u8) {
+    ram.store::<Byte>(addr, val as u32);
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Error while reading the cheat file
+    IoError(io::Error),
+    /// Invalid cheat code
+    Parse {
+        /// Line where the error occured, starting at 1
+        line: usize,
+        message: String,
+    },
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::IoError(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::IoError(ref e) => write!(f, "I/O error: {}", e),
+            Error::Parse { line, ref message } =>
+                write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+#[test]
+fn cheat_code_parse() {
+    let codes = [
+        ("800A0000 1234", Code::Write16 { addr: 0xa0000, val: 0x1234 }),
+        ("300A0010 00FF", Code::Write8 { addr: 0xa0010, val: 0xff }),
+        ("100A0000 0001", Code::Increment16 { addr: 0xa0000, val: 1 }),
+        ("110A0000 0001", Code::Decrement16 { addr: 0xa0000, val: 1 }),
+        ("200A0000 0001", Code::Increment8 { addr: 0xa0000, val: 1 }),
+        ("210A0000 0001", Code::Decrement8 { addr: 0xa0000, val: 1 }),
+        ("D00A0000 1234", Code::Equal16 { addr: 0xa0000, val: 0x1234 }),
+        ("D10A0000 1234", Code::NotEqual16 { addr: 0xa0000, val: 0x1234 }),
+        ("E00A0000 0012", Code::Equal8 { addr: 0xa0000, val: 0x12 }),
+        ("E10A0000 0012", Code::NotEqual8 { addr: 0xa0000, val: 0x12 }),
+        ("50000304 0010", Code::Serial {
+            count: 3,
+            addr_step: 4,
+            val_step: 0x10,
+        }),
+    ];
+
+    for &(s, code) in &codes {
+        assert!(Code::parse(s) == Ok(code));
+        assert!(code.to_string() == s);
+    }
+
+    // Whitespace is optional and the digits are case insensitive
+    assert!(Code::parse("800a0000\t12 34") ==
+            Ok(Code::Write16 { addr: 0xa0000, val: 0x1234 }));
+
+    // The high byte of the value is ignored by the 8bit codes
+    assert!(Code::parse("300A0010 12FF").unwrap().to_string() ==
+            "300A0010 00FF");
+
+    assert!(Code::parse("800A000 0000") ==
+            Err("Invalid code '800A000 0000'".into()));
+    assert!(Code::parse("800A0000 00000").is_err());
+    assert!(Code::parse("800A0000 00G0").is_err());
+    assert!(Code::parse("").is_err());
+    assert!(Code::parse("FF000000 0000") ==
+            Err("Unsupported code type FF".into()));
+}
+
+#[test]
+fn cheat_writes() {
+    let mut ram = Ram::new();
+
+    let cheat = Cheat::new("Writes",
+                           "800A0000 1234\n\
+                            300A0010 00FF\n\
+                            100A0000 0001\n\
+                            210A0010 0001\n\
+                            \n\
+                            110A0020 0001\n\
+                            200A0030 0040\n\
+                            100A0040 3536\n").unwrap();
+
+    cheat.apply(&mut ram);
+
+    assert!(load16(&ram, 0xa0000) == 0x1235);
+    assert!(load8(&ram, 0xa0010) == 0xfe);
+    assert!(load16(&ram, 0xa0020) == 0xcac9);
+    // Arithmetic codes wrap around
+    assert!(load8(&ram, 0xa0030) == 0x0a);
+    assert!(load16(&ram, 0xa0040) == 0);
+
+    // The codes run again every frame
+    cheat.apply(&mut ram);
+
+    assert!(load16(&ram, 0xa0000) == 0x1235);
+    assert!(load16(&ram, 0xa0020) == 0xcac8);
+
+    // Halfword accesses at the end of the RAM wrap around
+    let cheat = Cheat::new("RAM end", "801FFFFF BEEF\n101FFFFF 0001").unwrap();
+
+    cheat.apply(&mut ram);
+
+    assert!(load8(&ram, 0x1fffff) == 0xf0);
+    assert!(load8(&ram, 0) == 0xbe);
+}
+
+#[test]
+fn cheat_conditionals() {
+    let mut ram = Ram::new();
+
+    store16(&mut ram, 0xa0000, 0x1234);
+    store8(&mut ram, 0xa0010, 0x56);
+
+    let cheat = Cheat::new("Conditionals",
+                           "D00A0000 1234\n\
+                            800A0100 0001\n\
+                            D00A0000 4321\n\
+                            800A0102 0001\n\
+                            D10A0000 1234\n\
+                            800A0104 0001\n\
+                            D10A0000 4321\n\
+                            800A0106 0001\n\
+                            E00A0010 0056\n\
+                            300A0108 0001\n\
+                            E00A0010 0065\n\
+                            300A0109 0001\n\
+                            E10A0010 0056\n\
+                            300A010A 0001\n\
+                            E10A0010 0065\n\
+                            300A010B 0001\n").unwrap();
+
+    cheat.apply(&mut ram);
+
+    assert!(load16(&ram, 0xa0100) == 1);
+    assert!(load16(&ram, 0xa0102) == 0xcaca);
+    assert!(load16(&ram, 0xa0104) == 0xcaca);
+    assert!(load16(&ram, 0xa0106) == 1);
+    assert!(load8(&ram, 0xa0108) == 1);
+    assert!(load8(&ram, 0xa0109) == 0xca);
+    assert!(load8(&ram, 0xa010a) == 0xca);
+    assert!(load8(&ram, 0xa010b) == 1);
+}
+
+#[test]
+fn cheat_conditional_serial() {
+    let mut ram = Ram::new();
+
+    let cheat = Cheat::new("Conditional repeat",
+                           "E00A0000 0001\n\
+                            50000204 0001\n\
+                            300A0300 0010\n\
+                            800A0400 0002\n").unwrap();
+
+    // The condition skips the repeater along with its write but not
+    // the following code
+    cheat.apply(&mut ram);
+
+    assert!(load8(&ram, 0xa0300) == 0xca);
+    assert!(load8(&ram, 0xa0304) == 0xca);
+    assert!(load16(&ram, 0xa0400) == 2);
+
+    store8(&mut ram, 0xa0000, 1);
+
+    cheat.apply(&mut ram);
+
+    assert!(load8(&ram, 0xa0300) == 0x10);
+    assert!(load8(&ram, 0xa0304) == 0x11);
+    assert!(load8(&ram, 0xa0308) == 0xca);
+}
+
+#[test]
+fn cheat_serial_repeater() {
+    let mut ram = Ram::new();
+
+    let cheat = Cheat::new("Serial", "50000304 0010\n800A0200 0100").unwrap();
+
+    cheat.apply(&mut ram);
+
+    for i in 0..3 {
+        assert!(load16(&ram, 0xa0200 + i * 4) == 0x100 + i as u16 * 0x10);
+    }
+    assert!(load16(&ram, 0xa020c) == 0xcaca);
+
+    // The value wraps around
+    let cheat = Cheat::new("Serial 8", "50000401 0001\n300A0300 00FE").unwrap();
+
+    cheat.apply(&mut ram);
+
+    assert!(load16(&ram, 0xa0300) == 0xfffe);
+    assert!(load16(&ram, 0xa0302) == 0x0100);
+
+    let cheat = Cheat::new("Empty", "50000004 0001\n800A0400 0001").unwrap();
+
+    cheat.apply(&mut ram);
+
+    assert!(load16(&ram, 0xa0400) == 0xcaca);
+
+    // Repeated 16bit writes at the end of the RAM wrap around
+    let cheat = Cheat::new("RAM end", "50000202 0000\n801FFFFE ABCD").unwrap();
+
+    cheat.apply(&mut ram);
+
+    assert!(load16(&ram, 0x1ffffe) == 0xabcd);
+    assert!(load16(&ram, 0) == 0xabcd);
+}
+
+#[test]
+fn cheat_list_errors() {
+    let parse_error = |source: &str| {
+        match CheatList::parse(source) {
+            Err(Error::Parse { line, message }) => (line, message),
+            _ => panic!("'{}' should not parse", source),
+        }
+    };
+
+    assert!(parse_error("800A0000 1234\n") ==
+            (1, "Code outside of a cheat".into()));
+    assert!(parse_error("# Comment\n[Test]\n800A000Z 1234\n") ==
+            (3, "Invalid code '800A000Z 1234'".into()));
+    assert!(parse_error("[Test]\nFF000000 0000 # Comment\n") ==
+            (2, "Unsupported code type FF".into()));
+
+    let repeater =
+        "'50000304 0010': serial repeater must be followed by an 80 or \
+         30 code in cheat 'Test'".to_string();
+
+    assert!(parse_error("[Test]\n50000304 0010\n\n[Next]\n800A0000 0001\n") ==
+            (2, repeater.clone()));
+    assert!(parse_error("[Test]\n50000304 0010\nD00A0000 0000\n") ==
+            (2, repeater.clone()));
+    assert!(parse_error("[Test]\n800A0000 1234\n50000304 0010") ==
+            (3, repeater));
+
+    let e = Cheat::new("Bad", "800A0000 1234\nFF000000 0000").unwrap_err();
+
+    assert!(e.to_string() == "line 2: Unsupported code type FF");
+
+    match CheatList::load_file(Path::new("/nonexistent/cheats.cht")) {
+        Err(Error::IoError(_)) => (),
+        _ => panic!("Missing file not reported"),
+    }
+}
+
+#[test]
+fn cheat_list_toggle() {
+    let mut cheats =
+        CheatList::parse("[A]\n800A0000 0001\n[ B ]\n800A0002 0002\n")
+        .unwrap();
+
+    assert!(cheats.len() == 2);
+    assert!(cheats.cheats()[1].name == "B");
+
+    assert!(!cheats.toggle(1));
+
+    let mut ram = Ram::new();
+
+    cheats.apply(&mut ram);
+
+    assert!(load16(&ram, 0xa0000) == 1);
+    assert!(load16(&ram, 0xa0002) == 0xcaca);
+
+    cheats.set_enabled(0, false);
+    cheats.set_enabled(1, true);
+
+    let mut ram = Ram::new();
+
+    cheats.apply(&mut ram);
+
+    assert!(load16(&ram, 0xa0000) == 0xcaca);
+    assert!(load16(&ram, 0xa0002) == 2);
+
+    assert!(cheats.remove(0).name == "A");
+
+    cheats.add(Cheat::new("C", "").unwrap());
+
+    assert!(cheats.len() == 2);
+    assert!(CheatList::new().is_empty());
+}
@@ -0,0 +1,112 @@
+//! Instruction cache isolation and tag test mode tests. The test
+//! programs run from KSEG1 so that they don't go through the
+//! instruction cache themselves.
+
+use gpu::{Gpu, VideoClock};
+use memory::{Interconnect, Word};
+use debugger::DummyDebugger;
+use shared::SharedState;
+use bios::Bios;
+
+use super::{Cpu, Instruction};
+use test_utils::{DummyRenderer, write_blob, read};
+
+/// Maximum number of instructions executed by a test program
+const TIMEOUT: usize = 1_000_000;
+
+fn run(cpu: &mut Cpu) {
+    let mut shared = SharedState::new();
+    let mut debugger = DummyDebugger;
+    let mut renderer = DummyRenderer;
+
+    cpu.set_pc(0xa0100000);
+
+    let mut timeout = true;
+    for _ in 0..TIMEOUT {
+        if (cpu.pc & 0x0fffffff) == 0xeadbee0 {
+            timeout = false;
+            break;
+        }
+        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer)
+            .unwrap();
+    }
+    assert!(timeout == false);
+}
+
+fn new_cpu() -> Cpu {
+    let bios = Bios::dummy();
+    let gpu = Gpu::new(VideoClock::Ntsc);
+    let inter = Interconnect::new(bios, gpu, None);
+
+    Cpu::new(inter)
+}
+
+#[test]
+fn test_isolated_cache_load_store() {
+    let mut cpu = new_cpu();
+
+    write_blob(&mut cpu, 0x80100000,
+               &[0x3c08fffe,   // lui   t0, 0xfffe
+                 0x35080130,   // ori   t0, t0, 0x130
+                 0x34090800,   // ori   t1, zero, 0x800
+                 0xad090000,   // sw    t1, 0(t0)
+                 0x3c0a0001,   // lui   t2, 0x1
+                 0x408a6000,   // mtc0  t2, $12
+                 0x3c0b1234,   // lui   t3, 0x1234
+                 0x356b5678,   // ori   t3, t3, 0x5678
+                 0xac0b0024,   // sw    t3, 0x24(zero)
+                 0x8c0c0024,   // lw    t4, 0x24(zero)
+                 0x900d0025,   // lbu   t5, 0x25(zero)
+                 0x40806000,   // mtc0  zero, $12
+                 0x8c0e0024,   // lw    t6, 0x24(zero)
+                 0x00000000,   // nop
+                 0x0bab6fb8,   // j     0x0eadbee0
+                 0x00000000]); // nop
+
+    run(&mut cpu);
+
+    // The store ended up in the cache
+    assert!(cpu.icache[2].instruction(1).0 == 0x12345678);
+    // Loads from the isolated cache return the cache data
+    assert!(cpu.regs[12] == 0x12345678);
+    assert!(cpu.regs[13] == 0x56);
+    // RAM is untouched
+    assert!(cpu.regs[14] == 0xcacacaca);
+    assert!(read::<Word>(&cpu, 0x24) == 0xcacacaca);
+}
+
+#[test]
+fn test_tag_test_invalidate() {
+    let mut cpu = new_cpu();
+
+    cpu.icache[2].set_tag_valid(0x80005020, 0xf);
+    cpu.icache[2].set_instruction(0, Instruction(0x1234));
+
+    write_blob(&mut cpu, 0x80100000,
+               &[0x3c08fffe,   // lui   t0, 0xfffe
+                 0x35080130,   // ori   t0, t0, 0x130
+                 0x34090804,   // ori   t1, zero, 0x804
+                 0xad090000,   // sw    t1, 0(t0)
+                 0x3c0a0001,   // lui   t2, 0x1
+                 0x408a6000,   // mtc0  t2, $12
+                 0xac001020,   // sw    zero, 0x1020(zero)
+                 0x40806000,   // mtc0  zero, $12
+                 0x00000000,   // nop
+                 0x0bab6fb8,   // j     0x0eadbee0
+                 0x00000000]); // nop
+
+    run(&mut cpu);
+
+    let line = &cpu.icache[2];
+
+    // The tag is set from the address and all the words are
+    // invalidated
+    assert!(line.tag() == 0x1000);
+    for i in 0..4 {
+        assert!(!line.valid(i));
+    }
+
+    // The data is untouched
+    assert!(line.instruction(0).0 == 0x1234);
+    assert!(read::<Word>(&cpu, 0x1020) == 0xcacacaca);
+}
@@ -0,0 +1,283 @@
+//! Shadow call stack used to generate guest backtraces.
+//!
+//! The MIPS architecture doesn't have a hardware call stack so we
+//! track function calls (`jal`/`jalr`) and returns (`jr`) as they're
+//! executed. Exceptions are treated like calls to the handler which
+//! return either through a `jr` to EPC or an `rfe`.
+//!
+//! The same stack is used by the profiler to attribute cycles to
+//! functions.
+
+use std::fmt;
+
+use debugger::symbols::SymbolTable;
+
+/// Entry in the shadow call stack
+#[derive(Clone, Copy, Debug, RustcDecodable, RustcEncodable)]
+pub struct CallFrame {
+    /// Address of the called function or exception handler
+    pub target: u32,
+    /// Address of the call instruction (or of the instruction that
+    /// was interrupted by the exception)
+    pub caller: u32,
+    /// Address we expect the function to return to
+    pub return_addr: u32,
+    /// True if this frame was created by an exception
+    pub exception: bool,
+}
+
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct CallStack {
+    /// Frames, outermost first
+    frames: Vec<CallFrame>,
+}
+
+impl CallStack {
+    pub fn new() -> CallStack {
+        CallStack {
+            frames: Vec::new(),
+        }
+    }
+
+    /// Current frames, outermost first
+    pub fn frames(&self) -> &[CallFrame] {
+        &self.frames
+    }
+
+    fn push(&mut self, frame: CallFrame) {
+        if self.frames.len() >= MAX_DEPTH {
+            // We probably missed some returns (longjmp, thread
+            // switches...), forget about the outermost frame.
+            self.frames.remove(0);
+        }
+
+        self.frames.push(frame);
+    }
+
+    /// Function call from `caller` to `target`
+    pub fn call(&mut self, caller: u32, target: u32, return_addr: u32) {
+        self.push(CallFrame {
+            target: target,
+            caller: caller,
+            return_addr: return_addr,
+            exception: false,
+        });
+    }
+
+    /// Exception raised while running `caller`, `epc` is the address
+    /// the handler should return to.
+    pub fn exception(&mut self, caller: u32, handler: u32, epc: u32) {
+        self.push(CallFrame {
+            target: handler,
+            caller: caller,
+            return_addr: epc,
+            exception: true,
+        });
+    }
+
+    /// Jump to the address contained in a register. If it matches
+    /// the return address of a frame we unwind the stack up to it.
+    pub fn jump_register(&mut self, target: u32) {
+        let pos = self.frames.iter().rposition(|f| f.return_addr == target);
+
+        if let Some(pos) = pos {
+            self.frames.truncate(pos);
+        }
+    }
+
+    /// Return from exception. The exception handler usually returns
+    /// with a `jr` to EPC with the RFE in the delay slot, in which
+    /// case the frame will already be gone. Otherwise (for instance
+    /// when returning to EPC + 4 after a syscall) we unwind to the
+    /// innermost exception frame if it's at the top of the stack.
+    pub fn return_from_exception(&mut self) {
+        let top_is_exception =
+            self.frames.last().map(|f| f.exception).unwrap_or(false);
+
+        if top_is_exception {
+            self.frames.pop();
+        }
+    }
+
+    /// Build a backtrace, `pc` being the address of the instruction
+    /// currently being executed
+    pub fn backtrace(&self, pc: u32) -> Backtrace {
+        let mut entries = Vec::with_capacity(self.frames.len() + 1);
+
+        let mut pc = pc;
+        let mut exception = false;
+
+        for frame in self.frames.iter().rev() {
+            entries.push(BacktraceEntry {
+                pc: pc,
+                function: Some(frame.target),
+                exception: exception,
+                symbol: None,
+            });
+
+            pc = frame.caller;
+            exception = frame.exception;
+        }
+
+        // Outermost frame, we don't know where the function starts
+        entries.push(BacktraceEntry {
+            pc: pc,
+            function: None,
+            exception: exception,
+            symbol: None,
+        });
+
+        Backtrace { entries: entries }
+    }
+}
+
+/// Guest backtrace
+#[derive(Clone, Debug)]
+pub struct Backtrace {
+    /// Entries, innermost first
+    pub entries: Vec<BacktraceEntry>,
+}
+
+impl Backtrace {
+    /// Resolve the symbol name of each entry using `lookup` which
+    /// must return a description of the given address if it's known
+    /// (for instance `function+0x10`)
+    pub fn resolve<F>(&mut self, lookup: F)
+        where F: Fn(u32) -> Option<String> {
+        for e in &mut self.entries {
+            e.symbol = lookup(e.pc);
+        }
+    }
+
+    /// Resolve the symbol name of each entry as `function+offset`
+    /// using `symbols`
+    pub fn resolve_symbols(&mut self, symbols: &SymbolTable) {
+        self.resolve(|addr| symbols.describe(addr))
+    }
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, e) in self.entries.iter().enumerate() {
+            try!(write!(f, "#{:<2} 0x{:08x}", i, e.pc));
+
+            match (&e.symbol, e.function) {
+                (&Some(ref s), _) => try!(write!(f, " in {}", s)),
+                (&None, Some(func)) => try!(write!(f, " in 0x{:08x}", func)),
+                (&None, None) => (),
+            }
+
+            if e.exception {
+                try!(write!(f, " <exception>"));
+            }
+
+            try!(writeln!(f, ""));
+        }
+
+        Ok(())
+    }
+}
+
+/// Single frame in a `Backtrace`
+#[derive(Clone, Debug)]
+pub struct BacktraceEntry {
+    /// Address of the instruction being executed in this frame
+    pub pc: u32,
+    /// Entry point of the function containing `pc` if known
+    pub function: Option<u32>,
+    /// True if this frame was interrupted by an exception
+    pub exception: bool,
+    /// Resolved symbol for `pc` if available
+    pub symbol: Option<String>,
+}
+
+/// Maximum number of frames in the call stack
+const MAX_DEPTH: usize = 128;
+
+#[test]
+fn call_stack_unwinding() {
+    let mut cs = CallStack::new();
+
+    cs.call(0x80010000, 0x80020000, 0x80010008);
+    cs.call(0x80020010, 0x80030000, 0x80020018);
+    // Interrupt in 0x80030000
+    cs.exception(0x80030004, 0x80000080, 0x80030004);
+    cs.call(0x80000090, 0x80040000, 0x80000098);
+
+    let bt = cs.backtrace(0x80040010);
+
+    let pcs: Vec<u32> = bt.entries.iter().map(|e| e.pc).collect();
+
+    assert!(pcs == vec![0x80040010, 0x80000090, 0x80030004,
+                        0x80020010, 0x80010000]);
+    assert!(bt.entries[2].exception);
+    assert!(bt.entries[4].function.is_none());
+
+    // Return from the handler through `jr k0` then RFE in the delay
+    // slot
+    cs.jump_register(0x80030004);
+    cs.return_from_exception();
+
+    let bt = cs.backtrace(0x80030004);
+
+    assert!(bt.entries.len() == 3);
+    assert!(bt.entries[0].function == Some(0x80030000));
+
+    cs.jump_register(0x80010008);
+
+    assert!(cs.backtrace(0x80010008).entries.len() == 1);
+}
+
+#[test]
+fn error_report_backtrace() {
+    use shared::SharedState;
+    use debugger::DummyDebugger;
+    use error::EmulationError;
+    use test_utils::{DummyRenderer, dummy_cpu, write_blob};
+
+    let mut cpu = dummy_cpu();
+    let mut shared = SharedState::new();
+
+    write_blob(&mut cpu, 0x10000, &[
+        // jal   0x80010100
+        0x0c004040,
+        // nop
+        0x00000000,
+    ]);
+
+    write_blob(&mut cpu, 0x10100, &[
+        // lui   $t0, 0xbe00
+        0x3c08be00,
+        // lw    $t1, 0($t0)
+        0x8d090000,
+    ]);
+
+    cpu.force_pc(0x80010000);
+
+    let mut report = None;
+
+    for _ in 0..4 {
+        let res = cpu.run_next_instruction(&mut DummyDebugger,
+                                           &mut shared,
+                                           &mut DummyRenderer);
+
+        if let Err(e) = res {
+            report = Some(e);
+            break;
+        }
+    }
+
+    let report = report.unwrap();
+
+    match report.error {
+        EmulationError::UnhandledLoad(..) => (),
+        ref e => panic!("Unexpected error {}", e),
+    }
+
+    let bt = &report.backtrace.entries;
+
+    assert!(bt.len() == 2);
+    assert!(bt[0].pc == 0x80010104);
+    assert!(bt[0].function == Some(0x80010100));
+    assert!(bt[1].pc == 0x80010000);
+}
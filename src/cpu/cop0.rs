@@ -3,21 +3,45 @@ use interrupt::InterruptState;
 /// Coprocessor 0: System control
 #[derive(RustcDecodable, RustcEncodable)]
 pub struct Cop0 {
+    /// Cop0 register 3: Breakpoint on execute address
+    bpc: u32,
+    /// Cop0 register 5: Breakpoint on data access address
+    bda: u32,
+    /// Cop0 register 7: Breakpoint control
+    dcic: u32,
+    /// Cop0 register 9: Breakpoint on data access mask
+    bdam: u32,
+    /// Cop0 register 11: Breakpoint on execute mask
+    bpcm: u32,
     /// Cop0 register 12: Status register
     sr: u32,
     /// Cop0 register 13: Cause register
     cause:  u32,
     /// Cop0 register 14: Exception PC
     epc: u32,
+    /// Exception nesting depth within the handler of a debug
+    /// exception, 0 when no debug exception is being handled. The
+    /// hardware breakpoints are suppressed until the handler returns
+    /// with RFE, otherwise a breakpoint covering the handler would
+    /// trigger forever. Exceptions taken from within the handler
+    /// (an IRQ for instance) increase the depth so that their own
+    /// RFE doesn't re-enable the breakpoints early.
+    debug_depth: u32,
 }
 
 impl Cop0 {
 
     pub fn new() -> Cop0 {
         Cop0 {
+            bpc:   0,
+            bda:   0,
+            dcic:  0,
+            bdam:  0,
+            bpcm:  0,
             sr:    0,
             cause: 0,
             epc:   0,
+            debug_depth: 0,
         }
     }
 
@@ -52,6 +76,102 @@ impl Cop0 {
         self.sr & 0x10000 != 0
     }
 
+    pub fn bpc(&self) -> u32 {
+        self.bpc
+    }
+
+    pub fn set_bpc(&mut self, bpc: u32) {
+        self.bpc = bpc;
+    }
+
+    pub fn bpcm(&self) -> u32 {
+        self.bpcm
+    }
+
+    pub fn set_bpcm(&mut self, bpcm: u32) {
+        self.bpcm = bpcm;
+    }
+
+    pub fn bda(&self) -> u32 {
+        self.bda
+    }
+
+    pub fn set_bda(&mut self, bda: u32) {
+        self.bda = bda;
+    }
+
+    pub fn bdam(&self) -> u32 {
+        self.bdam
+    }
+
+    pub fn set_bdam(&mut self, bdam: u32) {
+        self.bdam = bdam;
+    }
+
+    pub fn dcic(&self) -> u32 {
+        self.dcic
+    }
+
+    pub fn set_dcic(&mut self, dcic: u32) {
+        // Bits [11:6] and [22:16] are always 0
+        self.dcic = dcic & 0xff80f03f;
+    }
+
+    /// Return true if the DCIC enable bits in `mask` are all set
+    /// along with the master enables for the execution and data
+    /// breakpoints (bits 23, 30 and 31). Always false while a debug
+    /// exception handler is running.
+    ///
+    /// XXX The "jump" breakpoints (DCIC bits 28 and 29) are not
+    /// implemented.
+    fn dcic_enabled(&self, mask: u32) -> bool {
+        let mask = mask | (1 << 31) | (1 << 30) | (1 << 23);
+
+        self.debug_depth == 0 && self.dcic & mask == mask
+    }
+
+    /// Check if executing the instruction at `pc` triggers the
+    /// execution breakpoint. If it does the hit is recorded in DCIC
+    /// and the caller must trigger a debug exception.
+    pub fn check_code_breakpoint(&mut self, pc: u32) -> bool {
+        if !self.dcic_enabled(1 << 24) {
+            return false;
+        }
+
+        let hit = (pc ^ self.bpc) & self.bpcm == 0;
+
+        if hit {
+            // "Any break" and "BPC code break"
+            self.dcic |= 0x3;
+        }
+
+        hit
+    }
+
+    /// Check if a data access at `addr` triggers the data
+    /// breakpoint. `write` is true for stores. If it does the hit is
+    /// recorded in DCIC and the caller must trigger a debug
+    /// exception.
+    pub fn check_data_breakpoint(&mut self, addr: u32, write: bool) -> bool {
+        // Bit 25 enables the data breakpoint, bit 26 and 27 select
+        // whether it triggers on reads and/or writes
+        let direction = if write { 1 << 27 } else { 1 << 26 };
+
+        if !self.dcic_enabled((1 << 25) | direction) {
+            return false;
+        }
+
+        let hit = (addr ^ self.bda) & self.bdam == 0;
+
+        if hit {
+            // "Any break", "BDA data break" and the direction flag
+            self.dcic |= 0x5;
+            self.dcic |= if write { 1 << 4 } else { 1 << 3 };
+        }
+
+        hit
+    }
+
     /// Update SR, CAUSE and EPC when an exception is
     /// triggered. Returns the address of the exception handler.
     pub fn enter_exception(&mut self,
@@ -67,6 +187,10 @@ impl Cop0 {
         // more than two recursive exception levels).
         let mode = self.sr & 0x3f;
 
+        if self.debug_depth > 0 {
+            self.debug_depth += 1;
+        }
+
         self.sr &= !0x3f;
         self.sr |= (mode << 2) & 0x3f;
 
@@ -93,11 +217,33 @@ impl Cop0 {
         }
     }
 
+    /// Enter a debug exception following a hardware breakpoint hit.
+    /// The state update is the same as for a regular `Break`
+    /// exception but the CPU jumps to the dedicated debug vector.
+    pub fn enter_debug_exception(&mut self,
+                                 pc: u32,
+                                 in_delay_slot: bool) -> u32 {
+        self.enter_exception(Exception::Break, pc, in_delay_slot);
+
+        self.debug_depth = 1;
+
+        match self.sr & (1 << 22) != 0 {
+            true  => 0xbfc00140,
+            false => 0x80000040,
+        }
+    }
+
     /// The counterpart to "enter_exception": shift SR's mode back
     /// into place. Doesn't touch CAUSE or EPC however.
     pub fn return_from_exception(&mut self) {
         let mode = self.sr & 0x3f;
 
+        // Only the RFE leaving the debug handler itself re-enables
+        // the hardware breakpoints
+        if self.debug_depth > 0 {
+            self.debug_depth -= 1;
+        }
+
         // Bits [5:4] (the third and last mode in the stack) remains
         // untouched and is therefore duplicated in the 2nd entry.
         self.sr &= !0xf;
@@ -148,3 +294,73 @@ pub enum Exception {
     /// Arithmetic overflow
     Overflow = 0xc,
 }
+
+#[test]
+fn hardware_breakpoints() {
+    let mut cop0 = Cop0::new();
+
+    cop0.set_bpc(0x80010000);
+    cop0.set_bpcm(0xfffffff0);
+    cop0.set_bda(0x801ff000);
+    cop0.set_bdam(0xffffff00);
+
+    // Nothing triggers until the breakpoints are enabled in DCIC
+    assert!(!cop0.check_code_breakpoint(0x80010000));
+    assert!(!cop0.check_data_breakpoint(0x801ff000, false));
+    assert!(cop0.dcic() == 0);
+
+    // Enable execution breakpoint and data breakpoint on write
+    cop0.set_dcic(0xcb800000);
+
+    assert!(!cop0.check_code_breakpoint(0x80010010));
+    assert!(cop0.dcic() == 0xcb800000);
+
+    assert!(cop0.check_code_breakpoint(0x8001000c));
+    assert!(cop0.dcic() == 0xcb800003);
+
+    cop0.set_dcic(0xcb800000);
+
+    assert!(!cop0.check_data_breakpoint(0x801ff0ff, false));
+    assert!(!cop0.check_data_breakpoint(0x801fe000, true));
+    assert!(cop0.check_data_breakpoint(0x801ff0ff, true));
+    assert!(cop0.dcic() == 0xcb800015);
+
+    // Without the master enable nothing triggers
+    cop0.set_dcic(0x4b800000);
+
+    assert!(!cop0.check_code_breakpoint(0x80010000));
+}
+
+#[test]
+fn breakpoints_suppressed_in_debug_handler() {
+    let mut cop0 = Cop0::new();
+
+    // Execution breakpoint covering the debug handler itself
+    cop0.set_bpc(0x80000040);
+    cop0.set_bpcm(0xffffff00);
+    cop0.set_bda(0x80000000);
+    cop0.set_bdam(0xffffff00);
+    cop0.set_dcic(0xcf800000);
+
+    assert!(cop0.check_code_breakpoint(0x80000000));
+
+    let handler = cop0.enter_debug_exception(0x80000000, false);
+
+    assert!(handler == 0x80000040);
+    assert!(!cop0.check_code_breakpoint(handler));
+    assert!(!cop0.check_data_breakpoint(0x80000010, false));
+    assert!(!cop0.check_data_breakpoint(0x80000010, true));
+
+    // An interrupt taken within the debug handler must not re-enable
+    // the breakpoints when it returns
+    cop0.enter_exception(Exception::Interrupt, handler, false);
+    cop0.return_from_exception();
+
+    assert!(!cop0.check_code_breakpoint(handler));
+
+    // Back to normal once the handler returns
+    cop0.return_from_exception();
+
+    assert!(cop0.check_code_breakpoint(0x80000000));
+    assert!(cop0.check_data_breakpoint(0x80000010, false));
+}
@@ -0,0 +1,302 @@
+//! Minimal arbitrary precision signed integers used by the GTE
+//! reference implementation. Speed is not a concern, the point is to
+//! never have to worry about intermediate results overflowing.
+
+use std::cmp::Ordering;
+use std::ops::{Add, Sub, Mul, Neg};
+
+/// Signed integer stored as sign + magnitude. The magnitude is a
+/// little endian vector of 32bit limbs without trailing zeroes. Zero
+/// is never negative.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BigInt {
+    negative: bool,
+    magnitude: Vec<u32>,
+}
+
+impl BigInt {
+    pub fn from_i64(v: i64) -> BigInt {
+        let abs =
+            if v < 0 {
+                (v as u64).wrapping_neg()
+            } else {
+                v as u64
+            };
+
+        BigInt::from_parts(v < 0, vec![abs as u32, (abs >> 32) as u32])
+    }
+
+    fn from_parts(negative: bool, mut magnitude: Vec<u32>) -> BigInt {
+        while magnitude.last() == Some(&0) {
+            magnitude.pop();
+        }
+
+        BigInt {
+            negative: negative && !magnitude.is_empty(),
+            magnitude: magnitude,
+        }
+    }
+
+    /// Multiply by `2^shift`
+    pub fn shl(&self, shift: u32) -> BigInt {
+        let words = (shift / 32) as usize;
+        let bits = shift % 32;
+
+        let mut magnitude = vec![0; words];
+        let mut carry = 0;
+
+        for &w in &self.magnitude {
+            let v = ((w as u64) << bits) | carry;
+
+            magnitude.push(v as u32);
+            carry = v >> 32;
+        }
+
+        magnitude.push(carry as u32);
+
+        BigInt::from_parts(self.negative, magnitude)
+    }
+
+    /// Arithmetic shift right: divide by `2^shift` rounding towards
+    /// negative infinity, like the hardware's SAR
+    pub fn sar(&self, shift: u32) -> BigInt {
+        let words = (shift / 32) as usize;
+        let bits = shift % 32;
+
+        if words >= self.magnitude.len() {
+            return if self.negative {
+                BigInt::from_i64(-1)
+            } else {
+                BigInt::from_i64(0)
+            };
+        }
+
+        // True if we shift out non-0 bits
+        let mut inexact = self.magnitude[..words].iter().any(|&w| w != 0);
+
+        if bits != 0 && self.magnitude[words] & ((1 << bits) - 1) != 0 {
+            inexact = true;
+        }
+
+        let src = &self.magnitude[words..];
+
+        let magnitude: Vec<u32> =
+            if bits == 0 {
+                src.to_vec()
+            } else {
+                (0..src.len()).map(|i| {
+                    let lo = src[i] >> bits;
+                    let hi = src.get(i + 1).map_or(0, |&w| w << (32 - bits));
+
+                    lo | hi
+                }).collect()
+            };
+
+        let q = BigInt::from_parts(self.negative, magnitude);
+
+        if self.negative && inexact {
+            q - BigInt::from_i64(1)
+        } else {
+            q
+        }
+    }
+
+    /// Return the low 64 bits of the two's complement representation
+    pub fn to_i64_wrapping(&self) -> i64 {
+        let lo = self.magnitude.get(0).cloned().unwrap_or(0) as u64;
+        let hi = self.magnitude.get(1).cloned().unwrap_or(0) as u64;
+
+        let abs = lo | (hi << 32);
+
+        if self.negative {
+            abs.wrapping_neg() as i64
+        } else {
+            abs as i64
+        }
+    }
+
+    /// Return the low 32 bits of the two's complement representation
+    pub fn to_i32_wrapping(&self) -> i32 {
+        self.to_i64_wrapping() as i32
+    }
+}
+
+fn cmp_magnitudes(a: &[u32], b: &[u32]) -> Ordering {
+    if a.len() != b.len() {
+        return a.len().cmp(&b.len());
+    }
+
+    for (x, y) in a.iter().rev().zip(b.iter().rev()) {
+        if x != y {
+            return x.cmp(y);
+        }
+    }
+
+    Ordering::Equal
+}
+
+fn add_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let len = ::std::cmp::max(a.len(), b.len());
+
+    let mut r = Vec::with_capacity(len + 1);
+    let mut carry = 0;
+
+    for i in 0..len {
+        let x = a.get(i).cloned().unwrap_or(0) as u64;
+        let y = b.get(i).cloned().unwrap_or(0) as u64;
+
+        let s = x + y + carry;
+
+        r.push(s as u32);
+        carry = s >> 32;
+    }
+
+    r.push(carry as u32);
+
+    r
+}
+
+/// Compute `a - b`, `a` must be greater than or equal to `b`
+fn sub_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut r = Vec::with_capacity(a.len());
+    let mut borrow = 0;
+
+    for i in 0..a.len() {
+        let x = a[i] as i64;
+        let y = b.get(i).cloned().unwrap_or(0) as i64;
+
+        let mut d = x - y - borrow;
+
+        if d < 0 {
+            d += 1 << 32;
+            borrow = 1;
+        } else {
+            borrow = 0;
+        }
+
+        r.push(d as u32);
+    }
+
+    r
+}
+
+impl Add for BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: BigInt) -> BigInt {
+        if self.negative == other.negative {
+            let m = add_magnitudes(&self.magnitude, &other.magnitude);
+
+            return BigInt::from_parts(self.negative, m);
+        }
+
+        match cmp_magnitudes(&self.magnitude, &other.magnitude) {
+            Ordering::Less => {
+                let m = sub_magnitudes(&other.magnitude, &self.magnitude);
+
+                BigInt::from_parts(other.negative, m)
+            }
+            _ => {
+                let m = sub_magnitudes(&self.magnitude, &other.magnitude);
+
+                BigInt::from_parts(self.negative, m)
+            }
+        }
+    }
+}
+
+impl Neg for BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        let negative = !self.negative;
+
+        BigInt::from_parts(negative, self.magnitude)
+    }
+}
+
+impl Sub for BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: BigInt) -> BigInt {
+        self + (-other)
+    }
+}
+
+impl Mul for BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: BigInt) -> BigInt {
+        let a = &self.magnitude;
+        let b = &other.magnitude;
+
+        let mut r = vec![0u32; a.len() + b.len()];
+
+        for (i, &x) in a.iter().enumerate() {
+            let mut carry = 0u64;
+
+            for (j, &y) in b.iter().enumerate() {
+                let v = (x as u64) * (y as u64) + r[i + j] as u64 + carry;
+
+                r[i + j] = v as u32;
+                carry = v >> 32;
+            }
+
+            r[i + b.len()] = carry as u32;
+        }
+
+        BigInt::from_parts(self.negative != other.negative, r)
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &BigInt) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &BigInt) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_magnitudes(&self.magnitude, &other.magnitude),
+            (true, true) => cmp_magnitudes(&other.magnitude, &self.magnitude),
+        }
+    }
+}
+
+#[test]
+fn bigint_arithmetic() {
+    let big = BigInt::from_i64;
+
+    let values = [0, 1, -1, 7, -7, 0x7fffffff, -0x80000000,
+                  0x123456789, -0x987654321, 0x7fffffffffff];
+
+    for &a in &values {
+        for &b in &values {
+            assert!((big(a) + big(b)).to_i64_wrapping() == a + b);
+            assert!((big(a) - big(b)).to_i64_wrapping() == a - b);
+            assert!(big(a).cmp(&big(b)) == a.cmp(&b));
+
+            if a.abs() < 0x100000000 && b.abs() < 0x100000000 {
+                assert!((big(a) * big(b)).to_i64_wrapping() == a * b);
+            }
+        }
+
+        for shift in 0..40 {
+            assert!(big(a).sar(shift).to_i64_wrapping() == a >> shift);
+
+            if a.abs() < 0x100000 {
+                assert!(big(a).shl(shift).to_i64_wrapping() == a << shift);
+            }
+        }
+    }
+
+    // Products wider than 64 bits
+    let p = big(-0x7fffffffffffffff) * big(0x7fffffffffffffff);
+
+    // floor(-(2^63 - 1)^2 / 2^62) = -2^64 + 3
+    assert!(p.sar(62).to_i64_wrapping() == 3);
+    assert!(p < BigInt::from_i64(0));
+}
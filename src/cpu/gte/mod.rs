@@ -2,7 +2,11 @@
 
 use std::{i16, u16};
 
+use timekeeper::Cycles;
+
 mod divider;
+mod bigint;
+pub mod reference;
 
 #[cfg(test)]
 mod tests;
@@ -95,12 +99,27 @@ impl Gte {
         }
     }
 
-    /// Execute GTE command
-    pub fn command(&mut self, command: u32) {
+    /// Execute GTE command. Returns an error describing the problem
+    /// if the command uses a feature we don't emulate.
+    pub fn command(&mut self, command: u32) -> Result<(), String> {
         let opcode = command & 0x3f;
 
         let config = CommandConfig::from_command(command);
 
+        if opcode == 0x12 {
+            // MVMVA is the only command where the matrix and control
+            // vector are configurable
+            if config.matrix == Matrix::Invalid {
+                return Err(format!("GTE multiplication with invalid \
+                                    matrix ({:08x})", command));
+            }
+
+            if config.vector_add == ControlVector::FarColor {
+                return Err(format!("GTE multiplication with far color \
+                                    vector ({:08x})", command));
+            }
+        }
+
         // Clear flags prior to command execution
         self.flags = 0;
 
@@ -126,12 +145,14 @@ impl Gte {
             0x3d => self.cmd_gpf(config),
             0x3e => self.cmd_gpl(config),
             0x3f => self.cmd_ncct(config),
-            _ => panic!("Unhandled GTE opcode {:02x}", opcode),
+            _ => return Err(format!("Unhandled GTE opcode {:02x}", opcode)),
         }
 
         // Update the flags MSB: OR together bits [30:23] + [18:13]
         let msb = self.flags & 0x7f87e000 != 0;
         self.flags |= (msb as u32) << 31;
+
+        Ok(())
     }
 
     /// Return the value of one of the "control" registers. Used by
@@ -1437,6 +1458,37 @@ impl Gte {
     }
 }
 
+/// Return the number of CPU cycles taken by a GTE command, including
+/// the cycle spent issuing it. Values are from the No$ PSX specs.
+pub fn command_duration(command: u32) -> Cycles {
+    match command & 0x3f {
+        0x01 => 15,
+        0x06 => 8,
+        0x0c => 6,
+        0x10 => 8,
+        0x11 => 8,
+        0x12 => 8,
+        0x13 => 19,
+        0x14 => 13,
+        0x16 => 44,
+        0x1b => 17,
+        0x1c => 11,
+        0x1e => 14,
+        0x20 => 30,
+        0x28 => 5,
+        0x29 => 8,
+        0x2a => 17,
+        0x2d => 5,
+        0x2e => 6,
+        0x30 => 23,
+        0x3d => 5,
+        0x3e => 5,
+        0x3f => 39,
+        // Unknown commands, we don't emulate them anyway
+        _ => 1,
+    }
+}
+
 /// Decoded command fields in GTE command instructions. Meaning varies
 /// depending on the command used.
 #[derive(Clone, Copy)]
@@ -0,0 +1,1033 @@
+//! Slow reference implementation of the GTE used to audit `Gte`.
+//!
+//! This is written straight from the No$ PSX specs formulas without
+//! any attempt at optimization: registers are decoded from their raw
+//! values before each command and every intermediate result is
+//! computed with arbitrary precision integers, overflows and
+//! saturations are only applied where the spec says they happen. It
+//! shares no code with `Gte` (besides the big integer helpers) so
+//! that both implementations are unlikely to have the same bugs.
+
+use std::fmt;
+
+use super::Gte;
+use super::bigint::BigInt;
+
+/// Keeps track of the differences between `Gte` and the reference
+/// implementation when running in audit mode
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct GteAudit {
+    /// Number of commands audited
+    commands: u64,
+    /// Number of commands for which a divergence was found
+    divergences: u64,
+    /// First divergence found
+    first: Option<Divergence>,
+}
+
+impl GteAudit {
+    pub fn new() -> GteAudit {
+        GteAudit {
+            commands: 0,
+            divergences: 0,
+            first: None,
+        }
+    }
+
+    /// Run `command` through `gte` and the reference implementation
+    /// and compare the results. `pc` is the address of the
+    /// instruction, used for reporting. Returns an error if `gte`
+    /// doesn't support the command.
+    pub fn command(&mut self,
+                   gte: &mut Gte,
+                   pc: u32,
+                   command: u32) -> Result<(), String> {
+        let mut reference = Reference::from_gte(gte);
+
+        try!(gte.command(command));
+
+        if !reference.command(command) {
+            // The reference doesn't know how to run this one
+            return Ok(());
+        }
+
+        self.commands += 1;
+
+        if let Some(d) = reference.compare(gte, pc, command) {
+            if self.divergences < MAX_REPORTED {
+                warn!("{}", d);
+            }
+
+            self.divergences += 1;
+
+            if self.first.is_none() {
+                self.first = Some(d);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Number of commands audited
+    pub fn commands(&self) -> u64 {
+        self.commands
+    }
+
+    /// Number of commands whose result didn't match the reference
+    pub fn divergences(&self) -> u64 {
+        self.divergences
+    }
+
+    /// First divergence found since the audit was started
+    pub fn first_divergence(&self) -> Option<&Divergence> {
+        self.first.as_ref()
+    }
+}
+
+/// Maximum number of divergences logged, the others are only counted
+const MAX_REPORTED: u64 = 32;
+
+/// GTE register identifier
+#[derive(Clone, Copy, PartialEq, Eq, Debug, RustcDecodable, RustcEncodable)]
+pub enum Register {
+    Data(u8),
+    Control(u8),
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Register::Data(r) =>
+                write!(f, "data register {} ({})",
+                       r, DATA_NAMES[r as usize]),
+            Register::Control(r) =>
+                write!(f, "control register {} ({})",
+                       r, CONTROL_NAMES[r as usize]),
+        }
+    }
+}
+
+/// First register whose value after a GTE command doesn't match the
+/// reference implementation
+#[derive(Clone, Debug, RustcDecodable, RustcEncodable)]
+pub struct Divergence {
+    /// Address of the COP2 instruction
+    pub pc: u32,
+    /// GTE command word
+    pub command: u32,
+    pub register: Register,
+    /// Value computed by the reference implementation
+    pub expected: u32,
+    /// Value computed by `Gte`
+    pub actual: u32,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "GTE divergence at 0x{:08x}: command 0x{:08x} ({}), \
+                        {} is 0x{:08x}, expected 0x{:08x}",
+                    self.pc,
+                    self.command,
+                    command_name(self.command),
+                    self.register,
+                    self.actual,
+                    self.expected));
+
+        if self.register == Register::Control(31) {
+            // List the FLAG bits that differ
+            let diff = self.actual ^ self.expected;
+
+            for bit in (12..32).rev() {
+                if diff & (1 << bit) != 0 {
+                    let sign =
+                        if self.actual & (1 << bit) != 0 {
+                            '+'
+                        } else {
+                            '-'
+                        };
+
+                    try!(write!(f, " {}{}", sign, FLAG_NAMES[bit - 12]));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Reference GTE state. Registers are kept in a decoded form, wider
+/// than necessary.
+struct Reference {
+    /// V0, V1, V2
+    v: [[i64; 3]; 3],
+    /// RGBC: color and GPU command code
+    rgbc: [u8; 4],
+    otz: u16,
+    /// IR0...IR3
+    ir: [i64; 4],
+    /// SXY0...SXY2 (SXYP is a mirror of SXY2 when read)
+    sxy: [[i64; 2]; 3],
+    /// SZ0...SZ3
+    sz: [i64; 4],
+    /// RGB0...RGB2
+    rgb: [[u8; 4]; 3],
+    res1: u32,
+    /// MAC0...MAC3
+    mac: [i32; 4],
+    lzcs: u32,
+
+    /// Rotation matrix
+    rt: [[i64; 3]; 3],
+    /// Translation vector
+    tr: [i64; 3],
+    /// Light source matrix
+    llm: [[i64; 3]; 3],
+    /// Background color
+    bk: [i64; 3],
+    /// Light color matrix
+    lcm: [[i64; 3]; 3],
+    /// Far color
+    fc: [i64; 3],
+    ofx: i64,
+    ofy: i64,
+    h: i64,
+    dqa: i64,
+    dqb: i64,
+    zsf3: i64,
+    zsf4: i64,
+    flag: u32,
+}
+
+impl Reference {
+    /// Decode the registers of `gte`
+    fn from_gte(gte: &Gte) -> Reference {
+        let d = |r: u32| gte.data(r);
+        let c = |r: u32| gte.control(r);
+
+        let lo = |v: u32| v as i16 as i64;
+        let hi = |v: u32| (v >> 16) as i16 as i64;
+        let bytes = |v: u32| {
+            [v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8]
+        };
+
+        // Matrices are stored as 9 consecutive halfwords, row major
+        let matrix = |first: u32| {
+            let mut m = [[0; 3]; 3];
+
+            for i in 0..9 {
+                let w = c(first + (i / 2) as u32);
+
+                m[i / 3][i % 3] =
+                    if i % 2 == 0 {
+                        lo(w)
+                    } else {
+                        hi(w)
+                    };
+            }
+
+            m
+        };
+
+        let vector = |first: u32| {
+            [c(first) as i32 as i64,
+             c(first + 1) as i32 as i64,
+             c(first + 2) as i32 as i64]
+        };
+
+        Reference {
+            v: [[lo(d(0)), hi(d(0)), lo(d(1))],
+                [lo(d(2)), hi(d(2)), lo(d(3))],
+                [lo(d(4)), hi(d(4)), lo(d(5))]],
+            rgbc: bytes(d(6)),
+            otz: d(7) as u16,
+            ir: [lo(d(8)), lo(d(9)), lo(d(10)), lo(d(11))],
+            sxy: [[lo(d(12)), hi(d(12))],
+                  [lo(d(13)), hi(d(13))],
+                  [lo(d(14)), hi(d(14))]],
+            sz: [d(16) as u16 as i64,
+                 d(17) as u16 as i64,
+                 d(18) as u16 as i64,
+                 d(19) as u16 as i64],
+            rgb: [bytes(d(20)), bytes(d(21)), bytes(d(22))],
+            res1: d(23),
+            mac: [d(24) as i32, d(25) as i32, d(26) as i32, d(27) as i32],
+            lzcs: d(30),
+
+            rt: matrix(0),
+            tr: vector(5),
+            llm: matrix(8),
+            bk: vector(13),
+            lcm: matrix(16),
+            fc: vector(21),
+            ofx: c(24) as i32 as i64,
+            ofy: c(25) as i32 as i64,
+            h: c(26) as u16 as i64,
+            dqa: c(27) as i16 as i64,
+            dqb: c(28) as i32 as i64,
+            zsf3: c(29) as i16 as i64,
+            zsf4: c(30) as i16 as i64,
+            flag: c(31),
+        }
+    }
+
+    /// Value of data register `reg` as read by MFC2
+    fn data(&self, reg: u8) -> u32 {
+        let halfwords = |lo: i64, hi: i64| {
+            (lo as u16 as u32) | ((hi as u32) << 16)
+        };
+        let word = |b: [u8; 4]| {
+            (b[0] as u32) |
+            ((b[1] as u32) << 8) |
+            ((b[2] as u32) << 16) |
+            ((b[3] as u32) << 24)
+        };
+
+        match reg {
+            0 | 2 | 4 => {
+                let v = self.v[reg as usize / 2];
+
+                halfwords(v[0], v[1])
+            }
+            1 | 3 | 5 => self.v[reg as usize / 2][2] as u32,
+            6 => word(self.rgbc),
+            7 => self.otz as u32,
+            8..=11 => self.ir[reg as usize - 8] as u32,
+            12..=14 => {
+                let sxy = self.sxy[reg as usize - 12];
+
+                halfwords(sxy[0], sxy[1])
+            }
+            15 => self.data(14),
+            16..=19 => self.sz[reg as usize - 16] as u32,
+            20..=22 => word(self.rgb[reg as usize - 20]),
+            23 => self.res1,
+            24..=27 => self.mac[reg as usize - 24] as u32,
+            // ORGB: IR1...IR3 / 80h, saturated to 0...1fh
+            28 | 29 => {
+                let mut orgb = 0;
+
+                for i in 0..3 {
+                    let c = self.ir[i + 1] >> 7;
+
+                    let c =
+                        if c < 0 {
+                            0
+                        } else if c > 0x1f {
+                            0x1f
+                        } else {
+                            c as u32
+                        };
+
+                    orgb |= c << (i * 5);
+                }
+
+                orgb
+            }
+            30 => self.lzcs,
+            // LZCR: number of leading bits equal to bit 31 of LZCS
+            31 => {
+                let sign = self.lzcs >> 31;
+
+                (0..32).take_while(|&b| (self.lzcs >> (31 - b)) & 1 == sign)
+                    .count() as u32
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Value of control register `reg` as read by CFC2
+    fn control(&self, reg: u8) -> u32 {
+        let matrix =
+            match reg {
+                0..=4 => Some(&self.rt),
+                8..=12 => Some(&self.llm),
+                16..=20 => Some(&self.lcm),
+                _ => None,
+            };
+
+        if let Some(m) = matrix {
+            let i = (reg % 8) as usize * 2;
+
+            let lo = m[i / 3][i % 3];
+
+            return if i == 8 {
+                // RT33, L33 and LB3 are sign-extended
+                lo as u32
+            } else {
+                let hi = m[(i + 1) / 3][(i + 1) % 3];
+
+                (lo as u16 as u32) | ((hi as u32) << 16)
+            };
+        }
+
+        match reg {
+            5..=7 => self.tr[reg as usize - 5] as u32,
+            13..=15 => self.bk[reg as usize - 13] as u32,
+            21..=23 => self.fc[reg as usize - 21] as u32,
+            24 => self.ofx as u32,
+            25 => self.ofy as u32,
+            // H is unsigned but the hardware sign-extends it when
+            // read
+            26 => self.h as i16 as u32,
+            27 => self.dqa as u32,
+            28 => self.dqb as u32,
+            29 => self.zsf3 as u32,
+            30 => self.zsf4 as u32,
+            31 => self.flag,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Compare all registers with `gte` and return the first
+    /// difference, if any
+    fn compare(&self, gte: &Gte, pc: u32, command: u32) -> Option<Divergence> {
+        let registers =
+            (0..32).map(|r| Register::Data(r))
+            .chain((0..32).map(|r| Register::Control(r)));
+
+        for register in registers {
+            let (expected, actual) =
+                match register {
+                    Register::Data(r) => (self.data(r), gte.data(r as u32)),
+                    Register::Control(r) => (self.control(r),
+                                             gte.control(r as u32)),
+                };
+
+            if expected != actual {
+                return Some(Divergence {
+                    pc: pc,
+                    command: command,
+                    register: register,
+                    expected: expected,
+                    actual: actual,
+                });
+            }
+        }
+
+        None
+    }
+
+    /// Run `command`. Returns false if the command is not supported.
+    fn command(&mut self, command: u32) -> bool {
+        let sf = if command & (1 << 19) != 0 { 12 } else { 0 };
+        let lm = command & (1 << 10) != 0;
+
+        self.flag = 0;
+
+        match command & 0x3f {
+            // RTPS
+            0x01 => self.rtp(0, sf, lm, true),
+            // NCLIP
+            0x06 => self.nclip(),
+            // OP
+            0x0c => self.op(sf, lm),
+            // DPCS
+            0x10 => {
+                let rgbc = self.rgbc;
+
+                self.dpc(rgbc, sf, lm);
+            }
+            // INTPL
+            0x11 => {
+                let mac: Vec<BigInt> =
+                    (0..3).map(|i| big(self.ir[i + 1]).shl(12)).collect();
+
+                self.interpolate(&mac, sf, lm);
+            }
+            // MVMVA
+            0x12 => {
+                let mx = (command >> 17) & 3;
+                let v = (command >> 15) & 3;
+                let cv = (command >> 13) & 3;
+
+                let m =
+                    match mx {
+                        0 => self.rt,
+                        1 => self.llm,
+                        2 => self.lcm,
+                        // Garbage matrix, not supported
+                        _ => return false,
+                    };
+
+                let v =
+                    match v {
+                        3 => [self.ir[1], self.ir[2], self.ir[3]],
+                        n => self.v[n as usize],
+                    };
+
+                let t =
+                    match cv {
+                        0 => self.tr,
+                        1 => self.bk,
+                        // Far color is buggy, not supported
+                        2 => return false,
+                        _ => [0; 3],
+                    };
+
+                self.transform(m, v, t, sf);
+                self.mac_to_ir(lm);
+            }
+            // NCDS
+            0x13 => self.ncd(0, sf, lm),
+            // NCDT
+            0x16 => for n in 0..3 {
+                self.ncd(n, sf, lm);
+            },
+            // NCCS
+            0x1b => self.ncc(0, sf, lm),
+            // CC
+            0x1c => {
+                let ir = [self.ir[1], self.ir[2], self.ir[3]];
+                let (lcm, bk) = (self.lcm, self.bk);
+
+                self.transform(lcm, ir, bk, sf);
+                self.mac_to_ir(lm);
+
+                self.color_color(sf, lm);
+            }
+            // NCS
+            0x1e => self.nc(0, sf, lm),
+            // NCT
+            0x20 => for n in 0..3 {
+                self.nc(n, sf, lm);
+            },
+            // SQR
+            0x28 => {
+                for i in 1..4 {
+                    let sq = big(self.ir[i]) * big(self.ir[i]);
+
+                    self.set_mac(i, sq, sf);
+                }
+
+                self.mac_to_ir(lm);
+            }
+            // DCPL
+            0x29 => {
+                let mac = self.color_times_ir();
+
+                self.interpolate(&mac, sf, lm);
+            }
+            // DPCT
+            0x2a => for _ in 0..3 {
+                let rgb0 = self.rgb[0];
+
+                self.dpc(rgb0, sf, lm);
+            },
+            // AVSZ3
+            0x2d => {
+                let sum = self.sz[1] + self.sz[2] + self.sz[3];
+                let zsf3 = self.zsf3;
+
+                self.average_z(zsf3, sum);
+            }
+            // AVSZ4
+            0x2e => {
+                let sum = self.sz[0] + self.sz[1] + self.sz[2] + self.sz[3];
+                let zsf4 = self.zsf4;
+
+                self.average_z(zsf4, sum);
+            }
+            // RTPT
+            0x30 => {
+                self.rtp(0, sf, lm, false);
+                self.rtp(1, sf, lm, false);
+                self.rtp(2, sf, lm, true);
+            }
+            // GPF
+            0x3d => {
+                for i in 1..4 {
+                    let p = big(self.ir[i]) * big(self.ir[0]);
+
+                    self.set_mac(i, p, sf);
+                }
+
+                self.mac_to_ir(lm);
+                self.push_color();
+            }
+            // GPL
+            0x3e => {
+                for i in 1..4 {
+                    let p = big(self.ir[i]) * big(self.ir[0]);
+                    let m = big(self.mac[i] as i64).shl(sf);
+
+                    self.set_mac(i, m + p, sf);
+                }
+
+                self.mac_to_ir(lm);
+                self.push_color();
+            }
+            // NCCT
+            0x3f => for n in 0..3 {
+                self.ncc(n, sf, lm);
+            },
+            _ => return false,
+        }
+
+        // Bit 31: OR of bits 30...23 and 18...13
+        if self.flag & 0x7f87e000 != 0 {
+            self.flag |= 1 << 31;
+        }
+
+        true
+    }
+
+    fn set_flag(&mut self, bit: u32) {
+        self.flag |= 1 << bit;
+    }
+
+    /// Check MAC1...3 (`i` = 1...3) for 44bit overflow and return the
+    /// value truncated to 44 bits
+    fn check_mac(&mut self, i: usize, value: BigInt) -> BigInt {
+        let i = i as u32;
+
+        if value > big((1 << 43) - 1) {
+            self.set_flag(31 - i);
+        } else if value < big(-(1 << 43)) {
+            self.set_flag(28 - i);
+        }
+
+        let v = value.to_i64_wrapping();
+
+        big((v << 20) >> 20)
+    }
+
+    /// MAC1...3 = value SAR sf
+    fn set_mac(&mut self, i: usize, value: BigInt, sf: u32) {
+        let value = self.check_mac(i, value);
+
+        self.mac[i] = value.sar(sf).to_i32_wrapping();
+    }
+
+    /// Saturate IR1...3 (`i` = 1...3) to -8000h...7fffh or 0...7fffh
+    /// if `lm` is set (Lm_B)
+    fn lm_b(&mut self, i: usize, value: i64, lm: bool) -> i64 {
+        let min = if lm { 0 } else { -0x8000 };
+
+        if value < min {
+            self.set_flag(25 - i as u32);
+            min
+        } else if value > 0x7fff {
+            self.set_flag(25 - i as u32);
+            0x7fff
+        } else {
+            value
+        }
+    }
+
+    /// IR1...3 = Lm_B(MAC1...3)
+    fn mac_to_ir(&mut self, lm: bool) {
+        for i in 1..4 {
+            let mac = self.mac[i] as i64;
+
+            self.ir[i] = self.lm_b(i, mac, lm);
+        }
+    }
+
+    /// Check MAC0 for 32bit overflow and store the low 32 bits.
+    /// Values derived from MAC0 (SX2, SY2, IR0 and OTZ) are computed
+    /// from the full precision value.
+    fn set_mac0(&mut self, value: BigInt) {
+        if value > big(0x7fffffff) {
+            self.set_flag(16);
+        } else if value < big(-0x80000000) {
+            self.set_flag(15);
+        }
+
+        self.mac[0] = value.to_i32_wrapping();
+    }
+
+    /// Saturate to 0...ffffh (Lm_D)
+    fn lm_d(&mut self, value: BigInt) -> i64 {
+        if value < big(0) {
+            self.set_flag(18);
+            0
+        } else if value > big(0xffff) {
+            self.set_flag(18);
+            0xffff
+        } else {
+            value.to_i64_wrapping()
+        }
+    }
+
+    /// Push a new color in the FIFO computed from MAC1...3 / 16,
+    /// saturated to 0...ffh (Lm_C). The code byte is taken from RGBC.
+    fn push_color(&mut self) {
+        let mut color = [0; 4];
+
+        for i in 0..3 {
+            let c = self.mac[i + 1] >> 4;
+
+            color[i] =
+                if c < 0 {
+                    self.set_flag(21 - i as u32);
+                    0
+                } else if c > 0xff {
+                    self.set_flag(21 - i as u32);
+                    0xff
+                } else {
+                    c as u8
+                };
+        }
+
+        color[3] = self.rgbc[3];
+
+        self.rgb[0] = self.rgb[1];
+        self.rgb[1] = self.rgb[2];
+        self.rgb[2] = color;
+    }
+
+    /// MAC1...3 = (T * 1000h + M * V) SAR sf. Overflows are checked
+    /// after each addition. Returns the MAC values before the shift.
+    fn transform(&mut self,
+                 m: [[i64; 3]; 3],
+                 v: [i64; 3],
+                 t: [i64; 3],
+                 sf: u32) -> Vec<BigInt> {
+        let mut results = Vec::with_capacity(3);
+
+        for r in 0..3 {
+            let mut acc = big(t[r]).shl(12);
+
+            for c in 0..3 {
+                acc = self.check_mac(r + 1, acc + big(m[r][c]) * big(v[c]));
+            }
+
+            self.mac[r + 1] = acc.sar(sf).to_i32_wrapping();
+
+            results.push(acc);
+        }
+
+        results
+    }
+
+    /// Perspective transformation of vector `n`. Depth cueing is only
+    /// done for the last vector.
+    fn rtp(&mut self, n: usize, sf: u32, lm: bool, depth_cue: bool) {
+        let (rt, v, tr) = (self.rt, self.v[n], self.tr);
+
+        let acc = self.transform(rt, v, tr, sf);
+
+        for i in 1..3 {
+            let mac = self.mac[i] as i64;
+
+            self.ir[i] = self.lm_b(i, mac, lm);
+        }
+
+        // The IR3 saturation flag is computed from MAC3 SAR 12
+        // regardless of sf and lm, but the value is saturated
+        // normally
+        let z = acc[2].sar(12);
+
+        if z < big(-0x8000) || z > big(0x7fff) {
+            self.set_flag(22);
+        }
+
+        let mac3 = self.mac[3] as i64;
+        let min = if lm { 0 } else { -0x8000 };
+
+        self.ir[3] =
+            if mac3 < min {
+                min
+            } else if mac3 > 0x7fff {
+                0x7fff
+            } else {
+                mac3
+            };
+
+        // SZ3 = MAC3 SAR ((1 - sf) * 12)
+        let sz3 = self.lm_d(z);
+
+        self.sz = [self.sz[1], self.sz[2], self.sz[3], sz3];
+
+        let div = self.divide();
+
+        let sx = big(div) * big(self.ir[1]) + big(self.ofx);
+        self.set_mac0(sx.clone());
+        let sx = self.lm_g(0, sx.sar(16));
+
+        let sy = big(div) * big(self.ir[2]) + big(self.ofy);
+        self.set_mac0(sy.clone());
+        let sy = self.lm_g(1, sy.sar(16));
+
+        self.sxy = [self.sxy[1], self.sxy[2], [sx, sy]];
+
+        if depth_cue {
+            let depth = big(div) * big(self.dqa) + big(self.dqb);
+
+            self.set_mac0(depth.clone());
+
+            let ir0 = depth.sar(12);
+
+            self.ir[0] =
+                if ir0 < big(0) {
+                    self.set_flag(12);
+                    0
+                } else if ir0 > big(0x1000) {
+                    self.set_flag(12);
+                    0x1000
+                } else {
+                    ir0.to_i64_wrapping()
+                };
+        }
+    }
+
+    /// Saturate SX2 (`i` = 0) or SY2 (`i` = 1) to -400h...3ffh (Lm_G)
+    fn lm_g(&mut self, i: u32, value: BigInt) -> i64 {
+        if value < big(-0x400) {
+            self.set_flag(14 - i);
+            -0x400
+        } else if value > big(0x3ff) {
+            self.set_flag(14 - i);
+            0x3ff
+        } else {
+            value.to_i64_wrapping()
+        }
+    }
+
+    /// Unsigned Newton-Raphson division H / SZ3, as described in the
+    /// specs. Returns a 1.16 value saturated to 1ffffh.
+    fn divide(&mut self) -> i64 {
+        let h = self.h;
+        let sz3 = self.sz[3];
+
+        if h >= sz3 * 2 {
+            self.set_flag(17);
+            return 0x1ffff;
+        }
+
+        // Count the leading zeroes of SZ3 as a 16bit value
+        let z = (0..16).take_while(|&b| sz3 & (0x8000 >> b) == 0).count();
+
+        let n = big(h).shl(z as u32);
+        let d = big(sz3).shl(z as u32);
+
+        let index = (d.clone() - big(0x7fc0)).sar(7).to_i64_wrapping();
+
+        let u = big(unr_table(index) + 0x101);
+
+        let d = (big(0x2000080) - d * u.clone()).sar(8);
+        let d = (big(0x80) + d * u).sar(8);
+
+        let n = (n * d + big(0x8000)).sar(16);
+
+        if n > big(0x1ffff) {
+            0x1ffff
+        } else {
+            n.to_i64_wrapping()
+        }
+    }
+
+    fn nclip(&mut self) {
+        let sxy = self.sxy;
+
+        let x = |i: usize| big(sxy[i][0]);
+        let y = |i: usize| big(sxy[i][1]);
+
+        let v = x(0) * y(1) + x(1) * y(2) + x(2) * y(0)
+            - x(0) * y(2) - x(1) * y(0) - x(2) * y(1);
+
+        self.set_mac0(v);
+    }
+
+    /// Outer product of IR with the rotation matrix diagonal
+    fn op(&mut self, sf: u32, lm: bool) {
+        let d = [self.rt[0][0], self.rt[1][1], self.rt[2][2]];
+        let ir = self.ir;
+
+        let p = |a: i64, b: i64| big(a) * big(b);
+
+        let mac1 = p(ir[3], d[1]) - p(ir[2], d[2]);
+        let mac2 = p(ir[1], d[2]) - p(ir[3], d[0]);
+        let mac3 = p(ir[2], d[0]) - p(ir[1], d[1]);
+
+        self.set_mac(1, mac1, sf);
+        self.set_mac(2, mac2, sf);
+        self.set_mac(3, mac3, sf);
+
+        self.mac_to_ir(lm);
+    }
+
+    fn average_z(&mut self, zsf: i64, sum: i64) {
+        let average = big(zsf) * big(sum);
+
+        self.set_mac0(average.clone());
+
+        self.otz = self.lm_d(average.sar(12)) as u16;
+    }
+
+    /// [R * IR1, G * IR2, B * IR3] SHL 4
+    fn color_times_ir(&mut self) -> Vec<BigInt> {
+        (0..3).map(|i| {
+            let v = big(self.rgbc[i] as i64) * big(self.ir[i + 1]);
+
+            self.check_mac(i + 1, v.shl(4))
+        }).collect()
+    }
+
+    /// MAC = MAC + (FC - MAC) * IR0, then IR = MAC SAR sf and the
+    /// color is pushed in the FIFO. `mac` is the value of MAC1...3
+    /// before shifting.
+    fn interpolate(&mut self, mac: &[BigInt], sf: u32, lm: bool) {
+        for i in 0..3 {
+            let sub = big(self.fc[i]).shl(12) - mac[i].clone();
+            let sub = self.check_mac(i + 1, sub);
+
+            // The intermediate IR value is always saturated to
+            // -8000h...7fffh
+            let ir = sub.sar(sf).to_i32_wrapping() as i64;
+            let ir = self.lm_b(i + 1, ir, false);
+
+            let v = big(ir) * big(self.ir[0]) + mac[i].clone();
+
+            self.set_mac(i + 1, v, sf);
+        }
+
+        self.mac_to_ir(lm);
+        self.push_color();
+    }
+
+    fn dpc(&mut self, color: [u8; 4], sf: u32, lm: bool) {
+        let mac: Vec<BigInt> =
+            (0..3).map(|i| big(color[i] as i64).shl(16)).collect();
+
+        self.interpolate(&mac, sf, lm);
+    }
+
+    /// Light source and light color transformations of vector `n`
+    fn light(&mut self, n: usize, sf: u32, lm: bool) {
+        let (llm, v) = (self.llm, self.v[n]);
+
+        self.transform(llm, v, [0; 3], sf);
+        self.mac_to_ir(lm);
+
+        let ir = [self.ir[1], self.ir[2], self.ir[3]];
+        let (lcm, bk) = (self.lcm, self.bk);
+
+        self.transform(lcm, ir, bk, sf);
+        self.mac_to_ir(lm);
+    }
+
+    fn nc(&mut self, n: usize, sf: u32, lm: bool) {
+        self.light(n, sf, lm);
+        self.push_color();
+    }
+
+    fn ncc(&mut self, n: usize, sf: u32, lm: bool) {
+        self.light(n, sf, lm);
+        self.color_color(sf, lm);
+    }
+
+    fn ncd(&mut self, n: usize, sf: u32, lm: bool) {
+        self.light(n, sf, lm);
+
+        let mac = self.color_times_ir();
+
+        self.interpolate(&mac, sf, lm);
+    }
+
+    /// MAC = ([R * IR1, G * IR2, B * IR3] SHL 4) SAR sf, common to
+    /// the NCC and CC commands
+    fn color_color(&mut self, sf: u32, lm: bool) {
+        let mac = self.color_times_ir();
+
+        for (i, m) in mac.into_iter().enumerate() {
+            self.mac[i + 1] = m.sar(sf).to_i32_wrapping();
+        }
+
+        self.mac_to_ir(lm);
+        self.push_color();
+    }
+}
+
+fn big(v: i64) -> BigInt {
+    BigInt::from_i64(v)
+}
+
+/// Entry `i` of the reciprocal table used by the divider, computed
+/// with the formula from the specs
+fn unr_table(i: i64) -> i64 {
+    let v = (0x40000 / (i + 0x100) + 1) / 2 - 0x101;
+
+    if v < 0 {
+        0
+    } else {
+        v
+    }
+}
+
+fn command_name(command: u32) -> &'static str {
+    match command & 0x3f {
+        0x01 => "RTPS",
+        0x06 => "NCLIP",
+        0x0c => "OP",
+        0x10 => "DPCS",
+        0x11 => "INTPL",
+        0x12 => "MVMVA",
+        0x13 => "NCDS",
+        0x14 => "CDP",
+        0x16 => "NCDT",
+        0x1b => "NCCS",
+        0x1c => "CC",
+        0x1e => "NCS",
+        0x20 => "NCT",
+        0x28 => "SQR",
+        0x29 => "DCPL",
+        0x2a => "DPCT",
+        0x2d => "AVSZ3",
+        0x2e => "AVSZ4",
+        0x30 => "RTPT",
+        0x3d => "GPF",
+        0x3e => "GPL",
+        0x3f => "NCCT",
+        _ => "unknown",
+    }
+}
+
+static DATA_NAMES: [&'static str; 32] = [
+    "VXY0", "VZ0", "VXY1", "VZ1", "VXY2", "VZ2", "RGBC", "OTZ",
+    "IR0", "IR1", "IR2", "IR3", "SXY0", "SXY1", "SXY2", "SXYP",
+    "SZ0", "SZ1", "SZ2", "SZ3", "RGB0", "RGB1", "RGB2", "RES1",
+    "MAC0", "MAC1", "MAC2", "MAC3", "IRGB", "ORGB", "LZCS", "LZCR",
+];
+
+static CONTROL_NAMES: [&'static str; 32] = [
+    "RT11RT12", "RT13RT21", "RT22RT23", "RT31RT32", "RT33",
+    "TRX", "TRY", "TRZ",
+    "L11L12", "L13L21", "L22L23", "L31L32", "L33",
+    "RBK", "GBK", "BBK",
+    "LR1LR2", "LR3LG1", "LG2LG3", "LB1LB2", "LB3",
+    "RFC", "GFC", "BFC",
+    "OFX", "OFY", "H", "DQA", "DQB", "ZSF3", "ZSF4", "FLAG",
+];
+
+/// Names of the FLAG bits 12...31
+static FLAG_NAMES: [&'static str; 20] = [
+    "IR0 saturated",
+    "SY2 saturated",
+    "SX2 saturated",
+    "MAC0 negative overflow",
+    "MAC0 positive overflow",
+    "divide overflow",
+    "SZ3/OTZ saturated",
+    "B saturated",
+    "G saturated",
+    "R saturated",
+    "IR3 saturated",
+    "IR2 saturated",
+    "IR1 saturated",
+    "MAC3 negative overflow",
+    "MAC2 negative overflow",
+    "MAC1 negative overflow",
+    "MAC3 positive overflow",
+    "MAC2 positive overflow",
+    "MAC1 positive overflow",
+    "error",
+];
+
+#[test]
+fn reference_unr_table() {
+    assert!(unr_table(0x00) == 0xff);
+    assert!(unr_table(0x01) == 0xfd);
+    assert!(unr_table(0xff) == 0x00);
+    assert!(unr_table(0x100) == 0x00);
+}
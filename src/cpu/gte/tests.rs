@@ -1,4 +1,5 @@
 use super::Gte;
+use super::reference::GteAudit;
 
 #[test]
 fn gte_lzcr() {
@@ -32,12 +33,38 @@ fn gte_ops() {
 
         let mut gte = test.initial.make_gte();
 
-        gte.command(test.command);
+        gte.command(test.command).unwrap();
 
         test.result.validate(gte);
     }
 }
 
+/// Make sure the reference implementation agrees with the hardware
+/// (through `Gte` which is validated by `gte_ops`)
+#[test]
+fn gte_reference_ops() {
+    let mut audit = GteAudit::new();
+
+    for test in TESTS {
+        let mut gte = test.initial.make_gte();
+
+        let audited = audit.commands();
+
+        audit.command(&mut gte, 0, test.command).unwrap();
+
+        if audit.commands() == audited {
+            panic!("Test '{}': command 0x{:08x} not supported by the reference",
+                   test.desc, test.command);
+        }
+
+        if let Some(d) = audit.first_divergence() {
+            panic!("Test '{}': {}", test.desc, d);
+        }
+    }
+
+    assert!(audit.commands() == TESTS.len() as u64);
+}
+
 struct Test {
     /// Test description
     desc: &'static str,
@@ -0,0 +1,49 @@
+//! GTE command duration and interlock tests
+
+use gpu::{Gpu, VideoClock};
+use memory::Interconnect;
+use debugger::DummyDebugger;
+use shared::SharedState;
+use bios::Bios;
+
+use super::Cpu;
+use test_utils::{DummyRenderer, write_blob};
+
+/// Run a GTE command immediately followed by a read of the FLAG
+/// register and return the number of cycles it took
+fn command_then_read(command: u32) -> u64 {
+    let bios = Bios::dummy();
+    let gpu = Gpu::new(VideoClock::Ntsc);
+    let inter = Interconnect::new(bios, gpu, None);
+    let mut cpu = Cpu::new(inter);
+    let mut shared = SharedState::new();
+    let mut debugger = DummyDebugger;
+    let mut renderer = DummyRenderer;
+
+    write_blob(&mut cpu, 0x80100000,
+               &[0x4a000000 | command, // cop2  command
+                 0x4848f800,           // cfc2  t0, $31
+                 0x00000000]);         // nop
+
+    cpu.set_pc(0xa0100000);
+
+    let start = shared.tk().now();
+
+    for _ in 0..2 {
+        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer)
+            .unwrap();
+    }
+
+    shared.tk().now() - start
+}
+
+#[test]
+fn test_gte_read_stalls_until_command_completes() {
+    let rtpt = command_then_read(0x30);
+    let ncct = command_then_read(0x3f);
+
+    // RTPT takes 23 cycles, NCCT 39. The CFC2 has to wait for the
+    // command to complete in both cases.
+    assert!(rtpt >= 23);
+    assert!(ncct - rtpt == 39 - 23);
+}
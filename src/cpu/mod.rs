@@ -1,21 +1,36 @@
 mod cop0;
 mod gte;
+mod call_stack;
 
 #[cfg(test)]
 mod tests;
+#[cfg(test)]
+mod cache_tests;
+#[cfg(test)]
+mod gte_timing_tests;
 
 use std::fmt::{Display, Formatter, Error};
 use std::default::Default;
+use std::panic::{self, AssertUnwindSafe};
 
 use memory::{Interconnect, Addressable, Byte, HalfWord, Word};
 use shared::SharedState;
 use gpu::renderer::Renderer;
 use interrupt::InterruptState;
 use debugger::Debugger;
+use error::{EmulationError, ErrorReport, Device};
+use timekeeper::Cycles;
+use bios::tracer::KernelTracer;
+use bios::hle::HleKernel;
 
 use self::cop0::{Cop0, Exception};
 use self::gte::Gte;
 
+pub use self::call_stack::{Backtrace, BacktraceEntry, CallStack, CallFrame};
+pub use self::gte::reference::{GteAudit,
+                               Divergence as GteDivergence,
+                               Register as GteRegister};
+
 /// This struct contains the CPU state, including the `Interconnect`
 /// instance which owns most of the peripherals.
 #[derive(RustcDecodable, RustcEncodable)]
@@ -43,6 +58,8 @@ pub struct Cpu {
     cop0: Cop0,
     /// Coprocessor 2: Geometry Transform Engine
     gte: Gte,
+    /// Date at which the GTE will be done with the current command
+    gte_busy_until: Cycles,
     /// Load initiated by the current instruction (will take effect
     /// after the load delay slot)
     load: (RegisterIndex, u32),
@@ -54,6 +71,16 @@ pub struct Cpu {
     /// If `true` break instructions will trigger the debugger instead
     /// of generating an exception.
     debug_on_break: bool,
+    /// Shadow call stack used to generate backtraces
+    call_stack: CallStack,
+    /// Optional BIOS kernel call tracer
+    kernel_tracer: Option<KernelTracer>,
+    /// State of the HLE kernel when running with the HLE BIOS,
+    /// created when the first trap is reached
+    hle_kernel: Option<HleKernel>,
+    /// When set every GTE command is checked against the reference
+    /// implementation
+    gte_audit: Option<GteAudit>,
 }
 
 impl Cpu {
@@ -80,10 +107,15 @@ impl Cpu {
             inter:          inter,
             cop0:           Cop0::new(),
             gte:            Gte::new(),
+            gte_busy_until: 0,
             load:           (RegisterIndex(0), 0),
             branch:         false,
             delay_slot:     false,
             debug_on_break: false,
+            call_stack:     CallStack::new(),
+            kernel_tracer:  None,
+            hle_kernel:     None,
+            gte_audit:      None,
         }
     }
 
@@ -91,6 +123,40 @@ impl Cpu {
         self.debug_on_break = enabled
     }
 
+    /// Enable or disable the GTE audit mode. When enabled every GTE
+    /// command is also run through a slow reference implementation
+    /// and divergences are reported.
+    pub fn set_gte_audit(&mut self, enabled: bool) {
+        self.gte_audit =
+            if enabled {
+                Some(GteAudit::new())
+            } else {
+                None
+            };
+    }
+
+    /// Return the GTE audit results if the audit mode is enabled
+    pub fn gte_audit(&self) -> Option<&GteAudit> {
+        self.gte_audit.as_ref()
+    }
+
+    /// Enable or disable the BIOS kernel call tracer
+    pub fn set_kernel_tracer(&mut self, tracer: Option<KernelTracer>) {
+        self.kernel_tracer = tracer
+    }
+
+    /// Return a mutable reference to the kernel call tracer if it's
+    /// enabled
+    pub fn kernel_tracer_mut(&mut self) -> Option<&mut KernelTracer> {
+        self.kernel_tracer.as_mut()
+    }
+
+    /// Return the state of the HLE kernel if the HLE BIOS has been
+    /// called at least once
+    pub fn hle_kernel(&self) -> Option<&HleKernel> {
+        self.hle_kernel.as_ref()
+    }
+
     /// Return a reference to the interconnect
     pub fn interconnect(&self) -> &Interconnect {
         &self.inter
@@ -101,26 +167,111 @@ impl Cpu {
         &mut self.inter
     }
 
-    /// Run the emulator until the start of the next frame
+    /// Run the emulator until the start of the next frame. Stops
+    /// early if an emulation error occurs.
     pub fn run_until_next_frame<D>(&mut self,
                                    debugger: &mut D,
                                    shared: &mut SharedState,
                                    renderer: &mut Renderer)
+                                   -> Result<(), ErrorReport>
         where D: Debugger {
         let frame = shared.counters().frame.get();
 
         while frame == shared.counters().frame.get() {
-            self.run_next_instruction(debugger, shared, renderer);
+            try!(self.run_next_instruction(debugger, shared, renderer));
         }
+
+        Ok(())
     }
 
-    /// Run a single CPU instruction and return
+    /// Run a single CPU instruction and return. If a fatal condition
+    /// was encountered by the CPU or one of the peripherals while
+    /// running the instruction it's returned as an error alongside
+    /// the guest backtrace. The emulator state is left untouched so
+    /// that the caller can dump it before resetting.
     pub fn run_next_instruction<D>(&mut self,
                                    debugger: &mut D,
                                    shared: &mut SharedState,
                                    renderer: &mut Renderer)
+                                   -> Result<(), ErrorReport>
+        where D: Debugger {
+        self.step(debugger, shared, renderer);
+
+        match shared.take_error() {
+            Some(e) => {
+                let mut backtrace = self.backtrace();
+
+                if let Some(symbols) = debugger.symbols() {
+                    backtrace.resolve_symbols(symbols);
+                }
+
+                Err(ErrorReport {
+                    error: e,
+                    backtrace: backtrace,
+                })
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Call `f` (typically a closure running the emulator with
+    /// `run_until_next_frame`) and if it panics log the guest
+    /// backtrace before resuming the unwinding. A panic is always an
+    /// emulator bug but knowing what the guest was doing helps
+    /// tracking it down.
+    pub fn report_panics<F, R>(&mut self, f: F) -> R
+        where F: FnOnce(&mut Cpu) -> R {
+        let res = panic::catch_unwind(AssertUnwindSafe(|| f(&mut *self)));
+
+        match res {
+            Ok(r) => r,
+            Err(e) => {
+                error!("Emulator panic, guest backtrace:\n{}",
+                       self.backtrace());
+
+                panic::resume_unwind(e)
+            }
+        }
+    }
+
+    /// Return the guest backtrace for the instruction currently being
+    /// executed (or the last one executed if we're between two
+    /// instructions). This is based on the function calls and returns
+    /// observed so far so it can be inaccurate if the code doesn't
+    /// follow the usual calling conventions.
+    pub fn backtrace(&self) -> Backtrace {
+        self.call_stack.backtrace(self.current_pc)
+    }
+
+    /// Run a single CPU instruction
+    fn step<D>(&mut self,
+               debugger: &mut D,
+               shared: &mut SharedState,
+               renderer: &mut Renderer)
         where D: Debugger {
 
+        self.execute_instruction(debugger, shared, renderer);
+
+        // Report the RAM modified by DMA transfers, they can be
+        // started by the instruction or run during the peripheral
+        // sync
+        if self.inter.dma_ram_writes_pending() {
+            for (start, len) in self.inter.take_dma_ram_writes() {
+                debugger.dma_write(self, start, len);
+            }
+        }
+    }
+
+    /// Fetch, decode and execute the instruction at PC or handle a
+    /// pending exception
+    fn execute_instruction<D>(&mut self,
+                              debugger: &mut D,
+                              shared: &mut SharedState,
+                              renderer: &mut Renderer)
+        where D: Debugger {
+
+        let start = shared.tk().now();
+
         // Synchronize the peripherals
         if shared.tk().sync_pending() {
             self.inter.sync(shared);
@@ -134,9 +285,15 @@ impl Cpu {
         // Debugger entrypoint: used for code breakpoints and stepping
         debugger.pc_change(self);
 
+        if let Some(mut tracer) = self.kernel_tracer.take() {
+            tracer.pc_change(self, debugger.symbols());
+            self.kernel_tracer = Some(tracer);
+        }
+
         if self.current_pc % 4 != 0 {
             // PC is not correctly aligned!
             self.exception(Exception::LoadAddressError);
+            self.profile(shared, start);
             return;
         }
 
@@ -155,6 +312,13 @@ impl Cpu {
         self.delay_slot = self.branch;
         self.branch     = false;
 
+        // Hardware execution breakpoint (BPC/BPCM)
+        if self.cop0.check_code_breakpoint(self.current_pc) {
+            self.debug_exception();
+            self.profile(shared, start);
+            return;
+        }
+
         // Check for pending interrupts
         if self.cop0.irq_active(*shared.irq_state()) {
             shared.counters_mut().cpu_interrupt.increment();
@@ -168,9 +332,30 @@ impl Cpu {
                                         renderer);
             }
             self.exception(Exception::Interrupt);
+            self.profile(shared, start);
+        } else if self.hle_trap(shared, renderer) {
+            // The HLE kernel function didn't return through the trap
+            // stub, the instruction is not executed
+            self.profile(shared, start);
         } else {
             // No interrupt pending, run the current instruction
             self.decode_and_execute(debugger, instruction, shared, renderer);
+            self.profile(shared, start);
+        }
+    }
+
+    /// Update the instruction counter and profiler after running an
+    /// instruction. `start` is the date at which the instruction
+    /// started executing.
+    fn profile(&self, shared: &mut SharedState, start: Cycles) {
+        let cycles = shared.tk().now() - start;
+
+        let counters = shared.counters_mut();
+
+        counters.cpu_instruction.increment();
+
+        if let Some(ref mut profiler) = counters.profiler {
+            profiler.record(self.current_pc, cycles, &self.call_stack);
         }
     }
 
@@ -208,7 +393,7 @@ impl Cpu {
             let line = &mut self.icache[line as usize];
 
             // Check the tag and validity
-            if line.tag() != tag || line.valid_index() > index {
+            if line.tag() != tag || !line.valid(index) {
                 // Cache miss. Fetch the cacheline starting at the
                 // current index. If the index is not 0 then some
                 // words are going to remain invalid in the cacheline.
@@ -224,14 +409,14 @@ impl Cpu {
                     shared.tk().tick(1);
 
                     let instruction =
-                        Instruction(self.inter.load_instruction(shared, cpc));
+                        Instruction(self.inter.load_instruction(shared, cpc, 1));
 
                     line.set_instruction(i, instruction);
                     cpc += 4;
                 }
 
                 // Set the tag and valid bits
-                line.set_tag_valid(pc);
+                line.set_tag_valid(pc, 0xf & (0xf << index));
             }
 
             // Cache line is now guaranteed to be valid
@@ -248,26 +433,60 @@ impl Cpu {
             // 5 cycles on average.
             shared.tk().tick(4);
 
-            Instruction(self.inter.load_instruction(shared, pc))
+            Instruction(self.inter.load_instruction(shared, pc, 4))
         }
     }
 
-    /// Memory read
+    /// Memory read. Returns `None` if the access triggered a
+    /// hardware data breakpoint, in which case the instruction must
+    /// be aborted without modifying its target register.
     fn load<A, D>(&mut self,
                   debugger: &mut D,
                   shared: &mut SharedState,
-                  addr: u32) -> u32
+                  addr: u32) -> Option<u32>
     where A: Addressable, D: Debugger {
-        debugger.memory_read(self, addr);
+        // Hardware data breakpoint (BDA/BDAM). The load never reaches
+        // the bus since the instruction will be restarted when the
+        // debug handler returns, otherwise the read side effects
+        // would happen twice.
+        if self.cop0.check_data_breakpoint(addr, false) {
+            self.debug_exception();
+            return None;
+        }
+
+        let val =
+            if self.cop0.cache_isolated() {
+                // When the cache is isolated loads never reach the bus
+                self.cache_load::<A>(addr)
+            } else {
+                self.inter.load::<A>(shared, addr)
+            };
+
+        debugger.memory_read(self, addr, A::size(), val);
 
-        self.inter.load::<A>(shared, addr)
+        Some(val)
     }
 
-    /// Memory read with as little side-effect as possible. Used for
-    /// debugging.
-    pub fn examine<A: Addressable>(&mut self, addr: u32) -> u32 {
+    /// Memory read without side-effect. Used for debugging. The
+    /// interrupt controller registers read as 0 since their state is
+    /// not available here, use `Interconnect::peek` to access them.
+    /// Unmapped addresses read as all ones.
+    pub fn examine<A: Addressable>(&self, addr: u32) -> u32 {
+        self.inter.peek_without_irq::<A>(addr).unwrap_or(!0)
+    }
+
+    /// Memory write for debugging. Only RAM, the scratchpad and the
+    /// BIOS can be modified, returns false if `addr` is
+    /// elsewhere. The instruction cache is flushed so that modified
+    /// code is reloaded.
+    pub fn deposit<A: Addressable>(&mut self, addr: u32, val: u32) -> bool {
+        let written = self.inter.poke::<A>(addr, val);
 
-        self.inter.load::<A>(&mut SharedState::new(), addr)
+        if written {
+            self.flush_icache();
+        }
+
+        written
     }
 
     /// Memory write
@@ -287,7 +506,15 @@ impl Cpu {
                    addr: u32,
                    val: u32)
     where A: Addressable, D: Debugger {
-        debugger.memory_write(self, addr);
+        // Hardware data breakpoint (BDA/BDAM). The store is discarded
+        // since the instruction will be restarted when the debug
+        // handler returns.
+        if self.cop0.check_data_breakpoint(addr, true) {
+            self.debug_exception();
+            return;
+        }
+
+        debugger.memory_write(self, addr, A::size(), val);
 
         if self.cop0.cache_isolated() {
             self.cache_maintenance::<A>(addr, val);
@@ -296,22 +523,44 @@ impl Cpu {
         }
     }
 
-    /// Handle writes when the cache is isolated
-    pub fn cache_maintenance<T: Addressable>(&mut self, addr: u32, val: u32) {
-        // Implementing full cache emulation requires handling many
-        // corner cases. For now I'm just going to add support for
-        // cache invalidation which is the only use case for cache
-        // isolation as far as I know.
-
+    /// Handle loads when the cache is isolated: the value is read
+    /// directly from the instruction cache data, regardless of the
+    /// tag.
+    fn cache_load<T: Addressable>(&self, addr: u32) -> u32 {
         let cc = self.inter.cache_control();
 
         if !cc.icache_enabled() {
-            panic!("Cache maintenance while instruction cache is disabled");
+            // Nothing is connected to the CPU, the bus floats
+            return !0;
         }
 
-        if T::size() != 4 || val != 0 {
-            panic!("Unsupported write while cache is isolated: {:08x}",
-                   val);
+        let line = (addr >> 4) & 0xff;
+        let index = (addr >> 2) & 3;
+
+        let word = self.icache[line as usize].instruction(index).0;
+
+        // Extract the addressed bytes from the word
+        let shift = (addr & 3) * 8;
+
+        match T::size() {
+            1 => (word >> shift) & 0xff,
+            2 => (word >> shift) & 0xffff,
+            _ => word,
+        }
+    }
+
+    /// Handle writes when the cache is isolated. The store never
+    /// reaches the bus, it either ends up in the instruction cache
+    /// data or tags depending on the cache control register or is
+    /// simply discarded.
+    pub fn cache_maintenance<T: Addressable>(&mut self,
+                                             addr: u32,
+                                             val: u32) {
+        let cc = self.inter.cache_control();
+
+        if !cc.icache_enabled() {
+            // The store goes nowhere
+            return;
         }
 
         let line = (addr >> 4) & 0xff;
@@ -320,14 +569,26 @@ impl Cpu {
         let line = &mut self.icache[line as usize];
 
         if cc.tag_test_mode() {
-            // In tag test mode the write invalidates the entire
-            // targeted cacheline
-            line.invalidate();
+            // In tag test mode the write sets the tag of the targeted
+            // cacheline from the address and clears all the valid
+            // bits. The BIOS uses this to flush the cache by writing
+            // 0 to every line. Mednafen also ignores the written
+            // value here.
+            line.set_tag_valid(addr, 0);
         } else {
-            // Otherwise the write ends up directly in the cache.
+            // Otherwise the write ends up directly in the cache
+            // data. Partial writes are shifted into place but
+            // clobber the entire word.
             let index = (addr >> 2) & 3;
 
-            let instruction = Instruction(val);
+            let val =
+                match T::size() {
+                    1 => val & 0xff,
+                    2 => val & 0xffff,
+                    _ => val,
+                };
+
+            let instruction = Instruction(val << ((addr & 3) * 8));
 
             line.set_instruction(index, instruction);
         }
@@ -354,12 +615,64 @@ impl Cpu {
                                       self.current_pc,
                                       self.delay_slot);
 
+        self.call_stack.exception(self.current_pc,
+                                  handler_addr,
+                                  self.cop0.epc());
+
         // Exceptions don't have a branch delay, we jump directly into
         // the handler
         self.pc      = handler_addr;
         self.next_pc = self.pc.wrapping_add(4);
     }
 
+    /// Trigger a debug exception following a hardware breakpoint hit
+    fn debug_exception(&mut self) {
+        let handler_addr =
+            self.cop0.enter_debug_exception(self.current_pc,
+                                            self.delay_slot);
+
+        self.call_stack.exception(self.current_pc,
+                                  handler_addr,
+                                  self.cop0.epc());
+
+        self.pc      = handler_addr;
+        self.next_pc = self.pc.wrapping_add(4);
+    }
+
+    /// HLE BIOS functions are implemented natively when the CPU
+    /// executes the first instruction of their trap stub. This must
+    /// only be called once we know that the instruction at
+    /// `current_pc` is really going to run, otherwise an interrupted
+    /// trap would run twice. Returns true if the kernel redirected
+    /// the execution, in which case the stub must not be executed.
+    fn hle_trap(&mut self,
+                shared: &mut SharedState,
+                renderer: &mut Renderer) -> bool {
+        let pc = self.current_pc;
+
+        if !HleKernel::is_trap(pc) || !self.inter.bios().is_hle() {
+            return false;
+        }
+
+        // The kernel expects to see the register values as seen by
+        // the next instruction
+        self.delayed_load();
+
+        let mut kernel = self.hle_kernel.take().unwrap_or_else(HleKernel::new);
+
+        let jump = kernel.trap(pc, self, shared, renderer);
+
+        self.hle_kernel = Some(kernel);
+
+        match jump {
+            Some(addr) => {
+                self.set_pc(addr);
+                true
+            }
+            None => false,
+        }
+    }
+
     /// Retrieve the value of a general purpose register
     fn reg(&self, index: RegisterIndex) -> u32 {
         self.regs[index.0 as usize]
@@ -408,6 +721,12 @@ impl Cpu {
         &self.regs
     }
 
+    /// Set the value of a general purpose register. Meant to be used
+    /// from the debugger, writes to R0 are ignored.
+    pub fn write_reg(&mut self, index: u32, val: u32) {
+        self.set_reg(RegisterIndex(index), val)
+    }
+
     pub fn sr(&self) -> u32 {
         self.cop0.sr()
     }
@@ -420,10 +739,26 @@ impl Cpu {
         self.hi
     }
 
+    /// Set LO. Meant to be used from the debugger.
+    pub fn set_lo(&mut self, lo: u32) {
+        self.lo = lo;
+    }
+
+    /// Set HI. Meant to be used from the debugger.
+    pub fn set_hi(&mut self, hi: u32) {
+        self.hi = hi;
+    }
+
     pub fn pc(&self) -> u32 {
         self.pc
     }
 
+    /// Address of the instruction currently being executed (`pc`
+    /// already points to the next one while an instruction runs)
+    pub fn current_pc(&self) -> u32 {
+        self.current_pc
+    }
+
     pub fn cause(&self, irq_state: InterruptState) -> u32 {
         self.cop0.cause(irq_state)
     }
@@ -440,6 +775,16 @@ impl Cpu {
         self.pc = pc;
         self.next_pc = self.pc.wrapping_add(4);
         self.delay_slot = false;
+        // In case we're called from `Debugger::pc_change`, the
+        // instruction is fetched from `current_pc`
+        self.current_pc = pc;
+    }
+
+    /// Invalidate the whole instruction cache
+    pub fn flush_icache(&mut self) {
+        for line in self.icache.iter_mut() {
+            line.set_tag_valid(0, 0);
+        }
     }
 
     /// Decode `instruction`'s opcode and run the function
@@ -501,7 +846,7 @@ impl Cpu {
             0b001111 => self.op_lui(instruction),
             0b010000 => self.op_cop0(instruction, shared),
             0b010001 => self.op_cop1(instruction),
-            0b010010 => self.op_cop2(instruction),
+            0b010010 => self.op_cop2(instruction, shared),
             0b010011 => self.op_cop3(instruction),
             0b100000 => self.op_lb(instruction, debugger, shared),
             0b100001 => self.op_lh(instruction, debugger, shared),
@@ -656,6 +1001,13 @@ impl Cpu {
 
         if test != 0 {
             self.branch(i);
+
+            if is_link {
+                // Return after the delay slot
+                let ra = self.pc.wrapping_add(4);
+
+                self.call_stack.call(self.current_pc, self.next_pc, ra);
+            }
         }
     }
 
@@ -665,6 +1017,8 @@ impl Cpu {
 
         self.next_pc = self.reg(s);
 
+        self.call_stack.jump_register(self.next_pc);
+
         self.delayed_load();
 
         self.branch = true;
@@ -679,6 +1033,8 @@ impl Cpu {
 
         self.next_pc = self.reg(s);
 
+        self.call_stack.call(self.current_pc, self.next_pc, ra);
+
         self.delayed_load();
 
         // Store return address in `d`
@@ -987,6 +1343,8 @@ impl Cpu {
 
         self.op_j(instruction);
 
+        self.call_stack.call(self.current_pc, self.next_pc, ra);
+
         // Store return address in R31
         self.set_reg(RegisterIndex(31), ra);
 
@@ -1158,9 +1516,10 @@ impl Cpu {
     fn op_cop0(&mut self, instruction: Instruction, shared: &mut SharedState) {
         match instruction.cop_opcode() {
             0b00000 => self.op_mfc0(instruction, shared),
-            0b00100 => self.op_mtc0(instruction),
-            0b10000 => self.op_rfe(instruction),
-            _       => panic!("unhandled cop0 instruction {}", instruction)
+            0b00100 => self.op_mtc0(instruction, shared),
+            0b10000 => self.op_rfe(instruction, shared),
+            _       => fatal!(shared, Device::Cpu,
+                              "Unhandled cop0 instruction {}", instruction),
         }
     }
 
@@ -1170,6 +1529,8 @@ impl Cpu {
         let cop_r = instruction.d().0;
 
         let v = match cop_r {
+            3 => self.cop0.bpc(),
+            5 => self.cop0.bda(),
             6 => {
                 // No$ says this register "randomly" memorizes a jump
                 // target after certain exceptions occur. Doesn't seem
@@ -1178,11 +1539,7 @@ impl Cpu {
                 warn!("Unhandled read from JUMP_DEST (cop0r6)");
                 0
             }
-            7 => {
-                // DCIC: breakpoint control
-                warn!("Unhandled read from DCIC (cop0r7)");
-                0
-            }
+            7 => self.cop0.dcic(),
             8 => {
                 // This register should be mostly useless on the
                 // PlayStation since it doesn't have virtual memory,
@@ -1192,18 +1549,24 @@ impl Cpu {
                 warn!("Unhandled read from BAD_VADDR (cop0r8)");
                 0
             }
+            9 => self.cop0.bdam(),
+            11 => self.cop0.bpcm(),
             12 => self.cop0.sr(),
             13 => self.cop0.cause(*shared.irq_state()),
             14 => self.cop0.epc(),
             15 => PROCESSOR_ID,
-            _  => panic!("Unhandled read from cop0r{}", cop_r),
+            _  => {
+                fatal!(shared, Device::Cpu,
+                       "Unhandled read from cop0r{}", cop_r);
+                0
+            }
         };
 
         self.delayed_load_chain(cpu_r, v);
     }
 
     /// Move To Coprocessor 0
-    fn op_mtc0(&mut self, instruction: Instruction) {
+    fn op_mtc0(&mut self, instruction: Instruction, shared: &mut SharedState) {
         let cpu_r = instruction.t();
         let cop_r = instruction.d().0;
 
@@ -1212,18 +1575,22 @@ impl Cpu {
         self.delayed_load();
 
         match cop_r {
-            3 | 5 | 6 | 7 | 9 | 11  => // Breakpoints registers
-                if v != 0 {
-                    panic!("Unhandled write to cop0r{}: {:08x}", cop_r, v)
-                },
+            3 => self.cop0.set_bpc(v),
+            5 => self.cop0.set_bda(v),
+            // JUMPDEST is read-only
+            6 => (),
+            7 => self.cop0.set_dcic(v),
+            9 => self.cop0.set_bdam(v),
+            11 => self.cop0.set_bpcm(v),
             12 => self.cop0.set_sr(v),
             13 => self.cop0.set_cause(v),
-            _  => panic!("Unhandled cop0 register {}", cop_r),
+            _  => fatal!(shared, Device::Cpu,
+                         "Unhandled cop0 register {}", cop_r),
         }
     }
 
     /// Return From Exception
-    fn op_rfe(&mut self, instruction: Instruction) {
+    fn op_rfe(&mut self, instruction: Instruction, shared: &mut SharedState) {
         self.delayed_load();
 
         // There are other instructions with the same encoding but all
@@ -1231,10 +1598,13 @@ impl Cpu {
         // implement them. Still, let's make sure we're not running
         // buggy code.
         if instruction.0 & 0x3f != 0b010000 {
-            panic!("Invalid cop0 instruction: {}", instruction);
+            fatal!(shared, Device::Cpu,
+                   "Invalid cop0 instruction: {}", instruction);
+            return;
         }
 
         self.cop0.return_from_exception();
+        self.call_stack.return_from_exception();
     }
 
     /// Coprocessor 1 opcode (does not exist on the PlayStation)
@@ -1245,7 +1615,7 @@ impl Cpu {
     }
 
     /// Coprocessor 2 opcode (GTE)
-    fn op_cop2(&mut self, instruction: Instruction) {
+    fn op_cop2(&mut self, instruction: Instruction, shared: &mut SharedState) {
         // XXX: we should check that the GTE is enabled in cop0's
         // status register, otherwise the cop2 instructions seem to
         // freeze the CPU (or maybe raise an exception?). Furthermore
@@ -1255,20 +1625,58 @@ impl Cpu {
         let cop_opcode = instruction.cop_opcode();
 
         if cop_opcode & 0x10 != 0 {
-            // GTE command
-            // XXX handle GTE command duration
-            self.gte.command(instruction.0);
+            // GTE command. We have to wait for the previous one to
+            // complete.
+            self.gte_stall(shared);
+
+            let res =
+                match self.gte_audit {
+                    Some(ref mut audit) => audit.command(&mut self.gte,
+                                                         self.current_pc,
+                                                         instruction.0),
+                    None => self.gte.command(instruction.0),
+                };
+
+            if let Err(desc) = res {
+                shared.set_error(EmulationError::Unimplemented(Device::Gte,
+                                                               desc));
+            }
+
+            // The command's first cycle has already been accounted for
+            // by the COP2 instruction
+            let duration = gte::command_duration(instruction.0);
+
+            self.gte_busy_until = shared.tk().now() + duration - 1;
         } else {
             match cop_opcode {
-                0b00000 => self.op_mfc2(instruction),
-                0b00010 => self.op_cfc2(instruction),
+                0b00000 => {
+                    self.gte_stall(shared);
+                    self.op_mfc2(instruction)
+                }
+                0b00010 => {
+                    self.gte_stall(shared);
+                    self.op_cfc2(instruction)
+                }
                 0b00100 => self.op_mtc2(instruction),
                 0b00110 => self.op_ctc2(instruction),
-                _       => panic!("unhandled GTE instruction {}", instruction),
+                _       => fatal!(shared, Device::Gte,
+                                  "Unhandled GTE instruction {}",
+                                  instruction),
             }
         }
     }
 
+    /// Stall the CPU until the GTE is done with the current command.
+    /// Must be called before reading a GTE register or issuing a new
+    /// command.
+    fn gte_stall(&mut self, shared: &mut SharedState) {
+        let now = shared.tk().now();
+
+        if self.gte_busy_until > now {
+            shared.tk().tick(self.gte_busy_until - now);
+        }
+    }
+
     /// Move From Coprocessor 2 Data register
     fn op_mfc2(&mut self, instruction: Instruction) {
         let cpu_r = instruction.t();
@@ -1333,10 +1741,11 @@ impl Cpu {
 
         let addr = self.reg(s).wrapping_add(i);
 
-        // Cast as i8 to force sign extension
-        let v = self.load::<Byte, D>(debugger, shared, addr) as i8;
-
-        self.delayed_load_chain(t, v as u32);
+        match self.load::<Byte, D>(debugger, shared, addr) {
+            // Cast as i8 to force sign extension
+            Some(v) => self.delayed_load_chain(t, v as i8 as u32),
+            None => self.delayed_load(),
+        }
     }
 
     /// Load Halfword (signed)
@@ -1353,10 +1762,11 @@ impl Cpu {
 
         // Address must be 16bit aligned
         if addr % 2 == 0 {
-            // Cast as i16 to force sign extension
-            let v = self.load::<HalfWord, D>(debugger, shared, addr) as i16;
-
-            self.delayed_load_chain(t, v as u32);
+            match self.load::<HalfWord, D>(debugger, shared, addr) {
+                // Cast as i16 to force sign extension
+                Some(v) => self.delayed_load_chain(t, v as i16 as u32),
+                None => self.delayed_load(),
+            }
         } else {
             self.delayed_load();
             self.exception(Exception::LoadAddressError);
@@ -1390,7 +1800,11 @@ impl Cpu {
         // Next we load the *aligned* word containing the first
         // addressed byte
         let aligned_addr = addr & !3;
-        let aligned_word = self.load::<Word, D>(debugger, shared, aligned_addr);
+        let aligned_word =
+            match self.load::<Word, D>(debugger, shared, aligned_addr) {
+                Some(w) => w,
+                None => return self.delayed_load(),
+            };
 
         // Depending on the address alignment we fetch the 1, 2, 3 or
         // 4 *most* significant bytes and put them in the target
@@ -1420,9 +1834,10 @@ impl Cpu {
 
         // Address must be 32bit aligned
         if addr % 4 == 0 {
-            let v = self.load::<Word, D>(debugger, shared, addr);
-
-            self.delayed_load_chain(t, v);
+            match self.load::<Word, D>(debugger, shared, addr) {
+                Some(v) => self.delayed_load_chain(t, v),
+                None => self.delayed_load(),
+            }
         } else {
             self.delayed_load();
             self.exception(Exception::LoadAddressError);
@@ -1441,9 +1856,10 @@ impl Cpu {
 
         let addr = self.reg(s).wrapping_add(i);
 
-        let v = self.load::<Byte, D>(debugger, shared, addr);
-
-        self.delayed_load_chain(t, v as u32);
+        match self.load::<Byte, D>(debugger, shared, addr) {
+            Some(v) => self.delayed_load_chain(t, v),
+            None => self.delayed_load(),
+        }
     }
 
     /// Load Halfword Unsigned
@@ -1460,9 +1876,10 @@ impl Cpu {
 
         // Address must be 16bit aligned
         if addr % 2 == 0 {
-            let v = self.load::<HalfWord, D>(debugger, shared, addr);
-
-            self.delayed_load_chain(t, v);
+            match self.load::<HalfWord, D>(debugger, shared, addr) {
+                Some(v) => self.delayed_load_chain(t, v),
+                None => self.delayed_load(),
+            }
         } else {
             self.delayed_load();
             self.exception(Exception::LoadAddressError);
@@ -1496,7 +1913,11 @@ impl Cpu {
         // Next we load the *aligned* word containing the first
         // addressed byte
         let aligned_addr = addr & !3;
-        let aligned_word = self.load::<Word, D>(debugger, shared, aligned_addr);
+        let aligned_word =
+            match self.load::<Word, D>(debugger, shared, aligned_addr) {
+                Some(w) => w,
+                None => return self.delayed_load(),
+            };
 
         // Depending on the address alignment we fetch the 1, 2, 3 or
         // 4 *least* significant bytes and put them in the target
@@ -1573,7 +1994,11 @@ impl Cpu {
         let aligned_addr = addr & !3;
         // Load the current value for the aligned word at the target
         // address
-        let cur_mem = self.load::<Word, D>(debugger, shared, aligned_addr);
+        let cur_mem =
+            match self.load::<Word, D>(debugger, shared, aligned_addr) {
+                Some(w) => w,
+                None => return self.delayed_load(),
+            };
 
         let mem =
             match addr & 3 {
@@ -1630,7 +2055,11 @@ impl Cpu {
         let aligned_addr = addr & !3;
         // Load the current value for the aligned word at the target
         // address
-        let cur_mem = self.load::<Word, D>(debugger, shared, aligned_addr);
+        let cur_mem =
+            match self.load::<Word, D>(debugger, shared, aligned_addr) {
+                Some(w) => w,
+                None => return self.delayed_load(),
+            };
 
         let mem =
             match addr & 3 {
@@ -1678,10 +2107,10 @@ impl Cpu {
 
         // Address must be 32bit aligned
         if addr % 4 == 0 {
-            let v = self.load::<Word, D>(debugger, shared, addr);
-
             // Send to coprocessor
-            self.gte.set_data(cop_r, v);
+            if let Some(v) = self.load::<Word, D>(debugger, shared, addr) {
+                self.gte.set_data(cop_r, v);
+            }
         } else {
             self.exception(Exception::LoadAddressError);
         }
@@ -1722,6 +2151,9 @@ impl Cpu {
         let s = instruction.s();
 
         let addr = self.reg(s).wrapping_add(i);
+
+        self.gte_stall(shared);
+
         let v = self.gte.data(cop_r);
 
         self.delayed_load();
@@ -1842,8 +2274,8 @@ struct RegisterIndex(u32);
 /// Instruction cache line
 #[derive(Clone, Copy, RustcDecodable, RustcEncodable)]
 struct ICacheLine {
-    /// Tag: high 22bits of the address associated with this cacheline
-    /// Valid bits: 3 bit index of the first valid word in line.
+    /// Tag: bits [30:12] of the address associated with this
+    /// cacheline. Valid bits: bits [3:0], one per word in the line.
     tag_valid: u32,
     /// Four words per line
     line: [Instruction; 4],
@@ -1855,7 +2287,7 @@ impl ICacheLine {
         // missbehaving software we fill them with "trap" values
         ICacheLine {
             // Tag is 0, all line valid
-            tag_valid: 0x0,
+            tag_valid: 0xf,
             // BREAK opcode
             //line: [Instruction(0xbadc0de5); 4],
             line: [Instruction(0); 4],
@@ -1867,27 +2299,15 @@ impl ICacheLine {
         self.tag_valid & 0xfffff000
     }
 
-    /// Return the cacheline's first valid word
-    fn valid_index(&self) -> u32 {
-        // We store the valid bits in bits [4:2], this way we can just
-        // mask the PC value in `set_tag_valid` without having to
-        // shuffle the bits around
-        (self.tag_valid >> 2) & 0x7
-    }
-
-    /// Set the cacheline's tag and valid bits. `pc` is the first
-    /// valid PC in the cacheline.
-    fn set_tag_valid(&mut self, pc: u32) {
-        self.tag_valid =  pc & 0x7ffff00c;
+    /// Return true if the word at `index` in the cacheline is valid
+    fn valid(&self, index: u32) -> bool {
+        self.tag_valid & (1 << index) != 0
     }
 
-    /// Invalidate the entire cacheline by pushing the index out of
-    /// range. Doesn't change the tag or contents of the line.
-    fn invalidate(&mut self) {
-        // Setting bit 4 means that the value returned by valid_index
-        // will be in the range [4, 7] which is outside the valid
-        // cacheline index range [0, 3].
-        self.tag_valid |= 0x10;
+    /// Set the cacheline's tag from `addr` and replace the valid bits
+    /// with `valid` (one bit per word)
+    fn set_tag_valid(&mut self, addr: u32, valid: u32) {
+        self.tag_valid = (addr & 0x7ffff000) | (valid & 0xf);
     }
 
     fn instruction(&self, index: u32) -> Instruction {
@@ -116,7 +116,8 @@ fn test_beq() {
             timeout = false;
             break;
         }
-        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer);
+        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer)
+            .unwrap();
     }
     assert!(timeout == false);
 
@@ -159,7 +160,8 @@ fn test_branch_in_branch_delay() {
             timeout = false;
             break;
         }
-        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer);
+        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer)
+            .unwrap();
     }
     assert!(timeout == false);
 
@@ -241,7 +243,8 @@ fn test_lwr_and_lwr_load_delay() {
             timeout = false;
             break;
         }
-        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer);
+        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer)
+            .unwrap();
     }
     assert!(timeout == false);
 
@@ -298,7 +301,8 @@ fn test_add_1() {
             timeout = false;
             break;
         }
-        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer);
+        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer)
+            .unwrap();
     }
     assert!(timeout == false);
 
@@ -344,7 +348,8 @@ fn test_arithmetic_branching_test() {
             timeout = false;
             break;
         }
-        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer);
+        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer)
+            .unwrap();
     }
     assert!(timeout == false);
 
@@ -408,7 +413,8 @@ fn test_bltzal_and_bgezal() {
             timeout = false;
             break;
         }
-        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer);
+        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer)
+            .unwrap();
     }
     assert!(timeout == false);
 
@@ -453,7 +459,8 @@ fn test_unaligned_loads() {
             timeout = false;
             break;
         }
-        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer);
+        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer)
+            .unwrap();
     }
     assert!(timeout == false);
 
@@ -502,7 +509,8 @@ fn test_load_delay_for_cop() {
             timeout = false;
             break;
         }
-        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer);
+        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer)
+            .unwrap();
     }
     assert!(timeout == false);
 
@@ -563,7 +571,8 @@ fn test_swl_and_swr() {
             timeout = false;
             break;
         }
-        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer);
+        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer)
+            .unwrap();
     }
     assert!(timeout == false);
 
@@ -612,7 +621,8 @@ fn test_multiple_load_cancelling() {
             timeout = false;
             break;
         }
-        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer);
+        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer)
+            .unwrap();
     }
     assert!(timeout == false);
 
@@ -685,7 +695,8 @@ fn test_lwl_and_lwr() {
             timeout = false;
             break;
         }
-        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer);
+        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer)
+            .unwrap();
     }
     assert!(timeout == false);
 
@@ -742,7 +753,8 @@ fn test_lh_and_lb_sign_extension() {
             timeout = false;
             break;
         }
-        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer);
+        cpu.run_next_instruction(&mut debugger, &mut shared, &mut renderer)
+            .unwrap();
     }
     assert!(timeout == false);
 
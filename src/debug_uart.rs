@@ -6,6 +6,7 @@
 
 use memory::Addressable;
 use shared::SharedState;
+use error::Device;
 
 #[derive(RustcDecodable, RustcEncodable)]
 pub struct DebugUart {
@@ -23,27 +24,42 @@ impl DebugUart {
     }
 
     pub fn load<A: Addressable>(&mut self,
-                                _: &mut SharedState,
+                                shared: &mut SharedState,
                                 offset: u32) -> u32 {
         if A::size() != 1 {
-            panic!("Unhandled debug UART load ({})", A::size())
+            fatal!(shared, Device::DebugUart,
+                   "Unhandled debug UART load ({})", A::size());
+            return 0;
         }
 
         match offset {
             // UART status register A. Return "Tx ready" bit set.
             0x21 => 1 << 2,
-            _ => panic!("Unhandled debug UART store: {:x}",
-                        offset),
+            _ => {
+                fatal!(shared, Device::DebugUart,
+                       "Unhandled debug UART load: {:x}", offset);
+                0
+            }
+        }
+    }
+
+    /// Register value without side effects, used for debugging
+    pub fn peek(&self, offset: u32) -> u32 {
+        match offset {
+            0x21 => 1 << 2,
+            _ => 0,
         }
     }
 
     pub fn store<A: Addressable>(&mut self,
-                                 _: &mut SharedState,
+                                 shared: &mut SharedState,
                                  offset: u32,
                                  val: u32) {
 
         if A::size() != 1 {
-            panic!("Unhandled debug UART store ({})", A::size())
+            fatal!(shared, Device::DebugUart,
+                   "Unhandled debug UART store ({})", A::size());
+            return;
         }
 
         let val = val as u8;
@@ -69,14 +85,13 @@ impl DebugUart {
             0x25 => {
                 // We don't implement interrupts for now
                 if val != 0 {
-                    panic!("Unhandled debug UART interrupt mask: {:02x}",
+                    fatal!(shared, Device::DebugUart,
+                           "Unhandled debug UART interrupt mask: {:02x}",
                            val);
                 }
             }
-            // Boot status register, is incremented by the BIOS during
-            // bootup
-            0x41 => debug!("BIOS boot status: {}", val),
-            _ => panic!("Unhandled debug UART store: {:x} {:02x}",
+            _ => fatal!(shared, Device::DebugUart,
+                        "Unhandled debug UART store: {:x} {:02x}",
                         offset, val),
         }
     }
@@ -0,0 +1,717 @@
+//! Generic breakpoint and watchpoint manager.
+//!
+//! `BreakpointDebugger` keeps track of code breakpoints and data
+//! watchpoints and reports every hit along with its cause. The hits
+//! can either be polled by the frontend after running the CPU
+//! (`take_hits`) or handled synchronously by a callback called from
+//! within the CPU before the next instruction is executed.
+//!
+//! Watchpoints also catch the RAM writes made by DMA transfers.
+
+use cpu::Cpu;
+use memory::Word;
+use memory::map;
+
+use super::Debugger;
+use super::symbols::SymbolTable;
+
+/// Code breakpoint
+#[derive(Clone, Debug)]
+pub struct Breakpoint {
+    /// Address of the instruction
+    pub addr: u32,
+    /// Number of hits to ignore before actually breaking
+    pub ignore_count: u32,
+    /// Number of times the breakpoint has been reached
+    hits: u32,
+}
+
+impl Breakpoint {
+    pub fn hits(&self) -> u32 {
+        self.hits
+    }
+}
+
+/// Type of memory access
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    /// Either read or write (only used for watchpoints, hits always
+    /// report the actual access)
+    ReadWrite,
+}
+
+impl Access {
+    fn matches(self, access: Access) -> bool {
+        self == Access::ReadWrite || self == access
+    }
+}
+
+/// Condition on the value read or written
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Condition {
+    Equal(u32),
+    NotEqual(u32),
+    /// `value & mask == expected`
+    Masked { mask: u32, expected: u32 },
+}
+
+impl Condition {
+    pub fn matches(self, value: u32) -> bool {
+        match self {
+            Condition::Equal(v) => value == v,
+            Condition::NotEqual(v) => value != v,
+            Condition::Masked { mask, expected } => value & mask == expected,
+        }
+    }
+}
+
+/// Data watchpoint
+#[derive(Clone, Debug)]
+pub struct Watchpoint {
+    /// First address of the watched range. The region bits are
+    /// ignored so that accesses through KUSEG, KSEG0 and KSEG1 all
+    /// match.
+    pub start: u32,
+    /// Length of the watched range in bytes
+    pub len: u32,
+    /// Type of access to watch
+    pub access: Access,
+    /// If set only accesses of this width in bytes (1, 2 or 4)
+    /// match. DMA transfers are considered 32bit accesses.
+    pub size: Option<u8>,
+    /// Optional condition on the value read or written. For DMA
+    /// transfers it's tested against the value at `start` after the
+    /// transfer.
+    pub condition: Option<Condition>,
+    /// Number of hits to ignore before actually breaking
+    pub ignore_count: u32,
+    /// Number of matching accesses so far
+    hits: u32,
+}
+
+impl Watchpoint {
+    /// Watch all accesses of type `access` to `len` bytes starting
+    /// at `start`
+    pub fn new(start: u32, len: u32, access: Access) -> Watchpoint {
+        Watchpoint {
+            start: start,
+            len: len,
+            access: access,
+            size: None,
+            condition: None,
+            ignore_count: 0,
+            hits: 0,
+        }
+    }
+
+    pub fn hits(&self) -> u32 {
+        self.hits
+    }
+
+    /// Return true if the `len` bytes at `addr` overlap the watched
+    /// range
+    fn overlaps(&self, addr: u32, len: u32) -> bool {
+        let addr = map::mask_region(addr);
+        let start = map::mask_region(self.start);
+
+        addr.wrapping_sub(start) < self.len || start.wrapping_sub(addr) < len
+    }
+
+    /// Return true if an access of `size` bytes at `addr` matches
+    /// the watchpoint
+    fn matches(&self, access: Access, addr: u32, size: u8) -> bool {
+        let size_matches =
+            match self.size {
+                Some(s) => s == size,
+                None => true,
+            };
+
+        size_matches &&
+            self.access.matches(access) &&
+            self.overlaps(addr, size as u32)
+    }
+}
+
+/// Handle returned by `BreakpointDebugger::add_watchpoint`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WatchpointId(u32);
+
+/// Reason for a break
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cause {
+    /// Explicit request through `Debugger::trigger_break`
+    Requested,
+    /// Code breakpoint reached
+    Breakpoint,
+    Watchpoint {
+        id: WatchpointId,
+        /// Address accessed
+        addr: u32,
+        /// Access type, never `ReadWrite`
+        access: Access,
+        /// Width of the access in bytes. For DMA transfers it's the
+        /// length of the transfer.
+        size: u32,
+        /// Value read or written, `None` for DMA transfers
+        value: Option<u32>,
+        /// True if the access was made by a DMA transfer
+        dma: bool,
+    },
+}
+
+/// Breakpoint or watchpoint hit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hit {
+    /// Address of the instruction that caused the hit. For code
+    /// breakpoints it's the address of the instruction about to be
+    /// executed, for watchpoints it's the instruction making the
+    /// access.
+    pub pc: u32,
+    pub cause: Cause,
+}
+
+/// Callback called when a hit occurs, before the next instruction
+/// is executed. It can inspect and modify the CPU state.
+pub type Handler = Box<FnMut(&mut Cpu, &Hit)>;
+
+/// Debugger implementation managing breakpoints and watchpoints
+pub struct BreakpointDebugger {
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<(WatchpointId, Watchpoint)>,
+    next_id: u32,
+    /// Number of entries in `hits` already passed to the handler.
+    /// Watchpoint hits are handled before the next instruction.
+    handled: usize,
+    /// Set by `trigger_break`
+    break_requested: bool,
+    /// True if `pc_change` has anything to do. Keeps the common case
+    /// cheap.
+    armed: bool,
+    hits: Vec<Hit>,
+    handler: Option<Handler>,
+    symbols: Option<SymbolTable>,
+}
+
+impl BreakpointDebugger {
+    pub fn new() -> BreakpointDebugger {
+        BreakpointDebugger {
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            next_id: 0,
+            handled: 0,
+            break_requested: false,
+            armed: false,
+            hits: Vec::new(),
+            handler: None,
+            symbols: None,
+        }
+    }
+
+    /// Set the callback called for every hit. Hits are still
+    /// recorded for `take_hits`.
+    pub fn set_handler(&mut self, handler: Handler) {
+        self.handler = Some(handler);
+    }
+
+    /// Set the symbol table returned by `Debugger::symbols`
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = Some(symbols);
+    }
+
+    /// Return the hits since the last call. The frontend should call
+    /// this after running the CPU and stop if it's not empty.
+    pub fn take_hits(&mut self) -> Vec<Hit> {
+        self.handled = 0;
+
+        ::std::mem::replace(&mut self.hits, Vec::new())
+    }
+
+    /// Add a code breakpoint at `addr`. Does nothing if there's
+    /// already one.
+    pub fn add_breakpoint(&mut self, addr: u32) {
+        if self.breakpoint(addr).is_none() {
+            self.breakpoints.push(Breakpoint {
+                addr: addr,
+                ignore_count: 0,
+                hits: 0,
+            });
+        }
+
+        self.update_armed();
+    }
+
+    /// Remove the breakpoint at `addr`, returns false if there was
+    /// none
+    pub fn remove_breakpoint(&mut self, addr: u32) -> bool {
+        let len = self.breakpoints.len();
+
+        self.breakpoints.retain(|b| b.addr != addr);
+
+        self.update_armed();
+
+        self.breakpoints.len() != len
+    }
+
+    pub fn breakpoint(&self, addr: u32) -> Option<&Breakpoint> {
+        self.breakpoints.iter().find(|b| b.addr == addr)
+    }
+
+    pub fn breakpoint_mut(&mut self, addr: u32) -> Option<&mut Breakpoint> {
+        self.breakpoints.iter_mut().find(|b| b.addr == addr)
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> WatchpointId {
+        let id = WatchpointId(self.next_id);
+
+        self.next_id = self.next_id.wrapping_add(1);
+
+        self.watchpoints.push((id, watchpoint));
+
+        id
+    }
+
+    /// Remove watchpoint `id`, returns false if it didn't exist
+    pub fn remove_watchpoint(&mut self, id: WatchpointId) -> bool {
+        let len = self.watchpoints.len();
+
+        self.watchpoints.retain(|&(i, _)| i != id);
+
+        self.watchpoints.len() != len
+    }
+
+    pub fn watchpoint(&self, id: WatchpointId) -> Option<&Watchpoint> {
+        self.watchpoints.iter().find(|w| w.0 == id).map(|w| &w.1)
+    }
+
+    pub fn watchpoint_mut(&mut self,
+                          id: WatchpointId) -> Option<&mut Watchpoint> {
+        self.watchpoints.iter_mut().find(|w| w.0 == id).map(|w| &mut w.1)
+    }
+
+    /// Remove all breakpoints and watchpoints
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+        self.update_armed();
+    }
+
+    fn update_armed(&mut self) {
+        self.armed =
+            self.break_requested ||
+            !self.breakpoints.is_empty() ||
+            self.handled < self.hits.len();
+    }
+
+    /// Called for every CPU memory access while watchpoints are set
+    fn memory_access(&mut self,
+                     cpu: &mut Cpu,
+                     access: Access,
+                     addr: u32,
+                     size: u8,
+                     val: u32) {
+        // Stores put the whole register on the bus
+        let val =
+            match size {
+                1 => val & 0xff,
+                2 => val & 0xffff,
+                _ => val,
+            };
+
+        let pc = cpu.current_pc();
+
+        for entry in &mut self.watchpoints {
+            let id = entry.0;
+            let w = &mut entry.1;
+
+            if !w.matches(access, addr, size) {
+                continue;
+            }
+
+            if let Some(c) = w.condition {
+                if !c.matches(val) {
+                    continue;
+                }
+            }
+
+            w.hits += 1;
+
+            if w.hits > w.ignore_count {
+                self.hits.push(Hit {
+                    pc: pc,
+                    cause: Cause::Watchpoint {
+                        id: id,
+                        addr: addr,
+                        access: access,
+                        size: size as u32,
+                        value: Some(val),
+                        dma: false,
+                    },
+                });
+            }
+        }
+
+        self.update_armed();
+    }
+}
+
+impl Debugger for BreakpointDebugger {
+    fn trigger_break(&mut self) {
+        self.break_requested = true;
+        self.armed = true;
+    }
+
+    fn pc_change(&mut self, cpu: &mut Cpu) {
+        if !self.armed {
+            return;
+        }
+
+        let pc = cpu.pc();
+
+        if self.break_requested {
+            self.break_requested = false;
+
+            self.hits.push(Hit {
+                pc: pc,
+                cause: Cause::Requested,
+            });
+        }
+
+        if let Some(b) = self.breakpoints.iter_mut().find(|b| b.addr == pc) {
+            b.hits += 1;
+
+            if b.hits > b.ignore_count {
+                self.hits.push(Hit {
+                    pc: pc,
+                    cause: Cause::Breakpoint,
+                });
+            }
+        }
+
+        if let Some(mut handler) = self.handler.take() {
+            for hit in &self.hits[self.handled..] {
+                handler(cpu, hit);
+            }
+
+            self.handler = Some(handler);
+        }
+
+        self.handled = self.hits.len();
+
+        self.update_armed();
+    }
+
+    fn memory_read(&mut self, cpu: &mut Cpu, addr: u32, size: u8, val: u32) {
+        if !self.watchpoints.is_empty() {
+            self.memory_access(cpu, Access::Read, addr, size, val);
+        }
+    }
+
+    fn memory_write(&mut self, cpu: &mut Cpu, addr: u32, size: u8, val: u32) {
+        if !self.watchpoints.is_empty() {
+            self.memory_access(cpu, Access::Write, addr, size, val);
+        }
+    }
+
+    fn dma_write(&mut self, cpu: &mut Cpu, addr: u32, len: u32) {
+        let pc = cpu.current_pc();
+
+        for entry in &mut self.watchpoints {
+            let id = entry.0;
+            let w = &mut entry.1;
+
+            // DMA transfers are always word-wide so watchpoints on
+            // narrower accesses never match
+            let matches =
+                w.access.matches(Access::Write) &&
+                w.size.unwrap_or(4) == 4 &&
+                w.overlaps(addr, len);
+
+            if !matches {
+                continue;
+            }
+
+            if let Some(c) = w.condition {
+                // The interrupt controller registers read as 0 here,
+                // see `Cpu::examine`
+                let v = cpu.examine::<Word>(w.start);
+
+                if !c.matches(v) {
+                    continue;
+                }
+            }
+
+            w.hits += 1;
+
+            if w.hits > w.ignore_count {
+                self.hits.push(Hit {
+                    pc: pc,
+                    cause: Cause::Watchpoint {
+                        id: id,
+                        addr: addr,
+                        access: Access::Write,
+                        size: len,
+                        value: None,
+                        dma: true,
+                    },
+                });
+            }
+        }
+
+        self.update_armed();
+    }
+
+    fn symbols(&self) -> Option<&SymbolTable> {
+        self.symbols.as_ref()
+    }
+}
+
+#[test]
+fn breakpoint_ignore_count() {
+    use test_utils::dummy_cpu;
+
+    let mut cpu = dummy_cpu();
+    let mut debugger = BreakpointDebugger::new();
+
+    debugger.add_breakpoint(0x80001000);
+    // Adding it again doesn't reset the configuration
+    debugger.breakpoint_mut(0x80001000).unwrap().ignore_count = 1;
+    debugger.add_breakpoint(0x80001000);
+
+    assert!(debugger.breakpoints().len() == 1);
+
+    cpu.force_pc(0x80001000);
+
+    // First hit ignored
+    debugger.pc_change(&mut cpu);
+    assert!(debugger.take_hits().is_empty());
+
+    debugger.pc_change(&mut cpu);
+    assert!(debugger.take_hits() == vec![Hit {
+        pc: 0x80001000,
+        cause: Cause::Breakpoint,
+    }]);
+    assert!(debugger.breakpoint(0x80001000).unwrap().hits() == 2);
+
+    // Other addresses don't match
+    cpu.force_pc(0x80001004);
+    debugger.pc_change(&mut cpu);
+    assert!(debugger.take_hits().is_empty());
+
+    assert!(debugger.remove_breakpoint(0x80001000));
+    assert!(!debugger.remove_breakpoint(0x80001000));
+    assert!(!debugger.armed);
+
+    cpu.force_pc(0x80001000);
+    debugger.pc_change(&mut cpu);
+    assert!(debugger.take_hits().is_empty());
+}
+
+#[test]
+fn watchpoint_filters() {
+    use test_utils::dummy_cpu;
+
+    let mut cpu = dummy_cpu();
+    let mut debugger = BreakpointDebugger::new();
+
+    let mut watch = Watchpoint::new(0x00002000, 4, Access::Write);
+    watch.size = Some(2);
+
+    let id = debugger.add_watchpoint(watch);
+
+    cpu.force_pc(0x80001004);
+
+    // Reads, 32bit accesses and accesses outside of the range aren't
+    // watched
+    debugger.memory_read(&mut cpu, 0x80002002, 2, 0);
+    debugger.memory_write(&mut cpu, 0x80002000, 4, 0);
+    debugger.memory_write(&mut cpu, 0x80001ffe, 2, 0);
+    debugger.memory_write(&mut cpu, 0x80002004, 2, 0);
+    assert!(debugger.take_hits().is_empty());
+
+    // Write through KSEG1, only the low 16bits of the register are
+    // stored
+    debugger.memory_write(&mut cpu, 0xa0002002, 2, 0xffff1234);
+
+    assert!(debugger.take_hits() == vec![Hit {
+        pc: 0x80001004,
+        cause: Cause::Watchpoint {
+            id: id,
+            addr: 0xa0002002,
+            access: Access::Write,
+            size: 2,
+            value: Some(0x1234),
+            dma: false,
+        },
+    }]);
+    assert!(debugger.watchpoint(id).unwrap().hits() == 1);
+
+    // ReadWrite watchpoints report the actual access
+    let rw = Watchpoint::new(0x3000, 1, Access::ReadWrite);
+    let rw = debugger.add_watchpoint(rw);
+
+    debugger.memory_read(&mut cpu, 0x00003000, 1, 0xab);
+
+    match debugger.take_hits()[0].cause {
+        Cause::Watchpoint { id, access, value, .. } => {
+            assert!(id == rw);
+            assert!(access == Access::Read);
+            assert!(value == Some(0xab));
+        }
+        _ => panic!("Read not reported"),
+    }
+
+    assert!(debugger.remove_watchpoint(id));
+    assert!(!debugger.remove_watchpoint(id));
+    assert!(debugger.watchpoint(id).is_none());
+}
+
+#[test]
+fn watchpoint_conditions() {
+    use test_utils::dummy_cpu;
+
+    let mut cpu = dummy_cpu();
+    let mut debugger = BreakpointDebugger::new();
+
+    let mut equal = Watchpoint::new(0x1000, 4, Access::Write);
+    equal.condition = Some(Condition::Equal(0x1234));
+
+    let mut not_equal = Watchpoint::new(0x2000, 4, Access::Write);
+    not_equal.condition = Some(Condition::NotEqual(0));
+
+    let mut masked = Watchpoint::new(0x3000, 4, Access::Write);
+    masked.condition = Some(Condition::Masked {
+        mask: 0xf0,
+        expected: 0x50,
+    });
+    // The first matching access is ignored
+    masked.ignore_count = 1;
+
+    let equal = debugger.add_watchpoint(equal);
+    let not_equal = debugger.add_watchpoint(not_equal);
+    let masked = debugger.add_watchpoint(masked);
+
+    debugger.memory_write(&mut cpu, 0x1000, 4, 0x4321);
+    debugger.memory_write(&mut cpu, 0x2000, 4, 0);
+    debugger.memory_write(&mut cpu, 0x3000, 4, 0x0f);
+    debugger.memory_write(&mut cpu, 0x3000, 4, 0x5f);
+    assert!(debugger.take_hits().is_empty());
+
+    // The condition is tested against the stored bytes only
+    debugger.memory_write(&mut cpu, 0x1000, 2, 0x10001234);
+    debugger.memory_write(&mut cpu, 0x2000, 1, 0x100);
+    debugger.memory_write(&mut cpu, 0x2000, 1, 0x1);
+    debugger.memory_write(&mut cpu, 0x3000, 4, 0x55);
+
+    let ids: Vec<_> =
+        debugger.take_hits().iter()
+        .map(|h| match h.cause {
+            Cause::Watchpoint { id, .. } => id,
+            _ => panic!("Unexpected hit"),
+        })
+        .collect();
+
+    assert!(ids == vec![equal, not_equal, masked]);
+    assert!(debugger.watchpoint(masked).unwrap().hits() == 2);
+}
+
+#[test]
+fn watchpoint_dma() {
+    use test_utils::dummy_cpu;
+
+    let mut cpu = dummy_cpu();
+    let mut debugger = BreakpointDebugger::new();
+
+    let any = debugger.add_watchpoint(Watchpoint::new(0x80003000, 4,
+                                                      Access::ReadWrite));
+
+    // DMA transfers are 32bit writes
+    let mut byte = Watchpoint::new(0x80003000, 4, Access::Write);
+    byte.size = Some(1);
+    debugger.add_watchpoint(byte);
+    debugger.add_watchpoint(Watchpoint::new(0x80003000, 4, Access::Read));
+
+    // The condition is checked against the memory after the transfer
+    let mut cond = Watchpoint::new(0x80003000, 4, Access::Write);
+    cond.condition = Some(Condition::Equal(0xcafe));
+    let cond = debugger.add_watchpoint(cond);
+
+    cpu.force_pc(0x80001000);
+
+    debugger.dma_write(&mut cpu, 0x2f00, 0x200);
+
+    assert!(debugger.take_hits() == vec![Hit {
+        pc: 0x80001000,
+        cause: Cause::Watchpoint {
+            id: any,
+            addr: 0x2f00,
+            access: Access::Write,
+            size: 0x200,
+            value: None,
+            dma: true,
+        },
+    }]);
+
+    cpu.deposit::<Word>(0x80003000, 0xcafe);
+
+    debugger.dma_write(&mut cpu, 0x3000, 4);
+    assert!(debugger.take_hits().len() == 2);
+    assert!(debugger.watchpoint(cond).unwrap().hits() == 1);
+
+    // Transfer ending right before the watched range
+    debugger.dma_write(&mut cpu, 0x2f00, 0x100);
+    assert!(debugger.take_hits().is_empty());
+}
+
+#[test]
+fn break_handler() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use test_utils::dummy_cpu;
+
+    let mut cpu = dummy_cpu();
+    let mut debugger = BreakpointDebugger::new();
+
+    let handled = Rc::new(RefCell::new(Vec::new()));
+    let h = handled.clone();
+
+    debugger.set_handler(Box::new(move |cpu: &mut Cpu, hit: &Hit| {
+        cpu.write_reg(8, hit.pc);
+        h.borrow_mut().push(*hit);
+    }));
+
+    debugger.add_watchpoint(Watchpoint::new(0x1000, 4, Access::Write));
+
+    cpu.force_pc(0x80001000);
+
+    debugger.trigger_break();
+    debugger.pc_change(&mut cpu);
+
+    assert!(handled.borrow().len() == 1);
+    assert!(handled.borrow()[0].cause == Cause::Requested);
+    assert!(cpu.regs()[8] == 0x80001000);
+
+    // The watchpoint hit is handled before the next instruction
+    debugger.memory_write(&mut cpu, 0x1000, 4, 0);
+    assert!(handled.borrow().len() == 1);
+
+    cpu.force_pc(0x80001004);
+    debugger.pc_change(&mut cpu);
+    assert!(handled.borrow().len() == 2);
+
+    // Nothing new, the handler isn't called again
+    debugger.pc_change(&mut cpu);
+    assert!(handled.borrow().len() == 2);
+
+    // All the hits are still available to the frontend
+    assert!(debugger.take_hits().len() == 2);
+
+    debugger.clear();
+    assert!(debugger.breakpoints().is_empty());
+    assert!(debugger.watchpoint(WatchpointId(0)).is_none());
+}
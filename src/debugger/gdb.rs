@@ -0,0 +1,1136 @@
+//! GDB remote serial protocol server. Works over any `Read + Write`
+//! transport, `GdbRemote::listen` accepts a connection over TCP.
+//!
+//! The stub only talks to GDB while the emulator is stopped: it takes
+//! control in `Debugger::pc_change` and serves requests until GDB
+//! resumes execution. Over TCP `poll_interrupt` can be called
+//! periodically by the frontend to handle GDB's Ctrl-C.
+//!
+//! Reverse execution (`reverse-stepi`, `reverse-continue`) is
+//! available when the stub is wrapped in a `TimeTravel` debugger, see
+//! `GdbRemote::set_reverse_execution`.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use cpu::Cpu;
+use interrupt::InterruptState;
+use memory::Byte;
+use memory::map;
+
+use super::{Debugger, Reverse};
+
+/// Remote debugging session
+pub struct GdbRemote<T: Read + Write> {
+    stream: T,
+    /// False once GDB has detached or the connection broke, we then
+    /// stop interrupting the emulation
+    connected: bool,
+    /// True if we should stop at the next instruction
+    break_pending: bool,
+    /// Stop reply to send for the current break
+    stop_reply: String,
+    /// Code breakpoints
+    breakpoints: Vec<u32>,
+    /// Data watchpoints
+    watchpoints: Vec<Watchpoint>,
+    /// True if reverse execution is advertised to GDB
+    reverse_execution: bool,
+    /// Reverse execution requested by GDB, not yet serviced
+    reverse: Option<Reverse>,
+}
+
+impl GdbRemote<TcpStream> {
+    /// Wait for GDB to connect on `addr` (`target remote addr`)
+    pub fn listen<A: ToSocketAddrs>(addr: A)
+                                    -> io::Result<GdbRemote<TcpStream>> {
+        let listener = try!(TcpListener::bind(addr));
+
+        info!("Waiting for GDB on {}", try!(listener.local_addr()));
+
+        let (stream, peer) = try!(listener.accept());
+
+        info!("GDB connected from {}", peer);
+
+        // Packets are small, we don't want them to be delayed
+        try!(stream.set_nodelay(true));
+
+        Ok(GdbRemote::new(stream))
+    }
+
+    /// Check if GDB requested an interruption (Ctrl-C) without
+    /// blocking. The next instruction will then trigger a break.
+    pub fn poll_interrupt(&mut self) -> io::Result<()> {
+        if !self.connected {
+            return Ok(());
+        }
+
+        try!(self.stream.set_nonblocking(true));
+
+        let mut b = [0];
+
+        let res = self.stream.read(&mut b);
+
+        try!(self.stream.set_nonblocking(false));
+
+        match res {
+            // Connection closed
+            Ok(0) => self.connected = false,
+            Ok(_) => if b[0] == 0x03 {
+                self.break_pending = true;
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => (),
+            Err(e) => return Err(e),
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: Read + Write> GdbRemote<T> {
+    /// Create a session over `stream`. GDB expects the target to be
+    /// stopped when it connects so the emulator will break at the
+    /// next instruction.
+    pub fn new(stream: T) -> GdbRemote<T> {
+        GdbRemote {
+            stream: stream,
+            connected: true,
+            break_pending: true,
+            stop_reply: "S05".into(),
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            reverse_execution: false,
+            reverse: None,
+        }
+    }
+
+    /// Let GDB use reverse execution (`bs` and `bc` packets). The
+    /// requests are only serviced if the stub is wrapped in a
+    /// `TimeTravel` debugger and the frontend runs the emulation
+    /// through `TimeTravel::run_next_instruction`, otherwise the
+    /// emulation would just keep going forward.
+    pub fn set_reverse_execution(&mut self, enable: bool) {
+        self.reverse_execution = enable;
+    }
+
+    /// Return true if GDB is still attached
+    pub fn connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Serve GDB requests until it resumes execution
+    fn serve(&mut self, cpu: &mut Cpu) {
+        let reply = self.stop_reply.clone();
+
+        if let Err(e) = self.send(&reply) {
+            return self.disconnect(e);
+        }
+
+        loop {
+            let packet =
+                match self.receive() {
+                    Ok(p) => p,
+                    Err(e) => return self.disconnect(e),
+                };
+
+            let reply =
+                match self.handle(cpu, &packet) {
+                    Some(r) => r,
+                    // Resume execution
+                    None => return,
+                };
+
+            if let Err(e) = self.send(&reply) {
+                return self.disconnect(e);
+            }
+
+            if !self.connected {
+                return;
+            }
+        }
+    }
+
+    fn disconnect(&mut self, e: io::Error) {
+        warn!("GDB connection lost: {}", e);
+
+        self.detach();
+    }
+
+    fn detach(&mut self) {
+        self.connected = false;
+        self.break_pending = false;
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+    }
+
+    /// Handle a single packet, returns the reply or `None` if the
+    /// execution should resume
+    fn handle(&mut self, cpu: &mut Cpu, packet: &str) -> Option<String> {
+        let (command, args) =
+            if packet.is_empty() {
+                ("", "")
+            } else {
+                packet.split_at(1)
+            };
+
+        let reply =
+            match command {
+                "?" => self.stop_reply.clone(),
+                "g" => read_registers(cpu),
+                "G" => {
+                    for (i, w) in args.as_bytes().chunks(8).enumerate() {
+                        match parse_le32(w) {
+                            Some(v) => write_register(cpu, i, v),
+                            None => return Some("E01".into()),
+                        }
+                    }
+                    "OK".into()
+                }
+                "p" =>
+                    match parse_hex(args) {
+                        Some(r) => format_le32(register(cpu, r as usize)),
+                        None => "E01".into(),
+                    },
+                "P" => {
+                    let mut parts = args.splitn(2, '=');
+
+                    let r = parts.next().and_then(parse_hex);
+                    let v = parts.next().and_then(|v| parse_le32(v.as_bytes()));
+
+                    match (r, v) {
+                        (Some(r), Some(v)) => {
+                            write_register(cpu, r as usize, v);
+                            "OK".into()
+                        }
+                        _ => "E01".into(),
+                    }
+                }
+                "m" =>
+                    match parse_addr_len(args) {
+                        // Each byte takes two hex digits in the reply
+                        Some((addr, len)) if len <= PACKET_SIZE / 2 => {
+                            let mut reply = String::new();
+
+                            // The debugger doesn't have access to the
+                            // SharedState so the interrupt controller
+                            // registers read as 0 here
+                            for i in 0..len {
+                                let b = cpu.examine::<Byte>(addr.wrapping_add(i));
+
+                                reply.push_str(&format!("{:02x}", b as u8));
+                            }
+
+                            reply
+                        }
+                        _ => "E01".into(),
+                    },
+                "M" => {
+                    let mut parts = args.splitn(2, ':');
+
+                    let range = parts.next().and_then(parse_addr_len);
+                    let data = parts.next().map(|d| d.as_bytes());
+
+                    match (range, data) {
+                        (Some((addr, len)), Some(data))
+                            if data.len() == len as usize * 2 => {
+                                let mut ok = true;
+
+                                for (i, b) in data.chunks(2).enumerate() {
+                                    let addr = addr.wrapping_add(i as u32);
+
+                                    ok &= match parse_hex_bytes(b) {
+                                        Some(b) => cpu.deposit::<Byte>(addr, b as u32),
+                                        None => false,
+                                    };
+                                }
+
+                                (if ok { "OK" } else { "E02" }).into()
+                            }
+                        _ => "E01".into(),
+                    }
+                }
+                "c" | "s" => {
+                    if let Some(addr) = parse_hex(args) {
+                        cpu.force_pc(addr);
+                    }
+
+                    self.break_pending = command == "s";
+                    return None;
+                }
+                "v" =>
+                    if args == "Cont?" {
+                        "vCont;c;s".into()
+                    } else if args.starts_with("Cont;") {
+                        // We only have one thread, the first action
+                        // applies
+                        self.break_pending = args[5..].starts_with('s');
+                        return None;
+                    } else {
+                        String::new()
+                    },
+                "b" if self.reverse_execution => {
+                    let request =
+                        match args {
+                            "s" => Reverse::Step,
+                            "c" => self.reverse_continue(),
+                            _ => return Some(String::new()),
+                        };
+
+                    self.reverse = Some(request);
+                    return None;
+                }
+                "Z" | "z" =>
+                    match self.breakpoint(command == "Z", args) {
+                        Some(()) => "OK".into(),
+                        None => String::new(),
+                    },
+                "q" => self.query(args),
+                "H" => "OK".into(),
+                "D" => {
+                    self.detach();
+                    "OK".into()
+                }
+                "k" => {
+                    self.detach();
+                    return None;
+                }
+                // Unsupported
+                _ => String::new(),
+            };
+
+        Some(reply)
+    }
+
+    /// Insert or remove a breakpoint or watchpoint. Returns `None`
+    /// for unsupported types.
+    fn breakpoint(&mut self, insert: bool, args: &str) -> Option<()> {
+        let mut parts = args.split(',');
+
+        let (btype, addr, len) =
+            match (parts.next(),
+                   parts.next().and_then(parse_hex),
+                   parts.next().and_then(parse_hex)) {
+                (Some(t), Some(a), Some(l)) => (t, a, l),
+                _ => return None,
+            };
+
+        let kind =
+            match btype {
+                // Software and hardware code breakpoints are handled
+                // the same way, we don't patch the code
+                "0" | "1" => {
+                    if insert {
+                        if !self.breakpoints.contains(&addr) {
+                            self.breakpoints.push(addr);
+                        }
+                    } else {
+                        self.breakpoints.retain(|&b| b != addr);
+                    }
+
+                    return Some(());
+                }
+                "2" => WatchKind::Write,
+                "3" => WatchKind::Read,
+                "4" => WatchKind::Access,
+                _ => return None,
+            };
+
+        let watch = Watchpoint {
+            kind: kind,
+            addr: addr,
+            len: len,
+        };
+
+        if insert {
+            self.watchpoints.push(watch);
+        } else {
+            self.watchpoints.retain(|w| *w != watch);
+        }
+
+        Some(())
+    }
+
+    /// Build a reverse continue request stopping at our breakpoints
+    /// and write watchpoints. Read watchpoints can't be used to stop
+    /// when going backwards.
+    fn reverse_continue(&self) -> Reverse {
+        let watchpoints =
+            self.watchpoints.iter()
+            .filter(|w| w.kind != WatchKind::Read)
+            .map(|w| (w.addr, w.len))
+            .collect();
+
+        Reverse::Continue {
+            breakpoints: self.breakpoints.clone(),
+            watchpoints: watchpoints,
+        }
+    }
+
+    fn query(&self, args: &str) -> String {
+        if args.starts_with("Supported") {
+            let mut features =
+                format!("PacketSize={:x};qXfer:features:read+", PACKET_SIZE);
+
+            if self.reverse_execution {
+                features.push_str(";ReverseStep+;ReverseContinue+");
+            }
+
+            return features;
+        }
+
+        if args == "Attached" {
+            return "1".into();
+        }
+
+        if args == "C" {
+            return "QC1".into();
+        }
+
+        if args == "fThreadInfo" {
+            return "m1".into();
+        }
+
+        if args == "sThreadInfo" {
+            return "l".into();
+        }
+
+        let xfer = "Xfer:features:read:target.xml:";
+
+        if args.starts_with(xfer) {
+            return match parse_addr_len(&args[xfer.len()..]) {
+                Some((offset, len)) =>
+                    xfer_chunk(&target_description(), offset, len),
+                None => "E01".into(),
+            };
+        }
+
+        String::new()
+    }
+
+    /// Check the watchpoints for an access of `kind` to the `len`
+    /// bytes at `addr`
+    fn check_watchpoints(&mut self, kind: WatchKind, addr: u32, len: u32) {
+        let hit = self.watchpoints.iter().find(|w| w.matches(kind, addr, len));
+
+        if let Some(w) = hit {
+            let name =
+                match w.kind {
+                    WatchKind::Write => "watch",
+                    WatchKind::Read => "rwatch",
+                    WatchKind::Access => "awatch",
+                };
+
+            self.stop_reply = format!("T05{}:{:08x};", name, addr);
+            self.break_pending = true;
+        }
+    }
+
+    /// Wait for the next packet from GDB and acknowledge it
+    fn receive(&mut self) -> io::Result<String> {
+        loop {
+            // Look for the start of the packet, ignoring acks and
+            // interrupt requests (we're already stopped)
+            while try!(self.read_byte()) != b'$' {
+            }
+
+            let mut data = Vec::new();
+
+            loop {
+                match try!(self.read_byte()) {
+                    b'#' => break,
+                    b => data.push(b),
+                }
+            }
+
+            let cs = [try!(self.read_byte()), try!(self.read_byte())];
+
+            if parse_hex_bytes(&cs) == Some(checksum(&data)) {
+                try!(self.stream.write_all(b"+"));
+
+                return Ok(String::from_utf8_lossy(&data).into_owned());
+            }
+
+            // Ask for retransmission
+            try!(self.stream.write_all(b"-"));
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum(data.as_bytes()));
+
+        try!(self.stream.write_all(packet.as_bytes()));
+
+        self.stream.flush()
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut b = [0];
+
+        match try!(self.stream.read(&mut b)) {
+            0 => Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                    "connection closed")),
+            _ => Ok(b[0]),
+        }
+    }
+}
+
+impl<T: Read + Write> Debugger for GdbRemote<T> {
+    fn trigger_break(&mut self) {
+        if self.connected {
+            self.break_pending = true;
+        }
+    }
+
+    fn pc_change(&mut self, cpu: &mut Cpu) {
+        if !self.connected {
+            return;
+        }
+
+        let pc = cpu.pc();
+
+        if !self.break_pending && !self.breakpoints.contains(&pc) {
+            return;
+        }
+
+        self.break_pending = false;
+
+        self.serve(cpu);
+
+        self.stop_reply = "S05".into();
+    }
+
+    fn memory_read(&mut self, _: &mut Cpu, addr: u32, size: u8, _: u32) {
+        self.check_watchpoints(WatchKind::Read, addr, size as u32);
+    }
+
+    fn memory_write(&mut self, _: &mut Cpu, addr: u32, size: u8, _: u32) {
+        self.check_watchpoints(WatchKind::Write, addr, size as u32);
+    }
+
+    fn dma_write(&mut self, _: &mut Cpu, addr: u32, len: u32) {
+        self.check_watchpoints(WatchKind::Write, addr, len);
+    }
+
+    fn reverse_request(&mut self) -> Option<Reverse> {
+        self.reverse.take()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum WatchKind {
+    Write,
+    Read,
+    /// Read or write
+    Access,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Watchpoint {
+    kind: WatchKind,
+    addr: u32,
+    len: u32,
+}
+
+impl Watchpoint {
+    /// Return true if an access of `kind` to the `len` bytes at
+    /// `addr` overlaps the watched range
+    fn matches(&self, kind: WatchKind, addr: u32, len: u32) -> bool {
+        let kind_matches = self.kind == kind || self.kind == WatchKind::Access;
+
+        // The DMA reports physical addresses
+        let addr = map::mask_region(addr);
+        let start = map::mask_region(self.addr);
+
+        kind_matches &&
+            (addr.wrapping_sub(start) < self.len ||
+             start.wrapping_sub(addr) < len)
+    }
+}
+
+/// Maximum packet size advertised to GDB
+const PACKET_SIZE: u32 = 0x1000;
+
+/// Number of registers in the target description: 32 GPRs, SR, LO,
+/// HI, BadVaddr, Cause, PC, 32 FPU registers, FCSR and FIR
+const NUM_REGISTERS: usize = 72;
+
+fn register(cpu: &Cpu, r: usize) -> u32 {
+    match r {
+        0..=31 => cpu.regs()[r],
+        32 => cpu.sr(),
+        33 => cpu.lo(),
+        34 => cpu.hi(),
+        35 => cpu.bad(),
+        // We don't have access to the live interrupt state from here
+        36 => cpu.cause(InterruptState::new()),
+        37 => cpu.pc(),
+        // There's no FPU
+        _ => 0,
+    }
+}
+
+fn write_register(cpu: &mut Cpu, r: usize, v: u32) {
+    match r {
+        0..=31 => cpu.write_reg(r as u32, v),
+        33 => cpu.set_lo(v),
+        34 => cpu.set_hi(v),
+        37 => if v != cpu.pc() {
+            cpu.force_pc(v)
+        },
+        // COP0 and FPU registers are read-only
+        _ => (),
+    }
+}
+
+fn read_registers(cpu: &Cpu) -> String {
+    (0..NUM_REGISTERS).map(|r| format_le32(register(cpu, r))).collect()
+}
+
+/// Target description telling GDB which registers we have
+fn target_description() -> String {
+    let mut xml = String::new();
+
+    xml.push_str("<?xml version=\"1.0\"?>\
+                  <!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+                  <target version=\"1.0\">\
+                  <architecture>mips:3000</architecture>\
+                  <feature name=\"org.gnu.gdb.mips.cpu\">");
+
+    for r in 0..32 {
+        xml.push_str(&format!("<reg name=\"r{}\" bitsize=\"32\" \
+                               regnum=\"{}\"/>", r, r));
+    }
+
+    xml.push_str("<reg name=\"lo\" bitsize=\"32\" regnum=\"33\"/>\
+                  <reg name=\"hi\" bitsize=\"32\" regnum=\"34\"/>\
+                  <reg name=\"pc\" bitsize=\"32\" regnum=\"37\"/>\
+                  </feature>\
+                  <feature name=\"org.gnu.gdb.mips.cp0\">\
+                  <reg name=\"status\" bitsize=\"32\" regnum=\"32\"/>\
+                  <reg name=\"badvaddr\" bitsize=\"32\" regnum=\"35\"/>\
+                  <reg name=\"cause\" bitsize=\"32\" regnum=\"36\"/>\
+                  </feature>\
+                  <feature name=\"org.gnu.gdb.mips.fpu\">");
+
+    // GDB insists on having an FPU
+    for r in 0..32 {
+        xml.push_str(&format!("<reg name=\"f{}\" bitsize=\"32\" \
+                               type=\"ieee_single\" regnum=\"{}\"/>",
+                              r, 38 + r));
+    }
+
+    xml.push_str("<reg name=\"fcsr\" bitsize=\"32\" group=\"float\" \
+                  regnum=\"70\"/>\
+                  <reg name=\"fir\" bitsize=\"32\" group=\"float\" \
+                  regnum=\"71\"/>\
+                  </feature>\
+                  </target>");
+
+    xml
+}
+
+/// Return the `qXfer` reply for `len` bytes of `doc` at `offset`
+fn xfer_chunk(doc: &str, offset: u32, len: u32) -> String {
+    let doc = doc.as_bytes();
+    let start = ::std::cmp::min(offset as usize, doc.len());
+    let end = ::std::cmp::min(start + len as usize, doc.len());
+
+    // 'l' marks the last chunk
+    let marker = if end == doc.len() { 'l' } else { 'm' };
+
+    let mut reply = marker.to_string();
+
+    reply.push_str(&String::from_utf8_lossy(&doc[start..end]));
+
+    reply
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+/// Registers are sent in target byte order
+fn format_le32(v: u32) -> String {
+    format!("{:02x}{:02x}{:02x}{:02x}",
+            v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8)
+}
+
+fn parse_le32(s: &[u8]) -> Option<u32> {
+    if s.len() != 8 {
+        return None;
+    }
+
+    let mut v = 0;
+
+    for (i, b) in s.chunks(2).enumerate() {
+        match parse_hex_bytes(b) {
+            Some(b) => v |= (b as u32) << (i * 8),
+            None => return None,
+        }
+    }
+
+    Some(v)
+}
+
+fn parse_hex(s: &str) -> Option<u32> {
+    if s.is_empty() {
+        None
+    } else {
+        u32::from_str_radix(s, 16).ok()
+    }
+}
+
+fn parse_hex_bytes(b: &[u8]) -> Option<u8> {
+    ::std::str::from_utf8(b).ok()
+        .and_then(|s| u8::from_str_radix(s, 16).ok())
+}
+
+/// Parse `addr,len`
+fn parse_addr_len(s: &str) -> Option<(u32, u32)> {
+    let mut parts = s.splitn(2, ',');
+
+    match (parts.next().and_then(parse_hex),
+           parts.next().and_then(parse_hex)) {
+        (Some(addr), Some(len)) => Some((addr, len)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write, Cursor};
+
+    use bios::Bios;
+    use cpu::Cpu;
+    use debugger::Debugger;
+    use debugger::timetravel::TimeTravel;
+    use gpu::{Gpu, VideoClock};
+    use memory::{Interconnect, Word};
+    use shared::SharedState;
+    use test_utils::{DummyRenderer, dummy_cpu};
+
+    use super::{GdbRemote, checksum};
+
+    /// Scripted GDB session
+    struct Transport {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Transport {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Transport {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn packet(data: &str) -> String {
+        format!("${}#{:02x}", data, checksum(data.as_bytes()))
+    }
+
+    fn session(packets: &[&str]) -> GdbRemote<Transport> {
+        let input: String = packets.iter().map(|p| packet(p)).collect();
+
+        GdbRemote::new(Transport {
+            input: Cursor::new(input.into_bytes()),
+            output: Vec::new(),
+        })
+    }
+
+    fn replies(gdb: &GdbRemote<Transport>) -> String {
+        String::from_utf8_lossy(&gdb.stream.output).into_owned()
+    }
+
+    /// Send `packets` to a stopped `gdb` and return its replies
+    fn resume(gdb: &mut GdbRemote<Transport>,
+              cpu: &mut Cpu,
+              packets: &[&str]) -> String {
+        let input: String = packets.iter().map(|p| packet(p)).collect();
+
+        gdb.stream.input = Cursor::new(input.into_bytes());
+        gdb.stream.output.clear();
+        gdb.pc_change(cpu);
+
+        replies(gdb)
+    }
+
+    fn stopped_cpu() -> Cpu {
+        let mut cpu = dummy_cpu();
+
+        cpu.force_pc(0x80001000);
+
+        cpu
+    }
+
+    #[test]
+    fn gdb_registers() {
+        let mut cpu = stopped_cpu();
+
+        cpu.write_reg(8, 0x12345678);
+
+        let mut gdb = session(&["?",
+                                "p8",
+                                "P9=efbeadde",
+                                "p9",
+                                // FPU registers read as 0
+                                "p40",
+                                "P25=04100080",
+                                "pzz",
+                                "P9",
+                                "P9=zz",
+                                "G1234",
+                                "D"]);
+
+        gdb.pc_change(&mut cpu);
+
+        let out = replies(&gdb);
+
+        assert!(out.contains(&packet("S05")));
+        assert!(out.contains(&packet("78563412")));
+        assert!(out.contains(&packet("efbeadde")));
+        assert!(out.contains(&packet("00000000")));
+        assert!(out.matches(&packet("E01")).count() == 4);
+        assert!(cpu.regs()[9] == 0xdeadbeef);
+        assert!(cpu.pc() == 0x80001004);
+
+        let mut gdb = session(&["g", "D"]);
+
+        gdb.pc_change(&mut cpu);
+
+        let out = replies(&gdb);
+        // Skip the stop reply and the checksum
+        let g = out.split('$').nth(2).unwrap();
+        let g = g.split('#').next().unwrap();
+
+        assert!(g.len() == 72 * 8);
+        assert!(g.starts_with("00000000"));
+    }
+
+    #[test]
+    fn gdb_memory() {
+        let mut cpu = stopped_cpu();
+
+        cpu.deposit::<Word>(0x80002000, 0xdeadbeef);
+
+        let mut gdb = session(&["m80002000,4",
+                                "M80002004,2:aa55",
+                                // Length mismatch
+                                "M80002004,2:aa",
+                                // Not a valid hex byte
+                                "M80002004,1:zz",
+                                // Registers can't be written
+                                "M1f801070,1:ff",
+                                "m80002000",
+                                // Doesn't fit in a packet
+                                "m0,ffffffff",
+                                "m0,801",
+                                "D"]);
+
+        gdb.pc_change(&mut cpu);
+
+        let out = replies(&gdb);
+
+        assert!(out.contains(&packet("efbeadde")));
+        assert!(out.matches(&packet("OK")).count() == 2);
+        assert!(out.matches(&packet("E01")).count() == 4);
+        assert!(out.matches(&packet("E02")).count() == 2);
+        assert!(cpu.examine::<Word>(0x80002004) == 0x55aa);
+    }
+
+    #[test]
+    fn gdb_breakpoints() {
+        let mut cpu = stopped_cpu();
+
+        let mut gdb = session(&["Z0,80001008,4",
+                                "Z1,8000100c,4",
+                                // Unsupported type
+                                "Z5,80001000,4",
+                                "Z0,80001008",
+                                "c"]);
+
+        gdb.pc_change(&mut cpu);
+
+        let out = replies(&gdb);
+
+        assert!(out.matches(&packet("OK")).count() == 2);
+        assert!(out.matches(&packet("")).count() == 2);
+
+        // Not a breakpoint, nothing happens
+        cpu.force_pc(0x80001004);
+        assert!(resume(&mut gdb, &mut cpu, &[]).is_empty());
+
+        cpu.force_pc(0x80001008);
+        let out = resume(&mut gdb, &mut cpu, &["z0,80001008,4", "c"]);
+        assert!(out.contains(&packet("S05")));
+
+        // Removed
+        assert!(resume(&mut gdb, &mut cpu, &[]).is_empty());
+
+        // Resuming at a given address
+        cpu.force_pc(0x8000100c);
+        resume(&mut gdb, &mut cpu, &["c80001000"]);
+        assert!(cpu.pc() == 0x80001000);
+    }
+
+    #[test]
+    fn gdb_watchpoints() {
+        let mut cpu = stopped_cpu();
+
+        let mut gdb = session(&["Z2,80002000,4",
+                                "Z3,80003000,4",
+                                "Z4,80004000,2",
+                                "c"]);
+
+        gdb.pc_change(&mut cpu);
+
+        // Wrong kind of access
+        gdb.memory_read(&mut cpu, 0x80002000, 4, 0);
+        gdb.memory_write(&mut cpu, 0x80003000, 4, 0);
+        // Outside of the watched ranges
+        gdb.memory_write(&mut cpu, 0x80001ffc, 4, 0);
+        gdb.memory_read(&mut cpu, 0x80004002, 1, 0);
+        assert!(resume(&mut gdb, &mut cpu, &[]).is_empty());
+
+        gdb.memory_write(&mut cpu, 0x80002002, 2, 0);
+        let out = resume(&mut gdb, &mut cpu, &["c"]);
+        assert!(out.contains(&packet("T05watch:80002002;")));
+
+        gdb.memory_read(&mut cpu, 0x80003003, 1, 0);
+        let out = resume(&mut gdb, &mut cpu, &["c"]);
+        assert!(out.contains(&packet("T05rwatch:80003003;")));
+
+        // Through an uncached mirror
+        gdb.memory_write(&mut cpu, 0xa0004001, 1, 0);
+        let out = resume(&mut gdb, &mut cpu, &["c"]);
+        assert!(out.contains(&packet("T05awatch:a0004001;")));
+
+        // DMA transfers report physical addresses
+        gdb.dma_write(&mut cpu, 0x00001ff0, 0x20);
+        let out = resume(&mut gdb, &mut cpu, &["z2,80002000,4", "c"]);
+        assert!(out.contains(&packet("T05watch:00001ff0;")));
+
+        // Removed, the stop reply is back to the default
+        gdb.dma_write(&mut cpu, 0x00001ff0, 0x20);
+        assert!(resume(&mut gdb, &mut cpu, &[]).is_empty());
+
+        gdb.trigger_break();
+        assert!(resume(&mut gdb, &mut cpu, &["c"]).contains(&packet("S05")));
+    }
+
+    #[test]
+    fn gdb_queries() {
+        let mut cpu = stopped_cpu();
+
+        let mut gdb = session(&["qSupported:swbreak+",
+                                "qAttached",
+                                "qC",
+                                "qfThreadInfo",
+                                "qsThreadInfo",
+                                "qXfer:features:read:target.xml:0,a",
+                                "qXfer:features:read:target.xml:0,10000",
+                                "qXfer:features:read:target.xml:10000,10",
+                                "qXfer:features:read:target.xml:zz",
+                                "qUnknown",
+                                "vCont?",
+                                "Hg0",
+                                "D"]);
+
+        gdb.pc_change(&mut cpu);
+
+        let out = replies(&gdb);
+
+        assert!(out.contains(&packet("PacketSize=1000;qXfer:features:read+")));
+        assert!(out.contains(&packet("1")));
+        assert!(out.contains(&packet("QC1")));
+        assert!(out.contains(&packet("m1")));
+        assert!(out.contains(&packet("l")));
+        assert!(out.contains(&packet("m<?xml vers")));
+        assert!(out.contains("$l<?xml"));
+        assert!(out.contains("</target>#"));
+        assert!(out.contains(&packet("E01")));
+        assert!(out.contains(&packet("")));
+        assert!(out.contains(&packet("vCont;c;s")));
+        assert!(out.contains(&packet("OK")));
+    }
+
+    #[test]
+    fn gdb_vcont_step() {
+        let mut cpu = stopped_cpu();
+
+        let mut gdb = session(&["vCont;s:1;c"]);
+
+        gdb.pc_change(&mut cpu);
+
+        cpu.force_pc(0x80001004);
+        let out = resume(&mut gdb, &mut cpu, &["vCont;c"]);
+        assert!(out.contains(&packet("S05")));
+
+        cpu.force_pc(0x80001008);
+        assert!(resume(&mut gdb, &mut cpu, &[]).is_empty());
+    }
+
+    #[test]
+    fn gdb_bad_checksum() {
+        let mut cpu = stopped_cpu();
+
+        let mut gdb = session(&[]);
+
+        let mut input = b"$?#00".to_vec();
+
+        input.extend_from_slice(packet("D").as_bytes());
+
+        gdb.stream.input = Cursor::new(input);
+        gdb.pc_change(&mut cpu);
+
+        let out = replies(&gdb);
+
+        // Retransmission requested then the detach is acknowledged
+        assert!(out.contains(&format!("-+{}", packet("OK"))));
+        assert!(!gdb.connected());
+    }
+
+    #[test]
+    fn gdb_disconnect() {
+        let mut cpu = stopped_cpu();
+
+        // The connection is closed without detaching
+        let mut gdb = session(&["Z0,80001000,4"]);
+
+        gdb.pc_change(&mut cpu);
+
+        assert!(!gdb.connected());
+
+        // Breakpoints are gone and we don't try to talk to GDB anymore
+        gdb.trigger_break();
+        assert!(resume(&mut gdb, &mut cpu, &["?"]).is_empty());
+
+        let mut gdb = session(&["k"]);
+
+        gdb.pc_change(&mut cpu);
+
+        assert!(!gdb.connected());
+        assert!(!replies(&gdb).contains(&packet("OK")));
+    }
+
+    /// Run the `addiu` loop at 0x80010000 under GDB and time travel
+    /// until GDB detaches
+    fn run_reverse(packets: &[&str], snapshot: bool)
+                   -> (TimeTravel<GdbRemote<Transport>>, Cpu) {
+        let inter = Interconnect::new(Bios::hle(),
+                                      Gpu::new(VideoClock::Ntsc),
+                                      None);
+        let mut cpu = Cpu::new(inter);
+        let mut shared = SharedState::new();
+        let mut renderer = DummyRenderer;
+
+        let program = [
+            // li    $t0, 0
+            0x24080000,
+            // loop:
+            // addiu $t0, $t0, 1
+            0x25080001,
+            // b     loop
+            0x1000fffe,
+            // nop
+            0x00000000,
+        ];
+
+        for (i, &w) in program.iter().enumerate() {
+            cpu.deposit::<Word>(0x80010000 + i as u32 * 4, w);
+        }
+
+        cpu.force_pc(0x80010000);
+
+        let mut gdb = session(packets);
+
+        gdb.set_reverse_execution(true);
+
+        let mut tt = TimeTravel::new(gdb, 1, 4);
+
+        if snapshot {
+            tt.snapshot(&cpu, &shared).unwrap();
+        }
+
+        for _ in 0..100 {
+            if !tt.inner().connected() {
+                return (tt, cpu);
+            }
+
+            tt.run_next_instruction(&mut cpu, &mut shared, &mut renderer)
+                .unwrap();
+        }
+
+        panic!("GDB session didn't end");
+    }
+
+    #[test]
+    fn reverse_step_and_continue() {
+        let (tt, cpu) =
+            run_reverse(&["qSupported:swbreak+",
+                          // Break on the `addiu`
+                          "Z0,80010004,4",
+                          "c",
+                          "c",
+                          // Back to the `nop` of the first iteration
+                          "bs",
+                          "p25",
+                          // Back to the first `addiu`
+                          "bc",
+                          "p25",
+                          "p8",
+                          "D"],
+                        true);
+
+        let out = replies(tt.inner());
+
+        assert!(out.contains("ReverseStep+;ReverseContinue+"));
+
+        let nop = out.find(&packet("0c000180")).unwrap();
+        let addiu = out.find(&packet("04000180")).unwrap();
+
+        assert!(nop < addiu);
+        // $t0 hasn't been incremented yet
+        assert!(out[addiu..].contains(&packet("00000000")));
+
+        // We detached at the `addiu` which then ran
+        assert!(tt.position() == 2);
+        assert!(cpu.regs()[8] == 1);
+    }
+
+    #[test]
+    fn reverse_without_history() {
+        let (tt, cpu) = run_reverse(&["bs", "p25", "D"], false);
+
+        let out = replies(tt.inner());
+
+        // We stop again at the first instruction
+        assert!(out.matches(&packet("S05")).count() == 2);
+        assert!(out.contains(&packet("00000180")));
+        assert!(tt.position() == 1);
+        assert!(cpu.pc() == 0x80010004);
+    }
+
+    #[test]
+    fn reverse_unsupported() {
+        let mut cpu = dummy_cpu();
+
+        let mut gdb = session(&["qSupported", "bs", "D"]);
+
+        gdb.pc_change(&mut cpu);
+
+        let out = replies(&gdb);
+
+        assert!(!out.contains("ReverseStep"));
+        assert!(out.contains(&packet("")));
+        assert!(gdb.reverse_request().is_none());
+    }
+}
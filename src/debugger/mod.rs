@@ -1,5 +1,13 @@
 use cpu::Cpu;
 
+use self::symbols::SymbolTable;
+
+pub mod symbols;
+pub mod gdb;
+pub mod breakpoints;
+pub mod timetravel;
+pub mod script;
+
 /// Trait defining the debugger interface
 pub trait Debugger {
     /// Signal a "break" which will put the emulator in debug mode at
@@ -11,11 +19,49 @@ pub trait Debugger {
     /// instructions so it needs to be as fast as possible.
     fn pc_change(&mut self, cpu: &mut Cpu);
 
-    /// Called by the CPU when it's about to load a value from memory.
-    fn memory_read(&mut self, cpu: &mut Cpu, addr: u32);
+    /// Called by the CPU after it loaded `val` from `addr`. `size`
+    /// is the width of the access in bytes (see
+    /// `Addressable::size`).
+    fn memory_read(&mut self, cpu: &mut Cpu, addr: u32, size: u8, val: u32);
+
+    /// Called by the CPU when it's about to write `val` to `addr`.
+    /// `size` is the width of the access in bytes, for `Byte` and
+    /// `HalfWord` stores `val` still contains the full register
+    /// value.
+    fn memory_write(&mut self, cpu: &mut Cpu, addr: u32, size: u8, val: u32);
+
+    /// Called after each instruction during which a DMA transfer
+    /// wrote `len` bytes to RAM starting at physical address
+    /// `addr`. `cpu.current_pc()` is that instruction, the transfer
+    /// may have been started by it or by an earlier one.
+    fn dma_write(&mut self, _cpu: &mut Cpu, _addr: u32, _len: u32) {
+    }
+
+    /// Return the symbol table used to resolve guest addresses, if
+    /// any
+    fn symbols(&self) -> Option<&SymbolTable> {
+        None
+    }
+
+    /// Return and clear the pending reverse execution request, if
+    /// any. Polled by `TimeTravel` after each call to `pc_change`.
+    fn reverse_request(&mut self) -> Option<Reverse> {
+        None
+    }
+}
 
-    /// Called by the CPU when it's about to write a value to memory.
-    fn memory_write(&mut self, cpu: &mut Cpu, addr: u32);
+/// Reverse execution request, see `Debugger::reverse_request`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reverse {
+    /// Go back one instruction
+    Step,
+    /// Go back to the last instruction hitting one of the code
+    /// `breakpoints` or writing to one of the `(addr, len)` ranges
+    /// in `watchpoints`
+    Continue {
+        breakpoints: Vec<u32>,
+        watchpoints: Vec<(u32, u32)>,
+    },
 }
 
 
@@ -30,9 +76,9 @@ impl Debugger for DummyDebugger {
     fn pc_change(&mut self, _: &mut Cpu) {
     }
 
-    fn memory_read(&mut self, _: &mut Cpu, _: u32) {
+    fn memory_read(&mut self, _: &mut Cpu, _: u32, _: u8, _: u32) {
     }
 
-    fn memory_write(&mut self, _: &mut Cpu, _: u32) {
+    fn memory_write(&mut self, _: &mut Cpu, _: u32, _: u8, _: u32) {
     }
 }
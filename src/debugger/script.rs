@@ -0,0 +1,792 @@
+//! Automation hooks for scripted testing.
+//!
+//! `Automation` wraps another `Debugger` and runs callbacks when the
+//! CPU reaches given addresses or at the end of given frames. The
+//! callbacks receive a `Context` giving access to the memory, the
+//! gamepads and the frontend (screenshots, output).
+//!
+//! Callbacks can be written in Rust or loaded from a small command
+//! language:
+//!
+//! ```text
+//! # Dump the save buffer when the game reaches its save routine
+//! on pc 0x80045a10
+//!     dump 0x800f0000 0x2000 save.bin
+//!     screenshot save.ppm
+//!
+//! # Skip the intro
+//! on frame 300
+//!     press start 5
+//!
+//! on every 60 frames
+//!     peek32 0x800a1234
+//! ```
+//!
+//! Each `on` line starts a new trigger, the indented lines below it
+//! are the commands run when it fires:
+//!
+//! * `peek8|peek16|peek32 ADDR`: output the value at `ADDR`,
+//! * `poke8|poke16|poke32 ADDR VALUE`: write to RAM or the
+//!   scratchpad,
+//! * `dump ADDR LEN FILE`: write `LEN` bytes at `ADDR` to `FILE`,
+//! * `press [PAD:]BUTTON [FRAMES]`: press a button on pad 1 (or
+//!   `PAD`), for `FRAMES` frames or until released,
+//! * `release [PAD:]BUTTON`,
+//! * `screenshot FILE`, `log TEXT` and `quit`: forwarded to the
+//!   frontend through `Automation::take_requests`.
+
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+
+use cpu::Cpu;
+use memory::{Addressable, Byte, HalfWord, Word};
+use padmemcard::gamepad::{Button, ButtonState};
+use shared::SharedState;
+
+use super::Debugger;
+use super::symbols::SymbolTable;
+
+/// Callback run by the automation hooks
+pub type Callback = Box<FnMut(&mut Context)>;
+
+/// Requests that have to be handled by the frontend
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Request {
+    /// Capture the current display to the given file
+    Screenshot(String),
+    /// Text output (`log` and `peek` commands, errors)
+    Output(String),
+    /// Stop the emulation
+    Quit,
+}
+
+/// State available to the callbacks
+pub struct Context<'a> {
+    cpu: &'a mut Cpu,
+    frame: u32,
+    held: &'a mut Vec<HeldButton>,
+    requests: &'a mut Vec<Request>,
+}
+
+impl<'a> Context<'a> {
+    pub fn cpu(&mut self) -> &mut Cpu {
+        &mut *self.cpu
+    }
+
+    /// Value of the frame counter
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    /// Read memory using `Cpu::examine`. Scripts run from the
+    /// debugger callbacks which don't have access to the SharedState
+    /// so the interrupt controller registers read as 0.
+    pub fn peek<A: Addressable>(&mut self, addr: u32) -> u32 {
+        self.cpu.examine::<A>(addr)
+    }
+
+    /// Write to RAM or the scratchpad, returns false if `addr` is
+    /// elsewhere
+    pub fn poke<A: Addressable>(&mut self, addr: u32, val: u32) -> bool {
+        self.cpu.deposit::<A>(addr, val)
+    }
+
+    /// Read `len` bytes starting at `addr`, see `peek`
+    pub fn dump(&mut self, addr: u32, len: u32) -> Vec<u8> {
+        (0..len)
+            .map(|i| self.cpu.examine::<Byte>(addr.wrapping_add(i)) as u8)
+            .collect()
+    }
+
+    /// Press `button` on gamepad `pad` (0 or 1). If `frames` is not
+    /// 0 the button is released automatically after that many
+    /// frames.
+    pub fn press(&mut self, pad: usize, button: Button, frames: u32) {
+        set_button(self.cpu, pad, button, ButtonState::Pressed);
+
+        self.held.retain(|h| !h.is(pad, button));
+
+        if frames > 0 {
+            self.held.push(HeldButton {
+                pad: pad,
+                button: button,
+                frames: frames,
+            });
+        }
+    }
+
+    pub fn release(&mut self, pad: usize, button: Button) {
+        set_button(self.cpu, pad, button, ButtonState::Released);
+
+        self.held.retain(|h| !h.is(pad, button));
+    }
+
+    /// Ask the frontend to capture the display to `path`
+    pub fn screenshot(&mut self, path: &str) {
+        self.requests.push(Request::Screenshot(path.into()));
+    }
+
+    /// Send text to the frontend
+    pub fn output(&mut self, text: String) {
+        self.requests.push(Request::Output(text));
+    }
+
+    /// Ask the frontend to stop the emulation
+    pub fn quit(&mut self) {
+        self.requests.push(Request::Quit);
+    }
+}
+
+/// When a frame callback runs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameTrigger {
+    /// Once, at the end of the given frame
+    At(u32),
+    /// At the end of every Nth frame
+    Every(u32),
+}
+
+impl FrameTrigger {
+    fn matches(self, frame: u32) -> bool {
+        match self {
+            FrameTrigger::At(f) => f == frame,
+            FrameTrigger::Every(n) => n != 0 && frame % n == 0,
+        }
+    }
+}
+
+/// Debugger wrapper running the automation callbacks
+pub struct Automation<D: Debugger> {
+    inner: D,
+    pc_callbacks: Vec<(u32, Callback)>,
+    frame_callbacks: Vec<(FrameTrigger, Callback)>,
+    /// Buttons waiting to be released
+    held: Vec<HeldButton>,
+    requests: Vec<Request>,
+    /// Frame counter at the end of the last frame
+    frame: u32,
+}
+
+impl<D: Debugger> Automation<D> {
+    pub fn new(inner: D) -> Automation<D> {
+        Automation {
+            inner: inner,
+            pc_callbacks: Vec::new(),
+            frame_callbacks: Vec::new(),
+            held: Vec::new(),
+            requests: Vec::new(),
+            frame: 0,
+        }
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut D {
+        &mut self.inner
+    }
+
+    /// Run `callback` every time the CPU is about to execute the
+    /// instruction at `addr`
+    pub fn on_pc(&mut self, addr: u32, callback: Callback) {
+        self.pc_callbacks.push((addr, callback));
+    }
+
+    /// Run `callback` at the end of the frames matching `trigger`
+    pub fn on_frame(&mut self, trigger: FrameTrigger, callback: Callback) {
+        self.frame_callbacks.push((trigger, callback));
+    }
+
+    /// Parse `source` and register its triggers
+    pub fn load_script(&mut self, source: &str) -> Result<(), Error> {
+        for (trigger, commands) in try!(parse(source)) {
+            let callback: Callback =
+                Box::new(move |ctx: &mut Context| run(&commands, ctx));
+
+            match trigger {
+                Trigger::Pc(addr) => self.on_pc(addr, callback),
+                Trigger::Frame(t) => self.on_frame(t, callback),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Return the requests made by the callbacks since the last call
+    pub fn take_requests(&mut self) -> Vec<Request> {
+        ::std::mem::replace(&mut self.requests, Vec::new())
+    }
+
+    /// Must be called by the frontend at the end of each frame (after
+    /// `Cpu::run_until_next_frame`). Releases the buttons whose delay
+    /// expired and runs the frame callbacks.
+    pub fn end_of_frame(&mut self, cpu: &mut Cpu, shared: &SharedState) {
+        let frame = shared.counters().frame.get();
+
+        // Several frames might have elapsed if the frontend doesn't
+        // call us for every frame
+        let elapsed = frame.wrapping_sub(self.frame);
+
+        self.frame = frame;
+
+        for h in &mut self.held {
+            h.frames = h.frames.saturating_sub(elapsed);
+
+            if h.frames == 0 {
+                set_button(cpu, h.pad, h.button, ButtonState::Released);
+            }
+        }
+
+        self.held.retain(|h| h.frames > 0);
+
+        for entry in &mut self.frame_callbacks {
+            if entry.0.matches(frame) {
+                let mut ctx = Context {
+                    cpu: &mut *cpu,
+                    frame: frame,
+                    held: &mut self.held,
+                    requests: &mut self.requests,
+                };
+
+                (entry.1)(&mut ctx);
+            }
+        }
+    }
+}
+
+impl<D: Debugger> Debugger for Automation<D> {
+    fn trigger_break(&mut self) {
+        self.inner.trigger_break();
+    }
+
+    fn pc_change(&mut self, cpu: &mut Cpu) {
+        if !self.pc_callbacks.is_empty() {
+            let pc = cpu.pc();
+
+            for entry in &mut self.pc_callbacks {
+                if entry.0 == pc {
+                    let mut ctx = Context {
+                        cpu: &mut *cpu,
+                        frame: self.frame,
+                        held: &mut self.held,
+                        requests: &mut self.requests,
+                    };
+
+                    (entry.1)(&mut ctx);
+                }
+            }
+        }
+
+        self.inner.pc_change(cpu);
+    }
+
+    fn memory_read(&mut self, cpu: &mut Cpu, addr: u32, size: u8, val: u32) {
+        self.inner.memory_read(cpu, addr, size, val);
+    }
+
+    fn memory_write(&mut self, cpu: &mut Cpu, addr: u32, size: u8, val: u32) {
+        self.inner.memory_write(cpu, addr, size, val);
+    }
+
+    fn dma_write(&mut self, cpu: &mut Cpu, addr: u32, len: u32) {
+        self.inner.dma_write(cpu, addr, len);
+    }
+
+    fn symbols(&self) -> Option<&SymbolTable> {
+        self.inner.symbols()
+    }
+}
+
+/// Button pressed for a limited number of frames
+struct HeldButton {
+    pad: usize,
+    button: Button,
+    /// Frames remaining before the button is released
+    frames: u32,
+}
+
+impl HeldButton {
+    fn is(&self, pad: usize, button: Button) -> bool {
+        self.pad == pad && self.button as u8 == button as u8
+    }
+}
+
+fn set_button(cpu: &mut Cpu, pad: usize, button: Button, state: ButtonState) {
+    let mut pads = cpu.interconnect_mut().pad_memcard_mut().gamepads_mut();
+
+    if let Some(pad) = pads.get_mut(pad) {
+        pad.profile_mut().set_button_state(button, state);
+    }
+}
+
+enum Trigger {
+    Pc(u32),
+    Frame(FrameTrigger),
+}
+
+/// Script command
+enum Command {
+    Peek(u8, u32),
+    Poke(u8, u32, u32),
+    Dump(u32, u32, String),
+    Press(usize, Button, u32),
+    Release(usize, Button),
+    Screenshot(String),
+    Log(String),
+    Quit,
+}
+
+fn run(commands: &[Command], ctx: &mut Context) {
+    for command in commands {
+        match *command {
+            Command::Peek(size, addr) => {
+                let (v, width) =
+                    match size {
+                        1 => (ctx.peek::<Byte>(addr), 2),
+                        2 => (ctx.peek::<HalfWord>(addr), 4),
+                        _ => (ctx.peek::<Word>(addr), 8),
+                    };
+
+                ctx.output(format!("0x{:08x}: 0x{:02$x}", addr, v, width));
+            }
+            Command::Poke(size, addr, val) => {
+                let ok =
+                    match size {
+                        1 => ctx.poke::<Byte>(addr, val),
+                        2 => ctx.poke::<HalfWord>(addr, val),
+                        _ => ctx.poke::<Word>(addr, val),
+                    };
+
+                if !ok {
+                    ctx.output(format!("Can't poke 0x{:08x}", addr));
+                }
+            }
+            Command::Dump(addr, len, ref path) => {
+                let data = ctx.dump(addr, len);
+
+                let res = File::create(path).and_then(|mut f| f.write_all(&data));
+
+                if let Err(e) = res {
+                    ctx.output(format!("Can't dump to '{}': {}", path, e));
+                }
+            }
+            Command::Press(pad, button, frames) =>
+                ctx.press(pad, button, frames),
+            Command::Release(pad, button) => ctx.release(pad, button),
+            Command::Screenshot(ref path) => ctx.screenshot(path),
+            Command::Log(ref text) => ctx.output(text.clone()),
+            Command::Quit => ctx.quit(),
+        }
+    }
+}
+
+/// Parse a script into its triggers and their commands
+fn parse(source: &str) -> Result<Vec<(Trigger, Vec<Command>)>, Error> {
+    let mut triggers: Vec<(Trigger, Vec<Command>)> = Vec::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let error = |message: String| Error {
+            line: i + 1,
+            message: message,
+        };
+
+        let indented = line.starts_with(' ') || line.starts_with('\t');
+
+        let line =
+            match line.find('#') {
+                Some(p) => &line[..p],
+                None => line,
+            };
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        if words.is_empty() {
+            continue;
+        }
+
+        if !indented {
+            let trigger =
+                match trigger(&words) {
+                    Ok(t) => t,
+                    Err(e) => return Err(error(e)),
+                };
+
+            triggers.push((trigger, Vec::new()));
+            continue;
+        }
+
+        let command =
+            match command(&words, line) {
+                Ok(c) => c,
+                Err(e) => return Err(error(e)),
+            };
+
+        match triggers.last_mut() {
+            Some(t) => t.1.push(command),
+            None => return Err(error("Command outside of a trigger".into())),
+        }
+    }
+
+    Ok(triggers)
+}
+
+fn trigger(words: &[&str]) -> Result<Trigger, String> {
+    if words[0] != "on" {
+        return Err(format!("Expected a trigger, got '{}'", words[0]));
+    }
+
+    let args = &words[1..];
+
+    match (args.len(), args.get(0).cloned()) {
+        (2, Some("pc")) => Ok(Trigger::Pc(try!(number(args[1])))),
+        (2, Some("frame")) =>
+            Ok(Trigger::Frame(FrameTrigger::At(try!(number(args[1]))))),
+        (2, Some("every")) if args[1] == "frame" =>
+            Ok(Trigger::Frame(FrameTrigger::Every(1))),
+        (3, Some("every")) if args[2] == "frames" => {
+            let n = try!(number(args[1]));
+
+            if n == 0 {
+                return Err("Frame interval can't be 0".into());
+            }
+
+            Ok(Trigger::Frame(FrameTrigger::Every(n)))
+        }
+        _ => Err(format!("Invalid trigger '{}'", args.join(" "))),
+    }
+}
+
+fn command(words: &[&str], line: &str) -> Result<Command, String> {
+    let args = &words[1..];
+
+    let expect_args = |min: usize, max: usize| {
+        if args.len() < min || args.len() > max {
+            Err(format!("Wrong number of arguments for '{}'", words[0]))
+        } else {
+            Ok(())
+        }
+    };
+
+    let size =
+        match words[0] {
+            "peek8" | "poke8" => 1,
+            "peek16" | "poke16" => 2,
+            _ => 4,
+        };
+
+    let command =
+        match words[0] {
+            "peek8" | "peek16" | "peek32" => {
+                try!(expect_args(1, 1));
+
+                Command::Peek(size, try!(number(args[0])))
+            }
+            "poke8" | "poke16" | "poke32" => {
+                try!(expect_args(2, 2));
+
+                Command::Poke(size, try!(number(args[0])), try!(number(args[1])))
+            }
+            "dump" => {
+                try!(expect_args(3, 3));
+
+                Command::Dump(try!(number(args[0])),
+                              try!(number(args[1])),
+                              args[2].into())
+            }
+            "press" => {
+                try!(expect_args(1, 2));
+
+                let (pad, button) = try!(pad_button(args[0]));
+
+                let frames =
+                    match args.get(1) {
+                        Some(f) => try!(number(f)),
+                        None => 0,
+                    };
+
+                Command::Press(pad, button, frames)
+            }
+            "release" => {
+                try!(expect_args(1, 1));
+
+                let (pad, button) = try!(pad_button(args[0]));
+
+                Command::Release(pad, button)
+            }
+            "screenshot" => {
+                try!(expect_args(1, 1));
+
+                Command::Screenshot(args[0].into())
+            }
+            "log" => {
+                // Keep the original spacing
+                let text = line.trim().splitn(2, char::is_whitespace)
+                    .nth(1)
+                    .unwrap_or("")
+                    .trim();
+
+                Command::Log(text.into())
+            }
+            "quit" => {
+                try!(expect_args(0, 0));
+
+                Command::Quit
+            }
+            c => return Err(format!("Unknown command '{}'", c)),
+        };
+
+    Ok(command)
+}
+
+/// Parse `[PAD:]BUTTON`, pads are numbered from 1
+fn pad_button(s: &str) -> Result<(usize, Button), String> {
+    let (pad, name) =
+        match s.find(':') {
+            Some(p) => {
+                let pad =
+                    match &s[..p] {
+                        "1" => 0,
+                        "2" => 1,
+                        _ => return Err(format!("Invalid pad '{}'", &s[..p])),
+                    };
+
+                (pad, &s[p + 1..])
+            }
+            None => (0, s),
+        };
+
+    let button =
+        match name {
+            "select" => Button::Select,
+            "start" => Button::Start,
+            "up" => Button::DUp,
+            "right" => Button::DRight,
+            "down" => Button::DDown,
+            "left" => Button::DLeft,
+            "l2" => Button::L2,
+            "r2" => Button::R2,
+            "l1" => Button::L1,
+            "r1" => Button::R1,
+            "triangle" => Button::Triangle,
+            "circle" => Button::Circle,
+            "cross" => Button::Cross,
+            "square" => Button::Square,
+            _ => return Err(format!("Unknown button '{}'", name)),
+        };
+
+    Ok((pad, button))
+}
+
+fn number(s: &str) -> Result<u32, String> {
+    let res =
+        if s.starts_with("0x") || s.starts_with("0X") {
+            u32::from_str_radix(&s[2..], 16)
+        } else {
+            s.parse()
+        };
+
+    res.map_err(|_| format!("Invalid number '{}'", s))
+}
+
+/// Error while parsing a script
+#[derive(Debug)]
+pub struct Error {
+    /// Line where the error occured, starting at 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[test]
+fn script_pc_hooks() {
+    use debugger::DummyDebugger;
+    use test_utils::dummy_cpu;
+
+    let mut cpu = dummy_cpu();
+
+    let dump = ::std::env::temp_dir().join("rustation_script_dump.bin");
+    let dump = dump.to_str().unwrap();
+
+    let script = format!("# Test script\n\
+                          on pc 0x80010000\n\
+                          \tpoke32 0x80002000 0\n\
+                          \tpoke8 0x80002000 0x12\n\
+                          \tpoke16 0x80002002 0x3456 # comment\n\
+                          \t# Registers can't be poked\n\
+                          \tpoke32 0x1f801070 1\n\
+                          \tpeek8 0x80002000\n\
+                          \tpeek16 0x80002002\n\
+                          \tpeek32 0X80002000\n\
+                          \tdump 0x80002000 4 {}\n\
+                          \tdump 0x80002000 4 /nonexistent/dump.bin\n\
+                          \n\
+                          on pc 0x80010004\n\
+                          \tlog all   done\n\
+                          \tquit\n", dump);
+
+    let mut automation = Automation::new(DummyDebugger);
+
+    automation.load_script(&script).unwrap();
+
+    // Not a trigger address
+    cpu.force_pc(0x80010008);
+    automation.pc_change(&mut cpu);
+    assert!(automation.take_requests().is_empty());
+
+    cpu.force_pc(0x80010000);
+    automation.pc_change(&mut cpu);
+
+    let requests = automation.take_requests();
+
+    assert!(requests[..4] ==
+            [Request::Output("Can't poke 0x1f801070".into()),
+             Request::Output("0x80002000: 0x12".into()),
+             Request::Output("0x80002002: 0x3456".into()),
+             Request::Output("0x80002000: 0x34560012".into())]);
+
+    match requests[4] {
+        Request::Output(ref s) =>
+            assert!(s.starts_with("Can't dump to '/nonexistent/dump.bin'")),
+        _ => panic!("Dump error not reported"),
+    }
+
+    assert!(requests.len() == 5);
+
+    let mut data = Vec::new();
+
+    {
+        use std::io::Read;
+
+        File::open(dump).unwrap().read_to_end(&mut data).unwrap();
+    }
+
+    ::std::fs::remove_file(dump).unwrap();
+
+    assert!(data == [0x12, 0x00, 0x56, 0x34]);
+
+    cpu.force_pc(0x80010004);
+    automation.pc_change(&mut cpu);
+
+    assert!(automation.take_requests() ==
+            vec![Request::Output("all   done".into()), Request::Quit]);
+}
+
+#[test]
+fn script_frame_hooks() {
+    use padmemcard::gamepad::DigitalProfile;
+    use debugger::DummyDebugger;
+    use test_utils::dummy_cpu;
+
+    let mut cpu = dummy_cpu();
+    let mut shared = SharedState::new();
+
+    cpu.interconnect_mut().pad_memcard_mut().gamepads_mut()[1]
+        .set_profile(Box::new(DigitalProfile::new()));
+
+    let script = "on every frame\n\
+                  \tlog tick\n\
+                  on every 3 frames\n\
+                  \tlog third\n\
+                  on frame 2\n\
+                  \tpress 2:start 2\n\
+                  \tpress 2:up\n\
+                  on frame 4\n\
+                  \trelease 2:up\n";
+
+    let mut automation = Automation::new(DummyDebugger);
+
+    automation.load_script(script).unwrap();
+
+    let tick = || Request::Output("tick".into());
+    let third = || Request::Output("third".into());
+
+    let pad_state = |cpu: &mut Cpu| {
+        cpu.interconnect_mut().pad_memcard_mut().gamepads_mut()[1]
+            .profile_mut().handle_command(3, 0).0
+    };
+
+    let mut next_frame = |automation: &mut Automation<DummyDebugger>,
+                          cpu: &mut Cpu,
+                          frames: u32| {
+        for _ in 0..frames {
+            shared.counters_mut().frame.increment();
+        }
+
+        automation.end_of_frame(cpu, &shared);
+        automation.take_requests()
+    };
+
+    assert!(next_frame(&mut automation, &mut cpu, 1) == vec![tick()]);
+    assert!(pad_state(&mut cpu) == 0xff);
+
+    // Buttons are active low
+    assert!(next_frame(&mut automation, &mut cpu, 1) == vec![tick()]);
+    assert!(pad_state(&mut cpu) == 0xe7);
+
+    assert!(next_frame(&mut automation, &mut cpu, 1) == vec![tick(), third()]);
+    assert!(pad_state(&mut cpu) == 0xe7);
+
+    // Start is released after two frames, up by the script
+    assert!(next_frame(&mut automation, &mut cpu, 1) == vec![tick()]);
+    assert!(pad_state(&mut cpu) == 0xff);
+
+    // The frontend skipped a frame
+    assert!(next_frame(&mut automation, &mut cpu, 2) == vec![tick(), third()]);
+}
+
+#[test]
+fn script_parse_errors() {
+    use debugger::DummyDebugger;
+
+    let errors = [
+        ("at pc 0", "Expected a trigger, got 'at'"),
+        ("on pc", "Invalid trigger 'pc'"),
+        ("on every 2", "Invalid trigger 'every 2'"),
+        ("on every 0 frames", "Frame interval can't be 0"),
+        ("on frame x", "Invalid number 'x'"),
+        ("\tjump 0", "Unknown command 'jump'"),
+        ("\tpeek32", "Wrong number of arguments for 'peek32'"),
+        ("\tpeek16 0xzz", "Invalid number '0xzz'"),
+        ("\tpoke8 0x10", "Wrong number of arguments for 'poke8'"),
+        ("\tpoke16 0x10 -1", "Invalid number '-1'"),
+        ("\tdump 0 4", "Wrong number of arguments for 'dump'"),
+        ("\tdump 0 four out.bin", "Invalid number 'four'"),
+        ("\tpress", "Wrong number of arguments for 'press'"),
+        ("\tpress start 1 2", "Wrong number of arguments for 'press'"),
+        ("\tpress start x", "Invalid number 'x'"),
+        ("\tpress 3:start", "Invalid pad '3'"),
+        ("\tpress 1:home", "Unknown button 'home'"),
+        ("\trelease start 1", "Wrong number of arguments for 'release'"),
+        ("\tscreenshot", "Wrong number of arguments for 'screenshot'"),
+        ("\tquit now", "Wrong number of arguments for 'quit'"),
+    ];
+
+    for &(line, message) in &errors {
+        // Comments and blank lines are counted
+        let script = format!("on frame 1\n# comment\n\n{}\n", line);
+
+        let mut automation = Automation::new(DummyDebugger);
+
+        let e = automation.load_script(&script).unwrap_err();
+
+        assert!(e.line == 4 && e.message == message,
+                "'{}': {}", line, e);
+        assert!(format!("{}", e) == format!("line 4: {}", message));
+
+        // Nothing is registered if the script is invalid
+        assert!(automation.frame_callbacks.is_empty());
+    }
+
+    let mut automation = Automation::new(DummyDebugger);
+
+    let e = automation.load_script("# comment\n  quit\n").unwrap_err();
+
+    assert!(e.line == 2 && e.message == "Command outside of a trigger");
+}
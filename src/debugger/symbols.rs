@@ -0,0 +1,468 @@
+//! Symbol tables used to resolve guest addresses into names.
+//!
+//! Symbols can be loaded from:
+//!
+//! * ELF executables (the `.symtab` section, DWARF debug information
+//!   is not used),
+//! * Psy-Q `.SYM` files generated by the official SDK linker (only
+//!   the symbol and function definitions are used, source line
+//!   information is skipped),
+//! * Simple text maps with one `address name` pair per line.
+
+use std::path::Path;
+use std::fs::File;
+use std::io::{self, Read};
+use std::fmt;
+
+/// A single symbol
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    /// Address of the symbol
+    pub address: u32,
+    /// Size of the object in bytes, if known
+    pub size: Option<u32>,
+    /// Symbol name
+    pub name: String,
+}
+
+/// Symbol table sorted by address
+#[derive(Clone, Debug)]
+pub struct SymbolTable {
+    /// Symbols sorted by address
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    /// Create an empty symbol table
+    pub fn new() -> SymbolTable {
+        SymbolTable {
+            symbols: Vec::new(),
+        }
+    }
+
+    /// Load a symbol file, the format is detected automatically
+    pub fn load_file(path: &Path) -> Result<SymbolTable, Error> {
+        let mut f = try!(File::open(path));
+
+        let mut data = Vec::new();
+
+        try!(f.read_to_end(&mut data));
+
+        SymbolTable::from_bytes(&data)
+    }
+
+    /// Parse a symbol file, the format is detected automatically
+    pub fn from_bytes(data: &[u8]) -> Result<SymbolTable, Error> {
+        if data.starts_with(b"\x7fELF") {
+            SymbolTable::from_elf(data)
+        } else if data.starts_with(b"MND") {
+            SymbolTable::from_psyq_sym(data)
+        } else {
+            match ::std::str::from_utf8(data) {
+                Ok(s) => SymbolTable::from_text_map(s),
+                Err(_) => Err(Error::UnknownFormat),
+            }
+        }
+    }
+
+    /// Parse the symbol table of a 32bit little endian ELF file
+    pub fn from_elf(data: &[u8]) -> Result<SymbolTable, Error> {
+        if !data.starts_with(b"\x7fELF") {
+            return Err(Error::UnknownFormat);
+        }
+
+        // We only support 32bit little endian files
+        if try!(read_u8(data, 4)) != 1 || try!(read_u8(data, 5)) != 1 {
+            return Err(Error::UnknownFormat);
+        }
+
+        let shoff = try!(read_u32(data, 0x20)) as usize;
+        let shentsize = try!(read_u16(data, 0x2e)) as usize;
+        let shnum = try!(read_u16(data, 0x30)) as usize;
+
+        let section = |index: usize| {
+            let h = shoff + index * shentsize;
+
+            Ok::<_, Error>(ElfSection {
+                stype: try!(read_u32(data, h + 4)),
+                offset: try!(read_u32(data, h + 16)) as usize,
+                size: try!(read_u32(data, h + 20)) as usize,
+                link: try!(read_u32(data, h + 24)) as usize,
+                entsize: try!(read_u32(data, h + 36)) as usize,
+            })
+        };
+
+        let mut table = SymbolTable::new();
+
+        for i in 0..shnum {
+            let symtab = try!(section(i));
+
+            // SHT_SYMTAB
+            if symtab.stype != 2 {
+                continue;
+            }
+
+            let strtab = try!(section(symtab.link));
+
+            let entsize =
+                if symtab.entsize == 0 {
+                    16
+                } else {
+                    symtab.entsize
+                };
+
+            for s in 0..(symtab.size / entsize) {
+                let e = symtab.offset + s * entsize;
+
+                let name = try!(read_u32(data, e)) as usize;
+                let value = try!(read_u32(data, e + 4));
+                let size = try!(read_u32(data, e + 8));
+                let info = try!(read_u8(data, e + 12));
+                let shndx = try!(read_u16(data, e + 14));
+
+                // Skip undefined symbols as well as section and file
+                // symbols
+                if shndx == 0 || (info & 0xf) > 2 {
+                    continue;
+                }
+
+                let name = try!(read_cstr(data, strtab.offset + name));
+
+                if name.is_empty() {
+                    continue;
+                }
+
+                let size = if size == 0 { None } else { Some(size) };
+
+                table.add(value, name, size);
+            }
+        }
+
+        Ok(table)
+    }
+
+    /// Parse a Psy-Q `.SYM` file
+    pub fn from_psyq_sym(data: &[u8]) -> Result<SymbolTable, Error> {
+        if !data.starts_with(b"MND") {
+            return Err(Error::UnknownFormat);
+        }
+
+        let mut table = SymbolTable::new();
+
+        // 8 byte header: "MND", version, unit and padding
+        let mut pos = 8;
+
+        while pos < data.len() {
+            let value = try!(read_u32(data, pos));
+            let tag = try!(read_u8(data, pos + 4));
+
+            pos += 5;
+
+            match tag {
+                // Global and local symbols
+                1 | 2 => {
+                    let name = try!(read_pstr(data, &mut pos));
+
+                    table.add(value, name, None);
+                }
+                // Line number information, no payload
+                0x80 | 0x8a | 0x9a => (),
+                // Increment line number by byte/halfword
+                0x82 => pos += 1,
+                0x84 => pos += 2,
+                // Set line number
+                0x86 => pos += 4,
+                // Set line number and file name
+                0x88 => {
+                    pos += 4;
+                    try!(read_pstr(data, &mut pos));
+                }
+                // Function start
+                0x8c => {
+                    // fp, fsize, retreg, mask, maskoffs, line
+                    pos += 2 + 4 + 2 + 4 + 4 + 4;
+                    // File name
+                    try!(read_pstr(data, &mut pos));
+
+                    let name = try!(read_pstr(data, &mut pos));
+
+                    table.add(value, name, None);
+                }
+                // Function end, block start and block end
+                0x8e | 0x90 | 0x92 => pos += 4,
+                // Definition: class, type, size, name
+                0x94 => {
+                    pos += 2 + 2 + 4;
+                    try!(read_pstr(data, &mut pos));
+                }
+                // Array/struct definition: class, type, size, dims,
+                // dimensions, tag, name
+                0x96 => {
+                    pos += 2 + 2 + 4;
+
+                    let dims = try!(read_u16(data, pos)) as usize;
+
+                    pos += 2 + dims * 4;
+
+                    try!(read_pstr(data, &mut pos));
+                    try!(read_pstr(data, &mut pos));
+                }
+                // Overlay definition: length, id
+                0x98 => pos += 8,
+                t => return Err(Error::Corrupt(
+                    format!("Unknown .SYM tag 0x{:02x} at offset 0x{:x}",
+                            t, pos - 1))),
+            }
+        }
+
+        Ok(table)
+    }
+
+    /// Parse a text map: one `address name` pair per line. The
+    /// address is in hexadecimal with or without a `0x` prefix. Empty
+    /// lines and lines starting with `#` are ignored.
+    pub fn from_text_map(map: &str) -> Result<SymbolTable, Error> {
+        let mut table = SymbolTable::new();
+
+        for (i, line) in map.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+
+            let (addr, name) =
+                match (fields.next(), fields.next()) {
+                    (Some(a), Some(n)) => (a, n),
+                    _ => return Err(Error::Corrupt(
+                        format!("Line {}: expected `address name`", i + 1))),
+                };
+
+            let addr = addr.trim_left_matches("0x").trim_left_matches("0X");
+
+            let addr =
+                match u32::from_str_radix(addr, 16) {
+                    Ok(a) => a,
+                    Err(_) => return Err(Error::Corrupt(
+                        format!("Line {}: invalid address", i + 1))),
+                };
+
+            table.add(addr, name.into(), None);
+        }
+
+        Ok(table)
+    }
+
+    /// Add a new symbol to the table
+    pub fn add(&mut self, address: u32, name: String, size: Option<u32>) {
+        let symbol = Symbol {
+            address: address,
+            size: size,
+            name: name,
+        };
+
+        let pos =
+            match self.symbols.binary_search_by(|s| s.address.cmp(&address)) {
+                Ok(p) => {
+                    if self.symbols[p] == symbol {
+                        // Duplicate
+                        return;
+                    }
+                    p
+                }
+                Err(p) => p,
+            };
+
+        self.symbols.insert(pos, symbol);
+    }
+
+    /// Add all the symbols in `other` to this table
+    pub fn merge(&mut self, other: SymbolTable) {
+        for s in other.symbols {
+            self.add(s.address, s.name, s.size);
+        }
+    }
+
+    /// Return the number of symbols in the table
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    /// Iterate over all the symbols sorted by address
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    /// Find the symbol containing `addr` and return it along with the
+    /// offset of `addr` within the symbol. If the symbol size is
+    /// unknown we assume that it extends up to the next symbol.
+    pub fn lookup(&self, addr: u32) -> Option<(&Symbol, u32)> {
+        let pos =
+            match self.symbols.binary_search_by(|s| s.address.cmp(&addr)) {
+                Ok(p) => p,
+                Err(0) => return None,
+                Err(p) => p - 1,
+            };
+
+        // If several symbols have the same address use the first one
+        let first = self.symbols[..pos].iter()
+            .rposition(|s| s.address != self.symbols[pos].address)
+            .map(|p| p + 1)
+            .unwrap_or(0);
+
+        let symbol = &self.symbols[first];
+        let offset = addr - symbol.address;
+
+        match symbol.size {
+            Some(size) if offset >= size => None,
+            _ => Some((symbol, offset)),
+        }
+    }
+
+    /// Return the address of the symbol called `name`
+    pub fn address_of(&self, name: &str) -> Option<u32> {
+        self.symbols.iter().find(|s| s.name == name).map(|s| s.address)
+    }
+
+    /// Describe `addr` as `symbol+offset` (or `symbol` if the offset
+    /// is 0). Returns `None` if no symbol contains `addr`.
+    pub fn describe(&self, addr: u32) -> Option<String> {
+        self.lookup(addr).map(|(s, offset)| {
+            if offset == 0 {
+                s.name.clone()
+            } else {
+                format!("{}+0x{:x}", s.name, offset)
+            }
+        })
+    }
+}
+
+struct ElfSection {
+    stype: u32,
+    offset: usize,
+    size: usize,
+    link: usize,
+    entsize: usize,
+}
+
+fn read_u8(data: &[u8], off: usize) -> Result<u8, Error> {
+    match data.get(off) {
+        Some(&b) => Ok(b),
+        None => Err(Error::Corrupt(format!("Unexpected end of file at 0x{:x}",
+                                           off))),
+    }
+}
+
+fn read_u16(data: &[u8], off: usize) -> Result<u16, Error> {
+    let b0 = try!(read_u8(data, off)) as u16;
+    let b1 = try!(read_u8(data, off + 1)) as u16;
+
+    Ok(b0 | (b1 << 8))
+}
+
+fn read_u32(data: &[u8], off: usize) -> Result<u32, Error> {
+    let h0 = try!(read_u16(data, off)) as u32;
+    let h1 = try!(read_u16(data, off + 2)) as u32;
+
+    Ok(h0 | (h1 << 16))
+}
+
+/// Read a `\0`-terminated string
+fn read_cstr(data: &[u8], off: usize) -> Result<String, Error> {
+    let s = match data.get(off..) {
+        Some(s) => s,
+        None => return Err(Error::Corrupt(format!("Bad string offset 0x{:x}",
+                                                  off))),
+    };
+
+    let len = s.iter().position(|&b| b == 0).unwrap_or(s.len());
+
+    Ok(String::from_utf8_lossy(&s[..len]).into_owned())
+}
+
+/// Read a length-prefixed string and advance `pos` past it
+fn read_pstr(data: &[u8], pos: &mut usize) -> Result<String, Error> {
+    let len = try!(read_u8(data, *pos)) as usize;
+
+    let start = *pos + 1;
+    let end = start + len;
+
+    if end > data.len() {
+        return Err(Error::Corrupt(format!("String overflows file at 0x{:x}",
+                                          *pos)));
+    }
+
+    *pos = end;
+
+    Ok(String::from_utf8_lossy(&data[start..end]).into_owned())
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Error while reading the symbol file
+    IoError(io::Error),
+    /// File is not in a known symbol file format
+    UnknownFormat,
+    /// The file is corrupted or uses an unsupported feature
+    Corrupt(String),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::IoError(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::IoError(ref e) => write!(f, "I/O error: {}", e),
+            Error::UnknownFormat => write!(f, "Unknown symbol file format"),
+            Error::Corrupt(ref s) => write!(f, "Invalid symbol file: {}", s),
+        }
+    }
+}
+
+#[test]
+fn text_map_lookup() {
+    let map = "# Test map\n\
+               80010000 main\n\
+               0x80010100 do_stuff\n\
+               \n\
+               80010200 other\n";
+
+    let table = SymbolTable::from_bytes(map.as_bytes()).unwrap();
+
+    assert!(table.len() == 3);
+
+    assert!(table.describe(0x8000ffff) == None);
+    assert!(table.describe(0x80010000) == Some("main".into()));
+    assert!(table.describe(0x80010010) == Some("main+0x10".into()));
+    assert!(table.describe(0x80010104) == Some("do_stuff+0x4".into()));
+    assert!(table.describe(0x80020000) == Some("other+0xfe00".into()));
+    assert!(table.address_of("other") == Some(0x80010200));
+}
+
+#[test]
+fn psyq_sym() {
+    let mut sym = b"MND\x01\x00\x00\x00\x00".to_vec();
+
+    // Global symbol "main" at 0x80010000
+    sym.extend_from_slice(b"\x00\x00\x01\x80\x01\x04main");
+    // Set line number
+    sym.extend_from_slice(b"\x00\x00\x01\x80\x86\x0a\x00\x00\x00");
+    // Function start "func" at 0x80010100
+    sym.extend_from_slice(b"\x00\x01\x01\x80\x8c");
+    sym.extend_from_slice(&[0; 20]);
+    sym.extend_from_slice(b"\x06main.c\x04func");
+    // Function end
+    sym.extend_from_slice(b"\x40\x01\x01\x80\x8e\x10\x00\x00\x00");
+
+    let table = SymbolTable::from_bytes(&sym).unwrap();
+
+    assert!(table.len() == 2);
+    assert!(table.describe(0x80010104) == Some("func+0x4".into()));
+    assert!(table.address_of("main") == Some(0x80010000));
+}
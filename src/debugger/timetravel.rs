@@ -0,0 +1,637 @@
+//! Time-travel debugging.
+//!
+//! `TimeTravel` wraps another `Debugger` and counts the instructions
+//! executed. The frontend calls `maybe_snapshot` between frames to
+//! take in-memory savestates at regular intervals. To go back in
+//! time the nearest snapshot preceding the target is restored and
+//! the emulation is re-executed deterministically up to the
+//! requested instruction, at which point the wrapped debugger is
+//! asked to break.
+//!
+//! The wrapped debugger can also request reverse execution itself
+//! through `Debugger::reverse_request` (that's how GDB's
+//! `reverse-stepi` and `reverse-continue` are implemented). The
+//! requests are serviced by `TimeTravel::run_next_instruction` which
+//! the frontend must then use instead of `Cpu::run_next_instruction`.
+//!
+//! The replay is only exact if the inputs are the same as during the
+//! original run: the gamepad profiles and the disc aren't part of the
+//! snapshots so changing the controller state between the snapshot
+//! and the target can make the replay diverge.
+
+use std::collections::VecDeque;
+use std::fmt;
+
+use rustc_serialize::json;
+
+use cpu::Cpu;
+use shared::SharedState;
+use gpu::renderer::Renderer;
+use error::ErrorReport;
+use memory::map;
+
+use super::{Debugger, Reverse};
+use super::symbols::SymbolTable;
+
+/// In-memory savestate
+struct Snapshot {
+    /// Number of instructions executed when the snapshot was taken
+    position: u64,
+    /// Frame counter when the snapshot was taken
+    frame: u32,
+    /// Serialized `Cpu` and `SharedState`
+    state: String,
+}
+
+/// Time-travel debugger wrapping `D`
+pub struct TimeTravel<D: Debugger> {
+    inner: D,
+    /// Number of instructions executed so far
+    position: u64,
+    /// Number of frames between two snapshots
+    interval: u32,
+    /// Maximum number of snapshots kept, the oldest ones are dropped
+    /// first
+    max_snapshots: usize,
+    /// Snapshots, oldest first
+    snapshots: VecDeque<Snapshot>,
+    /// Reverse execution requested by the wrapped debugger and the
+    /// position of the instruction it was stopped at
+    pending: Option<(Reverse, u64)>,
+}
+
+impl<D: Debugger> TimeTravel<D> {
+    /// Wrap `inner`, taking a snapshot every `interval` frames and
+    /// keeping at most `max_snapshots` of them
+    pub fn new(inner: D,
+               interval: u32,
+               max_snapshots: usize) -> TimeTravel<D> {
+        TimeTravel {
+            inner: inner,
+            position: 0,
+            interval: ::std::cmp::max(interval, 1),
+            max_snapshots: ::std::cmp::max(max_snapshots, 1),
+            snapshots: VecDeque::new(),
+            pending: None,
+        }
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut D {
+        &mut self.inner
+    }
+
+    /// Number of instructions executed since the creation of the
+    /// debugger
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Position of the oldest instruction we can go back to
+    pub fn earliest_position(&self) -> Option<u64> {
+        self.snapshots.front().map(|s| s.position)
+    }
+
+    /// Run a single instruction and service the reverse execution
+    /// requests made by the wrapped debugger while it was stopped
+    pub fn run_next_instruction(&mut self,
+                                cpu: &mut Cpu,
+                                shared: &mut SharedState,
+                                renderer: &mut Renderer) -> Result<(), Error> {
+        if let Err(e) = cpu.run_next_instruction(self, shared, renderer) {
+            return Err(Error::Emulation(e));
+        }
+
+        match self.pending.take() {
+            Some((request, current)) => {
+                let res = self.reverse(cpu, shared, renderer, request, current);
+
+                if res.is_err() {
+                    // Give the control back to the debugger where we
+                    // ended up
+                    self.inner.trigger_break();
+                }
+
+                res
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Like `Cpu::run_until_next_frame` but using
+    /// `run_next_instruction` to service the reverse execution
+    /// requests
+    pub fn run_until_next_frame(&mut self,
+                                cpu: &mut Cpu,
+                                shared: &mut SharedState,
+                                renderer: &mut Renderer) -> Result<(), Error> {
+        let frame = shared.counters().frame.get();
+
+        while frame == shared.counters().frame.get() {
+            try!(self.run_next_instruction(cpu, shared, renderer));
+        }
+
+        Ok(())
+    }
+
+    /// Take a snapshot if at least `interval` frames have been
+    /// emulated since the last one. Must be called between two
+    /// instructions, typically after `Cpu::run_until_next_frame`.
+    pub fn maybe_snapshot(&mut self,
+                          cpu: &Cpu,
+                          shared: &SharedState) -> Result<(), Error> {
+        let frame = shared.counters().frame.get();
+
+        let due =
+            match self.snapshots.back() {
+                Some(s) =>
+                    s.position > self.position ||
+                    frame.wrapping_sub(s.frame) >= self.interval,
+                None => true,
+            };
+
+        if due {
+            self.snapshot(cpu, shared)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Take a snapshot of the current state
+    pub fn snapshot(&mut self,
+                    cpu: &Cpu,
+                    shared: &SharedState) -> Result<(), Error> {
+        let state =
+            match json::encode(&(cpu, shared)) {
+                Ok(s) => s,
+                Err(e) => return Err(Error::Serialization(format!("{:?}", e))),
+            };
+
+        // If we went back in time the snapshots taken in the "future"
+        // might no longer be valid if the state has been modified
+        let position = self.position;
+
+        while self.snapshots.back().map_or(false, |s| s.position >= position) {
+            self.snapshots.pop_back();
+        }
+
+        if self.snapshots.len() >= self.max_snapshots {
+            self.snapshots.pop_front();
+        }
+
+        self.snapshots.push_back(Snapshot {
+            position: self.position,
+            frame: shared.counters().frame.get(),
+            state: state,
+        });
+
+        Ok(())
+    }
+
+    /// Go back to the state right before the last instruction was
+    /// executed. The wrapped debugger will break at this instruction.
+    pub fn step_back(&mut self,
+                     cpu: &mut Cpu,
+                     shared: &mut SharedState,
+                     renderer: &mut Renderer) -> Result<(), Error> {
+        if self.position == 0 {
+            return Err(Error::NoSnapshot);
+        }
+
+        let target = self.position - 1;
+
+        self.seek(cpu, shared, renderer, target)
+    }
+
+    /// Restore the state after `target` instructions. The wrapped
+    /// debugger will break at the next instruction.
+    pub fn seek(&mut self,
+                cpu: &mut Cpu,
+                shared: &mut SharedState,
+                renderer: &mut Renderer,
+                target: u64) -> Result<(), Error> {
+        try!(self.restore(cpu, shared, renderer, target));
+
+        self.inner.trigger_break();
+
+        Ok(())
+    }
+
+    /// Go back to the last instruction before the current one that
+    /// hits one of the code `breakpoints` or writes to one of the
+    /// `(addr, len)` `watchpoints`. If none is found in the available
+    /// history we go back to the oldest snapshot. Returns the
+    /// position of the instruction the wrapped debugger will break
+    /// at.
+    pub fn reverse_continue(&mut self,
+                            cpu: &mut Cpu,
+                            shared: &mut SharedState,
+                            renderer: &mut Renderer,
+                            breakpoints: &[u32],
+                            watchpoints: &[(u32, u32)]) -> Result<u64, Error> {
+        let end = self.position;
+
+        self.rewind_to_hit(cpu, shared, renderer,
+                           end, breakpoints, watchpoints)
+    }
+
+    /// `reverse_continue` looking for hits before position `end`
+    fn rewind_to_hit(&mut self,
+                     cpu: &mut Cpu,
+                     shared: &mut SharedState,
+                     renderer: &mut Renderer,
+                     end: u64,
+                     breakpoints: &[u32],
+                     watchpoints: &[(u32, u32)]) -> Result<u64, Error> {
+        let found =
+            try!(self.find_last(cpu, shared, renderer,
+                                end, breakpoints, watchpoints));
+
+        match found {
+            Some(pos) => Ok(pos),
+            None => {
+                let start =
+                    match self.earliest_position() {
+                        Some(p) => p,
+                        None => return Err(Error::NoSnapshot),
+                    };
+
+                try!(self.seek(cpu, shared, renderer, start));
+
+                Ok(start)
+            }
+        }
+    }
+
+    /// Go back to the last instruction that wrote to the `len` bytes
+    /// at `addr` (either directly or by starting a DMA transfer). On
+    /// success the state is restored right before the instruction
+    /// making the write, the wrapped debugger will break there and
+    /// its position is returned. If no write is found in the
+    /// available history the state is left unchanged and `None` is
+    /// returned.
+    pub fn last_write(&mut self,
+                      cpu: &mut Cpu,
+                      shared: &mut SharedState,
+                      renderer: &mut Renderer,
+                      addr: u32,
+                      len: u32) -> Result<Option<u64>, Error> {
+        let end = self.position;
+
+        let found =
+            try!(self.find_last(cpu, shared, renderer,
+                                end, &[], &[(addr, len)]));
+
+        if found.is_none() {
+            // Not found, go back to where we started
+            try!(self.restore(cpu, shared, renderer, end));
+        }
+
+        Ok(found)
+    }
+
+    /// Look for the last instruction before position `end` hitting
+    /// one of `breakpoints` or writing to one of `watchpoints`. If
+    /// one is found the state is restored right before it, the
+    /// wrapped debugger is asked to break there and its position is
+    /// returned. Otherwise `None` is returned and the state is left
+    /// at the position of the oldest snapshot searched.
+    fn find_last(&mut self,
+                 cpu: &mut Cpu,
+                 shared: &mut SharedState,
+                 renderer: &mut Renderer,
+                 end: u64,
+                 breakpoints: &[u32],
+                 watchpoints: &[(u32, u32)]) -> Result<Option<u64>, Error> {
+        let newest =
+            match self.snapshots.iter().rposition(|s| s.position <= end) {
+                Some(i) => i,
+                None => return Err(Error::NoSnapshot),
+            };
+
+        // Look for the hit in each interval between two snapshots,
+        // newest first
+        let mut window_end = end;
+
+        for index in (0..newest + 1).rev() {
+            let mut replay = Replay::new(breakpoints, watchpoints);
+
+            try!(self.replay(cpu, shared, renderer,
+                             index, &mut replay, window_end));
+
+            if let Some(pos) = replay.last_hit {
+                let mut replay = Replay::new(&[], &[]);
+
+                try!(self.replay(cpu, shared, renderer,
+                                 index, &mut replay, pos));
+
+                self.inner.trigger_break();
+
+                return Ok(Some(pos));
+            }
+
+            window_end = self.snapshots[index].position;
+        }
+
+        Ok(None)
+    }
+
+    /// Service a reverse execution `request` made by the wrapped
+    /// debugger while it was stopped at instruction `current`. The
+    /// instruction has been executed since then.
+    fn reverse(&mut self,
+               cpu: &mut Cpu,
+               shared: &mut SharedState,
+               renderer: &mut Renderer,
+               request: Reverse,
+               current: u64) -> Result<(), Error> {
+        match request {
+            Reverse::Step => self.seek(cpu, shared, renderer, current - 1),
+            Reverse::Continue { breakpoints, watchpoints } =>
+                self.rewind_to_hit(cpu, shared, renderer, current,
+                                   &breakpoints, &watchpoints)
+                .map(|_| ()),
+        }
+    }
+
+    /// Restore the state after `target` instructions without
+    /// breaking
+    fn restore(&mut self,
+               cpu: &mut Cpu,
+               shared: &mut SharedState,
+               renderer: &mut Renderer,
+               target: u64) -> Result<(), Error> {
+        let index =
+            match self.snapshots.iter().rposition(|s| s.position <= target) {
+                Some(i) => i,
+                None => return Err(Error::NoSnapshot),
+            };
+
+        let mut replay = Replay::new(&[], &[]);
+
+        self.replay(cpu, shared, renderer, index, &mut replay, target)
+    }
+
+    /// Restore snapshot `index` and run until `target` instructions
+    /// have been executed
+    fn replay(&mut self,
+              cpu: &mut Cpu,
+              shared: &mut SharedState,
+              renderer: &mut Renderer,
+              index: usize,
+              replay: &mut Replay,
+              target: u64) -> Result<(), Error> {
+        let decoded =
+            json::decode::<(Cpu, SharedState)>(&self.snapshots[index].state);
+
+        let (mut restored, restored_shared) =
+            match decoded {
+                Ok(s) => s,
+                Err(e) => return Err(Error::Serialization(format!("{:?}", e))),
+            };
+
+        restored.interconnect_mut()
+            .take_unserialized(cpu.interconnect_mut());
+
+        *cpu = restored;
+        *shared = restored_shared;
+
+        replay.position = self.snapshots[index].position;
+
+        while replay.position < target {
+            let res = cpu.run_next_instruction(&mut *replay, shared, renderer);
+
+            if let Err(e) = res {
+                self.position = replay.position;
+                return Err(Error::Emulation(e));
+            }
+        }
+
+        self.position = replay.position;
+
+        Ok(())
+    }
+}
+
+impl<D: Debugger> Debugger for TimeTravel<D> {
+    fn trigger_break(&mut self) {
+        self.inner.trigger_break();
+    }
+
+    fn pc_change(&mut self, cpu: &mut Cpu) {
+        self.position += 1;
+
+        self.inner.pc_change(cpu);
+
+        while let Some(request) = self.inner.reverse_request() {
+            // Position of the instruction the debugger is stopped at
+            let current = self.position - 1;
+
+            if self.earliest_position().map_or(false, |p| p < current) {
+                // Serviced in `run_next_instruction` once we have
+                // access to the rest of the emulator state
+                self.pending = Some((request, current));
+                break;
+            }
+
+            warn!("No history before instruction {}, can't go back", current);
+
+            // Stop here again
+            self.inner.trigger_break();
+            self.inner.pc_change(cpu);
+        }
+    }
+
+    fn memory_read(&mut self, cpu: &mut Cpu, addr: u32, size: u8, val: u32) {
+        self.inner.memory_read(cpu, addr, size, val);
+    }
+
+    fn memory_write(&mut self, cpu: &mut Cpu, addr: u32, size: u8, val: u32) {
+        self.inner.memory_write(cpu, addr, size, val);
+    }
+
+    fn dma_write(&mut self, cpu: &mut Cpu, addr: u32, len: u32) {
+        self.inner.dma_write(cpu, addr, len);
+    }
+
+    fn symbols(&self) -> Option<&SymbolTable> {
+        self.inner.symbols()
+    }
+}
+
+/// Debugger used while replaying: counts the instructions and
+/// optionally looks for code breakpoints and writes to memory ranges
+struct Replay {
+    position: u64,
+    /// Code breakpoints
+    breakpoints: Vec<u32>,
+    /// Watched ranges: address with the region bits masked and length
+    watch: Vec<(u32, u32)>,
+    /// Position of the last instruction that hit a breakpoint or
+    /// wrote to `watch`
+    last_hit: Option<u64>,
+}
+
+impl Replay {
+    fn new(breakpoints: &[u32], watch: &[(u32, u32)]) -> Replay {
+        Replay {
+            position: 0,
+            breakpoints: breakpoints.to_vec(),
+            watch: watch.iter()
+                .map(|&(addr, len)| (map::mask_region(addr), len))
+                .collect(),
+            last_hit: None,
+        }
+    }
+
+    fn hit(&mut self) {
+        // `position` has already been incremented for the current
+        // instruction
+        self.last_hit = Some(self.position - 1);
+    }
+
+    fn write(&mut self, addr: u32, len: u32) {
+        let addr = map::mask_region(addr);
+
+        let hit =
+            self.watch.iter().any(|&(start, watch_len)| {
+                addr.wrapping_sub(start) < watch_len ||
+                    start.wrapping_sub(addr) < len
+            });
+
+        if hit {
+            self.hit();
+        }
+    }
+}
+
+impl Debugger for Replay {
+    fn trigger_break(&mut self) {
+    }
+
+    fn pc_change(&mut self, cpu: &mut Cpu) {
+        self.position += 1;
+
+        if self.breakpoints.contains(&cpu.pc()) {
+            self.hit();
+        }
+    }
+
+    fn memory_read(&mut self, _: &mut Cpu, _: u32, _: u8, _: u32) {
+    }
+
+    fn memory_write(&mut self, _: &mut Cpu, addr: u32, size: u8, _: u32) {
+        self.write(addr, size as u32);
+    }
+
+    fn dma_write(&mut self, _: &mut Cpu, addr: u32, len: u32) {
+        self.write(addr, len);
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// No snapshot old enough to reach the requested position
+    NoSnapshot,
+    /// The snapshot couldn't be serialized or deserialized
+    Serialization(String),
+    /// The replay ran into an emulation error
+    Emulation(ErrorReport),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::NoSnapshot =>
+                write!(f, "No snapshot available for this position"),
+            Error::Serialization(ref s) =>
+                write!(f, "Snapshot serialization failed: {}", s),
+            Error::Emulation(ref e) =>
+                write!(f, "Emulation error during replay: {}", e),
+        }
+    }
+}
+
+#[test]
+fn reverse_execution() {
+    use bios::Bios;
+    use gpu::{Gpu, VideoClock};
+    use memory::{Interconnect, Word};
+    use test_utils::DummyRenderer;
+    use debugger::DummyDebugger;
+
+    let inter = Interconnect::new(Bios::hle(),
+                                  Gpu::new(VideoClock::Ntsc),
+                                  None);
+    let mut cpu = Cpu::new(inter);
+    let mut shared = SharedState::new();
+    let mut renderer = DummyRenderer;
+
+    let program = [
+        // lui   $t1, 0x8000
+        0x3c098000,
+        // li    $t0, 0
+        0x24080000,
+        // loop:
+        // addiu $t0, $t0, 1
+        0x25080001,
+        // sw    $t0, 0x2000($t1)
+        0xad282000,
+        // b     loop
+        0x1000fffd,
+        // nop
+        0x00000000,
+    ];
+
+    for (i, &w) in program.iter().enumerate() {
+        cpu.deposit::<Word>(0x80010000 + i as u32 * 4, w);
+    }
+
+    cpu.force_pc(0x80010000);
+
+    let mut tt = TimeTravel::new(DummyDebugger, 1, 4);
+
+    fn run(tt: &mut TimeTravel<DummyDebugger>,
+           cpu: &mut Cpu,
+           shared: &mut SharedState,
+           n: u32) {
+        for _ in 0..n {
+            cpu.run_next_instruction(tt, shared, &mut DummyRenderer).unwrap();
+        }
+    }
+
+    run(&mut tt, &mut cpu, &mut shared, 2);
+    tt.snapshot(&cpu, &shared).unwrap();
+
+    // Five iterations of the loop
+    run(&mut tt, &mut cpu, &mut shared, 5 * 4);
+
+    assert!(tt.position() == 22);
+    assert!(cpu.examine::<Word>(0x80002000) == 5);
+
+    // Undo the `nop` in the delay slot
+    tt.step_back(&mut cpu, &mut shared, &mut renderer).unwrap();
+
+    assert!(tt.position() == 21);
+    assert!(cpu.pc() == 0x80010014);
+
+    // Go back to the last `sw`
+    let pos = tt.last_write(&mut cpu, &mut shared, &mut renderer,
+                            0xa0002000, 4).unwrap();
+
+    assert!(pos == Some(19));
+    assert!(tt.position() == 19);
+    assert!(cpu.pc() == 0x8001000c);
+    assert!(cpu.regs()[8] == 5);
+    assert!(cpu.examine::<Word>(0x80002000) == 4);
+
+    // Nothing ever wrote there
+    let pos = tt.last_write(&mut cpu, &mut shared, &mut renderer,
+                            0x80003000, 4).unwrap();
+
+    assert!(pos == None);
+    assert!(tt.position() == 19);
+}
@@ -0,0 +1,95 @@
+//! Fatal emulation errors.
+//!
+//! When the emulated software does something we don't know how to
+//! handle (unmapped memory access, unimplemented peripheral feature
+//! etc...) we don't want to bring the whole host application down
+//! with a `panic!`. Instead the peripheral records the error in the
+//! `SharedState` and the CPU returns it to the caller after the
+//! current instruction completes. Only the first error is kept since
+//! the following ones are often a consequence of it.
+
+use std::fmt;
+
+use cpu::Backtrace;
+
+/// Hardware block that raised an `EmulationError`
+#[derive(Clone, Copy, PartialEq, Eq, Debug, RustcDecodable, RustcEncodable)]
+pub enum Device {
+    Cpu,
+    Gte,
+    Interconnect,
+    InterruptController,
+    Dma,
+    Gpu,
+    Spu,
+    Timers,
+    CdRom,
+    MDec,
+    PadMemCard,
+    DebugUart,
+}
+
+/// Fatal condition encountered while running the emulation
+#[derive(Clone, PartialEq, Eq, Debug, RustcDecodable, RustcEncodable)]
+pub enum EmulationError {
+    /// Instruction fetch from an address that doesn't contain any
+    /// code
+    UnhandledFetch(u32),
+    /// Load from an address that isn't mapped to any device. The
+    /// second value is the size of the access in bytes.
+    UnhandledLoad(u32, u8),
+    /// Store to an address that isn't mapped to any device. Contains
+    /// the address, the size of the access in bytes and the value.
+    UnhandledStore(u32, u8, u32),
+    /// The emulated software attempted to use a feature of `Device`
+    /// that isn't emulated. The string describes the offending
+    /// access.
+    Unimplemented(Device, String),
+}
+
+impl fmt::Display for EmulationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EmulationError::UnhandledFetch(addr) =>
+                write!(f, "Unhandled instruction fetch at 0x{:08x}", addr),
+            EmulationError::UnhandledLoad(addr, size) =>
+                write!(f, "Unhandled {}byte load at 0x{:08x}", size, addr),
+            EmulationError::UnhandledStore(addr, size, val) =>
+                write!(f, "Unhandled {}byte store at 0x{:08x}: 0x{:08x}",
+                       size, addr, val),
+            EmulationError::Unimplemented(device, ref what) =>
+                write!(f, "{:?}: {}", device, what),
+        }
+    }
+}
+
+/// `EmulationError` returned by `Cpu::run_next_instruction` with the
+/// guest backtrace at the time of the error
+#[derive(Clone, Debug)]
+pub struct ErrorReport {
+    pub error: EmulationError,
+    /// Symbols are resolved if the debugger has a symbol table
+    pub backtrace: Backtrace,
+}
+
+impl fmt::Display for ErrorReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}\nGuest backtrace:\n{}", self.error, self.backtrace)
+    }
+}
+
+/// Record an `EmulationError::Unimplemented` for `$device` in the
+/// `SharedState`. The remaining arguments are formatted like
+/// `format!`:
+///
+/// ```ignore
+/// fatal!(shared, Device::Spu, "Unhandled SPU load ({})", T::size());
+/// ```
+macro_rules! fatal {
+    ($shared:expr, $device:expr, $($arg:tt)+) => ({
+        let desc = format!($($arg)+);
+
+        $shared.set_error(::error::EmulationError::Unimplemented($device,
+                                                                 desc));
+    })
+}
@@ -0,0 +1,206 @@
+//! Expansion region 2. On retail consoles nothing is connected there
+//! besides the POST register but development units and debugging
+//! hardware put a dual UART and a few control registers in this
+//! region.
+//!
+//! Unmapped addresses behave like an open bus: loads return all ones
+//! and stores are ignored.
+
+use memory::Addressable;
+use shared::SharedState;
+use debug_uart::DebugUart;
+
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct Expansion2 {
+    /// Dual UART used to output debug messages
+    uart: DebugUart,
+    /// Last value written to the POST register, used by the BIOS to
+    /// report its boot status on an external 7-segment display
+    post: u8,
+    /// Last value written to the DTL-H2000 POST/LED register
+    led: u8,
+    /// DTL-H2000 DIP switches. `None` for retail consoles.
+    dtl_switches: Option<u8>,
+}
+
+impl Expansion2 {
+    pub fn new() -> Expansion2 {
+        Expansion2 {
+            uart: DebugUart::new(),
+            post: 0,
+            led: 0,
+            dtl_switches: None,
+        }
+    }
+
+    /// Last value written to the POST register
+    pub fn post(&self) -> u8 {
+        self.post
+    }
+
+    /// Last value written to the DTL-H2000 POST/LED register
+    pub fn led(&self) -> u8 {
+        self.led
+    }
+
+    /// Set the state of the DTL-H2000 DIP switches, `None` to emulate
+    /// a retail console where the register reads as open bus
+    pub fn set_dtl_switches(&mut self, switches: Option<u8>) {
+        self.dtl_switches = switches;
+    }
+
+    pub fn load<A: Addressable>(&mut self,
+                                shared: &mut SharedState,
+                                offset: u32) -> u32 {
+        if is_uart(offset) {
+            return self.uart.load::<A>(shared, offset);
+        }
+
+        let mut r = 0;
+
+        for i in 0..A::size() as u32 {
+            let b = self.load_byte(offset + i);
+
+            r |= (b as u32) << (8 * i);
+        }
+
+        r
+    }
+
+    /// Byte value without side effects, used for debugging
+    pub fn peek(&self, offset: u32) -> u8 {
+        if is_uart(offset) {
+            self.uart.peek(offset) as u8
+        } else {
+            self.load_byte(offset)
+        }
+    }
+
+    pub fn store<A: Addressable>(&mut self,
+                                 shared: &mut SharedState,
+                                 offset: u32,
+                                 val: u32) {
+        if is_uart(offset) {
+            return self.uart.store::<A>(shared, offset, val);
+        }
+
+        for i in 0..A::size() as u32 {
+            self.store_byte(offset + i, (val >> (8 * i)) as u8);
+        }
+    }
+
+    fn load_byte(&self, offset: u32) -> u8 {
+        match offset {
+            // DTL-H2000 DIP switches
+            0x40 => self.dtl_switches.unwrap_or(0xff),
+            _ => 0xff,
+        }
+    }
+
+    fn store_byte(&mut self, offset: u32, val: u8) {
+        match offset {
+            // Boot status register, is incremented by the BIOS during
+            // bootup
+            0x41 => {
+                debug!("BIOS boot status: {}", val);
+                self.post = val;
+            }
+            // DTL-H2000 POST/LED
+            0x42 => {
+                debug!("DTL-H POST/LED: 0x{:02x}", val);
+                self.led = val;
+            }
+            // PS2 POST2 register, written by the PS1 mode of the PS2
+            // BIOS
+            0x70 => (),
+            _ => debug!("Expansion 2 store to open bus: {:x} {:02x}",
+                        offset, val),
+        }
+    }
+}
+
+/// The dual UART registers live at offsets 0x20 to 0x2f
+fn is_uart(offset: u32) -> bool {
+    match offset {
+        0x20..=0x2f => true,
+        _ => false,
+    }
+}
+
+#[test]
+fn open_bus() {
+    use memory::{Byte, HalfWord, Word};
+
+    let mut shared = SharedState::new();
+    let mut exp2 = Expansion2::new();
+
+    assert!(exp2.load::<Word>(&mut shared, 0x100) == 0xffffffff);
+    assert!(exp2.load::<HalfWord>(&mut shared, 0x1e) == 0xffff);
+    assert!(exp2.load::<Byte>(&mut shared, 0x30) == 0xff);
+    assert!(exp2.peek(0x1000) == 0xff);
+
+    // No DIP switches on retail consoles
+    assert!(exp2.load::<Byte>(&mut shared, 0x40) == 0xff);
+
+    // Stores to the open bus and the PS2 POST2 register are ignored
+    exp2.store::<Word>(&mut shared, 0x1000, 0x12345678);
+    exp2.store::<Byte>(&mut shared, 0x70, 0x12);
+    assert!(exp2.load::<Word>(&mut shared, 0x1000) == 0xffffffff);
+
+    assert!(shared.take_error().is_none());
+}
+
+#[test]
+fn dtl_registers() {
+    use memory::{Byte, HalfWord, Word};
+
+    let mut shared = SharedState::new();
+    let mut exp2 = Expansion2::new();
+
+    exp2.set_dtl_switches(Some(0x0c));
+    assert!(exp2.load::<Byte>(&mut shared, 0x40) == 0x0c);
+    assert!(exp2.peek(0x40) == 0x0c);
+
+    // The POST and LED registers are write only
+    assert!(exp2.load::<Word>(&mut shared, 0x40) == 0xffffff0c);
+
+    exp2.store::<Byte>(&mut shared, 0x41, 0x0f);
+    assert!(exp2.post() == 0x0f);
+
+    // A wider store spans several registers
+    exp2.store::<HalfWord>(&mut shared, 0x41, 0xa510);
+    assert!(exp2.post() == 0x10);
+    assert!(exp2.led() == 0xa5);
+
+    exp2.set_dtl_switches(None);
+    assert!(exp2.load::<Byte>(&mut shared, 0x40) == 0xff);
+
+    assert!(shared.take_error().is_none());
+}
+
+#[test]
+fn uart_registers() {
+    use memory::{Byte, Word};
+
+    let mut shared = SharedState::new();
+    let mut exp2 = Expansion2::new();
+
+    // Tx ready
+    assert!(exp2.load::<Byte>(&mut shared, 0x21) == 0x04);
+    assert!(exp2.peek(0x21) == 0x04);
+    assert!(exp2.peek(0x2f) == 0);
+
+    for &c in b"ok\n" {
+        exp2.store::<Byte>(&mut shared, 0x23, c as u32);
+    }
+
+    exp2.store::<Byte>(&mut shared, 0x25, 0);
+    assert!(shared.take_error().is_none());
+
+    // Only byte accesses and a disabled interrupt mask are supported
+    exp2.load::<Word>(&mut shared, 0x20);
+    assert!(shared.take_error().is_some());
+
+    exp2.store::<Byte>(&mut shared, 0x25, 1);
+    assert!(shared.take_error().is_some());
+}
@@ -0,0 +1,93 @@
+//! Expansion region 3 (0x1fa00000). Nothing is connected there on
+//! retail consoles, development hardware can use it for extra ROM or
+//! I/O.
+
+use rustc_serialize::{Decodable, Encodable, Decoder, Encoder};
+
+use memory::Addressable;
+use shared::SharedState;
+
+pub struct Expansion3 {
+    module: Box<Expansion3Module>,
+}
+
+impl Expansion3 {
+
+    pub fn disconnected() -> Expansion3 {
+        Expansion3 {
+            module: Box::new(Disconnected),
+        }
+    }
+
+    pub fn set_module(&mut self, module: Box<Expansion3Module>) {
+        self.module = module;
+    }
+
+    pub fn load<T: Addressable>(&mut self,
+                                shared: &mut SharedState,
+                                offset: u32) -> u32 {
+        let mut r = 0;
+
+        for i in 0..T::size() {
+            let b = self.module.load(shared, offset + i as u32);
+
+            r |= (b as u32) << (8 * i);
+        }
+
+        r
+    }
+
+    pub fn store<T: Addressable>(&mut self,
+                                 shared: &mut SharedState,
+                                 offset: u32,
+                                 val: u32) {
+        for i in 0..T::size() {
+            let b = (val >> (8 * i)) as u8;
+
+            self.module.store(shared, offset + i as u32, b);
+        }
+    }
+}
+
+impl Encodable for Expansion3 {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        // Like the parallel I/O modules the expansion 3 modules can
+        // be defined outside of this crate so they're not stored in
+        // the savestate
+        s.emit_nil()
+    }
+}
+
+impl Decodable for Expansion3 {
+    fn decode<D: Decoder>(d: &mut D) -> Result<Expansion3, D::Error> {
+        try!(d.read_nil());
+
+        Ok(Expansion3::disconnected())
+    }
+}
+
+/// Interface implemented by the devices connected to the expansion 3
+/// region
+pub trait Expansion3Module {
+    /// Load 8bits at offset `offset` (within the expansion 3 memory
+    /// region)
+    fn load(&mut self, shared: &mut SharedState, offset: u32) -> u8;
+
+    /// Byte store at offset `offset` (within the expansion 3 memory
+    /// region)
+    fn store(&mut self, shared: &mut SharedState, offset: u32, val: u8);
+}
+
+/// Empty expansion 3 region
+pub struct Disconnected;
+
+impl Expansion3Module for Disconnected {
+    fn load(&mut self, _: &mut SharedState, _: u32) -> u8 {
+        // Open bus
+        !0
+    }
+
+    fn store(&mut self, _: &mut SharedState, _: u32, _: u8) {
+        // NOP
+    }
+}
@@ -1,3 +1,5 @@
+use std::cell::Cell;
+
 use rustc_serialize::{Decodable, Encodable, Decoder, Encoder};
 
 use memory::Addressable;
@@ -5,6 +7,7 @@ use memory::timers::Timers;
 use shared::SharedState;
 use interrupt::Interrupt;
 use timekeeper::{Peripheral, Cycles, FracCycles};
+use error::Device;
 
 use self::renderer::{Renderer, Vertex, PrimitiveAttributes};
 use self::renderer::{BlendMode, SemiTransparencyMode, TextureDepth};
@@ -205,8 +208,10 @@ impl Gpu {
 
     /// Return the current phase of the GPU dotclock relative to the
     /// CPU clock
-    pub fn dotclock_phase(&self) -> FracCycles {
-        panic!("GPU dotclock phase not implemented");
+    pub fn dotclock_phase(&self, shared: &mut SharedState) -> FracCycles {
+        fatal!(shared, Device::Gpu, "GPU dotclock phase not implemented");
+
+        FracCycles::from_cycles(0)
     }
 
     /// Return the period of the HSync signal in CPU clock periods
@@ -382,7 +387,8 @@ impl Gpu {
                                 offset: u32) -> u32 {
 
         if T::size() != 4 {
-            panic!("Unhandled GPU load ({})", T::size());
+            fatal!(shared, Device::Gpu, "Unhandled GPU load ({})", T::size());
+            return 0;
         }
 
         self.sync(shared);
@@ -397,6 +403,15 @@ impl Gpu {
         r
     }
 
+    /// Register value without side effects, used for debugging
+    pub fn peek(&self, offset: u32) -> u32 {
+        match offset {
+            0 => self.read_word,
+            4 => self.status(),
+            _ => 0,
+        }
+    }
+
     pub fn store<T: Addressable>(&mut self,
                                  shared: &mut SharedState,
                                  renderer: &mut Renderer,
@@ -405,21 +420,25 @@ impl Gpu {
                                  val: u32) {
 
         if T::size() != 4 {
-            panic!("Unhandled GPU load ({})", T::size());
+            fatal!(shared, Device::Gpu, "Unhandled GPU store ({})", T::size());
+            return;
         }
 
         self.sync(shared);
 
         match offset {
-            0 => self.gp0(renderer, val),
+            0 => self.gp0(shared, renderer, val),
             4 => self.gp1(shared, renderer, val, timers),
             _ => unreachable!(),
         }
     }
 
     /// Dispatch to the current GP0 handler method
-    pub fn gp0(&mut self, renderer: &mut Renderer, val: u32) {
-        (self.gp0_handler)(self, renderer, val);
+    pub fn gp0(&mut self,
+               shared: &mut SharedState,
+               renderer: &mut Renderer,
+               val: u32) {
+        (self.gp0_handler)(self, shared, renderer, val);
     }
 
     /// Retrieve value of the status register
@@ -488,8 +507,11 @@ impl Gpu {
     }
 
     /// GP0 handler method: handle a command word
-    fn gp0_handle_command(&mut self, renderer: &mut Renderer, val: u32) {
-        let (len, attributes) = self.gp0_parse_command(val);
+    fn gp0_handle_command(&mut self,
+                          shared: &mut SharedState,
+                          renderer: &mut Renderer,
+                          val: u32) {
+        let (len, attributes) = self.gp0_parse_command(shared, val);
 
         self.gp0_words_remaining = len;
         self.gp0_attributes = attributes;
@@ -498,11 +520,14 @@ impl Gpu {
         *self.gp0_handler = Gpu::gp0_handle_parameter;
 
         // Call the parameter handling function for the current word
-        self.gp0_handle_parameter(renderer, val);
+        self.gp0_handle_parameter(shared, renderer, val);
     }
 
     /// GP0 handler method: handle a command parameter
-    fn gp0_handle_parameter(&mut self, renderer: &mut Renderer, val: u32) {
+    fn gp0_handle_parameter(&mut self,
+                            shared: &mut SharedState,
+                            renderer: &mut Renderer,
+                            val: u32) {
         self.gp0_command.push_word(val);
         self.gp0_words_remaining -= 1;
 
@@ -517,11 +542,23 @@ impl Gpu {
             // certain cases, for instance for image load commands.
             *self.gp0_handler = Gpu::gp0_handle_command;
             (self.gp0_attributes.callback)(self, renderer);
+
+            if let Some(index) = self.gp0_command.overrun.get() {
+                self.gp0_command.overrun.set(None);
+
+                fatal!(shared, Device::Gpu,
+                       "GP0 command 0x{:08x} used parameter {} out of {}",
+                       self.gp0_command.buffer[0], index,
+                       self.gp0_command.len);
+            }
         }
     }
 
     /// GP0 handler method: handle shaded polyline color word
-    fn gp0_handle_shaded_polyline_color(&mut self, _: &mut Renderer, val: u32) {
+    fn gp0_handle_shaded_polyline_color(&mut self,
+                                        _: &mut SharedState,
+                                        _: &mut Renderer,
+                                        val: u32) {
         *self.gp0_handler =
             if is_polyline_end_marker(val) {
                 // We found the end-of-polyline marker, we're done.
@@ -537,6 +574,7 @@ impl Gpu {
 
     /// GP0 handler method: handle shaded polyline vertex word
     fn gp0_handle_shaded_polyline_vertex(&mut self,
+                                         _: &mut SharedState,
                                          renderer: &mut Renderer,
                                          val: u32) {
         // We don't test for the end-of-polyline marker here because
@@ -566,6 +604,7 @@ impl Gpu {
 
     /// GP0 handler method: handle monochrome polyline position word
     fn gp0_handle_monochrome_polyline_vertex(&mut self,
+                                             _: &mut SharedState,
                                              renderer: &mut Renderer,
                                              val: u32) {
         if is_polyline_end_marker(val) {
@@ -593,7 +632,9 @@ impl Gpu {
 
 
     /// Parse GP0 command and return its length in words and attributes
-    fn gp0_parse_command(&self, gp0: u32) -> (u32, Gp0Attributes) {
+    fn gp0_parse_command(&self,
+                         shared: &mut SharedState,
+                         gp0: u32) -> (u32, Gp0Attributes) {
         let opcode = gp0 >> 24;
 
         let dither = self.dither();
@@ -658,7 +699,12 @@ impl Gpu {
                 0xe4 => (1,  Gpu::gp0_drawing_area_bottom_right, false),
                 0xe5 => (1,  Gpu::gp0_drawing_offset, false),
                 0xe6 => (1,  Gpu::gp0_mask_bit_setting, false),
-                _    => panic!("Unhandled GP0 command {:08x}", gp0),
+                _    => {
+                    fatal!(shared, Device::Gpu,
+                           "Unhandled GP0 command {:08x}", gp0);
+                    // Treat the command as a NOP
+                    (1, Gpu::gp0_nop, false)
+                }
             };
 
         let textured = opcode & 0x4 != 0;
@@ -1126,7 +1172,10 @@ impl Gpu {
     }
 
     /// GP0 handler method: handle image load
-    fn gp0_handle_image_load(&mut self, renderer: &mut Renderer, word: u32) {
+    fn gp0_handle_image_load(&mut self,
+                             _: &mut SharedState,
+                             renderer: &mut Renderer,
+                             word: u32) {
         self.load_buffer.push_gp0_word(word);
 
         self.gp0_words_remaining -= 1;
@@ -1258,8 +1307,9 @@ impl Gpu {
                 timers.video_timings_changed(shared, self);
                 self.update_display_mode(renderer);
             }
-            0x10 => self.gp1_get_info(val),
-            _    => panic!("Unhandled GP1 command {:08x}", val),
+            0x10 => self.gp1_get_info(shared, val),
+            _    => fatal!(shared, Device::Gpu,
+                           "Unhandled GP1 command {:08x}", val),
         }
     }
 
@@ -1374,7 +1424,7 @@ impl Gpu {
     }
 
     /// Return various GPU state information in the GPUREAD register
-    fn gp1_get_info(&mut self, val: u32) {
+    fn gp1_get_info(&mut self, shared: &mut SharedState, val: u32) {
         // XXX what happens if we're in the middle of a framebuffer
         // read?
         let v =
@@ -1401,7 +1451,11 @@ impl Gpu {
                 }
                 // GPU version. Seems to always be 2?
                 7 => 2,
-                _ => panic!("Unsupported GP1 info command {:08x}", val),
+                _ => {
+                    fatal!(shared, Device::Gpu,
+                           "Unsupported GP1 info command {:08x}", val);
+                    return;
+                }
             };
 
         self.read_word = v;
@@ -1439,7 +1493,8 @@ impl Gpu {
         self.field = Field::Top;
 
         if val & 0x80 != 0 {
-            panic!("Unsupported display mode {:08x}", val);
+            fatal!(shared, Device::Gpu,
+                   "Unsupported display mode {:08x}", val);
         }
 
         self.sync(shared);
@@ -1448,7 +1503,10 @@ impl Gpu {
 
 /// Wrapper around the `gp0_handler` function pointer in order to be
 /// able to serialize it
-callback!(struct Gp0Handler(fn (&mut Gpu, &mut Renderer, u32)) {
+callback!(struct Gp0Handler(fn (&mut Gpu,
+                                &mut SharedState,
+                                &mut Renderer,
+                                u32)) {
     Gpu::gp0_handle_command,
     Gpu::gp0_handle_parameter,
     Gpu::gp0_handle_shaded_polyline_vertex,
@@ -1596,6 +1654,10 @@ struct CommandBuffer {
     buffer: [u32; 12],
     /// Number of words queued in buffer
     len:    u8,
+    /// Set to the index of the first parameter accessed past `len`
+    /// by a command handler. Reported as an error once the command
+    /// returns.
+    overrun: Cell<Option<u8>>,
 }
 
 impl CommandBuffer {
@@ -1603,6 +1665,7 @@ impl CommandBuffer {
         CommandBuffer {
             buffer: [0; 12],
             len:    0,
+            overrun: Cell::new(None),
         }
     }
 
@@ -1622,9 +1685,14 @@ impl ::std::ops::Index<usize> for CommandBuffer {
     type Output = u32;
 
     fn index<'a>(&'a self, index: usize) -> &'a u32 {
+        static MISSING: u32 = 0;
+
         if index >= self.len as usize {
-            panic!("Command buffer index out of range: {} ({})",
-                   index, self.len);
+            if self.overrun.get().is_none() {
+                self.overrun.set(Some(index as u8));
+            }
+
+            return &MISSING;
         }
 
         &self.buffer[index]
@@ -54,7 +54,10 @@ impl InterruptState {
         self.mask
     }
 
-    pub fn set_mask(&mut self, mask: u16) {
+    /// Set the interrupt mask. Returns `Err` with the offending bits
+    /// if an unsupported interrupt is unmasked, in which case the
+    /// mask is left untouched.
+    pub fn set_mask(&mut self, mask: u16) -> Result<(), u16> {
         // Temporary hack: trigger an error if a non-implemented
         // interrupt is requested
         let supported = [ Interrupt::VBlank,
@@ -69,10 +72,12 @@ impl InterruptState {
                                         |mask, &it| mask & !(1 << it as u16));
 
         if rem != 0 {
-            panic!("Unsupported interrupt: {:04x}", rem);
+            return Err(rem);
         }
 
         self.mask = mask;
+
+        Ok(())
     }
 
     /// Trigger the interrupt `which`, must be called on the rising
@@ -9,6 +9,8 @@ extern crate rustc_serialize;
 mod box_array;
 #[macro_use]
 mod serializer;
+#[macro_use]
+pub mod error;
 
 pub mod gpu;
 pub mod cdrom;
@@ -21,12 +23,19 @@ pub mod debugger;
 pub mod assembler;
 pub mod parallel_io;
 pub mod debug_uart;
+pub mod expansion_2;
+pub mod expansion_3;
+pub mod profiler;
+pub mod cheats;
 
 mod interrupt;
 mod timekeeper;
 mod spu;
 mod mdec;
 
+#[cfg(test)]
+mod test_utils;
+
 /// Version of the rustation library set in Cargo.toml
 pub const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
@@ -1,5 +1,6 @@
 use memory::Addressable;
 use shared::SharedState;
+use error::Device;
 
 /// Motion Decoder (sometimes called macroblock or movie decoder).
 #[derive(RustcDecodable, RustcEncodable)]
@@ -45,33 +46,49 @@ impl MDec {
     }
 
     pub fn load<T: Addressable>(&mut self,
-                                 _: &mut SharedState,
+                                 shared: &mut SharedState,
                                  offset: u32) -> u32 {
 
         if T::size() != 4 {
-            panic!("Unhandled MDEC load ({})", T::size());
+            fatal!(shared, Device::MDec,
+                   "Unhandled MDEC load ({})", T::size());
+            return 0;
         }
 
         match offset {
             4 => self.status(),
-            _ => panic!("Unhandled MDEC load: {:08x}", offset),
+            _ => {
+                fatal!(shared, Device::MDec,
+                       "Unhandled MDEC load: {:08x}", offset);
+                0
+            }
         }
     }
 
+    /// Register value without side effects, used for debugging
+    pub fn peek(&self, offset: u32) -> u32 {
+        match offset {
+            4 => self.status(),
+            _ => 0,
+        }
+    }
 
     pub fn store<T: Addressable>(&mut self,
-                                 _: &mut SharedState,
+                                 shared: &mut SharedState,
                                  offset: u32,
                                  val: u32) {
 
         if T::size() != 4 {
-            panic!("Unhandled MDEC store ({})", T::size());
+            fatal!(shared, Device::MDec,
+                   "Unhandled MDEC store ({})", T::size());
+            return;
         }
 
         match offset {
-            0 => self.command(val),
+            0 => self.command(shared, val),
             4 => self.set_control(val),
-            _ => panic!("Unhandled MDEC store: {:08x} {:08x}", offset, val),
+            _ => fatal!(shared, Device::MDec,
+                        "Unhandled MDEC store: {:08x} {:08x}", offset, val),
         }
     }
 
@@ -108,10 +125,10 @@ impl MDec {
     }
 
     /// Handle writes to the command register
-    pub fn command(&mut self, cmd: u32) {
+    pub fn command(&mut self, shared: &mut SharedState, cmd: u32) {
         self.command_remaining -= 1;
 
-        (self.command_handler)(self, cmd);
+        (self.command_handler)(self, shared, cmd);
 
         if self.command_remaining == 0 {
             *self.command_handler = MDec::handle_command;
@@ -119,7 +136,7 @@ impl MDec {
         }
     }
 
-    fn handle_command(&mut self, cmd: u32) {
+    fn handle_command(&mut self, shared: &mut SharedState, cmd: u32) {
         let opcode = cmd >> 29;
 
         // Those internal variables (accessible through the status
@@ -137,7 +154,7 @@ impl MDec {
         self.output_signed = (cmd >> 26) & 1 != 0;
         self.output_bit15 = (cmd >> 25) & 1 != 0;
 
-        let (len, handler): (u16, fn(&mut MDec, u32)) =
+        let (len, handler): (u16, fn(&mut MDec, &mut SharedState, u32)) =
             match opcode {
                 // Set quantization matrices. Bit 0 tells us whether we're
                 // setting only the luma table or luma + chroma.
@@ -146,14 +163,18 @@ impl MDec {
                     false => (16, MDec::handle_monochrome_quant_matrix),
                 },
                 3 => (32, MDec::handle_idct_matrix),
-                n => panic!("Unsupported MDEC opcode {} ({:08x})", n, cmd),
+                n => {
+                    fatal!(shared, Device::MDec,
+                           "Unsupported MDEC opcode {} ({:08x})", n, cmd);
+                    return;
+                }
             };
 
         self.command_remaining = len;
         *self.command_handler = handler;
     }
 
-    fn handle_color_quant_matrices(&mut self, cmd: u32) {
+    fn handle_color_quant_matrices(&mut self, _: &mut SharedState, cmd: u32) {
         let index = (31 - self.command_remaining) as usize;
 
         let matrix = index / 16;
@@ -166,7 +187,9 @@ impl MDec {
         }
     }
 
-    fn handle_monochrome_quant_matrix(&mut self, cmd: u32) {
+    fn handle_monochrome_quant_matrix(&mut self,
+                                      _: &mut SharedState,
+                                      cmd: u32) {
         let index = (15 - self.command_remaining) as usize;
 
         let index = index * 4;
@@ -178,7 +201,7 @@ impl MDec {
         }
     }
 
-    fn handle_idct_matrix(&mut self, cmd: u32) {
+    fn handle_idct_matrix(&mut self, _: &mut SharedState, cmd: u32) {
         let index = (31 - self.command_remaining) as usize;
 
         let index = index * 2;
@@ -209,7 +232,7 @@ impl MDec {
     }
 }
 
-callback!(struct CommandHandler(fn (&mut MDec, u32)) {
+callback!(struct CommandHandler(fn (&mut MDec, &mut SharedState, u32)) {
     MDec::handle_command,
     MDec::handle_color_quant_matrices,
     MDec::handle_monochrome_quant_matrix,
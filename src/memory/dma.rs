@@ -1,5 +1,6 @@
 use shared::SharedState;
 use interrupt::Interrupt;
+use error::Device;
 
 /// Direct Memory Access
 #[derive(RustcDecodable, RustcEncodable)]
@@ -204,7 +205,7 @@ impl Channel {
     }
 
     /// Set the value of the control register
-    pub fn set_control(&mut self, val: u32) {
+    pub fn set_control(&mut self, shared: &mut SharedState, val: u32) {
 
         self.direction = match val & 1 != 0 {
             true  => Direction::FromRam,
@@ -222,7 +223,10 @@ impl Channel {
             0 => Sync::Manual,
             1 => Sync::Request,
             2 => Sync::LinkedList,
-            n => panic!("Unknown DMA sync mode {}", n),
+            n => {
+                fatal!(shared, Device::Dma, "Unknown DMA sync mode {}", n);
+                Sync::Manual
+            }
         };
 
         self.chop_dma_sz = ((val >> 16) & 7) as u8;
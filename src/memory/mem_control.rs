@@ -0,0 +1,321 @@
+//! Memory control registers: base addresses of the expansion regions
+//! and access timings of the devices connected to the external bus.
+
+use timekeeper::Cycles;
+use shared::SharedState;
+use error::Device;
+use super::{Addressable, map};
+
+/// Regions whose timings are configured in the memory control
+/// registers. The value is the index of the region's "delay/size"
+/// register.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Region {
+    Expansion1 = 2,
+    Expansion3 = 3,
+    Bios = 4,
+    Spu = 5,
+    CdRom = 6,
+    Expansion2 = 7,
+}
+
+impl Region {
+    /// Return the region containing `abs_addr` if it's on the
+    /// external bus
+    pub fn from_address(abs_addr: u32) -> Option<Region> {
+        if map::BIOS.contains(abs_addr).is_some() {
+            Some(Region::Bios)
+        } else if map::EXPANSION_1.contains(abs_addr).is_some() {
+            Some(Region::Expansion1)
+        } else if map::SPU.contains(abs_addr).is_some() {
+            Some(Region::Spu)
+        } else if map::CDROM.contains(abs_addr).is_some() {
+            Some(Region::CdRom)
+        } else if map::EXPANSION_2.contains(abs_addr).is_some() {
+            Some(Region::Expansion2)
+        } else if map::EXPANSION_3.contains(abs_addr).is_some() {
+            Some(Region::Expansion3)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct MemControl {
+    /// Raw register values:
+    ///
+    /// * 0: Expansion 1 base address
+    /// * 1: Expansion 2 base address
+    /// * 2 to 7: delay/size of each `Region`
+    /// * 8: common delay
+    regs: [u32; 9],
+    /// Timings of each region, updated when the registers change.
+    /// Indexed by `Region as usize - 2`.
+    timings: [AccessTimings; 6],
+}
+
+impl MemControl {
+    pub fn new() -> MemControl {
+        let mut mc = MemControl {
+            // Values set by the BIOS on boot
+            regs: [0x1f000000,
+                   0x1f802000,
+                   0x0013243f,
+                   0x00003022,
+                   0x0013243f,
+                   0x200931e1,
+                   0x00020843,
+                   0x00070777,
+                   0x00031125],
+            timings: [AccessTimings::new(); 6],
+        };
+
+        mc.update_timings();
+
+        mc
+    }
+
+    pub fn load<T: Addressable>(&self,
+                                shared: &mut SharedState,
+                                offset: u32) -> u32 {
+        if T::size() != 4 {
+            fatal!(shared, Device::Interconnect,
+                   "Unhandled MEM_CONTROL access ({})", T::size());
+        }
+
+        self.regs[(offset >> 2) as usize]
+    }
+
+    /// Register value without side effects, used for debugging
+    pub fn peek(&self, offset: u32) -> u32 {
+        self.regs[(offset >> 2) as usize]
+    }
+
+    pub fn store<T: Addressable>(&mut self,
+                                 shared: &mut SharedState,
+                                 offset: u32,
+                                 val: u32) {
+        if T::size() != 4 {
+            fatal!(shared, Device::Interconnect,
+                   "Unhandled MEM_CONTROL access ({})", T::size());
+            return;
+        }
+
+        match offset {
+            0 => // Expansion 1 base address
+                if val != 0x1f000000 {
+                    fatal!(shared, Device::Interconnect,
+                           "Bad expansion 1 base address: 0x{:08x}", val);
+                },
+            4 => // Expansion 2 base address
+                if val != 0x1f802000 {
+                    fatal!(shared, Device::Interconnect,
+                           "Bad expansion 2 base address: 0x{:08x}", val);
+                },
+            _ => (),
+        }
+
+        self.regs[(offset >> 2) as usize] = val;
+
+        self.update_timings();
+    }
+
+    /// Number of CPU cycles taken by a read of type `T` in `region`
+    pub fn read_timing<T: Addressable>(&self, region: Region) -> Cycles {
+        self.timings[region as usize - 2].read[size_index::<T>()]
+    }
+
+    /// Number of CPU cycles taken by a write of type `T` in `region`
+    pub fn write_timing<T: Addressable>(&self, region: Region) -> Cycles {
+        self.timings[region as usize - 2].write[size_index::<T>()]
+    }
+
+    fn update_timings(&mut self) {
+        let com_delay = self.regs[8];
+
+        for (i, t) in self.timings.iter_mut().enumerate() {
+            *t = AccessTimings::from_registers(self.regs[i + 2], com_delay);
+        }
+    }
+}
+
+/// Cycles taken by byte, halfword and word accesses
+#[derive(Clone, Copy, RustcDecodable, RustcEncodable)]
+struct AccessTimings {
+    read: [Cycles; 3],
+    write: [Cycles; 3],
+}
+
+impl AccessTimings {
+    fn new() -> AccessTimings {
+        AccessTimings {
+            read: [0; 3],
+            write: [0; 3],
+        }
+    }
+
+    /// Compute the timings from a region's delay/size register and
+    /// the common delay register. The delay/size register layout is:
+    ///
+    /// * [3:0]: write delay
+    /// * [7:4]: read delay
+    /// * 8: add the "recovery" period (COM0)
+    /// * 9: add the "hold" period (COM1)
+    /// * 10: add the "floating" period (COM2)
+    /// * 11: add the "pre-strobe" period (COM3)
+    /// * 12: data bus width: 0 for 8bits, 1 for 16bits
+    /// * [20:16]: size of the region (number of address bits)
+    ///
+    /// The common delay register contains the four COM periods in
+    /// 4bit fields.
+    ///
+    /// Accesses wider than the bus are split in several sequential
+    /// accesses, the first one being slower. The formula is the one
+    /// used by duckstation, it seems to match hardware measurements
+    /// reasonably well. The "hold" period is ignored.
+    fn from_registers(delay_size: u32, com_delay: u32) -> AccessTimings {
+        let com = |n: u32| ((com_delay >> (n * 4)) & 0xf) as Cycles;
+
+        let mut first = 0;
+        let mut seq = 0;
+        let mut min = 0;
+
+        if delay_size & (1 << 8) != 0 {
+            first += com(0).saturating_sub(1);
+            seq += com(0).saturating_sub(1);
+        }
+
+        if delay_size & (1 << 10) != 0 {
+            first += com(2);
+            seq += com(2);
+        }
+
+        if delay_size & (1 << 11) != 0 {
+            min = com(3);
+        }
+
+        if first < 6 {
+            first += 1;
+        }
+
+        let bus_16bits = delay_size & (1 << 12) != 0;
+
+        let timings = |delay: Cycles| {
+            let first = ::std::cmp::max(first + delay + 2, min + 6);
+            let seq = ::std::cmp::max(seq + delay + 2, min + 2);
+
+            if bus_16bits {
+                [first, first, first + seq]
+            } else {
+                [first, first + seq, first + 3 * seq]
+            }
+        };
+
+        AccessTimings {
+            read: timings(((delay_size >> 4) & 0xf) as Cycles),
+            write: timings((delay_size & 0xf) as Cycles),
+        }
+    }
+}
+
+/// Index in the `AccessTimings` arrays for an access of type `T`
+fn size_index<T: Addressable>() -> usize {
+    match T::size() {
+        1 => 0,
+        2 => 1,
+        _ => 2,
+    }
+}
+
+#[test]
+fn bus_timings() {
+    use super::{Byte, HalfWord, Word};
+
+    let mc = MemControl::new();
+
+    // 8bit BIOS bus: a word read is split in four byte reads
+    assert!(mc.read_timing::<Byte>(Region::Bios) == 7);
+    assert!(mc.read_timing::<HalfWord>(Region::Bios) == 13);
+    assert!(mc.read_timing::<Word>(Region::Bios) == 25);
+
+    // 16bit SPU bus
+    assert!(mc.read_timing::<HalfWord>(Region::Spu) == 21);
+    assert!(mc.read_timing::<Word>(Region::Spu) == 41);
+}
+
+#[test]
+fn bus_timings_update() {
+    use super::{Byte, HalfWord, Word};
+
+    let mut mc = MemControl::new();
+    let mut shared = SharedState::new();
+
+    let spu = mc.read_timing::<Word>(Region::Spu);
+
+    // 16bit BIOS bus without any delay
+    mc.store::<Word>(&mut shared, 0x10, 0x00001000);
+
+    assert!(mc.peek(0x10) == 0x00001000);
+    assert!(mc.read_timing::<Byte>(Region::Bios) == 6);
+    assert!(mc.read_timing::<HalfWord>(Region::Bios) == 6);
+    assert!(mc.read_timing::<Word>(Region::Bios) == 8);
+    assert!(mc.write_timing::<Word>(Region::Bios) == 8);
+
+    // Read delay of 5 and pre-strobe period (COM3)
+    mc.store::<Word>(&mut shared, 0x10, 0x00001850);
+
+    assert!(mc.read_timing::<HalfWord>(Region::Bios) == 9);
+    assert!(mc.read_timing::<Word>(Region::Bios) == 16);
+    assert!(mc.write_timing::<Word>(Region::Bios) == 14);
+
+    // The common delay register affects all the regions, here we
+    // remove the pre-strobe period and shorten the recovery
+    mc.store::<Word>(&mut shared, 0x20, 0x00001123);
+
+    assert!(mc.read_timing::<Word>(Region::Bios) == 15);
+    assert!(mc.write_timing::<Word>(Region::Bios) == 8);
+    assert!(mc.read_timing::<Word>(Region::Spu) != spu);
+
+    // Recovery period (COM0)
+    mc.store::<Word>(&mut shared, 0x10, 0x00001100);
+
+    assert!(mc.read_timing::<HalfWord>(Region::Bios) == 6);
+    assert!(mc.read_timing::<Word>(Region::Bios) == 10);
+
+    assert!(shared.take_error().is_none());
+}
+
+#[test]
+fn bad_mem_control_stores() {
+    use super::{HalfWord, Word};
+
+    let mut mc = MemControl::new();
+    let mut shared = SharedState::new();
+
+    // Only word accesses are supported
+    mc.store::<HalfWord>(&mut shared, 0x10, 0x1000);
+
+    assert!(shared.take_error().is_some());
+    assert!(mc.peek(0x10) == 0x0013243f);
+    assert!(mc.read_timing::<Word>(Region::Bios) == 25);
+
+    // The expansion regions can't be relocated
+    mc.store::<Word>(&mut shared, 0, 0x1f100000);
+    assert!(shared.take_error().is_some());
+
+    mc.store::<Word>(&mut shared, 4, 0x1f802000);
+    assert!(shared.take_error().is_none());
+}
+
+#[test]
+fn bus_regions() {
+    assert!(Region::from_address(0x1fc00100) == Some(Region::Bios));
+    assert!(Region::from_address(0x1f000000) == Some(Region::Expansion1));
+    assert!(Region::from_address(0x1f801c00) == Some(Region::Spu));
+    assert!(Region::from_address(0x1f801800) == Some(Region::CdRom));
+    assert!(Region::from_address(0x1f802041) == Some(Region::Expansion2));
+    assert!(Region::from_address(0x1fa00000) == Some(Region::Expansion3));
+    assert!(Region::from_address(0x1f801070) == None);
+    assert!(Region::from_address(0x00001000) == None);
+}
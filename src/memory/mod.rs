@@ -1,14 +1,18 @@
 pub mod timers;
 mod ram;
 mod dma;
+mod mem_control;
+pub mod search;
 
-use self::ram::{Ram, ScratchPad};
+pub use self::ram::{Ram, RamSize};
+use self::ram::ScratchPad;
 use self::dma::{Dma, Port, Direction, Step, Sync};
 use self::timers::Timers;
+use self::mem_control::{MemControl, Region};
 
 use shared::SharedState;
 use bios::Bios;
-use timekeeper::Peripheral;
+use timekeeper::{Peripheral, Cycles};
 use gpu::Gpu;
 use gpu::renderer::Renderer;
 use spu::Spu;
@@ -17,7 +21,9 @@ use cdrom::disc::Disc;
 use padmemcard::PadMemCard;
 use mdec::MDec;
 use parallel_io::ParallelIo;
-use debug_uart::DebugUart;
+use expansion_2::Expansion2;
+use expansion_3::Expansion3;
+use error::{EmulationError, Device};
 
 /// Global interconnect
 #[derive(RustcDecodable, RustcEncodable)]
@@ -44,15 +50,20 @@ pub struct Interconnect {
     pad_memcard: PadMemCard,
     /// Motion decoder
     mdec: MDec,
-    /// Contents of the RAM_SIZE register which is probably a
-    /// configuration register for the memory controller.
+    /// Contents of the RAM_SIZE register, configures the RAM window
+    /// in the first 8MB of the address space
     ram_size: u32,
     /// Memory control registers
-    mem_control: [u32; 9],
+    mem_control: MemControl,
     /// Parallel I/O
     parallel_io: ParallelIo,
-    /// Debug UART
-    debug_uart: DebugUart,
+    /// Expansion 2: debug UART and POST registers
+    expansion_2: Expansion2,
+    /// Expansion 3
+    expansion_3: Expansion3,
+    /// RAM ranges written by DMA transfers since the last call to
+    /// `take_dma_ram_writes`, as `(address, length)`
+    dma_ram_writes: Vec<(u32, u32)>,
 }
 
 impl Interconnect {
@@ -71,10 +82,14 @@ impl Interconnect {
             cdrom: CdRom::new(disc),
             pad_memcard: PadMemCard::new(),
             mdec: MDec::new(),
-            ram_size: 0,
-            mem_control: [0; 9],
+            // Value set by the BIOS on boot: 8MB window, the RAM is
+            // mirrored if it's smaller than that
+            ram_size: 0xb88,
+            mem_control: MemControl::new(),
             parallel_io: ParallelIo::disconnected(),
-            debug_uart: DebugUart::new(),
+            expansion_2: Expansion2::new(),
+            expansion_3: Expansion3::disconnected(),
+            dma_ram_writes: Vec::new(),
         }
     }
 
@@ -119,6 +134,52 @@ impl Interconnect {
         self.bios = bios
     }
 
+    /// Move the state that isn't stored in savestates (BIOS image,
+    /// disc, parallel I/O and expansion 3 modules and gamepad
+    /// profiles) from `other` to `self`. Used when loading
+    /// savestates taken from `other`.
+    pub fn take_unserialized(&mut self, other: &mut Interconnect) {
+        ::std::mem::swap(&mut self.bios, &mut other.bios);
+        ::std::mem::swap(&mut self.parallel_io, &mut other.parallel_io);
+        ::std::mem::swap(&mut self.expansion_3, &mut other.expansion_3);
+
+        let disc = other.cdrom.remove_disc();
+        self.cdrom.set_disc(disc);
+
+        self.pad_memcard.swap_profiles(&mut other.pad_memcard);
+    }
+
+    /// Replace the RAM with `size` bytes of uninitialized
+    /// memory. Meant to be called before starting the emulation
+    /// since the RAM contents are lost. The size is stored in
+    /// savestates.
+    pub fn set_ram_size(&mut self, size: RamSize) {
+        self.ram = Ram::with_size(size);
+    }
+
+    /// Translate an `offset` in the RAM region into an offset in the
+    /// RAM based on the window configured in the RAM_SIZE
+    /// register. Returns `None` if the offset is in one of the
+    /// "locked" or "high-Z" parts of the window.
+    fn ram_offset(&self, offset: u32) -> Option<u32> {
+        // Size of the RAM window for each configuration. I only
+        // handle the part of the window mapped to the first RAM
+        // bank, the RAM itself is mirrored within the window if it's
+        // smaller.
+        let window = match (self.ram_size >> 9) & 7 {
+            0 | 2 => 1024 * 1024,
+            1 | 3 => 4 * 1024 * 1024,
+            4 | 6 => 2 * 1024 * 1024,
+            _ => 8 * 1024 * 1024,
+        };
+
+        if offset < window {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
     /// Return a reference to the Ram instance
     pub fn ram(&self) -> &Ram {
         &self.ram
@@ -144,16 +205,214 @@ impl Interconnect {
         &mut self.parallel_io
     }
 
+    /// Return a mutable reference to the expansion 2 registers
+    pub fn expansion_2_mut(&mut self) -> &mut Expansion2 {
+        &mut self.expansion_2
+    }
+
+    /// Return a mutable reference to the expansion 3 interface
+    pub fn expansion_3_mut(&mut self) -> &mut Expansion3 {
+        &mut self.expansion_3
+    }
+
+    /// Read memory or a register without any side effect, used for
+    /// debugging. Time doesn't advance and the peripherals aren't
+    /// synchronized so the registers are a snapshot of their state
+    /// at the last sync. Returns `None` if `addr` isn't mapped or is
+    /// in expansion region 1 or 3 since their modules can't be read
+    /// without side effects.
+    pub fn peek<T: Addressable>(&self,
+                                shared: &SharedState,
+                                addr: u32) -> Option<u32> {
+        self.peek_irq::<T>(Some(shared), addr)
+    }
+
+    /// Same as `peek` for callers without access to the
+    /// `SharedState`, the interrupt controller registers read as 0.
+    pub fn peek_without_irq<T: Addressable>(&self, addr: u32) -> Option<u32> {
+        self.peek_irq::<T>(None, addr)
+    }
+
+    fn peek_irq<T: Addressable>(&self,
+                                shared: Option<&SharedState>,
+                                addr: u32) -> Option<u32> {
+        let abs_addr = map::mask_region(addr);
+
+        if let Some(offset) = map::RAM.contains(abs_addr) {
+            return self.ram_offset(offset).map(|o| self.ram.load::<T>(o));
+        }
+
+        if let Some(offset) = map::SCRATCH_PAD.contains(abs_addr) {
+            return Some(self.scratch_pad.load::<T>(offset));
+        }
+
+        if let Some(offset) = map::BIOS.contains(abs_addr) {
+            return Some(self.bios.load::<T>(offset));
+        }
+
+        if let Some(offset) = map::EXPANSION_2.contains(abs_addr) {
+            let mut r = 0;
+
+            for i in 0..T::size() as u32 {
+                r |= (self.expansion_2.peek(offset + i) as u32) << (8 * i);
+            }
+
+            return Some(r);
+        }
+
+        // Partial reads return the addressed portion of the
+        // registers, wider reads span several registers
+        let mut r = 0;
+
+        for i in 0..T::size() as u32 {
+            let (register, pos) =
+                match self.peek_register(shared, abs_addr.wrapping_add(i)) {
+                    Some(r) => r,
+                    None => return None,
+                };
+
+            r |= ((register >> (pos * 8)) & 0xff) << (8 * i);
+        }
+
+        Some(r)
+    }
+
+    /// Return the value of the register containing the byte at
+    /// `abs_addr` and the position of that byte in the register, or
+    /// `None` if `abs_addr` isn't a register we can peek
+    fn peek_register(&self,
+                     shared: Option<&SharedState>,
+                     abs_addr: u32) -> Option<(u32, u32)> {
+        if let Some(offset) = map::IRQ_CONTROL.contains(abs_addr) {
+            let register =
+                match shared {
+                    Some(shared) => {
+                        let irq = shared.irq_state_snapshot();
+
+                        match offset & !3 {
+                            0 => irq.status() as u32,
+                            _ => irq.mask() as u32,
+                        }
+                    }
+                    None => 0,
+                };
+
+            return Some((register, offset & 3));
+        }
+
+        if let Some(offset) = map::DMA.contains(abs_addr) {
+            return self.peek_dma(offset & !3).map(|r| (r, offset & 3));
+        }
+
+        if let Some(offset) = map::GPU.contains(abs_addr) {
+            return Some((self.gpu.peek(offset & !3), offset & 3));
+        }
+
+        if let Some(offset) = map::TIMERS.contains(abs_addr) {
+            return Some((self.timers.peek(offset & !3), offset & 3));
+        }
+
+        if let Some(offset) = map::CDROM.contains(abs_addr) {
+            // 8bit registers
+            return Some((self.cdrom.peek(offset), 0));
+        }
+
+        if let Some(offset) = map::MDEC.contains(abs_addr) {
+            return Some((self.mdec.peek(offset & !3), offset & 3));
+        }
+
+        if let Some(offset) = map::SPU.contains(abs_addr) {
+            // 16bit registers
+            return Some((self.spu.peek(offset & !1), offset & 1));
+        }
+
+        if let Some(offset) = map::PAD_MEMCARD.contains(abs_addr) {
+            // The data and status registers are 32bit wide, the rest
+            // are 16bit
+            let pos = if offset < 8 { offset & 3 } else { offset & 1 };
+
+            return Some((self.pad_memcard.peek(offset - pos), pos));
+        }
+
+        if let Some(offset) = map::RAM_SIZE.contains(abs_addr) {
+            return Some((self.ram_size, offset & 3));
+        }
+
+        if let Some(offset) = map::MEM_CONTROL.contains(abs_addr) {
+            return Some((self.mem_control.peek(offset & !3), offset & 3));
+        }
+
+        if let Some(offset) = map::CACHE_CONTROL.contains(abs_addr) {
+            return Some((self.cache_control.0, offset & 3));
+        }
+
+        None
+    }
+
+    /// Write to RAM, the scratchpad or the BIOS without any side
+    /// effect, used for debugging. Returns false if `addr` isn't in
+    /// one of those regions.
+    pub fn poke<T: Addressable>(&mut self, addr: u32, val: u32) -> bool {
+        let abs_addr = map::mask_region(addr);
+
+        if let Some(offset) = map::RAM.contains(abs_addr) {
+            return match self.ram_offset(offset) {
+                Some(offset) => {
+                    self.ram.store::<T>(offset, val);
+                    true
+                }
+                None => false,
+            };
+        }
+
+        if let Some(offset) = map::SCRATCH_PAD.contains(abs_addr) {
+            self.scratch_pad.store::<T>(offset, val);
+            return true;
+        }
+
+        if let Some(offset) = map::BIOS.contains(abs_addr) {
+            self.bios.store::<T>(offset, val);
+            return true;
+        }
+
+        false
+    }
+
+    /// Return true if DMA transfers wrote to RAM since the last call
+    /// to `take_dma_ram_writes`
+    pub fn dma_ram_writes_pending(&self) -> bool {
+        !self.dma_ram_writes.is_empty()
+    }
+
+    /// Return the RAM ranges written by DMA transfers since the last
+    /// call, as `(address, length)` pairs of physical addresses
+    pub fn take_dma_ram_writes(&mut self) -> Vec<(u32, u32)> {
+        ::std::mem::replace(&mut self.dma_ram_writes, Vec::new())
+    }
+
     /// Interconnect: load instruction at `PC`. Only the RAM and BIOS
     /// are supported, would it make sense to fetch instructions from
-    /// anything else?
+    /// anything else? `charged` is the number of cycles already
+    /// accounted for by the CPU for this fetch.
     pub fn load_instruction(&mut self,
                             shared: &mut SharedState,
-                            pc: u32) -> u32 {
+                            pc: u32,
+                            charged: Cycles) -> u32 {
         let abs_addr = map::mask_region(pc);
 
         if let Some(offset) = map::RAM.contains(abs_addr) {
-            return self.ram.load::<Word>(offset);
+            if let Some(offset) = self.ram_offset(offset) {
+                return self.ram.load::<Word>(offset);
+            }
+        }
+
+        // Fetching from the external bus is a lot slower than from
+        // the RAM, the CPU only accounts for the RAM timings so we
+        // add the difference
+        if let Some(region) = Region::from_address(abs_addr) {
+            let timing = self.mem_control.read_timing::<Word>(region);
+
+            shared.tk().tick(timing.saturating_sub(charged));
         }
 
         if let Some(offset) = map::BIOS.contains(abs_addr) {
@@ -164,28 +423,54 @@ impl Interconnect {
             return self.parallel_io.load::<Word>(shared, offset);
         }
 
-        panic!("unhandled instruction load at address {:08x}", pc);
+        if let Some(offset) = map::EXPANSION_3.contains(abs_addr) {
+            return self.expansion_3.load::<Word>(shared, offset);
+        }
+
+        shared.set_error(EmulationError::UnhandledFetch(pc));
+
+        !0
     }
 
     /// Interconnect: load value at `addr`
     pub fn load<T: Addressable>(&mut self,
                                 shared: &mut SharedState,
                                 addr: u32) -> u32 {
+        let abs_addr = map::mask_region(addr);
+
+        // The timings of the devices on the external bus are
+        // configured in the memory control registers.
+        //
         // XXX Since I don't implement CPU pipelining correctly for
-        // now I just pretend the memory is pretty fast. In reality it
-        // will depend on the device being accessed and then it could
-        // be pipelined in the CPU to reduce stalling.
-        shared.tk().tick(2);
+        // now I just pretend the rest of the memory is pretty
+        // fast. In reality it will depend on the device being
+        // accessed and then it could be pipelined in the CPU to
+        // reduce stalling.
+        let cycles =
+            match Region::from_address(abs_addr) {
+                Some(region) => self.mem_control.read_timing::<T>(region),
+                None => 2,
+            };
 
-        let abs_addr = map::mask_region(addr);
+        shared.tk().tick(cycles);
 
         if let Some(offset) = map::RAM.contains(abs_addr) {
-            return self.ram.load::<T>(offset);
+            return match self.ram_offset(offset) {
+                Some(offset) => self.ram.load::<T>(offset),
+                None => {
+                    // The real hardware triggers a bus error which we
+                    // don't emulate
+                    shared.set_error(EmulationError::UnhandledLoad(addr,
+                                                                   T::size()));
+                    !0
+                }
+            };
         }
 
         if let Some(offset) = map::SCRATCH_PAD.contains(abs_addr) {
             if addr > 0xa0000000 {
-                panic!("ScratchPad access through uncached memory");
+                fatal!(shared, Device::Interconnect,
+                       "ScratchPad access through uncached memory");
             }
 
             return self.scratch_pad.load::<T>(offset);
@@ -200,12 +485,16 @@ impl Interconnect {
                 match offset {
                     0 => shared.irq_state().status() as u32,
                     4 => shared.irq_state().mask() as u32,
-                    _ => panic!("Unhandled IRQ load at address {:08x}", addr),
+                    _ => {
+                        fatal!(shared, Device::InterruptController,
+                               "Unhandled IRQ load at address {:08x}", addr);
+                        0
+                    }
                 };
         }
 
         if let Some(offset) = map::DMA.contains(abs_addr) {
-            return self.dma_reg::<T>(offset);
+            return self.dma_reg::<T>(shared, offset);
         }
 
         if let Some(offset) = map::GPU.contains(abs_addr) {
@@ -225,7 +514,7 @@ impl Interconnect {
         }
 
         if let Some(offset) = map::SPU.contains(abs_addr) {
-            return self.spu.load::<T>(offset);
+            return self.spu.load::<T>(shared, offset);
         }
 
         if let Some(offset) = map::PAD_MEMCARD.contains(abs_addr) {
@@ -241,29 +530,29 @@ impl Interconnect {
         }
 
         if let Some(offset) = map::MEM_CONTROL.contains(abs_addr) {
-
-            if T::size() != 4 {
-                panic!("Unhandled MEM_CONTROL access ({})", T::size());
-            }
-
-            let index = (offset >> 2) as usize;
-
-            return self.mem_control[index];
+            return self.mem_control.load::<T>(shared, offset);
         }
 
         if let Some(_) = map::CACHE_CONTROL.contains(abs_addr) {
             if T::size() != 4 {
-                panic!("Unhandled cache control access ({})", T::size());
+                fatal!(shared, Device::Interconnect,
+                       "Unhandled cache control access ({})", T::size());
             }
 
             return self.cache_control.0;
         }
 
         if let Some(offset) = map::EXPANSION_2.contains(abs_addr) {
-            return self.debug_uart.load::<T>(shared, offset);
+            return self.expansion_2.load::<T>(shared, offset);
+        }
+
+        if let Some(offset) = map::EXPANSION_3.contains(abs_addr) {
+            return self.expansion_3.load::<T>(shared, offset);
         }
 
-        panic!("unhandled load at address {:08x}", addr);
+        shared.set_error(EmulationError::UnhandledLoad(addr, T::size()));
+
+        !0
     }
 
     /// Interconnect: store `val` into `addr`
@@ -275,14 +564,27 @@ impl Interconnect {
 
         let abs_addr = map::mask_region(addr);
 
+        // XXX The other writes go through the CPU write queue, I
+        // consider them free for now
+        if let Some(region) = Region::from_address(abs_addr) {
+            shared.tk().tick(self.mem_control.write_timing::<T>(region));
+        }
+
         if let Some(offset) = map::RAM.contains(abs_addr) {
-            self.ram.store::<T>(offset, val);
+            match self.ram_offset(offset) {
+                Some(offset) => self.ram.store::<T>(offset, val),
+                // The real hardware triggers a bus error which we
+                // don't emulate
+                None => shared.set_error(
+                    EmulationError::UnhandledStore(addr, T::size(), val)),
+            }
             return;
         }
 
         if let Some(offset) = map::SCRATCH_PAD.contains(abs_addr) {
             if addr > 0xa0000000 {
-                panic!("ScratchPad access through uncached memory");
+                fatal!(shared, Device::Interconnect,
+                       "ScratchPad access through uncached memory");
             }
 
             return self.scratch_pad.store::<T>(offset, val);
@@ -291,8 +593,14 @@ impl Interconnect {
         if let Some(offset) = map::IRQ_CONTROL.contains(abs_addr) {
             match offset {
                 0 => shared.irq_state().ack(val as u16),
-                4 => shared.irq_state().set_mask(val as u16),
-                _ => panic!("Unhandled IRQ store at address {:08x}"),
+                4 =>
+                    if let Err(rem) = shared.irq_state().set_mask(val as u16) {
+                        fatal!(shared, Device::InterruptController,
+                               "Unsupported interrupt: {:04x}", rem);
+                    },
+                _ => fatal!(shared, Device::InterruptController,
+                            "Unhandled IRQ store at address {:08x}: {:08x}",
+                            addr, val),
             }
             return;
         }
@@ -328,7 +636,7 @@ impl Interconnect {
         }
 
         if let Some(offset) = map::SPU.contains(abs_addr) {
-            self.spu.store::<T>(offset, val);
+            self.spu.store::<T>(shared, offset, val);
             return;
         }
 
@@ -339,7 +647,9 @@ impl Interconnect {
 
         if let Some(_) = map::CACHE_CONTROL.contains(abs_addr) {
             if T::size() != 4 {
-                panic!("Unhandled cache control access");
+                fatal!(shared, Device::Interconnect,
+                       "Unhandled cache control access ({})", T::size());
+                return;
             }
 
             self.cache_control = CacheControl(val);
@@ -348,88 +658,87 @@ impl Interconnect {
         }
 
         if let Some(offset) = map::MEM_CONTROL.contains(abs_addr) {
-
-            if T::size() != 4 {
-                panic!("Unhandled MEM_CONTROL access ({})", T::size());
-            }
-
-            let val = val;
-
-            match offset {
-                0 => // Expansion 1 base address
-                    if val != 0x1f000000 {
-                        panic!("Bad expansion 1 base address: 0x{:08x}", val);
-                    },
-                4 => // Expansion 2 base address
-                    if val != 0x1f802000 {
-                        panic!("Bad expansion 2 base address: 0x{:08x}", val);
-                    },
-                _ =>
-                    warn!("Unhandled write to MEM_CONTROL register {:x}: \
-                           0x{:08x}",
-                          offset, val),
-            }
-
-            let index = (offset >> 2) as usize;
-
-            self.mem_control[index] = val;
-
+            self.mem_control.store::<T>(shared, offset, val);
             return;
         }
 
         if let Some(_) = map::RAM_SIZE.contains(abs_addr) {
 
             if T::size() != 4 {
-                panic!("Unhandled RAM_SIZE access");
+                fatal!(shared, Device::Interconnect,
+                       "Unhandled RAM_SIZE access ({})", T::size());
+                return;
             }
 
             self.ram_size = val;
             return;
         }
 
+        if let Some(offset) = map::EXPANSION_1.contains(abs_addr) {
+            self.parallel_io.store::<T>(shared, offset, val);
+            return;
+        }
+
         if let Some(offset) = map::EXPANSION_2.contains(abs_addr) {
-            self.debug_uart.store::<T>(shared, offset, val);
+            self.expansion_2.store::<T>(shared, offset, val);
+            return;
+        }
+
+        if let Some(offset) = map::EXPANSION_3.contains(abs_addr) {
+            self.expansion_3.store::<T>(shared, offset, val);
             return;
         }
 
-        panic!("unhandled store into address {:08x}: {:08x}",
-               addr, val);
+        shared.set_error(EmulationError::UnhandledStore(addr, T::size(), val));
     }
 
     /// DMA register read
-    fn dma_reg<T: Addressable>(&self, offset: u32) -> u32 {
-
-        // The DMA uses 32bit registers
+    fn dma_reg<T: Addressable>(&self,
+                               shared: &mut SharedState,
+                               offset: u32) -> u32 {
+        // Byte and halfword reads fetch only a portion of the register
         let align = offset & 3;
-        let offset = offset & !3;
 
+        match self.peek_dma(offset & !3) {
+            Some(v) => v >> (align * 8),
+            None => {
+                fatal!(shared, Device::Dma,
+                       "Unhandled DMA read at {:x}", offset & !3);
+                0
+            }
+        }
+    }
+
+    /// Return the value of the DMA register at the word-aligned
+    /// `offset` or `None` if there's no such register. DMA register
+    /// reads have no side effect.
+    fn peek_dma(&self, offset: u32) -> Option<u32> {
         let major = (offset & 0x70) >> 4;
         let minor = offset & 0xf;
 
         let res =
             match major {
                 // Per-channel registers
-                0...6 => {
+                0..=6 => {
                     let channel = self.dma.channel(Port::from_index(major));
 
                     match minor {
                         0 => channel.base(),
                         4 => channel.block_control(),
                         8 => channel.control(),
-                        _ => panic!("Unhandled DMA read at {:x}", offset)
+                        _ => return None,
                     }
                 },
                 // Common DMA registers
                 7 => match minor {
                     0 => self.dma.control(),
                     4 => self.dma.interrupt(),
-                    _ => panic!("Unhandled DMA read at {:x}", offset)
+                    _ => return None,
                 },
-                _ => panic!("Unhandled DMA read at {:x}", offset)
+                _ => return None,
             };
 
-        // Byte and halfword reads fetch only a portion of the register
-        res >> (align * 8)
+        Some(res)
     }
 
     /// DMA register write
@@ -450,16 +759,17 @@ impl Interconnect {
         let active_port =
             match major {
                 // Per-channel registers
-                0...6 => {
+                0..=6 => {
                     let port = Port::from_index(major);
                     let channel = self.dma.channel_mut(port);
 
                     match minor {
                         0 => channel.set_base(val),
                         4 => channel.set_block_control(val),
-                        8 => channel.set_control(val),
-                        _ => panic!("Unhandled DMA write {:x}: {:08x}",
-                                    offset, val)
+                        8 => channel.set_control(shared, val),
+                        _ => fatal!(shared, Device::Dma,
+                                    "Unhandled DMA write {:x}: {:08x}",
+                                    offset, val),
                     }
 
                     if channel.active() {
@@ -473,14 +783,18 @@ impl Interconnect {
                     match minor {
                         0 => self.dma.set_control(val),
                         4 => self.dma.set_interrupt(shared, val),
-                        _ => panic!("Unhandled DMA write {:x}: {:08x}",
+                        _ => fatal!(shared, Device::Dma,
+                                    "Unhandled DMA write {:x}: {:08x}",
                                     offset, val),
                     }
 
                     None
                 }
-                _ => panic!("Unhandled DMA write {:x}: {:08x}",
-                            offset, val),
+                _ => {
+                    fatal!(shared, Device::Dma,
+                           "Unhandled DMA write {:x}: {:08x}", offset, val);
+                    None
+                }
             };
 
         if let Some(port) = active_port {
@@ -498,27 +812,37 @@ impl Interconnect {
         // chopping or priority handling)
 
         match self.dma.channel(port).sync() {
-                Sync::LinkedList => self.do_dma_linked_list(renderer, port),
-                _                => self.do_dma_block(renderer, port),
+                Sync::LinkedList =>
+                    self.do_dma_linked_list(shared, renderer, port),
+                _ => self.do_dma_block(shared, renderer, port),
         }
 
         self.dma.done(shared, port);
     }
 
     /// Emulate DMA transfer for linked list synchronization mode.
-    fn do_dma_linked_list(&mut self, renderer: &mut Renderer, port: Port) {
+    fn do_dma_linked_list(&mut self,
+                          shared: &mut SharedState,
+                          renderer: &mut Renderer,
+                          port: Port) {
+        let mask = self.ram.mask() & !3;
+
         let channel = self.dma.channel_mut(port);
 
-        let mut addr = channel.base() & 0x1ffffc;
+        let mut addr = channel.base() & mask;
 
         if channel.direction() == Direction::ToRam {
-            panic!("Invalid DMA direction for linked list mode");
+            fatal!(shared, Device::Dma,
+                   "Invalid DMA direction for linked list mode");
+            return;
         }
 
         // I don't know if the DMA even supports linked list mode for
         // anything besides the GPU
         if port != Port::Gpu {
-            panic!("Attempted linked list DMA on port {:?}", port);
+            fatal!(shared, Device::Dma,
+                   "Attempted linked list DMA on port {:?}", port);
+            return;
         }
 
         loop {
@@ -530,12 +854,12 @@ impl Interconnect {
             let mut remsz = header >> 24;
 
             while remsz > 0 {
-                addr = (addr + 4) & 0x1ffffc;
+                addr = (addr + 4) & mask;
 
                 let command = self.ram.load::<Word>(addr);
 
                 // Send command to the GPU
-                self.gpu.gp0(renderer, command);
+                self.gpu.gp0(shared, renderer, command);
 
                 remsz -= 1;
             }
@@ -549,13 +873,18 @@ impl Interconnect {
                 break;
             }
 
-            addr = header & 0x1ffffc;
+            addr = header & mask;
         }
     }
 
     /// Emulate DMA transfer for Manual and Request synchronization
     /// modes.
-    fn do_dma_block(&mut self, renderer: &mut Renderer, port: Port) {
+    fn do_dma_block(&mut self,
+                    shared: &mut SharedState,
+                    renderer: &mut Renderer,
+                    port: Port) {
+        let mask = self.ram.mask();
+
         let channel = self.dma.channel_mut(port);
 
         let increment = match channel.step() {
@@ -579,18 +908,19 @@ impl Interconnect {
             // that's how the hardware behaves (i.e. the RAM
             // address wraps and the two LSB are ignored, seems
             // reasonable enough
-            let cur_addr = addr & 0x1ffffc;
+            let cur_addr = addr & mask & !3;
 
             match channel.direction() {
                 Direction::FromRam => {
                     let src_word = self.ram.load::<Word>(cur_addr);
 
                     match port {
-                        Port::Gpu => self.gpu.gp0(renderer, src_word),
-                        Port::MDecIn => self.mdec.command(src_word),
+                        Port::Gpu => self.gpu.gp0(shared, renderer, src_word),
+                        Port::MDecIn => self.mdec.command(shared, src_word),
                         // XXX ignre transfers to the SPU for now
                         Port::Spu => (),
-                        _ => panic!("Unhandled DMA destination port {:?}",
+                        _ => fatal!(shared, Device::Dma,
+                                    "Unhandled DMA destination port {:?}",
                                     port),
                     }
                 }
@@ -602,18 +932,24 @@ impl Interconnect {
                             // of table marker
                             1 => 0xffffff,
                             // Pointer to the previous entry
-                            _ => addr.wrapping_sub(4) & 0x1fffff,
+                            _ => addr.wrapping_sub(4) & mask,
                         },
                         Port::Gpu => {
                             // XXX to be implemented
                             debug!("DMA GPU READ");
                             0
                         }
-                        Port::CdRom => self.cdrom.dma_read_word(),
-                        _ => panic!("Unhandled DMA source port {:?}", port),
+                        Port::CdRom => self.cdrom.dma_read_word(shared),
+                        _ => {
+                            fatal!(shared, Device::Dma,
+                                   "Unhandled DMA source port {:?}", port);
+                            0
+                        }
                     };
 
                     self.ram.store::<Word>(cur_addr, src_word);
+
+                    record_ram_write(&mut self.dma_ram_writes, cur_addr);
                 }
             }
 
@@ -623,6 +959,27 @@ impl Interconnect {
     }
 }
 
+/// Add the word at `addr` to the list of RAM ranges written by the
+/// DMA, merging it with the last range if they're contiguous
+fn record_ram_write(ranges: &mut Vec<(u32, u32)>, addr: u32) {
+    if let Some(last) = ranges.last_mut() {
+        // Incrementing transfer
+        if last.0.wrapping_add(last.1) == addr {
+            last.1 += 4;
+            return;
+        }
+
+        // Decrementing transfer (used by the OTC channel)
+        if addr.wrapping_add(4) == last.0 {
+            last.0 = addr;
+            last.1 += 4;
+            return;
+        }
+    }
+
+    ranges.push((addr, 4));
+}
+
 #[derive(Clone,Copy, RustcDecodable, RustcEncodable)]
 pub struct CacheControl(u32);
 
@@ -672,6 +1029,7 @@ impl Addressable for Word {
 }
 
 pub mod map {
+    #[derive(Clone, Copy)]
     pub struct Range(pub u32, pub u32);
 
     impl Range {
@@ -707,8 +1065,8 @@ pub mod map {
         addr & REGION_MASK[index]
     }
 
-    /// Main RAM: 2MB mirrored four times over the first 8MB (probably
-    /// in case they decided to use a bigger RAM later on?)
+    /// Main RAM: 2MB (8MB on development kits) mirrored over the
+    /// first 8MB depending on the RAM_SIZE configuration
     pub const RAM: Range = Range(0x00000000, 8 * 1024 * 1024);
 
     /// Expansion region 1
@@ -748,8 +1106,206 @@ pub mod map {
     pub const SPU: Range = Range(0x1f801c00, 640);
 
     /// Expansion region 2
-    pub const EXPANSION_2: Range = Range(0x1f802000, 66);
+    pub const EXPANSION_2: Range = Range(0x1f802000, 8 * 1024);
+
+    /// Expansion region 3
+    pub const EXPANSION_3: Range = Range(0x1fa00000, 2 * 1024 * 1024);
 
     /// Cache control register. Full address since it's in KSEG2
     pub const CACHE_CONTROL: Range = Range(0xfffe0130, 4);
+
+    /// Name and range of all the regions above, sorted by address.
+    /// Lets debugging tools display the memory map.
+    pub const REGIONS: &'static [(&'static str, Range)] = &[
+        ("RAM", RAM),
+        ("Expansion 1", EXPANSION_1),
+        ("ScratchPad", SCRATCH_PAD),
+        ("Memory control", MEM_CONTROL),
+        ("Gamepad/memory card", PAD_MEMCARD),
+        ("RAM size", RAM_SIZE),
+        ("IRQ control", IRQ_CONTROL),
+        ("DMA", DMA),
+        ("Timers", TIMERS),
+        ("CDROM", CDROM),
+        ("GPU", GPU),
+        ("MDEC", MDEC),
+        ("SPU", SPU),
+        ("Expansion 2", EXPANSION_2),
+        ("Expansion 3", EXPANSION_3),
+        ("BIOS", BIOS),
+        ("Cache control", CACHE_CONTROL),
+        ];
+}
+
+#[test]
+fn peek_poke() {
+    use gpu::VideoClock;
+
+    let mut inter = Interconnect::new(Bios::dummy(),
+                                      Gpu::new(VideoClock::Ntsc),
+                                      None);
+    let shared = SharedState::new();
+
+    assert!(inter.poke::<Word>(0x80001000, 0x12345678));
+    assert!(inter.peek::<HalfWord>(&shared, 0xa0001002) == Some(0x1234));
+    // The 2MB of RAM are mirrored over the RAM window
+    assert!(inter.peek::<Word>(&shared, 0x00201000) == Some(0x12345678));
+
+    assert!(inter.poke::<Byte>(0x1f800010, 0xab));
+    assert!(inter.peek::<Byte>(&shared, 0x1f800010) == Some(0xab));
+
+    assert!(inter.poke::<Word>(0xbfc00000, 0));
+    assert!(inter.peek::<Word>(&shared, 0xbfc00000) == Some(0));
+
+    // Registers can be read but not written
+    assert!(!inter.poke::<Word>(0x1f801074, 1));
+    assert!(!inter.poke::<Byte>(0x1f802041, 1));
+
+    // 1MB RAM window
+    inter.ram_size = 0;
+
+    assert!(!inter.poke::<Word>(0x80100000, 1));
+    assert!(inter.peek::<Word>(&shared, 0x80100000).is_none());
+    assert!(inter.peek::<Word>(&shared, 0x800ffffc).is_some());
+}
+
+#[test]
+fn peek_regions() {
+    use gpu::VideoClock;
+
+    let mut inter = Interconnect::new(Bios::dummy(),
+                                      Gpu::new(VideoClock::Ntsc),
+                                      None);
+    let mut shared = SharedState::new();
+
+    assert!(shared.irq_state().set_mask(0x5).is_ok());
+    inter.cache_control = CacheControl(0x1e988);
+
+    let word = |addr| inter.peek::<Word>(&shared, addr);
+    let half = |addr| inter.peek::<HalfWord>(&shared, addr);
+    let byte = |addr| inter.peek::<Byte>(&shared, addr);
+
+    assert!(word(0xbfc00100) == Some(inter.bios.load::<Word>(0x100)));
+
+    // Expansion 1 and 3 modules can't be read without side effects
+    assert!(byte(0x1f000000).is_none());
+    assert!(byte(0x1fa00000).is_none());
+
+    // Expansion 2 open bus and UART status
+    assert!(word(0x1f802000) == Some(0xffffffff));
+    assert!(byte(0x1f802021) == Some(0x04));
+
+    assert!(word(0x1f801010) == Some(0x0013243f));
+    assert!(word(0x1f801020) == Some(0x00031125));
+
+    assert!(word(0x1f801044) == Some(inter.pad_memcard.peek(4)));
+    assert!(half(0x1f80104a) == Some(inter.pad_memcard.peek(10)));
+    assert!(half(0x1f80104e) == Some(inter.pad_memcard.peek(14)));
+
+    assert!(word(0x1f801060) == Some(0xb88));
+
+    assert!(half(0x1f801070) == Some(0));
+    assert!(word(0x1f801074) == Some(0x5));
+
+    assert!(word(0x1f8010f0) == Some(inter.dma.control()));
+    // No such DMA register
+    assert!(word(0x1f80108c).is_none());
+
+    assert!(word(0x1f801104) == Some(inter.timers.peek(4)));
+
+    let cdrom = (0..4).fold(0, |r, i| r | inter.cdrom.peek(i) << (i * 8));
+
+    assert!(word(0x1f801800) == Some(cdrom));
+    assert!(byte(0x1f801801) == Some(inter.cdrom.peek(1)));
+
+    assert!(word(0x1f801814) == Some(inter.gpu.peek(4)));
+    assert!(word(0x1f801824) == Some(inter.mdec.peek(4)));
+
+    assert!(half(0x1f801daa) == Some(inter.spu.peek(0x1aa)));
+
+    assert!(word(0xfffe0130) == Some(0x1e988));
+
+    // Unmapped addresses and accesses running past the end of a
+    // region
+    assert!(byte(0x1f801400).is_none());
+    assert!(half(0x1f801024).is_none());
+    assert!(word(0x1f801022).is_none());
+    assert!(word(0xfffe0134).is_none());
+}
+
+#[test]
+fn memory_map() {
+    for w in map::REGIONS.windows(2) {
+        let (a, b) = ((w[0].1).0, (w[1].1).0);
+        let a_end = a + (w[0].1).1;
+
+        assert!(a_end <= b);
+    }
+}
+
+#[test]
+fn peek_partial_registers() {
+    use gpu::VideoClock;
+    use interrupt::Interrupt;
+
+    let mut inter = Interconnect::new(Bios::dummy(),
+                                      Gpu::new(VideoClock::Ntsc),
+                                      None);
+    let mut shared = SharedState::new();
+
+    // Partial reads return the addressed bytes of the register
+    assert!(inter.peek::<Byte>(&shared, 0x1f801012) == Some(0x13));
+    assert!(inter.peek::<HalfWord>(&shared, 0x1f801012) == Some(0x0013));
+
+    inter.timers.store::<HalfWord>(&mut shared, &mut inter.gpu, 0x10, 0xabcd);
+    assert!(inter.peek::<Byte>(&shared, 0x1f801110) == Some(0xcd));
+    assert!(inter.peek::<Byte>(&shared, 0x1f801111) == Some(0xab));
+    assert!(inter.peek::<Byte>(&shared, 0x1f801112) == Some(0));
+    assert!(inter.peek::<HalfWord>(&shared, 0x1f801110) == Some(0xabcd));
+
+    // The upper half of the interrupt status is not the mask
+    assert!(shared.irq_state().set_mask(0xff).is_ok());
+    shared.irq_state().assert(Interrupt::VBlank);
+    assert!(inter.peek::<HalfWord>(&shared, 0x1f801070) == Some(1));
+    assert!(inter.peek::<HalfWord>(&shared, 0x1f801072) == Some(0));
+    assert!(inter.peek::<Byte>(&shared, 0x1f801074) == Some(0xff));
+
+    // Without the shared state the interrupt registers read as 0 but
+    // the other registers are still there
+    assert!(inter.peek_without_irq::<HalfWord>(0x1f801070) == Some(0));
+    assert!(inter.peek_without_irq::<Byte>(0x1f801074) == Some(0));
+    assert!(inter.peek_without_irq::<HalfWord>(0x1f801110) == Some(0xabcd));
+
+    // Wide reads span several 16bit SPU registers
+    assert!(inter.peek::<Word>(&shared, 0x1f801c00) ==
+            inter.peek::<HalfWord>(&shared, 0x1f801c00)
+            .map(|lo| lo | inter.spu.peek(2) << 16));
+}
+
+#[test]
+fn bios_fetch_timing() {
+    use gpu::VideoClock;
+
+    let mut inter = Interconnect::new(Bios::dummy(),
+                                      Gpu::new(VideoClock::Ntsc),
+                                      None);
+    let mut shared = SharedState::new();
+
+    let bios = inter.mem_control.read_timing::<Word>(Region::Bios);
+
+    // The cycles already charged by the CPU aren't counted twice
+    for &charged in &[1, 4] {
+        let start = shared.tk().now();
+
+        inter.load_instruction(&mut shared, 0xbfc00000, charged);
+
+        assert!(shared.tk().now() - start == bios - charged);
+    }
+
+    // RAM fetches are entirely accounted for by the CPU
+    let start = shared.tk().now();
+
+    inter.load_instruction(&mut shared, 0x80000000, 4);
+
+    assert!(shared.tk().now() == start);
 }
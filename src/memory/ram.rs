@@ -2,27 +2,62 @@ use rustc_serialize::{Decodable, Encodable, Decoder, Encoder};
 
 use super::Addressable;
 
+/// Amount of RAM installed in the console
+#[derive(Clone, Copy, Debug, PartialEq, Eq, RustcDecodable, RustcEncodable)]
+pub enum RamSize {
+    /// 2MB, retail consoles
+    Retail,
+    /// 8MB, DTL-H development kits
+    DevKit,
+}
+
+impl RamSize {
+    /// Size in bytes
+    pub fn bytes(self) -> usize {
+        match self {
+            RamSize::Retail => 2 * 1024 * 1024,
+            RamSize::DevKit => 8 * 1024 * 1024,
+        }
+    }
+}
+
 /// RAM
 pub struct Ram {
+    size: RamSize,
     /// RAM buffer. Boxed in order not to overflow the stack at the
-    /// construction site. Might change once "placement new" is
-    /// available.
-    data: Box<[u8; RAM_SIZE]>
+    /// construction site.
+    data: Box<[u8]>,
 }
 
 impl Ram {
 
-    /// Instantiate main RAM with garbage values
+    /// Instantiate 2MB of main RAM with garbage values
     pub fn new() -> Ram {
+        Ram::with_size(RamSize::Retail)
+    }
 
-        Ram { data: box_array![0xca; RAM_SIZE] }
+    /// Instantiate main RAM of the given size with garbage values
+    pub fn with_size(size: RamSize) -> Ram {
+        Ram {
+            size: size,
+            data: vec![0xca; size.bytes()].into_boxed_slice(),
+        }
+    }
+
+    pub fn size(&self) -> RamSize {
+        self.size
+    }
+
+    /// Mask used to wrap offsets around the RAM size
+    pub fn mask(&self) -> u32 {
+        (self.size.bytes() - 1) as u32
     }
 
     /// Fetch the little endian value at `offset`
     pub fn load<T: Addressable>(&self, offset: u32) -> u32 {
-        // The two MSB are ignored, the 2MB RAM is mirorred four times
-        // over the first 8MB of address space
-        let offset = (offset & 0x1fffff) as usize;
+        // The RAM is mirrored over the RAM window configured in the
+        // RAM_SIZE register
+        let offset = (offset & self.mask()) as usize;
 
         let mut v = 0;
 
@@ -35,16 +70,24 @@ impl Ram {
 
     /// Store the 32bit little endian word `val` into `offset`
     pub fn store<T: Addressable>(&mut self, offset: u32, val: u32) {
-        // The two MSB are ignored, the 2MB RAM is mirorred four times
-        // over the first 8MB of address space
-        let offset = (offset & 0x1fffff) as usize;
+        // The RAM is mirrored over the RAM window configured in the
+        // RAM_SIZE register
+        let offset = (offset & self.mask()) as usize;
 
         for i in 0..T::size() as usize {
             self.data[offset + i] = (val >> (i * 8)) as u8;
         }
     }
+
+    /// Return the raw contents of the RAM
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
 }
 
+// The RAM is serialized as a plain byte sequence like it was before
+// the 8MB configuration was supported, the size is deduced from the
+// length so that older savestates can still be loaded.
 impl Encodable for Ram {
     fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
         s.emit_seq(self.data.len(), |s| {
@@ -59,11 +102,16 @@ impl Encodable for Ram {
 impl Decodable for Ram {
     fn decode<D: Decoder>(d: &mut D) -> Result<Ram, D::Error> {
         d.read_seq(|d, len| {
-            if len != RAM_SIZE {
-                return Err(d.error("wrong RAM length"));
-            }
+            let size =
+                if len == RamSize::Retail.bytes() {
+                    RamSize::Retail
+                } else if len == RamSize::DevKit.bytes() {
+                    RamSize::DevKit
+                } else {
+                    return Err(d.error("wrong RAM length"));
+                };
 
-            let mut ram = Ram::new();
+            let mut ram = Ram::with_size(size);
 
             for (i, b) in ram.data.iter_mut().enumerate() {
                 *b = try!(d.read_seq_elt(i, Decodable::decode))
@@ -138,9 +186,6 @@ impl Decodable for ScratchPad {
     }
 }
 
-/// Main PlayStation RAM: 2Megabytes
-const RAM_SIZE: usize = 2 * 1024 * 1024;
-
 /// ScatchPad (data cache used as fast RAM): 1Kilobyte
 const SCRATCH_PAD_SIZE: usize = 1024;
 
@@ -204,3 +249,51 @@ fn ram_write() {
     ram.store::<Byte>(35, 0xab);
     assert!(ram.load::<Word>(32) == 0xab345678);
 }
+
+#[test]
+fn ram_size() {
+    use super::Word;
+
+    let mut retail = Ram::new();
+    let mut devkit = Ram::with_size(RamSize::DevKit);
+
+    for ram in [&mut retail, &mut devkit].iter_mut() {
+        ram.store::<Word>(0x000100, 0x11111111);
+        ram.store::<Word>(0x600100, 0x22222222);
+    }
+
+    // 2MB mirrored over the window
+    assert!(retail.load::<Word>(0x000100) == 0x22222222);
+    assert!(retail.load::<Word>(0x200100) == 0x22222222);
+
+    assert!(devkit.load::<Word>(0x000100) == 0x11111111);
+    assert!(devkit.load::<Word>(0x200100) == 0xcacacaca);
+    assert!(devkit.load::<Word>(0x600100) == 0x22222222);
+    assert!(devkit.load::<Word>(0x800100) == 0x11111111);
+}
+
+#[test]
+fn ram_serialization() {
+    use rustc_serialize::json;
+    use super::Word;
+
+    let mut devkit = Ram::with_size(RamSize::DevKit);
+
+    devkit.store::<Word>(0x600100, 0x22222222);
+
+    let state = json::encode(&devkit).unwrap();
+    let devkit = json::decode::<Ram>(&state).unwrap();
+
+    assert!(devkit.size() == RamSize::DevKit);
+    assert!(devkit.load::<Word>(0x600100) == 0x22222222);
+
+    // Savestates from before the 8MB support only contain the 2MB of
+    // RAM data
+    let state = json::encode(&vec![0x33u8; 2 * 1024 * 1024]).unwrap();
+    let retail = json::decode::<Ram>(&state).unwrap();
+
+    assert!(retail.size() == RamSize::Retail);
+    assert!(retail.load::<Word>(0x100) == 0x33333333);
+
+    assert!(json::decode::<Ram>("[1, 2, 3]").is_err());
+}
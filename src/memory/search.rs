@@ -0,0 +1,353 @@
+//! RAM search, used to find the variables of interest when building
+//! cheat codes.
+//!
+//! A search starts either with a known value or with an "unknown"
+//! scan matching every address, then each refinement compares the
+//! current RAM contents with a value or with the contents at the
+//! previous step and drops the candidates that don't match.
+
+use super::ram::Ram;
+
+/// Width of the searched values
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Width {
+    Byte,
+    HalfWord,
+    Word,
+}
+
+impl Width {
+    /// Size in bytes
+    pub fn size(self) -> u32 {
+        match self {
+            Width::Byte => 1,
+            Width::HalfWord => 2,
+            Width::Word => 4,
+        }
+    }
+}
+
+/// Condition used to refine the search
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    /// Equal to the given value
+    Equal(i64),
+    /// Different from the given value
+    NotEqual(i64),
+    /// Same value as the previous step
+    Unchanged,
+    /// Different value from the previous step
+    Changed,
+    /// Greater than at the previous step
+    Increased,
+    /// Less than at the previous step
+    Decreased,
+    /// Increased by exactly the given amount since the previous step
+    IncreasedBy(i64),
+    /// Decreased by exactly the given amount since the previous step
+    DecreasedBy(i64),
+}
+
+impl Filter {
+    fn matches(self, value: i64, previous: i64) -> bool {
+        match self {
+            Filter::Equal(v) => value == v,
+            Filter::NotEqual(v) => value != v,
+            Filter::Unchanged => value == previous,
+            Filter::Changed => value != previous,
+            Filter::Increased => value > previous,
+            Filter::Decreased => value < previous,
+            Filter::IncreasedBy(d) => value - previous == d,
+            Filter::DecreasedBy(d) => previous - value == d,
+        }
+    }
+}
+
+/// Search result
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Candidate {
+    /// Address in KSEG0
+    pub address: u32,
+    /// Current value
+    pub value: i64,
+    /// Value at the last refinement
+    pub previous: i64,
+}
+
+/// RAM search state
+pub struct Search {
+    width: Width,
+    /// Interpret the values as two's complement signed integers
+    signed: bool,
+    /// Offsets of the remaining candidates in RAM, sorted. `None`
+    /// after an unknown value scan: every aligned offset is a
+    /// candidate.
+    candidates: Option<Vec<u32>>,
+    /// RAM contents at the last refinement
+    previous: Vec<u8>,
+}
+
+impl Search {
+    /// Start a search for an unknown value, every aligned address is
+    /// a candidate
+    pub fn unknown(ram: &Ram, width: Width, signed: bool) -> Search {
+        Search {
+            width: width,
+            signed: signed,
+            candidates: None,
+            previous: ram.data().to_vec(),
+        }
+    }
+
+    /// Start a search for the addresses currently containing `value`
+    pub fn for_value(ram: &Ram,
+                     width: Width,
+                     signed: bool,
+                     value: i64) -> Search {
+        let mut search = Search::unknown(ram, width, signed);
+
+        search.refine(ram, Filter::Equal(value));
+
+        search
+    }
+
+    pub fn width(&self) -> Width {
+        self.width
+    }
+
+    pub fn signed(&self) -> bool {
+        self.signed
+    }
+
+    /// Number of remaining candidates
+    pub fn len(&self) -> usize {
+        match self.candidates {
+            Some(ref c) => c.len(),
+            None => self.previous.len() / self.width.size() as usize,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drop the candidates that don't match `filter` and record the
+    /// current values for the next refinement
+    pub fn refine(&mut self, ram: &Ram, filter: Filter) {
+        let data = ram.data();
+
+        let candidates =
+            match self.candidates.take() {
+                Some(c) =>
+                    c.into_iter()
+                    .filter(|&o| self.matches(data, o, filter))
+                    .collect(),
+                None => {
+                    let size = self.width.size();
+                    let count = data.len() as u32 / size;
+
+                    (0..count)
+                        .map(|i| i * size)
+                        .filter(|&o| self.matches(data, o, filter))
+                        .collect()
+                }
+            };
+
+        self.candidates = Some(candidates);
+        self.previous.clear();
+        self.previous.extend_from_slice(data);
+    }
+
+    /// Return up to `count` candidates starting at index `start` in
+    /// the list of results, sorted by address. Lets the frontend
+    /// display large result sets one page at a time.
+    pub fn results(&self, ram: &Ram, start: usize, count: usize) -> Vec<Candidate> {
+        let end = ::std::cmp::min(start.saturating_add(count), self.len());
+
+        if start >= end {
+            return Vec::new();
+        }
+
+        let data = ram.data();
+
+        (start..end).map(|i| {
+            let offset = self.offset(i);
+
+            Candidate {
+                address: 0x80000000 | offset,
+                value: self.value(data, offset),
+                previous: self.value(&self.previous, offset),
+            }
+        }).collect()
+    }
+
+    /// Offset of candidate `index`
+    fn offset(&self, index: usize) -> u32 {
+        match self.candidates {
+            Some(ref c) => c[index],
+            None => index as u32 * self.width.size(),
+        }
+    }
+
+    fn matches(&self, data: &[u8], offset: u32, filter: Filter) -> bool {
+        filter.matches(self.value(data, offset), self.value(&self.previous, offset))
+    }
+
+    /// Read the value at `offset` in `data`
+    fn value(&self, data: &[u8], offset: u32) -> i64 {
+        let offset = offset as usize;
+
+        let mut v = 0u32;
+
+        for i in 0..self.width.size() as usize {
+            v |= (data[offset + i] as u32) << (i * 8);
+        }
+
+        match (self.width, self.signed) {
+            (Width::Byte, true) => v as i8 as i64,
+            (Width::HalfWord, true) => v as i16 as i64,
+            (Width::Word, true) => v as i32 as i64,
+            _ => v as i64,
+        }
+    }
+}
+
+#[test]
+fn search_known_value() {
+    use super::HalfWord;
+
+    let mut ram = Ram::new();
+
+    ram.store::<HalfWord>(0x1000, 100);
+    ram.store::<HalfWord>(0x2000, 100);
+    ram.store::<HalfWord>(0x3000, 100);
+    // Not aligned, not a candidate
+    ram.store::<HalfWord>(0x4001, 100);
+
+    let mut search = Search::for_value(&ram, Width::HalfWord, false, 100);
+
+    assert!(search.len() == 3);
+
+    // Lives lost
+    ram.store::<HalfWord>(0x1000, 99);
+    ram.store::<HalfWord>(0x2000, 101);
+
+    let mut unchanged = Search::for_value(&ram, Width::HalfWord, false, 101);
+
+    search.refine(&ram, Filter::Decreased);
+
+    assert!(search.results(&ram, 0, 10) == vec![Candidate {
+        address: 0x80001000,
+        value: 99,
+        previous: 100,
+    }]);
+
+    unchanged.refine(&ram, Filter::Unchanged);
+    assert!(unchanged.len() == 1);
+
+    ram.store::<HalfWord>(0x2000, 102);
+
+    assert!(unchanged.results(&ram, 0, 1) == vec![Candidate {
+        address: 0x80002000,
+        value: 102,
+        previous: 101,
+    }]);
+
+    unchanged.refine(&ram, Filter::Changed);
+    assert!(unchanged.len() == 1);
+
+    unchanged.refine(&ram, Filter::NotEqual(102));
+    assert!(unchanged.is_empty());
+    assert!(unchanged.results(&ram, 0, 10).is_empty());
+}
+
+#[test]
+fn search_signed() {
+    use super::{Byte, HalfWord, Word};
+
+    let mut ram = Ram::new();
+
+    ram.store::<Byte>(0x100, 0xff);
+
+    let byte = Search::for_value(&ram, Width::Byte, true, -1);
+
+    assert!(byte.results(&ram, 0, 10) == vec![Candidate {
+        address: 0x80000100,
+        value: -1,
+        previous: -1,
+    }]);
+
+    // Unsigned searches never match negative values
+    assert!(Search::for_value(&ram, Width::Byte, false, -1).is_empty());
+    assert!(Search::for_value(&ram, Width::Byte, false, 0xff).len() == 1);
+
+    // Going from 1 to -1 is a decrease when the value is signed, an
+    // increase otherwise
+    ram.store::<HalfWord>(0x200, 1);
+
+    let mut signed = Search::for_value(&ram, Width::HalfWord, true, 1);
+    let mut unsigned = Search::for_value(&ram, Width::HalfWord, false, 1);
+
+    ram.store::<HalfWord>(0x200, 0xffff);
+
+    signed.refine(&ram, Filter::DecreasedBy(2));
+    unsigned.refine(&ram, Filter::Increased);
+
+    assert!(signed.results(&ram, 0, 1)[0].value == -1);
+    assert!(unsigned.results(&ram, 0, 1)[0].value == 0xffff);
+
+    ram.store::<HalfWord>(0x200, 0x8000);
+
+    signed.refine(&ram, Filter::Decreased);
+    unsigned.refine(&ram, Filter::Decreased);
+
+    assert!(signed.results(&ram, 0, 1)[0].value == -0x8000);
+    assert!(unsigned.results(&ram, 0, 1)[0].value == 0x8000);
+
+    ram.store::<Word>(0x4000, 0xfffffffe);
+
+    let mut word = Search::for_value(&ram, Width::Word, true, -2);
+
+    ram.store::<Word>(0x4000, 0xffffffff);
+
+    word.refine(&ram, Filter::IncreasedBy(1));
+
+    let results = word.results(&ram, 0, 10);
+
+    assert!(results.len() == 1);
+    assert!(results[0].value == -1 && results[0].previous == -2);
+
+    ram.store::<Word>(0x4000, 0x7fffffff);
+
+    word.refine(&ram, Filter::Increased);
+    assert!(word.results(&ram, 0, 1)[0].value == 0x7fffffff);
+}
+
+#[test]
+fn search_unknown_value() {
+    use super::Word;
+
+    let mut ram = Ram::new();
+
+    let mut search = Search::unknown(&ram, Width::Word, false);
+
+    assert!(search.len() == 512 * 1024);
+    assert!(search.results(&ram, 0x1000, 1)[0].address == 0x80004000);
+
+    // Pages past the end are empty or truncated
+    assert!(search.results(&ram, 512 * 1024, 10).is_empty());
+    assert!(search.results(&ram, 512 * 1024 - 1, !0).len() == 1);
+
+    ram.store::<Word>(0x4000, 0x12345678);
+
+    search.refine(&ram, Filter::Changed);
+
+    assert!(search.results(&ram, 0, !0) == vec![Candidate {
+        address: 0x80004000,
+        value: 0x12345678,
+        previous: 0xcacacaca,
+    }]);
+    assert!(search.results(&ram, 1, 10).is_empty());
+
+    assert!(Search::unknown(&ram, Width::HalfWord, true).len() == 1024 * 1024);
+}
@@ -3,6 +3,7 @@ use gpu::Gpu;
 use super::Addressable;
 use interrupt::Interrupt;
 use shared::SharedState;
+use error::Device;
 
 #[derive(Debug, RustcDecodable, RustcEncodable)]
 pub struct Timers {
@@ -34,7 +35,8 @@ impl Timers {
                                  val: u32) {
 
         if T::size() == 1 {
-            panic!("Unhandled byte timer store");
+            fatal!(shared, Device::Timers, "Unhandled byte timer store");
+            return;
         }
 
         let val = val as u16;
@@ -47,9 +49,10 @@ impl Timers {
 
         match offset & 0xf {
             0 => timer.set_counter(val),
-            4 => timer.set_mode(val),
+            4 => timer.set_mode(shared, val),
             8 => timer.set_target(val),
-            n => panic!("Unhandled timer register {}", n),
+            n => fatal!(shared, Device::Timers,
+                        "Unhandled timer register {}", n),
         }
 
         if timer.needs_gpu() {
@@ -64,7 +67,8 @@ impl Timers {
                                 offset: u32) -> u32 {
 
         if T::size() == 1 {
-            panic!("Unhandled byte timer load");
+            fatal!(shared, Device::Timers, "Unhandled byte timer load");
+            return 0;
         }
 
         let instance = offset >> 4;
@@ -75,14 +79,38 @@ impl Timers {
 
         let val = match offset & 0xf {
             0 => timer.counter(),
-            4 => timer.mode(),
+            4 => timer.read_mode(),
             8 => timer.target(),
-            n => panic!("Unhandled timer register {}", n),
+            n => {
+                fatal!(shared, Device::Timers,
+                       "Unhandled timer register {}", n);
+                0
+            }
         };
 
         val as u32
     }
 
+    /// Register value without side effects, used for debugging. The
+    /// counters are only as recent as the last sync.
+    pub fn peek(&self, offset: u32) -> u32 {
+        let timer =
+            match self.timers.get((offset >> 4) as usize) {
+                Some(t) => t,
+                None => return 0,
+            };
+
+        let val =
+            match offset & 0xf {
+                0 => timer.counter(),
+                4 => timer.mode(),
+                8 => timer.target(),
+                _ => 0,
+            };
+
+        val as u32
+    }
+
     /// Called by the GPU when the video timings change since it can
     /// affect the timers that use them.
     pub fn video_timings_changed(&mut self,
@@ -201,7 +229,7 @@ impl Timer {
             },
             Clock::GpuDotClock => {
                 self.period = gpu.dotclock_period();
-                self.phase  = gpu.dotclock_phase();
+                self.phase  = gpu.dotclock_phase(shared);
             },
             Clock::GpuHSync => {
                 self.period = gpu.hsync_period();
@@ -286,7 +314,7 @@ impl Timer {
                 };
 
             if self.negate_irq {
-                panic!("Unhandled negate IRQ!");
+                fatal!(shared, Device::Timers, "Unhandled negate IRQ!");
             } else {
                 // Pulse interrupt
                 shared.irq_state().assert(interrupt);
@@ -338,7 +366,8 @@ impl Timer {
         self.clock_source.clock(self.instance).needs_gpu()
     }
 
-    fn mode(&mut self) -> u16 {
+    /// Value of the mode register
+    fn mode(&self) -> u16 {
         let mut r = 0u16;
 
         r |= self.use_sync as u16;
@@ -354,7 +383,13 @@ impl Timer {
         r |= (self.target_reached as u16) << 11;
         r |= (self.overflow_reached as u16) << 12;
 
-        // Reading mode resets those flags
+        r
+    }
+
+    /// Read the mode register, this resets the "reached" flags
+    fn read_mode(&mut self) -> u16 {
+        let r = self.mode();
+
         self.target_reached   = false;
         self.overflow_reached = false;
 
@@ -362,7 +397,7 @@ impl Timer {
     }
 
     /// Set the value of the mode register
-    fn set_mode(&mut self, val: u16) {
+    fn set_mode(&mut self, shared: &mut SharedState, val: u16) {
         self.use_sync = (val & 1) != 0;
         self.sync = Sync::from_field((val >> 1) & 3);
         self.target_wrap = (val >> 3) & 1 != 0;
@@ -379,15 +414,17 @@ impl Timer {
         self.counter = 0;
 
         if self.wrap_irq {
-            panic!("Wrap IRQ not supported");
+            fatal!(shared, Device::Timers, "Wrap IRQ not supported");
         }
 
         if (self.wrap_irq || self.target_irq) && !self.repeat_irq {
-            panic!("One shot timer interrupts are not supported: {:?}", self);
+            fatal!(shared, Device::Timers,
+                   "One shot timer interrupts are not supported: {:?}", self);
         }
 
         if self.negate_irq {
-            panic!("Only pulse interrupts are supported: {:?}", self);
+            fatal!(shared, Device::Timers,
+                   "Only pulse interrupts are supported: {:?}", self);
         }
 
         if self.use_sync {
@@ -56,6 +56,11 @@ impl GamePad {
     pub fn set_profile(&mut self, profile: Box<Profile>) {
         self.profile = profile
     }
+
+    /// Exchange the profiles of `self` and `other`
+    pub fn swap_profile(&mut self, other: &mut GamePad) {
+        ::std::mem::swap(&mut self.profile, &mut other.profile);
+    }
 }
 
 impl Encodable for GamePad {
@@ -0,0 +1,105 @@
+//! Memory card storage
+
+/// Size of a memory card image in bytes
+pub const SIZE: usize = 128 * 1024;
+/// Size of a frame, the unit of the card's read and write commands
+pub const FRAME_SIZE: usize = 128;
+/// Number of frames on the card
+pub const NUM_FRAMES: u32 = (SIZE / FRAME_SIZE) as u32;
+
+/// Contents of a 128KiB memory card
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct MemoryCard {
+    data: Vec<u8>,
+}
+
+impl MemoryCard {
+    /// Create a freshly formatted memory card
+    pub fn new() -> MemoryCard {
+        let mut data = vec![0; SIZE];
+
+        // Header frame
+        data[0] = b'M';
+        data[1] = b'C';
+
+        // Directory frames: all the blocks are free and not chained
+        for frame in data[FRAME_SIZE..16 * FRAME_SIZE].chunks_mut(FRAME_SIZE) {
+            frame[0] = 0xa0;
+            frame[8] = 0xff;
+            frame[9] = 0xff;
+        }
+
+        // Broken frame list: no broken frame
+        for frame in data[16 * FRAME_SIZE..36 * FRAME_SIZE].chunks_mut(FRAME_SIZE) {
+            for b in &mut frame[0..4] {
+                *b = 0xff;
+            }
+            frame[8] = 0xff;
+            frame[9] = 0xff;
+        }
+
+        // Write test frame, a copy of the header
+        data[63 * FRAME_SIZE] = b'M';
+        data[63 * FRAME_SIZE + 1] = b'C';
+
+        for frame in data[0..64 * FRAME_SIZE].chunks_mut(FRAME_SIZE) {
+            let c = checksum(frame);
+
+            frame[FRAME_SIZE - 1] = c;
+        }
+
+        MemoryCard {
+            data: data,
+        }
+    }
+
+    /// Create a memory card from a raw image. Returns `None` if
+    /// `image` doesn't have the right size.
+    pub fn from_image(image: Vec<u8>) -> Option<MemoryCard> {
+        if image.len() == SIZE {
+            Some(MemoryCard {
+                data: image,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Return the raw image of the card, for instance to save it to
+    /// a file
+    pub fn image(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn image_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    /// Return the contents of `frame` or `None` if it's out of range
+    pub fn frame(&self, frame: u32) -> Option<&[u8]> {
+        if frame < NUM_FRAMES {
+            let start = frame as usize * FRAME_SIZE;
+
+            Some(&self.data[start..start + FRAME_SIZE])
+        } else {
+            None
+        }
+    }
+
+    pub fn frame_mut(&mut self, frame: u32) -> Option<&mut [u8]> {
+        if frame < NUM_FRAMES {
+            let start = frame as usize * FRAME_SIZE;
+
+            Some(&mut self.data[start..start + FRAME_SIZE])
+        } else {
+            None
+        }
+    }
+}
+
+/// Compute the checksum of a header or directory frame: the XOR of
+/// all the bytes but the last one which is where the checksum is
+/// stored.
+pub fn checksum(frame: &[u8]) -> u8 {
+    frame[..FRAME_SIZE - 1].iter().fold(0, |c, &b| c ^ b)
+}
@@ -4,10 +4,13 @@ use memory::Addressable;
 use interrupt::Interrupt;
 use timekeeper::{Peripheral, Cycles};
 use shared::SharedState;
+use error::Device;
 
 use self::gamepad::GamePad;
+use self::memorycard::MemoryCard;
 
 pub mod gamepad;
+pub mod memorycard;
 
 #[derive(RustcDecodable, RustcEncodable)]
 pub struct PadMemCard {
@@ -55,6 +58,11 @@ pub struct PadMemCard {
     pad1: GamePad,
     /// Gamepad in slot 2
     pad2: GamePad,
+    /// Memory card in slot 1. The serial protocol isn't implemented
+    /// yet, only the HLE BIOS can access the cards for now.
+    memory_card1: Option<MemoryCard>,
+    /// Memory card in slot 2
+    memory_card2: Option<MemoryCard>,
     /// Bus state machine
     bus: BusState,
 }
@@ -76,6 +84,8 @@ impl PadMemCard {
             rx_not_empty: false,
             pad1: GamePad::disconnected(),
             pad2: GamePad::disconnected(),
+            memory_card1: None,
+            memory_card2: None,
             bus: BusState::Idle,
         }
     }
@@ -89,8 +99,8 @@ impl PadMemCard {
         match offset {
             0  => {
                 if T::size() != 1 {
-                    panic!("Unhandled gamepad TX access ({})",
-                           T::size());
+                    fatal!(shared, Device::PadMemCard,
+                           "Unhandled gamepad TX access ({})", T::size());
                 }
 
                 self.send_command(shared, val as u8);
@@ -99,12 +109,14 @@ impl PadMemCard {
             10 => {
                 if T::size() == 1 {
                     // Byte access behaves like a halfword
-                    panic!("Unhandled byte gamepad control access");
+                    fatal!(shared, Device::PadMemCard,
+                           "Unhandled byte gamepad control access");
                 }
                 self.set_control(shared, val as u16);
             }
             14 => self.baud_div = val as u16,
-            _ => panic!("Unhandled write to gamepad register {} {:04x}",
+            _ => fatal!(shared, Device::PadMemCard,
+                        "Unhandled write to gamepad register {} {:04x}",
                         offset, val as u16),
         }
     }
@@ -118,8 +130,8 @@ impl PadMemCard {
         match offset {
             0 => {
                 if T::size() != 1 {
-                    panic!("Unhandled gamepad RX access ({})",
-                           T::size());
+                    fatal!(shared, Device::PadMemCard,
+                           "Unhandled gamepad RX access ({})", T::size());
                 }
 
                 let res = self.response as u32;
@@ -134,8 +146,23 @@ impl PadMemCard {
             }
             10 => self.control() as u32,
             14 => self.baud_div as u32,
-            _ => panic!("Unhandled gamepad read {:?} 0x{:x}",
-                        T::size(), offset),
+            _ => {
+                fatal!(shared, Device::PadMemCard,
+                       "Unhandled gamepad read {:?} 0x{:x}",
+                       T::size(), offset);
+                0
+            }
+        }
+    }
+
+    /// Register value without side effects, used for debugging
+    pub fn peek(&self, offset: u32) -> u32 {
+        match offset {
+            0 => self.response as u32,
+            4 => self.stat(),
+            10 => self.control() as u32,
+            14 => self.baud_div as u32,
+            _ => 0,
         }
     }
 
@@ -164,7 +191,8 @@ impl PadMemCard {
                     if self.rx_not_empty {
                         // XXX should push in the non-emulated RX FIFO
                         // instead of overwritting `self.response`
-                        panic!("Gamepad RX while FIFO isn't empty");
+                        fatal!(shared, Device::PadMemCard,
+                               "Gamepad RX while FIFO isn't empty");
                     }
 
                     self.response = r;
@@ -227,11 +255,35 @@ impl PadMemCard {
         [ &mut self.pad1, &mut self.pad2 ]
     }
 
+    /// Return a mutable reference to the memory card slots, used to
+    /// insert and remove the cards.
+    pub fn memory_cards_mut(&mut self) -> [&mut Option<MemoryCard>; 2] {
+        [ &mut self.memory_card1, &mut self.memory_card2 ]
+    }
+
+    /// Return the memory card in slot `port` (0 or 1) if there's one
+    pub fn memory_card_mut(&mut self, port: usize) -> Option<&mut MemoryCard> {
+        match port {
+            0 => self.memory_card1.as_mut(),
+            1 => self.memory_card2.as_mut(),
+            _ => None,
+        }
+    }
+
+    /// Exchange the gamepad profiles with `other`. Used when loading
+    /// savestates since the profiles aren't serialized.
+    pub fn swap_profiles(&mut self, other: &mut PadMemCard) {
+        self.pad1.swap_profile(&mut other.pad1);
+        self.pad2.swap_profile(&mut other.pad2);
+    }
+
     fn send_command(&mut self, shared: &mut SharedState, cmd: u8) {
         if !self.tx_en {
             // It should be stored in the FIFO and sent when tx_en is
             // set (I think)
-            panic!("Unhandled gamepad command while tx_en is disabled");
+            fatal!(shared, Device::PadMemCard,
+                   "Unhandled gamepad command while tx_en is disabled");
+            return;
         }
 
         if self.bus.is_busy() {
@@ -349,18 +401,21 @@ impl PadMemCard {
             self.target = Target::from_control(ctrl);
 
             if self.rx_en {
-                panic!("Gamepad rx_en not implemented");
+                fatal!(shared, Device::PadMemCard,
+                       "Gamepad rx_en not implemented");
             }
 
             if self.dsr_it && !self.interrupt && self.dsr {
                 // Interrupt should trigger here but that really
                 // shouldn't happen I think.
-                panic!("dsr_it enabled while DSR signal is active");
+                fatal!(shared, Device::PadMemCard,
+                       "dsr_it enabled while DSR signal is active");
             }
 
             if ctrl & 0xf00 != 0 {
                 // XXX add support for those interrupts
-                panic!("Unsupported gamepad interrupts: {:04x}", ctrl);
+                fatal!(shared, Device::PadMemCard,
+                       "Unsupported gamepad interrupts: {:04x}", ctrl);
             }
 
             if !prev_select && self.select {
@@ -58,9 +58,9 @@ impl ExeLoader {
         let text_len = try!(read_u32(r));
 
         // Let's be on the safe side and reject anormaly big
-        // programs. Since the PlayStation RAM is 2MB big it doesn't
-        // make sense to have bigger programs
-        if text_len > 2 * 1024 * 1024 {
+        // programs. Since the development kits have 8MB of RAM it
+        // doesn't make sense to have bigger programs
+        if text_len > 8 * 1024 * 1024 {
             return Err(Error::TooBig(text_len));
         }
 
@@ -283,7 +283,7 @@ impl ExeLoader {
             Addiu(SP, SP, 24)
         ]).unwrap();
 
-        let (mc, _) = asm.machine_code();
+        let (mc, _) = asm.machine_code().unwrap();
 
         self.loader = mc;
     }
@@ -301,14 +301,18 @@ impl ExeLoader {
     /// Patch the BIOS animation jump to run the loader code
     /// instead. Returns an error if the patching failed.
     pub fn patch_bios(&self, bios: &mut Bios) -> Result<(), ()> {
-        let mut asm = Assembler::from_base(0);
+        // The hook is in the BIOS, the jump keeps the KSEG1 segment
+        // of the caller so we reach the loader through its KSEG1
+        // mirror
+        let mut asm = Assembler::from_base(0xa0000000 | ::memory::map::BIOS.0);
 
         // Assemble the jump instruction
-        let instruction = Jal(Label::Absolute(LOADER_ENTRY_ADDRESS));
+        let target = 0xa0000000 | LOADER_ENTRY_ADDRESS;
+        let instruction = Jal(Label::Absolute(target));
 
         asm.assemble(&[instruction]).unwrap();
 
-        let (mc, _) = asm.machine_code();
+        let (mc, _) = asm.machine_code().unwrap();
 
         // It should only have generated a single instruction
         assert!(mc.len() == 4);
@@ -395,3 +399,33 @@ const LOADER_ENTRY_OFFSET: u32 = 0x200;
 /// Absolute address of the entry point for the loader code
 const LOADER_ENTRY_ADDRESS: u32 =
     ::memory::map::EXPANSION_1.0 + LOADER_ENTRY_OFFSET;
+
+#[test]
+fn exe_round_trip() {
+    use assembler::exe::Exe;
+
+    let mut exe = Exe::new(0x80010000, vec![1, 2, 3, 4, 5]);
+
+    exe.entry = 0x80010004;
+    exe.initial_gp = 0x80012345;
+    exe.memfill_base = 0x80020000;
+    exe.memfill_len = 0x100;
+    exe.region = Some(Region::Europe);
+
+    let mut out = Vec::new();
+    exe.write(&mut out).unwrap();
+
+    // Header and text padded to a full sector
+    assert!(out.len() == 2048 * 2);
+
+    let loader = ExeLoader::load(&mut &out[..]).unwrap();
+
+    assert!(loader.base == 0x80010000);
+    assert!(loader.entry == 0x80010004);
+    assert!(loader.initial_gp == 0x80012345);
+    assert!(loader.initial_sp == 0x801ffff0);
+    assert!(loader.memfill_base == 0x80020000);
+    assert!(loader.memfill_len == 0x100);
+    assert!(loader.region == Some(Region::Europe));
+    assert!(&loader.text[..5] == &[1, 2, 3, 4, 5]);
+}
@@ -37,6 +37,17 @@ impl ParallelIo {
 
         r
     }
+
+    pub fn store<T: Addressable>(&mut self,
+                                 shared: &mut SharedState,
+                                 offset: u32,
+                                 val: u32) {
+        for i in 0..T::size() {
+            let b = (val >> (8 * i)) as u8;
+
+            self.module.store(shared, offset + i as u32, b);
+        }
+    }
 }
 
 impl Encodable for ParallelIo {
@@ -0,0 +1,438 @@
+//! Counting profiler for the guest code.
+//!
+//! When enabled (see `Counters::profiler`) the CPU reports every
+//! instruction it executes along with the number of cycles it took
+//! (including instruction fetch and memory access timings) and its
+//! shadow call stack (see `cpu::CallStack`), which lets us attribute
+//! cycles to functions.
+//!
+//! Code that doesn't follow the usual calling conventions (tail
+//! calls, `longjmp`, thread switches...) will confuse the call stack
+//! tracking somewhat, see the `CallStack` documentation.
+//!
+//! Each unique call stack is stored once as a node of a call tree so
+//! that recording an instruction doesn't depend on the depth of the
+//! stack. The per-function statistics are aggregated from the tree
+//! when they're requested.
+
+use std::collections::HashMap;
+use std::io;
+
+use cpu::CallStack;
+
+/// Statistics for a single PC
+#[derive(Clone, Copy, Default, RustcDecodable, RustcEncodable)]
+pub struct PcStats {
+    /// Number of times the instruction was executed
+    pub instructions: u64,
+    /// Total number of CPU cycles spent executing the instruction
+    pub cycles: u64,
+}
+
+/// Statistics for a single function, identified by its entry point
+#[derive(Clone, Copy, Default, RustcDecodable, RustcEncodable)]
+pub struct FunctionStats {
+    /// Number of times the function was called
+    pub calls: u64,
+    /// Number of instructions executed in the function itself
+    pub self_instructions: u64,
+    /// Cycles spent in the function itself
+    pub self_cycles: u64,
+    /// Cycles spent in the function and all its callees
+    pub total_cycles: u64,
+}
+
+/// Node of the call tree, represents a unique call stack
+#[derive(Clone, Copy, RustcDecodable, RustcEncodable)]
+struct Node {
+    /// Address of the first instruction of the function
+    entry: u32,
+    /// Index of the caller's node, `NO_PARENT` for the root
+    parent: u32,
+    /// True if `entry` is also one of the callers, in which case the
+    /// cycles are already accounted for in the callers' inclusive
+    /// time
+    recursive: bool,
+    /// Number of times this stack was entered
+    calls: u64,
+    /// Number of instructions executed with this exact stack
+    instructions: u64,
+    /// Cycles spent with this exact stack
+    cycles: u64,
+}
+
+#[derive(RustcDecodable, RustcEncodable)]
+pub struct Profiler {
+    /// Statistics for each PC executed since the last reset
+    pcs: HashMap<u32, PcStats>,
+    /// Call tree. A node is always created after its parent.
+    nodes: Vec<Node>,
+    /// Index of the node for each (parent node, entry point) pair,
+    /// the key is `parent << 32 | entry`
+    children: HashMap<u64, u32>,
+    /// Nodes of the current call stack. The first entry is the root
+    /// which is where we were when profiling started.
+    path: Vec<u32>,
+    /// Target and return address of the CPU call stack frames
+    /// matching `path[1..]`, used to detect changes
+    frames: Vec<(u32, u32)>,
+    /// Total number of instructions executed while profiling
+    instructions: u64,
+    /// Total number of cycles elapsed while profiling
+    cycles: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            pcs: HashMap::new(),
+            nodes: Vec::new(),
+            children: HashMap::new(),
+            path: Vec::new(),
+            frames: Vec::new(),
+            instructions: 0,
+            cycles: 0,
+        }
+    }
+
+    /// Discard all the statistics gathered so far
+    pub fn reset(&mut self) {
+        *self = Profiler::new();
+    }
+
+    /// Called by the CPU after executing the instruction at `pc`
+    /// which took `cycles` to complete. `stack` is the call stack
+    /// after the instruction ran.
+    pub fn record(&mut self, pc: u32, cycles: u64, stack: &CallStack) {
+        if self.nodes.is_empty() {
+            // First instruction since the profiler was started, we
+            // don't know which function we're in, use the outermost
+            // caller (or the current PC) as the root.
+            let root = stack.frames().first().map_or(pc, |f| f.caller);
+
+            self.nodes.push(Node::new(root, NO_PARENT, false));
+            self.nodes[0].calls = 1;
+            self.path.push(0);
+
+            self.sync(stack);
+        }
+
+        self.instructions += 1;
+        self.cycles += cycles;
+
+        {
+            let stats = self.pcs.entry(pc).or_insert_with(Default::default);
+
+            stats.instructions += 1;
+            stats.cycles += cycles;
+        }
+
+        {
+            let current = self.path[self.path.len() - 1] as usize;
+            let node = &mut self.nodes[current];
+
+            node.instructions += 1;
+            node.cycles += cycles;
+        }
+
+        // If the instruction was a call or a return it only takes
+        // effect from the next instruction: the delay slot belongs to
+        // the target
+        self.sync(stack);
+    }
+
+    /// Update `path` to match `stack`
+    fn sync(&mut self, stack: &CallStack) {
+        let frames = stack.frames();
+
+        // Most instructions don't touch the stack
+        let unchanged =
+            frames.len() == self.frames.len() &&
+            frames.last().map(|f| (f.target, f.return_addr)) ==
+            self.frames.last().cloned();
+
+        if unchanged {
+            return;
+        }
+
+        let common =
+            self.frames.iter().zip(frames.iter())
+            .take_while(|&(&m, f)| m == (f.target, f.return_addr))
+            .count();
+
+        self.frames.truncate(common);
+        self.path.truncate(common + 1);
+
+        for f in &frames[common..] {
+            let parent = self.path[self.path.len() - 1];
+            let node = self.child(parent, f.target);
+
+            self.nodes[node as usize].calls += 1;
+
+            self.path.push(node);
+            self.frames.push((f.target, f.return_addr));
+        }
+    }
+
+    /// Return the node for a call to `entry` from `parent`, creating
+    /// it if necessary
+    fn child(&mut self, parent: u32, entry: u32) -> u32 {
+        let key = ((parent as u64) << 32) | entry as u64;
+
+        if let Some(&node) = self.children.get(&key) {
+            return node;
+        }
+
+        let mut recursive = false;
+        let mut n = parent;
+
+        while n != NO_PARENT {
+            let caller = &self.nodes[n as usize];
+
+            if caller.entry == entry {
+                recursive = true;
+                break;
+            }
+
+            n = caller.parent;
+        }
+
+        let node = self.nodes.len() as u32;
+
+        self.nodes.push(Node::new(entry, parent, recursive));
+        self.children.insert(key, node);
+
+        node
+    }
+
+    /// Return the function entry points of the current call stack,
+    /// outermost first
+    pub fn call_stack(&self) -> Vec<u32> {
+        self.path.iter().map(|&n| self.nodes[n as usize].entry).collect()
+    }
+
+    /// Total number of instructions executed since the last reset
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Total number of cycles elapsed since the last reset
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Per-PC statistics
+    pub fn pcs(&self) -> &HashMap<u32, PcStats> {
+        &self.pcs
+    }
+
+    /// Per-function statistics
+    pub fn functions(&self) -> HashMap<u32, FunctionStats> {
+        // Cycles spent in each node and its callees. Children always
+        // come after their parent so we can accumulate backwards.
+        let mut inclusive: Vec<u64> = self.nodes.iter().map(|n| n.cycles).collect();
+
+        for (i, n) in self.nodes.iter().enumerate().skip(1).rev() {
+            inclusive[n.parent as usize] += inclusive[i];
+        }
+
+        let mut functions = HashMap::new();
+
+        for (n, &total) in self.nodes.iter().zip(inclusive.iter()) {
+            let stats: &mut FunctionStats =
+                functions.entry(n.entry).or_insert_with(Default::default);
+
+            stats.calls += n.calls;
+            stats.self_instructions += n.instructions;
+            stats.self_cycles += n.cycles;
+
+            // Recursive calls are already counted by the outermost
+            // call
+            if !n.recursive {
+                stats.total_cycles += total;
+            }
+        }
+
+        functions
+    }
+
+    /// Write a flat profile to `w`: one table for the functions
+    /// sorted by self cycles and one for the individual instructions
+    /// sorted by cycles.
+    pub fn write_flat(&self, w: &mut io::Write) -> io::Result<()> {
+        let total = ::std::cmp::max(self.cycles, 1) as f64;
+
+        try!(writeln!(w, "Total: {} instructions, {} cycles",
+                      self.instructions, self.cycles));
+        try!(writeln!(w, ""));
+
+        let functions = self.functions();
+        let mut functions: Vec<_> = functions.iter().collect();
+
+        functions.sort_by(|a, b| b.1.self_cycles.cmp(&a.1.self_cycles)
+                          .then(a.0.cmp(b.0)));
+
+        try!(writeln!(w, "  self%   total%  self cycles  instructions      calls  function"));
+
+        for (&entry, stats) in functions {
+            try!(writeln!(w, "{:6.2}% {:7.2}% {:12} {:13} {:10}  0x{:08x}",
+                          stats.self_cycles as f64 * 100. / total,
+                          stats.total_cycles as f64 * 100. / total,
+                          stats.self_cycles,
+                          stats.self_instructions,
+                          stats.calls,
+                          entry));
+        }
+
+        try!(writeln!(w, ""));
+
+        let mut pcs: Vec<_> = self.pcs.iter().collect();
+
+        pcs.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(b.0)));
+
+        try!(writeln!(w, "  time%       cycles  instructions  pc"));
+
+        for (&pc, stats) in pcs {
+            try!(writeln!(w, "{:6.2}% {:12} {:13}  0x{:08x}",
+                          stats.cycles as f64 * 100. / total,
+                          stats.cycles,
+                          stats.instructions,
+                          pc));
+        }
+
+        Ok(())
+    }
+
+    /// Write the profile in the "collapsed stack" format used by
+    /// flamegraph tools: one line per unique stack with the function
+    /// names separated by semicolons followed by the number of cycles
+    /// spent in that stack.
+    pub fn write_collapsed(&self, w: &mut io::Write) -> io::Result<()> {
+        let mut stacks: Vec<(Vec<u32>, u64)> =
+            self.nodes.iter()
+            .filter(|n| n.instructions > 0)
+            .map(|n| (self.stack(n), n.cycles))
+            .collect();
+
+        stacks.sort();
+
+        for (stack, cycles) in stacks {
+            let names: Vec<String> =
+                stack.iter().map(|e| format!("0x{:08x}", e)).collect();
+
+            try!(writeln!(w, "{} {}", names.join(";"), cycles));
+        }
+
+        Ok(())
+    }
+
+    /// Return the function entry points leading to `node`, outermost
+    /// first
+    fn stack(&self, node: &Node) -> Vec<u32> {
+        let mut stack = vec![node.entry];
+        let mut n = node.parent;
+
+        while n != NO_PARENT {
+            let caller = &self.nodes[n as usize];
+
+            stack.push(caller.entry);
+            n = caller.parent;
+        }
+
+        stack.reverse();
+
+        stack
+    }
+}
+
+impl Node {
+    fn new(entry: u32, parent: u32, recursive: bool) -> Node {
+        Node {
+            entry: entry,
+            parent: parent,
+            recursive: recursive,
+            calls: 0,
+            instructions: 0,
+            cycles: 0,
+        }
+    }
+}
+
+/// `Node::parent` value for the root of the call tree
+const NO_PARENT: u32 = !0;
+
+#[test]
+fn profiler_call_tracking() {
+    let mut p = Profiler::new();
+    let mut cs = CallStack::new();
+
+    // main: 0x1000
+    p.record(0x1000, 2, &cs);
+    // jal 0x2000
+    cs.call(0x1004, 0x2000, 0x100c);
+    p.record(0x1004, 1, &cs);
+    p.record(0x1008, 1, &cs);
+    p.record(0x2000, 3, &cs);
+    // jr ra
+    cs.jump_register(0x100c);
+    p.record(0x2004, 1, &cs);
+    p.record(0x2008, 1, &cs);
+    p.record(0x100c, 1, &cs);
+
+    assert!(p.instructions() == 7);
+    assert!(p.cycles() == 10);
+    assert!(p.call_stack() == vec![0x1000]);
+
+    let main = p.functions()[&0x1000];
+    let func = p.functions()[&0x2000];
+
+    // The delay slots are attributed to the target function
+    assert!(main.self_cycles == 5);
+    assert!(main.total_cycles == 10);
+    assert!(func.calls == 1);
+    assert!(func.self_instructions == 3);
+    assert!(func.self_cycles == 5);
+    assert!(func.total_cycles == 5);
+
+    let mut collapsed = Vec::new();
+
+    p.write_collapsed(&mut collapsed).unwrap();
+
+    assert!(collapsed ==
+            b"0x00001000 5\n0x00001000;0x00002000 5\n".to_vec());
+}
+
+#[test]
+fn profiler_recursion() {
+    let mut p = Profiler::new();
+    let mut cs = CallStack::new();
+
+    p.record(0x1000, 1, &cs);
+    // f calls itself twice, then main calls f again
+    cs.call(0x1000, 0x2000, 0x1008);
+    p.record(0x1004, 1, &cs);
+    cs.call(0x2000, 0x2000, 0x2008);
+    p.record(0x2000, 2, &cs);
+    cs.call(0x2000, 0x2000, 0x2008);
+    p.record(0x2000, 4, &cs);
+    p.record(0x2004, 8, &cs);
+    // Return straight to main
+    cs.jump_register(0x1008);
+    p.record(0x2008, 16, &cs);
+    cs.call(0x1008, 0x2000, 0x1010);
+    p.record(0x100c, 32, &cs);
+    p.record(0x2000, 64, &cs);
+
+    assert!(p.call_stack() == vec![0x1000, 0x2000]);
+
+    let main = p.functions()[&0x1000];
+    let f = p.functions()[&0x2000];
+
+    assert!(f.calls == 4);
+    assert!(f.self_instructions == 5);
+    assert!(f.self_cycles == 2 + 4 + 8 + 16 + 64);
+    // Cycles spent in the recursive calls are only counted once
+    assert!(f.total_cycles == f.self_cycles);
+    assert!(main.self_cycles == 1 + 1 + 32);
+    assert!(main.total_cycles == p.cycles());
+}
@@ -1,5 +1,7 @@
 use timekeeper::TimeKeeper;
 use interrupt::InterruptState;
+use error::EmulationError;
+use profiler::Profiler;
 
 /// State shared between various modules
 #[derive(RustcDecodable, RustcEncodable)]
@@ -7,6 +9,9 @@ pub struct SharedState {
     tk: TimeKeeper,
     irq_state: InterruptState,
     counters: Counters,
+    /// First fatal error encountered since the last call to
+    /// `take_error`
+    error: Option<EmulationError>,
 }
 
 impl SharedState {
@@ -15,6 +20,7 @@ impl SharedState {
             tk: TimeKeeper::new(),
             irq_state: InterruptState::new(),
             counters: Counters::new(),
+            error: None,
         }
     }
 
@@ -26,6 +32,11 @@ impl SharedState {
         &mut self.irq_state
     }
 
+    /// Return a copy of the interrupt controller state
+    pub fn irq_state_snapshot(&self) -> InterruptState {
+        self.irq_state
+    }
+
     pub fn counters(&self) -> &Counters {
         &self.counters
     }
@@ -33,6 +44,26 @@ impl SharedState {
     pub fn counters_mut(&mut self) -> &mut Counters {
         &mut self.counters
     }
+
+    /// Record a fatal emulation error. If an error is already pending
+    /// the new one is logged and discarded.
+    pub fn set_error(&mut self, error: EmulationError) {
+        error!("Emulation error: {}", error);
+
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+
+    /// Return the pending error, if any
+    pub fn error(&self) -> Option<&EmulationError> {
+        self.error.as_ref()
+    }
+
+    /// Return the pending error and clear it
+    pub fn take_error(&mut self) -> Option<EmulationError> {
+        self.error.take()
+    }
 }
 
 /// Struct holding various counters for debugging and profiling
@@ -48,6 +79,11 @@ pub struct Counters {
     /// Incremented when the CPU is preempted by an external
     /// interrupt.
     pub cpu_interrupt: Counter,
+    /// Incremented for each instruction executed by the CPU
+    pub cpu_instruction: Counter,
+    /// Guest code profiler, `None` if profiling is disabled. Can be
+    /// set at any time to start profiling.
+    pub profiler: Option<Profiler>,
 }
 
 impl Counters {
@@ -56,6 +92,8 @@ impl Counters {
             frame: Counter(0),
             framebuffer_swap: Counter(0),
             cpu_interrupt: Counter(0),
+            cpu_instruction: Counter(0),
+            profiler: None,
         }
     }
 }
@@ -1,6 +1,8 @@
 use rustc_serialize::{Decodable, Encodable, Decoder, Encoder};
 
 use memory::Addressable;
+use shared::SharedState;
+use error::Device;
 
 /// Sound Processing Unit
 pub struct Spu {
@@ -25,9 +27,13 @@ impl Spu {
         }
     }
 
-    pub fn store<T: Addressable>(&mut self, offset: u32, val: u32) {
+    pub fn store<T: Addressable>(&mut self,
+                                 shared: &mut SharedState,
+                                 offset: u32,
+                                 val: u32) {
         if T::size() != 2 {
-            panic!("Unhandled SPU store ({})", T::size());
+            fatal!(shared, Device::Spu, "Unhandled SPU store ({})", T::size());
+            return;
         }
 
         let val = val as u16;
@@ -76,9 +82,9 @@ impl Spu {
                 regmap::TRANSFER_FIFO =>
                     self.fifo_write(val),
                 regmap::CONTROL =>
-                    self.set_control(val),
+                    self.set_control(shared, val),
                 regmap::TRANSFER_CONTROL =>
-                    self.set_transfer_control(val),
+                    self.set_transfer_control(shared, val),
                 regmap::CD_VOLUME_LEFT => (),
                 regmap::CD_VOLUME_RIGHT => (),
                 regmap::EXT_VOLUME_LEFT => (),
@@ -115,7 +121,8 @@ impl Spu {
                 regmap::REVERB_APF_RIGHT2 => (),
                 regmap::REVERB_INPUT_VOLUME_LEFT => (),
                 regmap::REVERB_INPUT_VOLUME_RIGHT => (),
-                _ => panic!("Unhandled SPU store {:x} {:04x}", offset, val),
+                _ => fatal!(shared, Device::Spu,
+                            "Unhandled SPU store {:x} {:04x}", offset, val),
             }
         }
 
@@ -124,9 +131,12 @@ impl Spu {
         }
     }
 
-    pub fn load<T: Addressable>(&mut self, offset: u32) -> u32 {
+    pub fn load<T: Addressable>(&mut self,
+                                shared: &mut SharedState,
+                                offset: u32) -> u32 {
         if T::size() != 2 {
-            panic!("Unhandled SPU load ({})", T::size());
+            fatal!(shared, Device::Spu, "Unhandled SPU load ({})", T::size());
+            return 0;
         }
 
         let index = (offset >> 1) as usize;
@@ -180,7 +190,11 @@ impl Spu {
                     regmap::CURRENT_VOLUME_RIGHT =>
                         // XXX return current value
                         shadow,
-                    _ => panic!("Unhandled SPU load {:x}", offset),
+                    _ => {
+                        fatal!(shared, Device::Spu,
+                               "Unhandled SPU load {:x}", offset);
+                        shadow
+                    }
                 }
             };
 
@@ -191,11 +205,11 @@ impl Spu {
         self.shadow_registers[regmap::CONTROL]
     }
 
-    fn set_control(&mut self, ctrl: u16) {
+    fn set_control(&mut self, shared: &mut SharedState, ctrl: u16) {
         // XXX if a game enables the SPU IRQ we're probably going to
         // be in trouble
         if ctrl & 0x40 != 0 {
-            panic!("Unhandled SPU IRQ");
+            fatal!(shared, Device::Spu, "Unhandled SPU IRQ");
         }
     }
 
@@ -203,11 +217,26 @@ impl Spu {
         self.control() & 0x3f
     }
 
+    /// Register value without side effects, used for debugging
+    pub fn peek(&self, offset: u32) -> u32 {
+        let index = (offset >> 1) as usize;
+
+        let r =
+            match index {
+                regmap::STATUS => self.status(),
+                0..=0xff => self.shadow_registers[index],
+                _ => 0,
+            };
+
+        r as u32
+    }
+
     /// Set the SPU RAM access pattern
-    fn set_transfer_control(&self, val: u16) {
+    fn set_transfer_control(&self, shared: &mut SharedState, val: u16) {
         // For now only support "normal" (i.e. sequential) access
         if val != 0x4 {
-            panic!("Unhandled SPU RAM access pattern {:x}", val);
+            fatal!(shared, Device::Spu,
+                   "Unhandled SPU RAM access pattern {:x}", val);
         }
     }
 
@@ -0,0 +1,70 @@
+//! Helpers shared by the unit tests of the various modules
+
+use bios::Bios;
+use gpu::{Gpu, VideoClock};
+use gpu::renderer::{Renderer, PrimitiveAttributes, Vertex};
+use memory::{Interconnect, Addressable, Word};
+use cpu::Cpu;
+
+/// Dummy GPU renderer to run the tests
+pub struct DummyRenderer;
+
+impl Renderer for DummyRenderer {
+    fn set_draw_offset(&mut self, _: i16, _: i16) {
+    }
+
+    fn set_draw_area(&mut self, _: (u16, u16), _: (u16, u16)) {
+    }
+
+    fn set_display_mode(&mut self,
+                        _: (u16, u16),
+                        _: (u16, u16),
+                        _: bool) {
+    }
+
+    fn push_line(&mut self, _: &PrimitiveAttributes, _: &[Vertex; 2]) {
+    }
+
+    fn push_triangle(&mut self, _: &PrimitiveAttributes, _: &[Vertex; 3]) {
+    }
+
+    fn push_quad(&mut self, _: &PrimitiveAttributes, _: &[Vertex; 4]) {
+    }
+
+    fn fill_rect(&mut self,
+                 _: [u8; 3],
+                 _: (u16, u16),
+                 _: (u16, u16)) {
+    }
+
+    fn load_image(&mut self,
+                  _: (u16, u16),
+                  _: (u16, u16),
+                  _: &[u16]) {
+    }
+}
+
+/// CPU with a dummy BIOS and no disc
+pub fn dummy_cpu() -> Cpu {
+    let inter = Interconnect::new(Bios::dummy(),
+                                  Gpu::new(VideoClock::Ntsc),
+                                  None);
+
+    Cpu::new(inter)
+}
+
+/// Store the words in `blob` in RAM starting at `address`
+pub fn write_blob(cpu: &mut Cpu,
+                  address: u32,
+                  blob: &[u32]) {
+    let ram = cpu.interconnect_mut().ram_mut();
+
+    for (i, &w) in blob.iter().enumerate() {
+        ram.store::<Word>(address + (i * 4) as u32, w);
+    }
+}
+
+/// Load a value from RAM at `address`
+pub fn read<T: Addressable>(cpu: &Cpu, address: u32) -> u32 {
+    cpu.interconnect().ram().load::<T>(address)
+}